@@ -0,0 +1,349 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::types::{Address, DatabaseRef},
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig},
+    config::ProxyExDetectorConfig,
+    dataset::ProxyData,
+    entities,
+    error::DetectorError,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    routing::{check_routing, RoutingResult},
+    run::RunTracker,
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DbErr, EntityTrait, QueryFilter, QueryOrder, QuerySelect,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`) instead of the database
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| check_file(args, proxies, files, p));
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("admin-routing");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) =
+        channel::bounded::<(Address, Option<RoutingResult>, Duration)>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        loop {
+            let (proxy, result, time) = match result_rx.recv() {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            let task = async {
+                let routing: entities::admin_routing::ActiveModel =
+                    routing_row(proxy, result, time).into();
+                entities::admin_routing::Entity::insert(routing)
+                    .on_conflict(
+                        OnConflict::column(entities::admin_routing::Column::Proxy)
+                            .do_nothing()
+                            .to_owned(),
+                    )
+                    .exec(&db)
+                    .await
+            };
+            match rt.block_on(task) {
+                Ok(_) => {}
+                Err(e) => {
+                    if e != DbErr::RecordNotInserted {
+                        panic!("{:?}", e);
+                    }
+                }
+            };
+            rt.block_on(cloned_tracker.done(&db, proxy.to_string().to_lowercase()))
+                .unwrap();
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            loop {
+                let proxy = match proxy_rx.recv() {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                info!(
+                    proxy = proxy.address.to_string().to_lowercase(),
+                    "Checking proxy"
+                );
+                // check the proxy when its latest implementation is in use
+                let task = async {
+                    let version = entities::version::Entity::find()
+                        .filter(
+                            entities::version::Column::Proxy
+                                .eq(proxy.address.to_string().to_lowercase()),
+                        )
+                        .order_by_desc(entities::version::Column::MinBlock)
+                        .one(&db)
+                        .await?;
+                    if let Some(version) = version {
+                        return Ok(Some(version.min_block + 1));
+                    }
+                    let creation = entities::creation::Entity::find()
+                        .filter(
+                            entities::creation::Column::Proxy
+                                .eq(proxy.address.to_string().to_lowercase()),
+                        )
+                        .one(&db)
+                        .await?;
+                    Ok::<_, DbErr>(creation.map(|c| c.creation_block + 1))
+                };
+                let blk = match rt.block_on(task).unwrap() {
+                    Some(blk) => blk,
+                    None => {
+                        error!(
+                            proxy = proxy.address.to_string().to_lowercase(),
+                            "No version or creation found"
+                        );
+                        let task = tracker.fail(
+                            &db,
+                            proxy.address.to_string().to_lowercase(),
+                            "No version or creation found".to_string(),
+                        );
+                        rt.block_on(task).unwrap();
+                        continue;
+                    }
+                };
+                let start_at = std::time::Instant::now();
+                let result = match check_proxy(p.clone(), proxy.address.cvt(), blk, budget) {
+                    Ok(r) => r,
+                    Err(e) => {
+                        error!(
+                            proxy = proxy.address.to_string().to_lowercase(),
+                            err = e.to_string(),
+                            "Failed to check admin routing"
+                        );
+                        let error = e.to_model(&tracker.detector, proxy.address.cvt(), None);
+                        rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                            .unwrap();
+                        let task = tracker.fail(
+                            &db,
+                            proxy.address.to_string().to_lowercase(),
+                            e.to_string(),
+                        );
+                        rt.block_on(task).unwrap();
+                        continue;
+                    }
+                };
+                result_tx
+                    .send((proxy.address.cvt(), result, start_at.elapsed()))
+                    .unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies = entities::proxy::Entity::find()
+            .filter(tracker.unfinished((entities::proxy::Entity, entities::proxy::Column::Address)))
+            .limit(1000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// Check the proxies in a ProxyData file when their latest implementation is in use,
+/// writing the rows of the `admin_routing` table to the files.
+fn check_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    check_into_files(proxies, files, args.jobs, &shutdown, |data| {
+        let proxy: Address = data.proxy.cvt();
+        info!(proxy = data.proxy.as_str(), "Checking proxy");
+        // the creation of a proxy is not in the file, so a proxy without invocations is skipped
+        let blk = match data.versions().last() {
+            Some((_, min_block)) => *min_block as i64 + 1,
+            None => {
+                error!(proxy = data.proxy.as_str(), "No version found");
+                return Vec::new();
+            }
+        };
+        let start_at = std::time::Instant::now();
+        let r = check_proxy(p.clone(), proxy, blk, budget)
+            .map(|result| routing_row(proxy, result, start_at.elapsed()))
+            .map_err(|e| {
+                error!(
+                    proxy = data.proxy.as_str(),
+                    err = e.to_string(),
+                    "Failed to check admin routing"
+                );
+                ErrorRecord::new(proxy, None, e)
+            });
+        vec![r]
+    });
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// The row of the `admin_routing` table, `result` is None if the proxy has no admin.
+fn routing_row(
+    proxy: Address,
+    result: Option<RoutingResult>,
+    time: Duration,
+) -> entities::admin_routing::Model {
+    entities::admin_routing::Model {
+        proxy: proxy.to_string().to_lowercase(),
+        admin: result.as_ref().map(|r| r.admin.to_string().to_lowercase()),
+        implementation: result
+            .as_ref()
+            .map(|r| r.implementation.to_string().to_lowercase()),
+        problematic: result.as_ref().map(|r| r.problematic()).unwrap_or(false),
+        probes: serde_json::to_value(result.as_ref().map(|r| r.probes.clone())).unwrap(),
+        admin_reached_logic: serde_json::to_value(result.as_ref().map(|r| r.admin_reached_logic()))
+            .unwrap(),
+        non_admin_reached_admin: serde_json::to_value(
+            result.as_ref().map(|r| r.non_admin_reached_admin()),
+        )
+        .unwrap(),
+        total_time: time.as_nanos() as i64,
+    }
+}
+
+fn check_proxy<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: Address,
+    blk: i64,
+    budget: Budget,
+) -> Result<Option<RoutingResult>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut state = p
+        .bc_state_at(TxPosition::new(blk as u64, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk as u64);
+    check_routing(&caller, &mut state, proxy, budget)
+}
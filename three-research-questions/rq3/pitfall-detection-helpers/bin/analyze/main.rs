@@ -2,24 +2,21 @@ use std::{collections::HashSet, io::Write};
 
 use clap::{command, Parser};
 use libsofl_core::{
-    blockchain::{
-        provider::{BcProvider, BcStateProvider},
-        transaction::Tx,
-    },
+    blockchain::transaction::Tx,
     conversion::ConvertTo,
     engine::{
         state::BcState,
         transition::TransitionSpecBuilder,
-        types::{Address, TxHash, U256},
+        types::{Address, DatabaseRef, TxHash, U256},
     },
 };
-use libsofl_reth::{blockchain::provider::RethProvider, config::RethConfig};
 use libsofl_utils::{
     config::Config,
     log::{config::LogConfig, info},
 };
 use proxyex_detector::{
     config::ProxyExDetectorConfig, entities, inspectors::collision::StorageAccessInspector,
+    provider::DetectorProvider,
 };
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
@@ -44,7 +41,6 @@ async fn main() -> Result<(), DbErr> {
     let args = Cli::parse();
     LogConfig::must_load().init();
 
-    let provider = RethConfig::must_load().bc_provider().unwrap();
     let db = ProxyExDetectorConfig::must_load().db().await?;
 
     let proxies = args
@@ -53,7 +49,9 @@ async fn main() -> Result<(), DbErr> {
         .map(|s| ConvertTo::<Address>::cvt(&s))
         .collect::<Vec<_>>();
 
-    let infos = analyze_all(&provider, &db, proxies.clone()).await.unwrap();
+    let infos =
+        proxyex_detector::with_provider!(|p| analyze_all(p.as_ref(), &db, proxies.clone()).await)
+            .unwrap();
 
     let mut output_file = std::fs::File::create(args.output).unwrap();
     for (proxy, info) in proxies.into_iter().zip(infos.into_iter()) {
@@ -80,11 +78,14 @@ struct Info {
     pub conflict_points: Vec<(TxHash, Vec<(U256, U256, String)>)>,
 }
 
-async fn analyze_all(
-    provider: &RethProvider,
+async fn analyze_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: &P,
     db: &DatabaseConnection,
     proxies: Vec<Address>,
-) -> Result<Vec<Info>, DbErr> {
+) -> Result<Vec<Info>, DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let mut infos = Vec::new();
     for proxy in proxies {
         let info = analyze_one(provider, db, proxy).await?;
@@ -93,11 +94,14 @@ async fn analyze_all(
     Ok(infos)
 }
 
-async fn analyze_one(
-    provider: &RethProvider,
+async fn analyze_one<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: &P,
     db: &DatabaseConnection,
     proxy: Address,
-) -> Result<Info, DbErr> {
+) -> Result<Info, DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let invocations = entities::invocation::Entity::find()
         .filter(entities::invocation::Column::Proxy.eq(proxy.to_string().to_lowercase()))
         .all(db)
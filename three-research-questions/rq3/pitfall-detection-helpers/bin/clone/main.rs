@@ -0,0 +1,259 @@
+use std::{sync::Arc, thread};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::types::DatabaseRef,
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    clone::{minimal_proxy_of, representatives, MinimalProxy},
+    config::ProxyExDetectorConfig,
+    entities,
+    error::DetectorError,
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, TransactionTrait,
+};
+
+/// Recognize the minimal proxies (EIP-1167, ClonesWithImmutableArgs, EIP-3448) by their bytecode template,
+/// record their hard-coded implementations in the `clone` table, and choose one representative clone per implementation,
+/// so that detectors run with `--representatives` skip the other clones.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let cfg = ProxyExDetectorConfig::must_load();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("clone");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) =
+        channel::bounded::<(String, Result<Option<MinimalProxy>, DetectorError>)>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        for (proxy, r) in result_rx {
+            let minimal_proxy = match r {
+                Ok(v) => v,
+                Err(e) => {
+                    rt.block_on(cloned_tracker.fail(&db, proxy, e.to_string()))
+                        .unwrap();
+                    continue;
+                }
+            };
+            if let Some(minimal_proxy) = minimal_proxy {
+                let clone = entities::clone::ActiveModel {
+                    proxy: ActiveValue::Set(proxy.clone()),
+                    kind: ActiveValue::Set(minimal_proxy.kind.as_str().to_string()),
+                    implementation: ActiveValue::Set(
+                        minimal_proxy.implementation.to_string().to_lowercase(),
+                    ),
+                    representative: ActiveValue::Set(false),
+                };
+                let task = entities::clone::Entity::insert(clone)
+                    .on_conflict(
+                        OnConflict::column(entities::clone::Column::Proxy)
+                            .do_nothing()
+                            .to_owned(),
+                    )
+                    .exec(&db);
+                match rt.block_on(task) {
+                    Ok(_) => {}
+                    Err(e) => {
+                        if e != DbErr::RecordNotInserted {
+                            panic!("{:?}", e);
+                        }
+                    }
+                };
+            }
+            rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            for proxy in proxy_rx {
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                let r = rt
+                    .block_on(deployed_block(&db, &proxy.address))
+                    .map_err(DetectorError::from)
+                    .and_then(|blk| match blk {
+                        Some(blk) => recognize_at(p.clone(), &proxy.address, blk),
+                        None => Ok(None),
+                    });
+                if let Err(e) = &r {
+                    error!(
+                        proxy = proxy.address.as_str(),
+                        err = e.to_string(),
+                        "Failed to read proxy code"
+                    );
+                }
+                result_tx.send((proxy.address, r)).unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies = entities::proxy::Entity::find()
+            .filter(tracker.unfinished((entities::proxy::Entity, entities::proxy::Column::Address)))
+            .limit(1000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+
+    // the clones recognized so far are grouped again, including those of previous sessions
+    let (clones, implementations) = assign_representatives(&db).await?;
+    info!(
+        clones = clones,
+        implementations = implementations,
+        "Representatives chosen"
+    );
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// A block where the proxy is deployed: the one after its first invocation, or after its creation.
+async fn deployed_block(db: &DatabaseConnection, proxy: &str) -> Result<Option<u64>, DbErr> {
+    let invocation = entities::invocation::Entity::find()
+        .filter(entities::invocation::Column::Proxy.eq(proxy))
+        .order_by_asc(entities::invocation::Column::Block)
+        .one(db)
+        .await?;
+    if let Some(invocation) = invocation {
+        return Ok(Some(invocation.block as u64 + 1));
+    }
+    let creation = entities::creation::Entity::find()
+        .filter(entities::creation::Column::Proxy.eq(proxy))
+        .one(db)
+        .await?;
+    Ok(creation.map(|c| c.creation_block as u64 + 1))
+}
+
+fn recognize_at<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: &str,
+    blk: u64,
+) -> Result<Option<MinimalProxy>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut state = p
+        .bc_state_at(TxPosition::new(blk, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    minimal_proxy_of(&mut state, proxy.cvt()).map_err(DetectorError::state_unavailable)
+}
+
+/// Mark the representative clone of each implementation, and return the number of clones and implementations.
+async fn assign_representatives(db: &DatabaseConnection) -> Result<(usize, usize), DbErr> {
+    let clones: Vec<(String, String, i32)> = entities::clone::Entity::find()
+        .select_only()
+        .column(entities::clone::Column::Proxy)
+        .column(entities::clone::Column::Implementation)
+        .column(entities::proxy::Column::InvocationCount)
+        .inner_join(entities::proxy::Entity)
+        .into_tuple()
+        .all(db)
+        .await?;
+    let chosen = representatives(&clones).into_iter().collect::<Vec<_>>();
+
+    let txn = db.begin().await?;
+    entities::clone::Entity::update_many()
+        .col_expr(entities::clone::Column::Representative, Expr::value(false))
+        .filter(entities::clone::Column::Representative.eq(true))
+        .exec(&txn)
+        .await?;
+    for chunk in chosen.chunks(1000) {
+        entities::clone::Entity::update_many()
+            .col_expr(entities::clone::Column::Representative, Expr::value(true))
+            .filter(entities::clone::Column::Proxy.is_in(chunk.to_vec()))
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+    Ok((clones.len(), chosen.len()))
+}
@@ -0,0 +1,490 @@
+use std::{collections::HashSet, sync::Arc, thread, time::Instant};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        transition::TransitionSpecBuilder,
+        types::{Address, DatabaseRef, TxHash},
+    },
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    config::ProxyExDetectorConfig,
+    dataset::{ImplInvocation, ProxyData},
+    diamond::{facet_versions, loupe_facets, DiamondAccesses, FacetCollision, FacetVersion},
+    entities,
+    error::DetectorError,
+    inspectors::collision::StorageAccessInspector,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    run::RunTracker,
+    selector::selector_to_string,
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    sea_query::{Expr, OnConflict, Query},
+    ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, TransactionTrait,
+};
+
+/// Find the diamonds (EIP-2535) among the proxies, track the versions of their facets along the `DiamondCut`s,
+/// and check the storage collisions between the diamond and each facet, and between every two facets.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`) instead of the database
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database,
+    /// one record with the rows of the `facet` and `facet_collision` tables per diamond
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+}
+
+/// The facet versions and collisions of a diamond, None if the proxy is not a diamond.
+type DiamondResult = Option<(Vec<FacetVersion>, Vec<FacetCollision>)>;
+
+/// The rows of a diamond written to the output file.
+#[derive(Debug, serde::Serialize)]
+struct DiamondRecord {
+    proxy: String,
+    facets: Vec<entities::facet::Model>,
+    collisions: Vec<entities::facet_collision::Model>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| check_file(args, proxies, files, p));
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("diamond");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) =
+        channel::bounded::<(String, Result<DiamondResult, DetectorError>)>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        for (proxy, r) in result_rx {
+            let (versions, collisions) = match r {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+                    continue;
+                }
+                Err(e) => {
+                    rt.block_on(cloned_tracker.fail(&db, proxy, format!("{:?}", e)))
+                        .unwrap();
+                    continue;
+                }
+            };
+            info!(
+                proxy = proxy.as_str(),
+                versions = versions.len(),
+                collisions = collisions.len(),
+                "Diamond checked"
+            );
+            rt.block_on(save_diamond(&db, &proxy, versions, collisions))
+                .unwrap();
+            rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+    let budget = BudgetConfig::load_or(Default::default()).expect("load budget config failed");
+
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let shutdown = shutdown.clone();
+        let budget = budget.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            for proxy in proxy_rx {
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                let task = async {
+                    entities::invocation::Entity::find()
+                        .filter(entities::invocation::Column::Proxy.eq(proxy.address.clone()))
+                        .order_by_asc(entities::invocation::Column::Block)
+                        .order_by_asc(entities::invocation::Column::Id)
+                        .all(&db)
+                        .await
+                };
+                let invocations = rt
+                    .block_on(task)
+                    .unwrap()
+                    .into_iter()
+                    .map(|i| ImplInvocation {
+                        tx: i.tx,
+                        implementation: i.implementation,
+                        block: i.block as u64,
+                    })
+                    .collect::<Vec<_>>();
+                let r = check_diamond(p.clone(), proxy.address.cvt(), &invocations, &budget);
+                if let Err(e) = &r {
+                    error!(
+                        proxy = proxy.address.as_str(),
+                        err = format!("{:?}", e),
+                        "Failed to check diamond"
+                    );
+                }
+                result_tx.send((proxy.address, r)).unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies =
+            entities::proxy::Entity::find()
+                .filter(
+                    Condition::all()
+                        .add(Expr::exists(
+                            Query::select()
+                                .from(entities::invocation::Entity)
+                                .and_where(
+                                    Expr::col((
+                                        entities::invocation::Entity,
+                                        entities::invocation::Column::Proxy,
+                                    ))
+                                    .equals((
+                                        entities::proxy::Entity,
+                                        entities::proxy::Column::Address,
+                                    )),
+                                )
+                                .take(),
+                        ))
+                        .add(tracker.unfinished((
+                            entities::proxy::Entity,
+                            entities::proxy::Column::Address,
+                        ))),
+                )
+                .limit(1000)
+                .all(&db)
+                .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// Check the proxies in a ProxyData file, writing the facet versions and collisions of each diamond to the files.
+fn check_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    let budget = BudgetConfig::load_or(Default::default()).expect("load budget config failed");
+    check_into_files(proxies, files, args.jobs, &shutdown, |data| {
+        let proxy: Address = data.proxy.cvt();
+        let proxy_addr = data.proxy.to_lowercase();
+        let r = match check_diamond(p.clone(), proxy, &data.ordered_impls(), &budget) {
+            Ok(Some((versions, collisions))) => {
+                info!(
+                    proxy = proxy_addr.as_str(),
+                    versions = versions.len(),
+                    collisions = collisions.len(),
+                    "Diamond checked"
+                );
+                Ok(DiamondRecord {
+                    facets: facet_rows(&proxy_addr, versions),
+                    collisions: collision_rows(&proxy_addr, collisions),
+                    proxy: proxy_addr,
+                })
+            }
+            Ok(None) => return Vec::new(),
+            Err(e) => {
+                error!(
+                    proxy = proxy_addr.as_str(),
+                    err = format!("{:?}", e),
+                    "Failed to check diamond"
+                );
+                Err(ErrorRecord::new(proxy, None, e))
+            }
+        };
+        vec![r]
+    });
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// Check whether the proxy is a diamond after its last invocation, and if so, replay all its invocations
+/// with every facet known, collecting the `DiamondCut`s and the storage accesses of the diamond and the facets.
+fn check_diamond<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: Address,
+    invocations: &[ImplInvocation],
+    budget: &BudgetConfig,
+) -> Result<DiamondResult, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let (first_block, last_block) = match (invocations.first(), invocations.last()) {
+        (Some(first), Some(last)) => (first.block, last.block),
+        _ => return Ok(None),
+    };
+    let loupe_at = |blk: u64| {
+        let mut state = p
+            .bc_state_at(TxPosition::new(blk, 0u64))
+            .map_err(DetectorError::state_unavailable)?;
+        let caller = HighLevelCaller::default()
+            .bypass_check()
+            .at_block(p.clone(), blk);
+        Ok::<_, DetectorError>(loupe_facets(&caller, &mut state, proxy))
+    };
+    let latest = match loupe_at(last_block + 1)? {
+        Some(facets) if !facets.is_empty() => facets,
+        _ => return Ok(None),
+    };
+    // facets added in the creation tx are not cut in any invocation
+    let initial = loupe_at(first_block)?.unwrap_or_default();
+    let mut facets = initial
+        .iter()
+        .chain(latest.iter())
+        .map(|(f, _)| *f)
+        .collect::<HashSet<_>>();
+    facets.extend(
+        invocations
+            .iter()
+            .map(|i| -> Address { i.implementation.cvt() }),
+    );
+
+    let deadline = budget.proxy_timeout().map(|t| Instant::now() + t);
+    let mut accesses = DiamondAccesses::default();
+    let mut cuts = Vec::new();
+    let mut replayed = HashSet::new();
+    for (index, inv) in invocations.iter().enumerate() {
+        let tx: TxHash = inv.tx.cvt();
+        if !replayed.insert(tx) {
+            continue;
+        }
+        let insp = StorageAccessInspector::new(
+            proxy,
+            inv.implementation.cvt(),
+            index,
+            invocations.len(),
+            false,
+        )
+        .with_facets(facets.clone());
+        let insp = replay_tx(p.clone(), tx, insp, budget.tx_budget().until(deadline)?)?;
+        accesses.extend(&insp);
+        if !insp.diamond_cuts.is_empty() {
+            cuts.push((inv.block, tx, insp.diamond_cuts));
+        }
+    }
+    Ok(Some((
+        facet_versions(&initial, first_block, &cuts),
+        accesses.collisions(),
+    )))
+}
+
+fn replay_tx<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    tx: TxHash,
+    insp: StorageAccessInspector,
+    budget: Budget,
+) -> Result<StorageAccessInspector, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut insp = insp.with_meter(BudgetMeter::new(budget));
+    let tx = p.tx(tx.cvt()).map_err(DetectorError::tx_not_found)?;
+    let tx_pos = tx
+        .position()
+        .ok_or_else(|| DetectorError::TxNotFound(format!("position of tx {}", tx.hash())))?;
+    let mut state = p
+        .bc_state_at(tx_pos)
+        .map_err(DetectorError::state_unavailable)?;
+    let spec = TransitionSpecBuilder::new()
+        .at_block(&p, tx_pos.block)
+        .append_tx(tx)
+        .build();
+    state
+        .transit(spec, &mut insp)
+        .map_err(DetectorError::evm_halt)?;
+    insp.meter.check()?;
+    Ok(insp)
+}
+
+/// The rows of the `facet` table.
+fn facet_rows(proxy: &str, versions: Vec<FacetVersion>) -> Vec<entities::facet::Model> {
+    versions
+        .into_iter()
+        .map(|v| entities::facet::Model {
+            proxy: proxy.to_string(),
+            facet: v.facet.to_string().to_lowercase(),
+            min_block: v.min_block as i64,
+            max_block: v.max_block.map(|b| b as i64),
+            selectors: serde_json::to_value(
+                v.selectors
+                    .iter()
+                    .map(selector_to_string)
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap(),
+            tx: v.tx.map(|tx| tx.to_string().to_lowercase()),
+        })
+        .collect()
+}
+
+/// The rows of the `facet_collision` table.
+fn collision_rows(
+    proxy: &str,
+    collisions: Vec<FacetCollision>,
+) -> Vec<entities::facet_collision::Model> {
+    collisions
+        .into_iter()
+        .map(|c| entities::facet_collision::Model {
+            proxy: proxy.to_string(),
+            first: c.first.to_string().to_lowercase(),
+            second: c.second.to_string().to_lowercase(),
+            slot_labels: serde_json::to_value(c.slot_labels).unwrap(),
+        })
+        .collect()
+}
+
+/// Replace the facet versions and collisions of the diamond.
+async fn save_diamond(
+    db: &DatabaseConnection,
+    proxy: &str,
+    versions: Vec<FacetVersion>,
+    collisions: Vec<FacetCollision>,
+) -> Result<(), DbErr> {
+    let versions = facet_rows(proxy, versions)
+        .into_iter()
+        .map(entities::facet::ActiveModel::from)
+        .collect::<Vec<_>>();
+    let collisions = collision_rows(proxy, collisions)
+        .into_iter()
+        .map(entities::facet_collision::ActiveModel::from)
+        .collect::<Vec<_>>();
+    // a diamond may be checked again after more invocations are imported
+    let txn = db.begin().await?;
+    entities::facet::Entity::delete_many()
+        .filter(entities::facet::Column::Proxy.eq(proxy))
+        .exec(&txn)
+        .await?;
+    entities::facet_collision::Entity::delete_many()
+        .filter(entities::facet_collision::Column::Proxy.eq(proxy))
+        .exec(&txn)
+        .await?;
+    if !versions.is_empty() {
+        // a facet changed twice in a block keeps its first version of the block
+        let r = entities::facet::Entity::insert_many(versions)
+            .on_conflict(
+                OnConflict::columns([
+                    entities::facet::Column::Proxy,
+                    entities::facet::Column::Facet,
+                    entities::facet::Column::MinBlock,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .exec(&txn)
+            .await;
+        match r {
+            Ok(_) | Err(DbErr::RecordNotInserted) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if !collisions.is_empty() {
+        entities::facet_collision::Entity::insert_many(collisions)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await
+}
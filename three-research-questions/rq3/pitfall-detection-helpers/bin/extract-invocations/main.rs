@@ -0,0 +1,296 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc, thread};
+
+use clap::{command, Parser};
+use crossbeam::channel;
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        transition::TransitionSpecBuilder,
+        types::{Address, BlockHashOrNumber, DatabaseRef},
+    },
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, debug, error, info},
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    config::ProxyExDetectorConfig,
+    dataset::{self, trace::ProxyDataCollector, ImplInvocation},
+    error::DetectorError,
+    inspectors::invocation::InvocationInspector,
+    io::open_sink,
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+};
+use rayon::{
+    iter::{IntoParallelIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+
+/// Derive the invocations of proxies (tx, implementation, block) by replaying all the txs in a block range,
+/// as an alternative to the BigQuery traces (see `ingest-traces`), e.g., for the blocks after the dataset cutoff.
+/// Every tx of every block is replayed, even with `--proxy`: whether a tx reaches a proxy is only known once it
+/// is executed, and the txs of a block are replayed one after another on the same state.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// The proxies watched, comma-separated
+    #[arg(long, value_delimiter = ',')]
+    proxy: Vec<String>,
+
+    /// A file of the proxies watched, one per line.
+    /// Without `--proxy` or `--proxies`, every contract forwarding its calldata with a delegatecall is taken as a proxy.
+    #[arg(long)]
+    proxies: Option<PathBuf>,
+
+    /// The first block replayed
+    #[arg(long)]
+    from_block: u64,
+
+    /// The last block replayed (inclusive)
+    #[arg(long)]
+    to_block: u64,
+
+    /// Write the ProxyData JSONL (or `.parquet`) file, e.g., `impl.json`
+    #[arg(short, long, required_unless_present = "db", conflicts_with = "db")]
+    output: Option<PathBuf>,
+
+    /// Save the invocations of each block in the `proxy` and `invocation` tables instead,
+    /// with the progress of each block in the `run` table
+    #[arg(long)]
+    db: bool,
+
+    /// Replay the blocks that failed in previous runs, only with `--db`
+    #[arg(long, requires = "db")]
+    retry_failed: bool,
+}
+
+fn main() {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    let mut proxies = args
+        .proxy
+        .iter()
+        .map(|p| p.to_lowercase())
+        .collect::<HashSet<_>>();
+    if let Some(path) = &args.proxies {
+        match dataset::trace::read_proxies(path) {
+            Ok(ps) => proxies.extend(ps),
+            Err(e) => {
+                error!(err = e.to_string().as_str(), "Failed to read proxies");
+                return;
+            }
+        }
+    }
+    let proxies = match proxies.is_empty() {
+        true => None,
+        false => Some(Arc::new(
+            proxies
+                .iter()
+                .map(|p| p.as_str().cvt())
+                .collect::<HashSet<Address>>(),
+        )),
+    };
+
+    proxyex_detector::with_provider!(|p| extract_all(args, proxies, p))
+}
+
+fn extract_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: Option<Arc<HashSet<Address>>>,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    info!(
+        from_block = args.from_block,
+        to_block = args.to_block,
+        "Extracting invocations"
+    );
+    let shutdown = Shutdown::install();
+    let tracker = RunTracker::new("extract-invocations");
+    // with `--db`, the blocks done or failed in previous runs are skipped
+    let (cfg, finished) = match args.db {
+        true => {
+            let rt = AsyncRuntime::new();
+            let cfg =
+                ProxyExDetectorConfig::load_or(Default::default()).expect("load config failed");
+            let db = rt
+                .block_on(cfg.db())
+                .expect("failed to connect to database");
+            rt.block_on(tracker.prepare(&db, args.retry_failed))
+                .expect("failed to prepare run");
+            let finished = rt
+                .block_on(tracker.finished(&db))
+                .expect("failed to load finished blocks");
+            (Some(cfg), finished)
+        }
+        false => (None, HashSet::new()),
+    };
+    let (result_tx, result_rx) =
+        channel::bounded::<(u64, Result<Vec<(String, ImplInvocation)>, DetectorError>)>(args.jobs);
+
+    // results are saved block by block, or merged and written when all blocks are replayed
+    let result_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let mut collector = ProxyDataCollector::default();
+        let mut failed = Vec::new();
+        let rt = AsyncRuntime::new();
+        let db = cfg.map(|cfg| {
+            rt.block_on(cfg.db())
+                .expect("failed to connect to database")
+        });
+        for (block, invocations) in result_rx {
+            let db = match &db {
+                Some(db) => db,
+                None => {
+                    match invocations {
+                        Ok(invocations) => collector.extend(invocations),
+                        Err(_) => failed.push(block),
+                    }
+                    continue;
+                }
+            };
+            let task = async {
+                result_tracker.start(db, [block.to_string()]).await?;
+                match invocations {
+                    Ok(invocations) => {
+                        dataset::save_block_invocations(db, block, invocations).await?;
+                        result_tracker.done(db, block.to_string()).await
+                    }
+                    Err(e) => {
+                        result_tracker
+                            .fail(db, block.to_string(), e.to_string())
+                            .await
+                    }
+                }
+            };
+            if let Err(e) = rt.block_on(task) {
+                error!(
+                    block = block,
+                    err = e.to_string().as_str(),
+                    "Failed to save invocations"
+                );
+            }
+        }
+        (collector, failed)
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    pool.install(|| {
+        (args.from_block..=args.to_block)
+            .into_par_iter()
+            .filter(|block| !finished.contains(&block.to_string()))
+            .for_each_with(result_tx, |result_tx, block| {
+                // abandon the blocks not started yet
+                if shutdown.requested() {
+                    return;
+                }
+                let r = extract_block(p.clone(), block, proxies.clone(), budget);
+                match &r {
+                    Ok(invocations) => debug!(
+                        block = block,
+                        invocations = invocations.len(),
+                        "Block replayed"
+                    ),
+                    Err(e) => error!(
+                        block = block,
+                        kind = e.kind(),
+                        err = e.to_string(),
+                        "Failed to replay block"
+                    ),
+                }
+                result_tx.send((block, r)).unwrap();
+            })
+    });
+
+    info!("Waiting for result thread to finish");
+    let (collector, failed) = result_thread.join().unwrap();
+    if shutdown.requested() {
+        info!("Interrupted, the blocks abandoned are not saved");
+    }
+    if let Some(output) = &args.output {
+        if !failed.is_empty() {
+            // a proxy invoked in a failed block would be written incompletely
+            error!(
+                failed = failed.len(),
+                first = ?failed.iter().min(),
+                "Some blocks failed, nothing is written"
+            );
+            return;
+        }
+        let data = collector.finish();
+        let r = open_sink(output).and_then(|mut sink| {
+            for d in &data {
+                sink.write_record(d)?;
+            }
+            sink.finish()
+        });
+        match r {
+            Ok(_) => info!(proxies = data.len(), "ProxyData written"),
+            Err(e) => error!(err = e.to_string().as_str(), "Failed to write proxy data"),
+        }
+    }
+}
+
+/// Replay the txs of the block one after another, and collect the invocations of the proxies.
+fn extract_block<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    block: u64,
+    proxies: Option<Arc<HashSet<Address>>>,
+    budget: Budget,
+) -> Result<Vec<(String, ImplInvocation)>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let txs = p
+        .txs_in_block(BlockHashOrNumber::Number(block))
+        .map_err(DetectorError::tx_not_found)?;
+    let mut state = p
+        .bc_state_at(TxPosition::new(block, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let mut invocations = Vec::new();
+    for tx in txs {
+        let tx_hash = tx.hash().to_string().to_lowercase();
+        let mut insp = InvocationInspector::new(proxies.clone(), BudgetMeter::new(budget));
+        let spec = TransitionSpecBuilder::new()
+            .at_block(&p, block)
+            .append_tx(tx)
+            .build();
+        state
+            .transit(spec, &mut insp)
+            .map_err(DetectorError::evm_halt)?;
+        insp.meter.check()?;
+        for (proxy, implementation) in insp.invocations {
+            invocations.push((
+                proxy.to_string().to_lowercase(),
+                ImplInvocation {
+                    tx: tx_hash.clone(),
+                    implementation: implementation.to_string().to_lowercase(),
+                    block,
+                },
+            ));
+        }
+    }
+    Ok(invocations)
+}
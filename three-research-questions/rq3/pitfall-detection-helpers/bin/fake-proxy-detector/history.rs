@@ -0,0 +1,459 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+    thread,
+};
+
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        transition::TransitionSpecBuilder,
+        types::{Address, BlockHashOrNumber, Bytes, Database, DatabaseRef, TxHash, U256},
+    },
+};
+use libsofl_utils::{
+    config::Config,
+    log::{error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    clone::not_redundant_clone,
+    config::ProxyExDetectorConfig,
+    convention::{ConventionConfig, ConventionRegistry},
+    dataset, entities,
+    error::DetectorError,
+    inspectors::storage_write::StorageWriteInspector,
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+    slot::StandardSlot,
+    timeline::{changes, timeline, Checkpoint},
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, TransactionTrait,
+};
+
+use crate::{check_actual_impl, Cli};
+
+/// Check the declared implementation against the delegated one at every block where either changes,
+/// and save the timeline of each proxy in the `fake_timeline` table.
+pub async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("fake-proxy-history");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) =
+        channel::bounded::<(String, Result<Vec<Checkpoint>, DetectorError>)>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        for (proxy, r) in result_rx {
+            match r {
+                Ok(checkpoints) => {
+                    rt.block_on(save_timeline(&db, &proxy, checkpoints))
+                        .unwrap();
+                    rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+                }
+                Err(e) => {
+                    let error = e.to_model(&cloned_tracker.detector, proxy.cvt(), None);
+                    rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                        .unwrap();
+                    rt.block_on(cloned_tracker.fail(&db, proxy, e.to_string()))
+                        .unwrap();
+                }
+            }
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let db = cfg.db().await.unwrap();
+    // without `--to-block`, the history of every proxy goes to the end of the dataset
+    let to_block = match args.to_block {
+        Some(b) => Some(b),
+        None => dataset::last_invocation_block(&db).await?,
+    };
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    // the custom slots and the probe selectors are checked once loaded
+    let conventions = ConventionConfig::load_checked().expect("load conventions config failed");
+    let registry = conventions.registry().unwrap();
+    let probes = conventions.probe_inputs().unwrap();
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let shutdown = shutdown.clone();
+        let registry = registry.clone();
+        let probes = probes.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            for proxy in proxy_rx {
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                info!(proxy = proxy.address.as_str(), "Checking proxy history");
+                let r = rt
+                    .block_on(delegations(&db, &proxy.address))
+                    .map_err(DetectorError::from)
+                    .and_then(|delegations| {
+                        check_history(
+                            p.clone(),
+                            &registry,
+                            &probes,
+                            proxy.address.cvt(),
+                            delegations,
+                            to_block,
+                            budget,
+                        )
+                    });
+                if let Err(e) = &r {
+                    error!(
+                        proxy = proxy.address.as_str(),
+                        err = e.to_string(),
+                        "Failed to check proxy history"
+                    );
+                }
+                result_tx.send((proxy.address, r)).unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let mut cond = Condition::all()
+            .add(tracker.unfinished((entities::proxy::Entity, entities::proxy::Column::Address)));
+        if args.representatives {
+            cond = cond.add(not_redundant_clone((
+                entities::proxy::Entity,
+                entities::proxy::Column::Address,
+            )));
+        }
+        let proxies = entities::proxy::Entity::find()
+            .filter(cond)
+            .limit(1000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// The creation of a proxy and the implementations it delegates to, read from the `creation` and `invocation` tables.
+struct Delegations {
+    /// None if unknown
+    creation_block: Option<u64>,
+    /// one per block and implementation, ordered by block
+    blocks: Vec<(u64, Address)>,
+    /// (block, tx, implementation) of the invocations in the blocks where several implementations are invoked
+    tied: Vec<(u64, TxHash, Address)>,
+}
+
+impl Delegations {
+    /// The implementation delegated to at the end of each block the proxy is invoked in,
+    /// i.e., the one of its last invocation in the block, ordered by the position of the txs.
+    fn at_block_ends<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+        self,
+        p: &P,
+    ) -> Result<Vec<(u64, Address)>, DetectorError>
+    where
+        <DB as DatabaseRef>::Error: std::fmt::Debug,
+    {
+        let mut last_in_block: BTreeMap<u64, (u64, Address)> = BTreeMap::new();
+        for (block, tx, implementation) in self.tied {
+            let index = p
+                .tx(tx.cvt())
+                .map_err(DetectorError::tx_not_found)?
+                .position()
+                .ok_or_else(|| DetectorError::TxNotFound(format!("position of tx {}", tx)))?
+                .index;
+            let last = last_in_block
+                .entry(block)
+                .or_insert((index, implementation));
+            if index > last.0 {
+                *last = (index, implementation);
+            }
+        }
+        let mut delegations = self.blocks;
+        delegations.retain(|(block, _)| !last_in_block.contains_key(block));
+        delegations.extend(
+            last_in_block
+                .into_iter()
+                .map(|(block, (_, implementation))| (block, implementation)),
+        );
+        delegations.sort_by_key(|(block, _)| *block);
+        Ok(delegations)
+    }
+}
+
+/// The creation and the delegations of the proxy.
+/// Only one row per block and implementation is read, and the invocations themselves only for the blocks where
+/// several implementations are invoked, since the invocations of a block are not ordered in the `invocation` table.
+async fn delegations(db: &DatabaseConnection, proxy: &str) -> Result<Delegations, DbErr> {
+    let creation = entities::creation::Entity::find()
+        .filter(entities::creation::Column::Proxy.eq(proxy))
+        .one(db)
+        .await?;
+    let blocks: Vec<(i64, String)> = entities::invocation::Entity::find()
+        .select_only()
+        .column(entities::invocation::Column::Block)
+        .column(entities::invocation::Column::Implementation)
+        .filter(entities::invocation::Column::Proxy.eq(proxy))
+        .group_by(entities::invocation::Column::Block)
+        .group_by(entities::invocation::Column::Implementation)
+        .order_by_asc(entities::invocation::Column::Block)
+        .into_tuple()
+        .all(db)
+        .await?;
+    let mut tied_blocks = blocks
+        .windows(2)
+        .filter(|w| w[0].0 == w[1].0)
+        .map(|w| w[0].0)
+        .collect::<Vec<_>>();
+    tied_blocks.dedup();
+    let tied: Vec<(i64, String, String)> = match tied_blocks.is_empty() {
+        true => vec![],
+        false => {
+            entities::invocation::Entity::find()
+                .select_only()
+                .column(entities::invocation::Column::Block)
+                .column(entities::invocation::Column::Tx)
+                .column(entities::invocation::Column::Implementation)
+                .filter(entities::invocation::Column::Proxy.eq(proxy))
+                .filter(entities::invocation::Column::Block.is_in(tied_blocks))
+                .into_tuple()
+                .all(db)
+                .await?
+        }
+    };
+    Ok(Delegations {
+        creation_block: creation.map(|c| c.creation_block as u64),
+        blocks: blocks
+            .into_iter()
+            .map(|(block, implementation)| (block as u64, implementation.cvt()))
+            .collect(),
+        tied: tied
+            .into_iter()
+            .map(|(block, tx, implementation)| (block as u64, tx.cvt(), implementation.cvt()))
+            .collect(),
+    })
+}
+
+/// The timeline of a proxy from its creation (or first invocation) to `to_block`.
+/// The declared implementation is read again after each tx writing the storage of the proxy or of its beacon,
+/// the delegated one is taken from the invocations, or probed at each change of a proxy never invoked.
+fn check_history<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    registry: &ConventionRegistry,
+    probes: &[Bytes],
+    proxy: Address,
+    delegations: Delegations,
+    to_block: Option<u64>,
+    budget: Budget,
+) -> Result<Vec<Checkpoint>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let first_invocation = delegations.blocks.first().map(|(b, _)| *b);
+    let from = match delegations.creation_block.or(first_invocation) {
+        Some(b) => b,
+        None => return Ok(vec![]),
+    };
+    let to = to_block.unwrap_or(from).max(from);
+
+    let slot_changes = changes(declared_implementations(
+        p.clone(),
+        registry,
+        proxy,
+        from,
+        to,
+        budget,
+    )?);
+    let delegation_changes = changes(
+        delegations
+            .at_block_ends(p.as_ref())?
+            .into_iter()
+            .filter(|(b, _)| *b <= to),
+    );
+    let mut checkpoints = timeline(&slot_changes, &delegation_changes);
+    if delegation_changes.is_empty() {
+        for checkpoint in checkpoints.iter_mut() {
+            checkpoint.actual_implementation = check_actual_impl(
+                p.clone(),
+                probes,
+                proxy,
+                checkpoint.block as i64 + 1,
+                budget,
+            )?;
+        }
+    }
+    Ok(checkpoints)
+}
+
+/// Replay the txs of every block in `[from, to]`, and find the declared implementation (zero if no convention matches)
+/// with its convention at the end of `from`, and after each tx writing the storage of the proxy or of its beacon.
+fn declared_implementations<
+    T: Tx + 'static,
+    DB: DatabaseRef + 'static,
+    P: DetectorProvider<T, DB>,
+>(
+    p: Arc<P>,
+    registry: &ConventionRegistry,
+    proxy: Address,
+    from: u64,
+    to: u64,
+    budget: Budget,
+) -> Result<Vec<(u64, (Address, Option<String>))>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut declared = Vec::new();
+    let mut watched = HashSet::from([proxy]);
+    for block in from..=to {
+        let txs = p
+            .txs_in_block(BlockHashOrNumber::Number(block))
+            .map_err(DetectorError::tx_not_found)?;
+        let mut state = p
+            .bc_state_at(TxPosition::new(block, 0u64))
+            .map_err(DetectorError::state_unavailable)?;
+        let caller = HighLevelCaller::default()
+            .bypass_check()
+            .at_block(p.clone(), block);
+        for tx in txs {
+            let mut insp = StorageWriteInspector::new(watched.clone(), BudgetMeter::new(budget));
+            let spec = TransitionSpecBuilder::new()
+                .at_block(&p, block)
+                .append_tx(tx)
+                .build();
+            state
+                .transit(spec, &mut insp)
+                .map_err(DetectorError::evm_halt)?;
+            insp.meter.check()?;
+            if insp.written {
+                let (implementation, contracts) =
+                    resolve_declared(registry, &caller, &mut state, proxy, budget)?;
+                declared.push((block, implementation));
+                watched = contracts;
+            }
+        }
+        // the value at the end of the first block is always read, e.g., a minimal proxy holds its implementation
+        // in its code, which is written without any SSTORE
+        if block == from {
+            let (implementation, contracts) =
+                resolve_declared(registry, &caller, &mut state, proxy, budget)?;
+            declared.push((block, implementation));
+            watched = contracts;
+        }
+    }
+    Ok(declared)
+}
+
+/// The declared implementation (zero if no convention matches) with its convention,
+/// and the contracts whose storage it depends on: the proxy, and its beacon if any.
+fn resolve_declared<S: BcState>(
+    registry: &ConventionRegistry,
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    budget: Budget,
+) -> Result<((Address, Option<String>), HashSet<Address>), DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let declared = match registry.resolve(caller, state, proxy, budget)? {
+        Some(m) => (m.implementation, Some(m.convention)),
+        None => (Address::ZERO, None),
+    };
+    let mut contracts = HashSet::from([proxy]);
+    let beacon = state
+        .storage(proxy, StandardSlot::Eip1967Beacon.slot())
+        .map_err(DetectorError::state_unavailable)?;
+    // the upper bytes of an address are zero
+    if beacon != U256::ZERO && beacon >> 160 == U256::ZERO {
+        contracts.insert(beacon.cvt());
+    }
+    Ok((declared, contracts))
+}
+
+async fn save_timeline(
+    db: &DatabaseConnection,
+    proxy: &str,
+    checkpoints: Vec<Checkpoint>,
+) -> Result<(), DbErr> {
+    let checkpoints = checkpoints
+        .into_iter()
+        .map(|c| entities::fake_timeline::ActiveModel {
+            proxy: ActiveValue::Set(proxy.to_string()),
+            block: ActiveValue::Set(c.block as i64),
+            slot_implementation: ActiveValue::Set(c.slot_implementation.to_string().to_lowercase()),
+            convention: ActiveValue::Set(c.convention.clone()),
+            actual_implementation: ActiveValue::Set(
+                c.actual_implementation
+                    .map(|a| a.to_string().to_lowercase()),
+            ),
+            mismatched: ActiveValue::Set(c.mismatched()),
+            slot_changed: ActiveValue::Set(c.slot_changed),
+            delegation_changed: ActiveValue::Set(c.delegation_changed),
+        })
+        .collect::<Vec<_>>();
+    // a proxy may be checked again after more invocations are imported
+    let txn = db.begin().await?;
+    entities::fake_timeline::Entity::delete_many()
+        .filter(entities::fake_timeline::Column::Proxy.eq(proxy))
+        .exec(&txn)
+        .await?;
+    if !checkpoints.is_empty() {
+        entities::fake_timeline::Entity::insert_many(checkpoints)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await
+}
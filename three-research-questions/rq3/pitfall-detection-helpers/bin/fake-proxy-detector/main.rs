@@ -1,28 +1,38 @@
-mod inspector;
+mod history;
 
 use std::{sync::Arc, thread, time::Duration};
 
 use clap::{command, Parser};
 use crossbeam::{channel, sync::WaitGroup};
-use inspector::ImplInspector;
 use libsofl_core::{
-    blockchain::{provider::BcStateProvider, tx_position::TxPosition},
+    blockchain::{transaction::Tx, tx_position::TxPosition},
     conversion::ConvertTo,
-    engine::types::{Address, Bytes, Database, U256},
-    error::SoflError,
+    engine::types::{Address, Bytes, DatabaseRef},
 };
-use libsofl_reth::{blockchain::provider::RethProvider, config::RethConfig};
 use libsofl_utils::{
     config::Config,
-    log::{config::LogConfig, info},
+    log::{config::LogConfig, error, info},
     solidity::caller::HighLevelCaller,
     sync::runtime::AsyncRuntime,
 };
-use proxyex_detector::{config::ProxyExDetectorConfig, entities};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    clone::not_redundant_clone,
+    config::ProxyExDetectorConfig,
+    convention::{ConventionConfig, ConventionRegistry},
+    dataset::ProxyData,
+    entities,
+    error::DetectorError,
+    inspectors::delegation::ImplInspector,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+};
 use rayon::ThreadPoolBuilder;
 use sea_orm::{
-    sea_query::{Expr, OnConflict, Query},
-    ColumnTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter,
+    sea_query::OnConflict, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QuerySelect,
 };
 
 #[derive(Parser, Debug)]
@@ -33,49 +43,99 @@ pub struct Cli {
 
     #[arg(short, long, default_value = "1")]
     jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check only one representative clone per implementation (see the `clone` binary)
+    #[arg(long, conflicts_with = "input")]
+    representatives: bool,
+
+    /// Check at every block where the declared or the delegated implementation changes,
+    /// saving a timeline per proxy in the `fake_timeline` table
+    #[arg(long, conflicts_with = "input")]
+    history: bool,
+
+    /// The last block of the history, the end of the dataset (the last invocation of any proxy) by default.
+    /// Every block of the history is replayed
+    #[arg(long, requires = "history")]
+    to_block: Option<u64>,
+
+    /// Check the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`) instead of the database
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), DbErr> {
     let args = Cli::parse();
-    let cfg = ProxyExDetectorConfig::must_load();
     let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
     log_cfg.console_level = args.log_level.clone();
     log_cfg.init();
 
-    let p = RethConfig::must_load().bc_provider().unwrap();
-    let p = Arc::new(p);
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| check_file(args, proxies, files, p));
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
 
-    check_all(args, cfg, p).await
+    proxyex_detector::with_provider!(|p| if args.history {
+        history::check_all(args, cfg, p).await
+    } else {
+        check_all(args, cfg, p).await
+    })
 }
 
-async fn check_all(
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
     args: Cli,
     cfg: ProxyExDetectorConfig,
-    p: Arc<RethProvider>,
-) -> Result<(), DbErr> {
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("fake-proxy-detector");
+    let shutdown = Shutdown::install();
     let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
-    let (result_tx, result_rx) =
-        channel::bounded::<(Address, Vec<(Address, Address, i64)>, Duration)>(args.jobs);
+    let (result_tx, result_rx) = channel::bounded::<(
+        Address,
+        Vec<(Address, Address, i64)>,
+        Option<String>,
+        Duration,
+    )>(args.jobs);
 
     let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
     let result_thread = thread::spawn(move || {
         let rt = AsyncRuntime::new();
         let db = rt.block_on(cloned_cfg.db()).unwrap();
         loop {
-            let (proxy, mismatched_impls, time) = match result_rx.recv() {
+            let (proxy, mismatched_impls, convention, time) = match result_rx.recv() {
                 Ok(v) => v,
                 Err(_) => break,
             };
             let task = async {
-                let fake = entities::fake_loose::ActiveModel {
-                    proxy: sea_orm::ActiveValue::Set(proxy.to_string().to_lowercase()),
-                    problematic: sea_orm::ActiveValue::Set(mismatched_impls.len() > 0),
-                    mismatched_impls: sea_orm::ActiveValue::Set(
-                        serde_json::to_value(mismatched_impls).unwrap(),
-                    ),
-                    total_time: sea_orm::ActiveValue::Set(time.as_nanos() as i64),
-                };
+                let fake: entities::fake_loose::ActiveModel =
+                    fake_row(proxy, mismatched_impls, convention, time).into();
                 entities::fake_loose::Entity::insert(fake)
                     .on_conflict(
                         OnConflict::column(entities::fake_loose::Column::Proxy)
@@ -93,6 +153,8 @@ async fn check_all(
                     }
                 }
             };
+            rt.block_on(cloned_tracker.done(&db, proxy.to_string().to_lowercase()))
+                .unwrap();
         }
     });
 
@@ -101,6 +163,13 @@ async fn check_all(
         .build()
         .unwrap();
 
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    // the custom slots and the probe selectors are checked once loaded
+    let conventions = ConventionConfig::load_checked().expect("load conventions config failed");
+    let registry = conventions.registry().unwrap();
+    let probes = conventions.probe_inputs().unwrap();
     let wg = WaitGroup::new();
     for _ in 0..args.jobs {
         let proxy_rx = proxy_rx.clone();
@@ -108,6 +177,10 @@ async fn check_all(
         let p = p.clone();
         let wg = wg.clone();
         let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let shutdown = shutdown.clone();
+        let registry = registry.clone();
+        let probes = probes.clone();
         pool.spawn(move || {
             let rt = AsyncRuntime::new();
             let db = rt.block_on(cfg.db()).unwrap();
@@ -116,11 +189,17 @@ async fn check_all(
                     Ok(v) => v,
                     Err(_) => break,
                 };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
                 info!(
                     proxy = proxy.address.to_string().to_lowercase(),
                     "Checking proxy"
                 );
                 let mut mismatched_impls = Vec::new();
+                // the convention of the first implementation found
+                let mut convention = None;
                 let time_elapsed;
                 if proxy.invocation_count > 0 {
                     let task = async {
@@ -134,13 +213,23 @@ async fn check_all(
                     };
                     let versions = rt.block_on(task).unwrap();
                     let start_at = std::time::Instant::now();
-                    for version in versions {
-                        let impl_ =
-                            check_impl_slot(p.clone(), proxy.address.cvt(), version.min_block + 1)
-                                .unwrap();
-                        let version_impl: Address = version.implementation.cvt();
-                        if impl_ != version_impl {
-                            mismatched_impls.push((impl_, version_impl, version.min_block + 1));
+                    let r = check_versions(
+                        p.clone(),
+                        &registry,
+                        proxy.address.cvt(),
+                        versions
+                            .into_iter()
+                            .map(|v| -> (Address, i64) { (v.implementation.cvt(), v.min_block) }),
+                        budget,
+                    );
+                    match r {
+                        Ok((mismatched, matched)) => {
+                            mismatched_impls = mismatched;
+                            convention = matched;
+                        }
+                        Err(e) => {
+                            fail_proxy(&rt, &db, &tracker, &proxy, e);
+                            continue;
                         }
                     }
                     time_elapsed = start_at.elapsed();
@@ -156,18 +245,31 @@ async fn check_all(
                     };
                     let creation = rt.block_on(task).unwrap().unwrap();
                     let start_at = std::time::Instant::now();
-                    let impl_ = check_impl_slot(
-                        p.clone(),
-                        proxy.address.cvt(),
-                        creation.creation_block + 1,
-                    )
-                    .unwrap();
-                    let actual_impl = check_actual_impl(
+                    let r = check_impl_slot(
                         p.clone(),
+                        &registry,
                         proxy.address.cvt(),
                         creation.creation_block + 1,
+                        budget,
                     )
-                    .unwrap();
+                    .and_then(|(impl_, matched)| {
+                        convention = matched;
+                        check_actual_impl(
+                            p.clone(),
+                            &probes,
+                            proxy.address.cvt(),
+                            creation.creation_block + 1,
+                            budget,
+                        )
+                        .map(|actual_impl| (impl_, actual_impl))
+                    });
+                    let (impl_, actual_impl) = match r {
+                        Ok(v) => v,
+                        Err(e) => {
+                            fail_proxy(&rt, &db, &tracker, &proxy, e);
+                            continue;
+                        }
+                    };
                     if let Some(actual_impl) = actual_impl {
                         if actual_impl != impl_ {
                             mismatched_impls.push((impl_, actual_impl, 0));
@@ -177,7 +279,12 @@ async fn check_all(
                 }
 
                 result_tx
-                    .send((proxy.address.cvt(), mismatched_impls, time_elapsed))
+                    .send((
+                        proxy.address.cvt(),
+                        mismatched_impls,
+                        convention,
+                        time_elapsed,
+                    ))
                     .unwrap();
             }
             drop(wg);
@@ -185,22 +292,32 @@ async fn check_all(
     }
 
     let db = cfg.db().await.unwrap();
-    let mut proxies_paginator = entities::proxy::Entity::find()
-        .filter(
-            Expr::exists(
-                Query::select()
-                    .from(entities::fake_loose::Entity)
-                    .and_where(
-                        Expr::col((entities::fake_loose::Entity, entities::fake_loose::Column::Proxy))
-                            .equals((entities::proxy::Entity, entities::proxy::Column::Address)),
-                    )
-                    .take(),
-            )
-            .not(),
-        )
-        .paginate(&db, 1000);
-    while let Some(proxies) = proxies_paginator.fetch_and_next().await? {
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let mut cond = Condition::all()
+            .add(tracker.unfinished((entities::proxy::Entity, entities::proxy::Column::Address)));
+        if args.representatives {
+            cond = cond.add(not_redundant_clone((
+                entities::proxy::Entity,
+                entities::proxy::Column::Address,
+            )));
+        }
+        let proxies = entities::proxy::Entity::find()
+            .filter(cond)
+            .limit(1000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
         for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
             proxy_tx.send(proxy).unwrap();
         }
     }
@@ -212,46 +329,177 @@ async fn check_all(
     drop(result_tx);
     info!("Waiting for result thread to finish");
     result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
 
     Ok(())
 }
 
-fn check_actual_impl(
-    p: Arc<RethProvider>,
-    proxy: Address,
-    blk: i64,
-) -> Result<Option<Address>, SoflError> {
-    let mut state = p.bc_state_at(TxPosition::new(blk as u64, 0u64))?;
-    let mut insp = ImplInspector {
-        proxy,
-        implementation: None,
-    };
-    let inputs: Bytes = "0x8da5cb5b".cvt();
-    let _ = HighLevelCaller::default()
-        .bypass_check()
-        .at_block(p.clone(), blk as u64)
-        .call(&mut state, proxy, inputs, None, &mut insp);
-    Ok(insp.implementation)
+/// Check the proxies in a ProxyData file at the first block of each version,
+/// writing the rows of the `fake_loose` table to the files.
+fn check_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    // the custom slots and the probe selectors are checked once loaded
+    let conventions = ConventionConfig::load_checked().expect("load conventions config failed");
+    let registry = conventions.registry().unwrap();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    check_into_files(proxies, files, args.jobs, &shutdown, |data| {
+        let proxy: Address = data.proxy.cvt();
+        info!(proxy = data.proxy.as_str(), "Checking proxy");
+        let versions = data.versions();
+        // the creation of a proxy is not in the file, so a proxy without invocations is skipped
+        if versions.is_empty() {
+            error!(proxy = data.proxy.as_str(), "No version found");
+            return Vec::new();
+        }
+        let start_at = std::time::Instant::now();
+        let versions = versions
+            .into_iter()
+            .map(|(implementation, min_block)| -> (Address, i64) {
+                (implementation.cvt(), min_block as i64)
+            });
+        let r = check_versions(p.clone(), &registry, proxy, versions, budget)
+            .map(|(mismatched_impls, convention)| {
+                fake_row(proxy, mismatched_impls, convention, start_at.elapsed())
+            })
+            .map_err(|e| {
+                error!(
+                    proxy = data.proxy.as_str(),
+                    err = e.to_string(),
+                    "Failed to check proxy"
+                );
+                ErrorRecord::new(proxy, None, e)
+            });
+        vec![r]
+    });
+    info!(interrupted = shutdown.requested(), "Session finished");
 }
 
-fn check_impl_slot(p: Arc<RethProvider>, proxy: Address, blk: i64) -> Result<Address, SoflError> {
-    let mut state = p.bc_state_at(TxPosition::new(blk as u64, 0u64))?;
+/// Compare the implementation found by the conventions with each version, given as (implementation, min_block),
+/// at the end of the first block of the version.
+/// Returns the mismatches (declared, version, block) and the convention of the first implementation found.
+fn check_versions<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    registry: &ConventionRegistry,
+    proxy: Address,
+    versions: impl Iterator<Item = (Address, i64)>,
+    budget: Budget,
+) -> Result<(Vec<(Address, Address, i64)>, Option<String>), DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut mismatched_impls = Vec::new();
+    let mut convention = None;
+    for (version_impl, min_block) in versions {
+        let (impl_, matched) = check_impl_slot(p.clone(), registry, proxy, min_block + 1, budget)?;
+        convention = convention.take().or(matched);
+        if impl_ != version_impl {
+            mismatched_impls.push((impl_, version_impl, min_block + 1));
+        }
+    }
+    Ok((mismatched_impls, convention))
+}
 
-    // EIP-1882
-    let slot: U256 = "0xc5f16f0fcc639fa48a6947836d9850f504798523bf8c9a3a87d5876cf622bcf7".cvt();
-    let value = state.storage(proxy, slot).unwrap();
-    if value != U256::ZERO {
-        return Ok(value.cvt());
+/// The row of the `fake_loose` table.
+fn fake_row(
+    proxy: Address,
+    mismatched_impls: Vec<(Address, Address, i64)>,
+    convention: Option<String>,
+    time: Duration,
+) -> entities::fake_loose::Model {
+    entities::fake_loose::Model {
+        proxy: proxy.to_string().to_lowercase(),
+        problematic: mismatched_impls.len() > 0,
+        mismatched_impls: serde_json::to_value(mismatched_impls).unwrap(),
+        convention,
+        total_time: time.as_nanos() as i64,
     }
+}
+
+fn fail_proxy(
+    rt: &AsyncRuntime,
+    db: &DatabaseConnection,
+    tracker: &RunTracker,
+    proxy: &entities::proxy::Model,
+    e: DetectorError,
+) {
+    error!(
+        proxy = proxy.address.to_string().to_lowercase(),
+        err = e.to_string(),
+        "Failed to check proxy"
+    );
+    let error = e.to_model(&tracker.detector, proxy.address.cvt(), None);
+    rt.block_on(entities::error::Entity::insert(error).exec(db))
+        .unwrap();
+    rt.block_on(tracker.fail(db, proxy.address.to_string().to_lowercase(), e.to_string()))
+        .unwrap();
+}
 
-    // EIP-1967
-    let slot: U256 = "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc".cvt();
-    let value = state.storage(proxy, slot).unwrap();
-    if value != U256::ZERO {
-        return Ok(value.cvt());
+/// Call the proxy with each probe until it delegates to an implementation at depth 1.
+fn check_actual_impl<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    probes: &[Bytes],
+    proxy: Address,
+    blk: i64,
+    budget: Budget,
+) -> Result<Option<Address>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk as u64);
+    for inputs in probes {
+        // each probe starts from the same state
+        let mut state = p
+            .bc_state_at(TxPosition::new(blk as u64, 0u64))
+            .map_err(DetectorError::state_unavailable)?;
+        let mut insp = ImplInspector {
+            proxy,
+            implementation: None,
+            beacon: None,
+            meter: BudgetMeter::new(budget),
+        };
+        let _ = caller.call(&mut state, proxy, inputs.clone(), None, &mut insp);
+        insp.meter.check()?;
+        if insp.implementation.is_some() {
+            return Ok(insp.implementation);
+        }
     }
+    Ok(None)
+}
 
-    Ok(Address::ZERO)
+/// The implementation found by the conventions of the registry (zero if none matches) and the matched convention.
+fn check_impl_slot<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    registry: &ConventionRegistry,
+    proxy: Address,
+    blk: i64,
+    budget: Budget,
+) -> Result<(Address, Option<String>), DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut state = p
+        .bc_state_at(TxPosition::new(blk as u64, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk as u64);
+    let matched = registry.resolve(&caller, &mut state, proxy, budget)?;
+    Ok(match matched {
+        Some(m) => (m.implementation, Some(m.convention)),
+        None => (Address::ZERO, None),
+    })
 }
 
 #[cfg(test)]
@@ -265,6 +513,7 @@ mod tests {
     };
     use libsofl_reth::config::RethConfig;
     use libsofl_utils::config::Config;
+    use proxyex_detector::{budget::Budget, convention::ConventionConfig};
 
     #[test]
     fn test_fake_proxy() {
@@ -279,8 +528,12 @@ mod tests {
             BlockHashOrNumber::Number(n) => n,
             _ => panic!(),
         };
-        let impl_ = super::check_impl_slot(p.clone(), proxy, blk as i64).unwrap();
+        let registry = ConventionConfig::default().registry().unwrap();
+        let (impl_, convention) =
+            super::check_impl_slot(p.clone(), &registry, proxy, blk as i64, Budget::default())
+                .unwrap();
         assert_eq!(impl_, slot_impl);
+        assert!(convention.is_some());
         assert_ne!(impl_, actual_impl);
     }
 
@@ -290,7 +543,14 @@ mod tests {
         let blk = 14936510i64;
         let p = RethConfig::must_load().bc_provider().unwrap();
         let p = Arc::new(p);
-        let impl_ = super::check_actual_impl(p.clone(), proxy, blk).unwrap();
+        let impl_ = super::check_actual_impl(
+            p.clone(),
+            &ConventionConfig::default().probe_inputs().unwrap(),
+            proxy,
+            blk,
+            Budget::default(),
+        )
+        .unwrap();
         assert_eq!(
             impl_.unwrap().to_string(),
             "0x425Dbc4951c72F5F0562C928537805ec053EC780"
@@ -303,7 +563,14 @@ mod tests {
         let blk = 18000000i64;
         let p = RethConfig::must_load().bc_provider().unwrap();
         let p = Arc::new(p);
-        let impl_ = super::check_actual_impl(p.clone(), proxy, blk).unwrap();
+        let impl_ = super::check_actual_impl(
+            p.clone(),
+            &ConventionConfig::default().probe_inputs().unwrap(),
+            proxy,
+            blk,
+            Budget::default(),
+        )
+        .unwrap();
         assert_eq!(
             impl_.unwrap().to_string(),
             "0x687924f76f8A6768da69db3775003f4De7F7357c"
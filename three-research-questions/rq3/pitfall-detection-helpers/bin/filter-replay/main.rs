@@ -3,32 +3,29 @@ use std::{collections::HashSet, sync::Arc};
 use clap::{arg, command, Parser};
 use crossbeam::{channel, sync::WaitGroup};
 use libsofl_core::{
-    blockchain::{
-        provider::{BcProvider, BcStateProvider},
-        transaction::Tx,
-    },
+    blockchain::transaction::Tx,
     conversion::ConvertTo,
     engine::{
         state::BcState,
         transition::TransitionSpecBuilder,
-        types::{Address, TxHash, U256},
+        types::{Address, DatabaseRef, TxHash, U256},
     },
 };
-use libsofl_reth::{blockchain::provider::RethProvider, config::RethConfig};
 use libsofl_utils::{
     config::Config,
-    log::{config::LogConfig, info},
+    log::{config::LogConfig, error, info},
     sync::runtime::AsyncRuntime,
 };
 use proxyex_detector::{
     config::ProxyExDetectorConfig, entities, inspectors::collision::StorageAccessInspector,
+    provider::DetectorProvider, run::RunTracker, shutdown::Shutdown,
 };
 use rayon::{
     iter::{IntoParallelRefIterator, ParallelIterator},
     ThreadPoolBuilder,
 };
 use sea_orm::{
-    sea_query::{Expr, IntoCondition, Query},
+    sea_query::{Expr, IntoCondition},
     ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter,
     QuerySelect, RelationTrait,
 };
@@ -41,6 +38,10 @@ pub struct Cli {
 
     #[arg(short, long, default_value = "1")]
     jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
 }
 
 #[tokio::main(flavor = "current_thread")]
@@ -49,27 +50,31 @@ async fn main() -> Result<(), DbErr> {
     let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
     log_cfg.console_level = args.log_level.clone();
     log_cfg.init();
-    let provider = RethConfig::must_load().bc_provider().unwrap();
-    let provider = Arc::new(provider);
     let cfg = ProxyExDetectorConfig::must_load();
 
-    analyze_all(args, cfg, provider).await
+    proxyex_detector::with_provider!(|provider| analyze_all(args, cfg, provider).await)
 }
 
-async fn analyze_all(
+async fn analyze_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
     args: Cli,
     cfg: ProxyExDetectorConfig,
-    provider: Arc<RethProvider>,
-) -> Result<(), DbErr> {
+    provider: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let pool = ThreadPoolBuilder::new()
         .num_threads(args.jobs + 1)
         .build()
         .unwrap();
 
+    let tracker = RunTracker::new("filter-replay");
+    let shutdown = Shutdown::install();
     let (proxy_tx, proxy_rx) = channel::bounded::<(Address, i32)>(args.jobs);
     let (info_tx, info_rx) = channel::bounded::<(Address, Info)>(args.jobs);
 
     let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
     let result_thread = std::thread::spawn(move || {
         let rt = AsyncRuntime::new();
         let db = rt.block_on(cloned_cfg.db()).unwrap();
@@ -79,14 +84,16 @@ async fn analyze_all(
                 Ok(t) => t,
                 Err(_) => {
                     if infos.len() > 0 {
-                        rt.block_on(save_infos(&db, &infos)).unwrap();
+                        rt.block_on(save_infos(&db, &cloned_tracker, &infos))
+                            .unwrap();
                     }
                     break;
                 }
             };
             infos.push((proxy, info));
             if infos.len() > 0 {
-                rt.block_on(save_infos(&db, &infos)).unwrap();
+                rt.block_on(save_infos(&db, &cloned_tracker, &infos))
+                    .unwrap();
                 infos.clear();
             }
         }
@@ -101,6 +108,8 @@ async fn analyze_all(
         let provider = provider.clone();
         let finished = finished.clone();
         let wg = wg.clone();
+        let tracker = tracker.clone();
+        let shutdown = shutdown.clone();
         pool.spawn(move || {
             let rt = AsyncRuntime::new();
             let db = rt.block_on(cfg.db()).unwrap();
@@ -109,9 +118,24 @@ async fn analyze_all(
                     Ok(t) => t,
                     Err(_) => break,
                 };
-                let info = rt
-                    .block_on(analyze_one(provider.clone(), &db, proxy, total))
-                    .unwrap();
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                let info = match rt.block_on(analyze_one(provider.clone(), &db, proxy, total)) {
+                    Ok(info) => info,
+                    Err(e) => {
+                        error!(
+                            proxy = proxy.to_string().to_lowercase(),
+                            err = ?e,
+                            "failed to analyze"
+                        );
+                        let task =
+                            tracker.fail(&db, proxy.to_string().to_lowercase(), format!("{:?}", e));
+                        rt.block_on(task).unwrap();
+                        continue;
+                    }
+                };
                 finished.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
                 info!(
                     proxy = proxy.to_string().to_lowercase(),
@@ -128,47 +152,47 @@ async fn analyze_all(
     }
 
     let db = cfg.db().await?;
-    let mut proxy_paginater = entities::replay::Entity::find()
-        .select_only()
-        .column(entities::replay::Column::Proxy)
-        .column(entities::proxy::Column::InvocationCount)
-        .join(
-            sea_orm::JoinType::InnerJoin,
-            entities::proxy::Relation::Replay
-                .def()
-                .rev()
-                .on_condition(|left, right| {
-                    Expr::col((left, entities::replay::Column::Proxy))
-                        .equals((right, entities::proxy::Column::Address))
-                        .into_condition()
-                }),
-        )
-        .filter(
-            Condition::all()
-                .add(entities::replay::Column::Problematic.eq(true))
-                .add(
-                    Expr::exists(
-                        Query::select()
-                            .from(entities::filtered_replay::Entity)
-                            .and_where(
-                                Expr::col((
-                                    entities::replay::Entity,
-                                    entities::replay::Column::Proxy,
-                                ))
-                                .equals((
-                                    entities::filtered_replay::Entity,
-                                    entities::filtered_replay::Column::Proxy,
-                                )),
-                            )
-                            .take(),
-                    )
-                    .not(),
-                ),
-        )
-        .into_tuple::<(String, i32)>()
-        .paginate(&db, 500);
-    while let Some(proxies) = proxy_paginater.fetch_and_next().await? {
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies =
+            entities::replay::Entity::find()
+                .select_only()
+                .column(entities::replay::Column::Proxy)
+                .column(entities::proxy::Column::InvocationCount)
+                .join(
+                    sea_orm::JoinType::InnerJoin,
+                    entities::proxy::Relation::Replay
+                        .def()
+                        .rev()
+                        .on_condition(|left, right| {
+                            Expr::col((left, entities::replay::Column::Proxy))
+                                .equals((right, entities::proxy::Column::Address))
+                                .into_condition()
+                        }),
+                )
+                .filter(
+                    Condition::all()
+                        .add(entities::replay::Column::Problematic.eq(true))
+                        .add(tracker.unfinished((
+                            entities::replay::Entity,
+                            entities::replay::Column::Proxy,
+                        ))),
+                )
+                .limit(500)
+                .into_tuple::<(String, i32)>()
+                .all(&db)
+                .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|(proxy, _)| proxy.clone()))
+            .await?;
         for (proxy, total) in proxies {
+            if shutdown.requested() {
+                break;
+            }
             let proxy: Address = proxy.cvt();
             proxy_tx.send((proxy, total)).unwrap();
         }
@@ -181,11 +205,16 @@ async fn analyze_all(
     drop(info_tx);
     info!("waiting for result thread to finish");
     result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
 
     Ok(())
 }
 
-async fn save_infos(db: &DatabaseConnection, infos: &Vec<(Address, Info)>) -> Result<(), DbErr> {
+async fn save_infos(
+    db: &DatabaseConnection,
+    tracker: &RunTracker,
+    infos: &Vec<(Address, Info)>,
+) -> Result<(), DbErr> {
     let mut entities = Vec::new();
     for (proxy, info) in infos {
         let entity = entities::filtered_replay::ActiveModel {
@@ -205,6 +234,9 @@ async fn save_infos(db: &DatabaseConnection, infos: &Vec<(Address, Info)>) -> Re
     entities::filtered_replay::Entity::insert_many(entities)
         .exec(db)
         .await?;
+    for (proxy, _) in infos {
+        tracker.done(db, proxy.to_string().to_lowercase()).await?;
+    }
     info!(count = infos.len(), "saved proxy infos");
     Ok(())
 }
@@ -215,12 +247,15 @@ struct Info {
     impl_sstores: Vec<(TxHash, Address, Vec<(U256, U256)>)>,
 }
 
-async fn analyze_one(
-    provider: Arc<RethProvider>,
+async fn analyze_one<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: Arc<P>,
     db: &DatabaseConnection,
     proxy: Address,
     total: i32,
-) -> Result<Info, DbErr> {
+) -> Result<Info, DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let replay = entities::replay::Entity::find()
         .filter(entities::replay::Column::Proxy.eq(proxy.to_string().to_lowercase()))
         .one(db)
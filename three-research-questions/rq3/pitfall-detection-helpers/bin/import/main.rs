@@ -1,13 +1,17 @@
 use std::io::{BufRead, BufReader};
 
-use clap::{command, Parser};
+use clap::{command, Parser, Subcommand};
 use indicatif::ProgressStyle;
 use libsofl_utils::{
     config::Config,
     log::{debug, error, info, info_span},
 };
-use proxyex_detector::{dataset::ProxyData, entities};
-use sea_orm::{sea_query, ActiveValue, DbErr, EntityTrait, TransactionTrait};
+use proxyex_detector::{
+    dataset::{self, ProxyData},
+    entities,
+    io::JsonlSource,
+};
+use sea_orm::{sea_query, ActiveValue, DatabaseConnection, DbErr, EntityTrait, TransactionTrait};
 use tracing_indicatif::{span_ext::IndicatifSpanExt, IndicatifLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
@@ -17,7 +21,16 @@ pub struct Cli {
     #[arg(short = 'l', long, default_value = "info")]
     level: String,
 
-    data: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Import the creation CSV data (`proxy,creation_tx:block[,first_invocation_tx:block]`)
+    Creation { data: String },
+    /// Import the ProxyData JSONL file (`impl.json` of logic-contract-prep) into the `invocation` table
+    Invocation { data: String },
 }
 
 #[tokio::main(flavor = "multi_thread")]
@@ -60,7 +73,15 @@ async fn main() {
         }
     };
 
-    let generator = build_creation_data_generator(&args.data).unwrap();
+    let progress = |msg: &str| progress_span.pb_set_message(msg);
+    match args.command {
+        Command::Creation { data } => import_creations(&db, &data, &progress).await,
+        Command::Invocation { data } => import_invocations(&db, &data, &progress).await,
+    }
+}
+
+async fn import_creations(db: &DatabaseConnection, data: &str, progress: &dyn Fn(&str)) {
+    let generator = build_creation_data_generator(data).unwrap();
 
     let mut finished_count = 0;
     for data in generator {
@@ -134,7 +155,7 @@ async fn main() {
         };
 
         finished_count += 1;
-        progress_span.pb_set_message(format!("Imported {}", finished_count).as_str());
+        progress(format!("Imported {}", finished_count).as_str());
     }
 }
 
@@ -191,31 +212,42 @@ fn build_creation_data_generator(
     Ok(Box::new(iter))
 }
 
-#[allow(unused)]
-fn build_proxy_data_generator(
-    proxy_data: &str,
-) -> Result<Box<dyn Iterator<Item = ProxyData>>, std::io::Error> {
-    match serde_json::from_str::<ProxyData>(proxy_data) {
-        Ok(data) => {
-            info!(data = proxy_data, "Proxy data is a single entry");
-            let v = vec![data];
-            Ok(Box::new(v.into_iter()))
+/// Import the invocations of each proxy in one transaction, replacing those imported before,
+/// so that an interrupted import can simply be run again.
+/// Proxies whose invocation count already matches the data are skipped.
+async fn import_invocations(db: &DatabaseConnection, data: &str, progress: &dyn Fn(&str)) {
+    // entries are read one line at a time, so that the memory is bounded by the largest proxy
+    let source = match JsonlSource::<ProxyData>::open(data) {
+        Ok(source) => source,
+        Err(e) => {
+            error!(err = e.to_string().as_str(), "Failed to open proxy data");
+            return;
         }
-        Err(_) => {
-            info!(
-                path = proxy_data,
-                "Considering proxy data as file containing multiple entries"
-            );
-            // try to parse proxy data as a file path
-            let reader = BufReader::new(std::fs::File::open(proxy_data)?);
-            let iter = reader
-                .lines()
-                .map(|line| {
-                    let line = line.unwrap();
-                    serde_json::from_str::<ProxyData>(&line).unwrap()
-                })
-                .into_iter();
-            Ok(Box::new(iter))
+    };
+
+    let mut finished_count = 0;
+    let mut skipped_count = 0;
+    for data in source {
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                error!(err = e.to_string().as_str(), "Skip malformed proxy data");
+                continue;
+            }
+        };
+        match dataset::save_invocations(db, data).await {
+            Ok(true) => finished_count += 1,
+            Ok(false) => skipped_count += 1,
+            Err(e) => {
+                error!(err = e.to_string().as_str(), "Failed to import invocations");
+                return;
+            }
         }
+        progress(format!("Imported {}, skipped {}", finished_count, skipped_count).as_str());
     }
+    info!(
+        imported = finished_count,
+        skipped = skipped_count,
+        "Invocations imported"
+    );
 }
@@ -0,0 +1,284 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use clap::{command, Parser};
+use crossbeam::channel;
+use indicatif::ProgressStyle;
+use libsofl_utils::{
+    config::Config,
+    log::{error, info, info_span},
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    config::ProxyExDetectorConfig,
+    dataset::{
+        self,
+        trace::{ProxyDataCollector, DEFAULT_CUTOFF_BLOCK},
+        ImplInvocation,
+    },
+    io::open_sink,
+    run::RunTracker,
+};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelIterator},
+    ThreadPoolBuilder,
+};
+use tracing_indicatif::{span_ext::IndicatifSpanExt, IndicatifLayer};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+/// Find the implementations invoked by each proxy in the raw BigQuery trace dumps
+/// (`logic-contract-prep/data/*.json`), and write them as `ProxyData` or save them in the `invocation` table.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// The proxy addresses, one per line (`all_proxy.txt` of proxy-contract-prep).
+    /// Without it, every contract forwarding its calldata with a delegatecall is taken as a proxy.
+    #[arg(long)]
+    proxies: Option<PathBuf>,
+
+    /// Traces in blocks after this one are ignored
+    #[arg(long, default_value_t = DEFAULT_CUTOFF_BLOCK)]
+    cutoff_block: u64,
+
+    /// Write the ProxyData JSONL (or `.parquet`) file, e.g., `impl.json`
+    #[arg(short, long, required_unless_present = "db", conflicts_with = "db")]
+    output: Option<PathBuf>,
+
+    /// Save the invocations of each shard in the `proxy` and `invocation` tables instead, as soon as it is read,
+    /// with the progress of each shard in the `run` table.
+    /// Without it, the invocations of all shards are kept in memory to write one record per proxy.
+    #[arg(long)]
+    db: bool,
+
+    /// Read again the shards that failed in previous runs, only with `--db`
+    #[arg(long, requires = "db")]
+    retry_failed: bool,
+
+    /// The trace shard files, or directories of `*.json` shard files
+    #[arg(required = true)]
+    data: Vec<PathBuf>,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    // prepare logger
+    let indicatif_layer = IndicatifLayer::new();
+    let log_filter = EnvFilter::try_from_default_env()
+        .or_else(|_| EnvFilter::try_new(args.log_level.clone()))
+        .expect("failed to create console logger filter");
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(indicatif_layer.get_stderr_writer())
+                .with_target(false)
+                .with_filter(log_filter),
+        )
+        .with(indicatif_layer)
+        .init();
+    info!("Trace ingestion started: {:?}", args);
+
+    let proxies = match &args.proxies {
+        Some(path) => match dataset::trace::read_proxies(path) {
+            Ok(proxies) => Some(proxies),
+            Err(e) => {
+                error!(err = e.to_string().as_str(), "Failed to read proxies");
+                return;
+            }
+        },
+        None => None,
+    };
+    let shards = match list_shards(&args.data) {
+        Ok(shards) => shards,
+        Err(e) => {
+            error!(err = e.to_string().as_str(), "Failed to list trace shards");
+            return;
+        }
+    };
+    info!(shards = shards.len(), "Trace shards found");
+
+    let tracker = RunTracker::new("ingest-traces");
+    // with `--db`, the shards done or failed in previous runs are skipped
+    let (cfg, finished) = match args.db {
+        true => {
+            let rt = AsyncRuntime::new();
+            let cfg =
+                ProxyExDetectorConfig::load_or(Default::default()).expect("load config failed");
+            let db = rt
+                .block_on(cfg.db())
+                .expect("failed to connect to database");
+            rt.block_on(tracker.prepare(&db, args.retry_failed))
+                .expect("failed to prepare run");
+            let finished = rt
+                .block_on(tracker.finished(&db))
+                .expect("failed to load finished shards");
+            (Some(cfg), finished)
+        }
+        false => (None, HashSet::new()),
+    };
+    let shards = shards
+        .into_iter()
+        .filter(|shard| !finished.contains(&shard_item(shard)))
+        .collect::<Vec<_>>();
+    if !finished.is_empty() {
+        info!(
+            finished = finished.len(),
+            remaining = shards.len(),
+            "Shards finished before are skipped"
+        );
+    }
+
+    let (result_tx, result_rx) =
+        channel::bounded::<(PathBuf, Result<Vec<(String, ImplInvocation)>, String>)>(args.jobs);
+
+    // results are saved shard by shard, or merged and written when all shards are read
+    let result_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let mut collector = ProxyDataCollector::default();
+        let mut failed = Vec::new();
+        let mut saved = 0;
+        let rt = AsyncRuntime::new();
+        let db = cfg.map(|cfg| {
+            rt.block_on(cfg.db())
+                .expect("failed to connect to database")
+        });
+        for (shard, invocations) in result_rx {
+            let db = match &db {
+                Some(db) => db,
+                None => {
+                    match invocations {
+                        Ok(invocations) => collector.extend(invocations),
+                        Err(_) => failed.push(shard),
+                    }
+                    continue;
+                }
+            };
+            let item = shard_item(&shard);
+            let task = async {
+                result_tracker.start(db, [item.clone()]).await?;
+                match invocations {
+                    Ok(invocations) => {
+                        saved += invocations.len();
+                        dataset::save_shard_invocations(db, invocations).await?;
+                        result_tracker.done(db, item.clone()).await
+                    }
+                    Err(e) => {
+                        failed.push(shard.clone());
+                        result_tracker.fail(db, item.clone(), e).await
+                    }
+                }
+            };
+            if let Err(e) = rt.block_on(task) {
+                error!(
+                    shard = item.as_str(),
+                    err = e.to_string().as_str(),
+                    "Failed to save invocations"
+                );
+            }
+        }
+        (collector, failed, saved)
+    });
+
+    let progress_span = info_span!("ingesting");
+    progress_span.pb_set_style(&ProgressStyle::default_bar());
+    progress_span.pb_set_length(shards.len() as u64);
+    progress_span.pb_start();
+
+    // shards are read in parallel, and their invocations are saved or merged as soon as each one is done
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+    pool.install(|| {
+        shards
+            .par_iter()
+            .for_each_with(result_tx, |result_tx, shard| {
+                let r = dataset::trace::read_shard(shard, args.cutoff_block, proxies.as_ref())
+                    .map_err(|e| e.to_string());
+                if let Err(e) = &r {
+                    error!(
+                        shard = shard.display().to_string().as_str(),
+                        err = e.as_str(),
+                        "Failed to read trace shard"
+                    );
+                }
+                result_tx.send((shard.clone(), r)).unwrap();
+                progress_span.pb_inc(1);
+            })
+    });
+    drop(progress_span);
+
+    info!("Waiting for result thread to finish");
+    let (collector, failed, saved) = result_thread.join().unwrap();
+    let output = match &args.output {
+        Some(output) => output,
+        None => {
+            info!(
+                invocations = saved,
+                failed = failed.len(),
+                "Invocations saved"
+            );
+            return;
+        }
+    };
+
+    if !failed.is_empty() {
+        // a proxy with invocations in a failed shard would be written incompletely
+        error!(
+            failed = failed.len(),
+            "Some trace shards failed, nothing is written"
+        );
+        return;
+    }
+    let data = collector.finish();
+    info!(proxies = data.len(), "Traces ingested");
+    let r = open_sink(output).and_then(|mut sink| {
+        for d in &data {
+            sink.write_record(d)?;
+        }
+        sink.finish()
+    });
+    match r {
+        Ok(_) => info!(
+            output = output.display().to_string().as_str(),
+            "ProxyData written"
+        ),
+        Err(e) => error!(err = e.to_string().as_str(), "Failed to write proxy data"),
+    }
+}
+
+/// The item of a shard in the `run` table.
+fn shard_item(shard: &Path) -> String {
+    shard.display().to_string()
+}
+
+/// The shard files given, with the `*.json` files of the directories given in the order of their names.
+fn list_shards(data: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut shards = Vec::new();
+    for path in data {
+        if !path.is_dir() {
+            shards.push(path.clone());
+            continue;
+        }
+        let mut files = std::fs::read_dir(path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        files.retain(|f| is_shard(f));
+        files.sort();
+        shards.extend(files);
+    }
+    Ok(shards)
+}
+
+fn is_shard(path: &Path) -> bool {
+    path.is_file() && path.extension().map_or(false, |e| e == "json")
+}
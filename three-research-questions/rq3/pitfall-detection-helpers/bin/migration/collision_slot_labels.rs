@@ -0,0 +1,33 @@
+use proxyex_detector::entities::collision;
+use sea_orm_migration::prelude::*;
+
+use crate::add_missing_column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_missing_column(
+            manager,
+            collision::Entity,
+            ColumnDef::new(collision::Column::SlotLabels)
+                .json()
+                .null()
+                .to_owned(),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(collision::Entity)
+                    .drop_column(collision::Column::SlotLabels)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,49 @@
+use proxyex_detector::entities::error;
+use sea_orm_migration::prelude::*;
+
+use crate::add_missing_column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // the errors saved before are not classified
+        let columns = [
+            ColumnDef::new(error::Column::Kind)
+                .string()
+                .not_null()
+                .default("unknown")
+                .to_owned(),
+            ColumnDef::new(error::Column::Tx).string().null().to_owned(),
+            ColumnDef::new(error::Column::Detector)
+                .string()
+                .not_null()
+                .default("unknown")
+                .to_owned(),
+        ];
+        for column in columns {
+            add_missing_column(manager, error::Entity, column).await?;
+        }
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        for column in [
+            error::Column::Kind,
+            error::Column::Tx,
+            error::Column::Detector,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(error::Entity)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use proxyex_detector::entities::fake_loose;
+use sea_orm_migration::prelude::*;
+
+use crate::add_missing_column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_missing_column(
+            manager,
+            fake_loose::Entity,
+            ColumnDef::new(fake_loose::Column::Convention)
+                .string()
+                .null()
+                .to_owned(),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(fake_loose::Entity)
+                    .drop_column(fake_loose::Column::Convention)
+                    .to_owned(),
+            )
+            .await
+    }
+}
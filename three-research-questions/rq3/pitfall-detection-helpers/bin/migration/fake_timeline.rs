@@ -0,0 +1,27 @@
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(
+                schema.create_table_from_entity(proxyex_detector::entities::fake_timeline::Entity),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(
+                Table::drop()
+                    .table(proxyex_detector::entities::fake_timeline::Entity)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -1,15 +1,31 @@
+mod admin_routing;
+mod clone;
 mod collision;
+mod collision_slot_labels;
 mod create_metadata;
 mod create_proxy_data;
 mod creation;
+mod error_kind;
+mod facet;
+mod facet_collision;
 mod fake;
 mod fake_loose;
+mod fake_loose_convention;
+mod fake_timeline;
 mod filtered_replay;
+mod impl_initialize;
 mod initialize;
+mod reachability;
 mod regression;
 mod regression_filter;
 mod replay;
+mod run;
+mod selector_clash;
+mod slot_history;
+mod slot_preimages;
 mod version;
+mod version_beacon;
+mod version_discrepancy;
 
 pub use sea_orm_migration::prelude::*;
 
@@ -31,10 +47,51 @@ impl MigratorTrait for Migrator {
             Box::new(fake_loose::Migration),
             Box::new(collision::Migration),
             Box::new(regression_filter::Migration),
+            Box::new(collision_slot_labels::Migration),
+            Box::new(slot_preimages::Migration),
+            Box::new(selector_clash::Migration),
+            Box::new(admin_routing::Migration),
+            Box::new(impl_initialize::Migration),
+            Box::new(reachability::Migration),
+            Box::new(run::Migration),
+            Box::new(error_kind::Migration),
+            Box::new(version_beacon::Migration),
+            Box::new(facet::Migration),
+            Box::new(facet_collision::Migration),
+            Box::new(clone::Migration),
+            Box::new(fake_loose_convention::Migration),
+            Box::new(fake_timeline::Migration),
+            Box::new(slot_history::Migration),
+            Box::new(version_discrepancy::Migration),
         ]
     }
 }
 
+/// Add a column to a table, unless the table already has it.
+/// The tables are created from the current entities, so a table created after the column is added to its entity
+/// already has it.
+pub async fn add_missing_column<T: Iden + 'static>(
+    manager: &SchemaManager<'_>,
+    table: T,
+    mut column: ColumnDef,
+) -> Result<(), DbErr> {
+    if manager
+        .has_column(&table.to_string(), &column.get_column_name())
+        .await?
+    {
+        return Ok(());
+    }
+    // SQLite only supports one change per ALTER TABLE statement
+    manager
+        .alter_table(
+            Table::alter()
+                .table(table)
+                .add_column(&mut column)
+                .to_owned(),
+        )
+        .await
+}
+
 use libsofl_utils::{config::Config, log::info};
 
 #[tokio::main]
@@ -43,7 +100,7 @@ async fn main() {
     let cfg = proxyex_detector::config::ProxyExDetectorConfig::load_or(Default::default())
         .expect("load config failed");
     let database_env = std::env::var("DATABASE_URL").ok();
-    std::env::set_var("DATABASE_URL", cfg.database_url.clone());
+    std::env::set_var("DATABASE_URL", cfg.connect_url());
 
     info!(db = cfg.database_url.as_str(), "Migrating database");
     cli::run_cli(Migrator).await;
@@ -55,3 +112,49 @@ async fn main() {
         std::env::remove_var("DATABASE_URL")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::Database;
+    use sea_orm_migration::prelude::*;
+
+    use super::Migrator;
+
+    #[tokio::test]
+    async fn test_migrate_sqlite() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+        let manager = SchemaManager::new(&db);
+        for table in [
+            "proxy",
+            "invocation",
+            "collision",
+            "regression",
+            "fake_loose",
+            "selector_clash",
+            "admin_routing",
+            "impl_initialize",
+            "reachability",
+            "run",
+            "error",
+            "facet",
+            "facet_collision",
+            "clone",
+            "fake_timeline",
+            "slot_history",
+            "version_discrepancy",
+        ] {
+            assert!(manager.has_table(table).await.unwrap(), "{}", table);
+        }
+        assert!(manager.has_column("error", "kind").await.unwrap());
+        assert!(manager.has_column("collision", "preimages").await.unwrap());
+        assert!(manager.has_column("version", "beacon").await.unwrap());
+        assert!(manager
+            .has_column("fake_loose", "convention")
+            .await
+            .unwrap());
+
+        Migrator::down(&db, None).await.unwrap();
+        assert!(!manager.has_table("proxy").await.unwrap());
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use proxyex_detector::entities::{
+    admin_routing, collision, fake_loose, filtered_replay, impl_initialize, initialize,
+    reachability, regression, run, selector_clash, version,
+};
+use sea_orm::Schema;
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+/// Mark the items that already have results as done, so that the detectors do not run them again.
+async fn backfill(
+    manager: &SchemaManager<'_>,
+    detector: &str,
+    mut select: SelectStatement,
+) -> Result<(), DbErr> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let select = select
+        .distinct()
+        .expr(Expr::val(detector))
+        .expr(Expr::val("done"))
+        .expr(Expr::val(1))
+        .expr(Expr::val(now))
+        .expr(Expr::val(now))
+        // SQLite cannot tell `ON CONFLICT` from a join constraint after `SELECT .. FROM t` without a WHERE clause
+        .and_where(Expr::val(true).into())
+        .to_owned();
+    let insert = Query::insert()
+        .into_table(run::Entity)
+        .columns([
+            run::Column::Item,
+            run::Column::Detector,
+            run::Column::Status,
+            run::Column::Attempts,
+            run::Column::CreatedAt,
+            run::Column::UpdatedAt,
+        ])
+        .select_from(select)
+        .map_err(|e| DbErr::Custom(e.to_string()))?
+        .on_conflict(
+            OnConflict::columns([run::Column::Detector, run::Column::Item])
+                .do_nothing()
+                .to_owned(),
+        )
+        .to_owned();
+    manager.exec_stmt(insert).await
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let schema = Schema::new(manager.get_database_backend());
+        manager
+            .create_table(schema.create_table_from_entity(run::Entity))
+            .await?;
+
+        backfill(
+            manager,
+            "replay",
+            Query::select()
+                .column(collision::Column::Proxy)
+                .from(collision::Entity)
+                .to_owned(),
+        )
+        .await?;
+        // "replay-replaced" is not backfilled: the rows of a proxy in `regression` do not tell whether all its
+        // invocations were simulated, and the rows of a proxy run again are kept as they are
+        backfill(
+            manager,
+            "regression",
+            Query::select()
+                .column(regression::Column::Tx)
+                .from(regression::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "fake-proxy-detector",
+            Query::select()
+                .column(fake_loose::Column::Proxy)
+                .from(fake_loose::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "filter-replay",
+            Query::select()
+                .column(filtered_replay::Column::Proxy)
+                .from(filtered_replay::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "version",
+            Query::select()
+                .column(version::Column::Proxy)
+                .from(version::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "uninitialized",
+            Query::select()
+                .column(initialize::Column::Proxy)
+                .from(initialize::Entity)
+                .and_where(Expr::col(initialize::Column::Uninitialized).is_not_null())
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "uninitialized-implementation",
+            Query::select()
+                .column(impl_initialize::Column::Implementation)
+                .from(impl_initialize::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "selector-clash",
+            Query::select()
+                .column(selector_clash::Column::Proxy)
+                .from(selector_clash::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "admin-routing",
+            Query::select()
+                .column(admin_routing::Column::Proxy)
+                .from(admin_routing::Entity)
+                .to_owned(),
+        )
+        .await?;
+        backfill(
+            manager,
+            "reachability",
+            Query::select()
+                .column(reachability::Column::Implementation)
+                .from(reachability::Entity)
+                .to_owned(),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(run::Entity).to_owned())
+            .await
+    }
+}
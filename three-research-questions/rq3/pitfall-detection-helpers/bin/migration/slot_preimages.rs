@@ -0,0 +1,50 @@
+use proxyex_detector::entities::{collision, regression};
+use sea_orm_migration::prelude::*;
+
+use crate::add_missing_column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_missing_column(
+            manager,
+            collision::Entity,
+            ColumnDef::new(collision::Column::Preimages)
+                .json()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+        add_missing_column(
+            manager,
+            regression::Entity,
+            ColumnDef::new(regression::Column::Preimages)
+                .json()
+                .null()
+                .to_owned(),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(regression::Entity)
+                    .drop_column(regression::Column::Preimages)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(collision::Entity)
+                    .drop_column(collision::Column::Preimages)
+                    .to_owned(),
+            )
+            .await
+    }
+}
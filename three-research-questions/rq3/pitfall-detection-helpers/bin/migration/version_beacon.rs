@@ -0,0 +1,33 @@
+use proxyex_detector::entities::version;
+use sea_orm_migration::prelude::*;
+
+use crate::add_missing_column;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_missing_column(
+            manager,
+            version::Entity,
+            ColumnDef::new(version::Column::Beacon)
+                .string()
+                .null()
+                .to_owned(),
+        )
+        .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(version::Entity)
+                    .drop_column(version::Column::Beacon)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    sync::{atomic::AtomicI32, Arc},
+    thread,
+    time::Duration,
+};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::types::{Address, DatabaseRef},
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig},
+    config::ProxyExDetectorConfig,
+    dataset::{first_used_implementations, ProxyData},
+    entities,
+    error::DetectorError,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    reachability::{check_reachability, ReachableCall},
+    run::RunTracker,
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{sea_query::OnConflict, ColumnTrait, DbErr, EntityTrait, QueryFilter, QuerySelect};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the implementations that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check the implementations of the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`)
+    /// instead of the database
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| check_file(args, proxies, files, p));
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("reachability");
+    let shutdown = Shutdown::install();
+    let (task_tx, task_rx) = channel::bounded::<(Address, i64)>(1000);
+    let (result_tx, result_rx) =
+        channel::bounded::<(Address, i64, Vec<ReachableCall>, Duration)>(1000);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        loop {
+            let (implementation, min_block, reachable_calls, time) = match result_rx.recv() {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            let task = async {
+                let reachability: entities::reachability::ActiveModel =
+                    reachability_row(implementation, min_block, reachable_calls, time).into();
+                entities::reachability::Entity::insert(reachability)
+                    .on_conflict(
+                        OnConflict::column(entities::reachability::Column::Implementation)
+                            .do_nothing()
+                            .to_owned(),
+                    )
+                    .exec(&db)
+                    .await
+            };
+            match rt.block_on(task) {
+                Ok(_) => {}
+                Err(e) => {
+                    if e != DbErr::RecordNotInserted {
+                        panic!("{:?}", e);
+                    }
+                }
+            };
+            let task = cloned_tracker.done(&db, implementation.to_string().to_lowercase());
+            rt.block_on(task).unwrap();
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let wg = WaitGroup::new();
+    let finished = Arc::new(AtomicI32::new(0));
+    for _ in 0..args.jobs {
+        let task_rx = task_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let finished = finished.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            loop {
+                let (implementation, min_block) = match task_rx.recv() {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                let start_at = std::time::Instant::now();
+                let reachable_calls =
+                    match check_implementation(p.clone(), implementation, min_block, budget) {
+                        Ok(r) => r,
+                        Err(e) => {
+                            error!(
+                                implementation = implementation.to_string().to_lowercase(),
+                                err = e.to_string(),
+                                "Failed to check implementation"
+                            );
+                            let error = e.to_model(&tracker.detector, implementation, None);
+                            rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                                .unwrap();
+                            let task = tracker.fail(
+                                &db,
+                                implementation.to_string().to_lowercase(),
+                                e.to_string(),
+                            );
+                            rt.block_on(task).unwrap();
+                            continue;
+                        }
+                    };
+                result_tx
+                    .send((
+                        implementation,
+                        min_block,
+                        reachable_calls,
+                        start_at.elapsed(),
+                    ))
+                    .unwrap();
+
+                finished.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                info!(
+                    finished = finished.load(std::sync::atomic::Ordering::SeqCst),
+                    "finished implementation"
+                );
+            }
+            drop(wg);
+        });
+    }
+
+    // each implementation is checked at the first block it is used by any proxy
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // implementations are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let implementations = entities::version::Entity::find()
+            .select_only()
+            .column(entities::version::Column::Implementation)
+            .column_as(entities::version::Column::MinBlock.min(), "min_block")
+            .filter(tracker.unfinished((
+                entities::version::Entity,
+                entities::version::Column::Implementation,
+            )))
+            .group_by(entities::version::Column::Implementation)
+            .limit(10000)
+            .into_tuple::<(String, i64)>()
+            .all(&db)
+            .await?;
+        if implementations.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, implementations.iter().map(|(i, _)| i.clone()))
+            .await?;
+        for (implementation, min_block) in implementations {
+            if shutdown.requested() {
+                break;
+            }
+            task_tx.send((implementation.cvt(), min_block)).unwrap();
+        }
+    }
+
+    info!("Waiting for all tasks to finish");
+    drop(task_tx);
+    wg.wait();
+
+    info!("Waiting for result thread to finish");
+    drop(result_tx);
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// Check the implementations of the proxies in a ProxyData file, each at the first block it is used by any proxy,
+/// writing the rows of the `reachability` table to the files.
+fn check_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    // the first use of an implementation is only known once all proxies are read
+    let implementations = first_used_implementations(proxies);
+    check_into_files(
+        implementations.into_iter(),
+        files,
+        args.jobs,
+        &shutdown,
+        |(implementation, min_block)| {
+            let addr: Address = implementation.cvt();
+            let start_at = std::time::Instant::now();
+            let r = check_implementation(p.clone(), addr, min_block as i64, budget)
+                .map(|calls| reachability_row(addr, min_block as i64, calls, start_at.elapsed()))
+                .map_err(|e| {
+                    error!(
+                        implementation = implementation.as_str(),
+                        err = e.to_string(),
+                        "Failed to check implementation"
+                    );
+                    ErrorRecord::new(addr, None, e)
+                });
+            vec![r]
+        },
+    );
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// The row of the `reachability` table.
+fn reachability_row(
+    implementation: Address,
+    min_block: i64,
+    reachable_calls: Vec<ReachableCall>,
+    time: Duration,
+) -> entities::reachability::Model {
+    entities::reachability::Model {
+        implementation: implementation.to_string().to_lowercase(),
+        min_block,
+        selfdestruct: reachable_calls.iter().any(|c| c.selfdestruct),
+        attacker_delegatecall: reachable_calls.iter().any(|c| c.attacker_delegatecall),
+        reachable_calls: serde_json::to_value(reachable_calls).unwrap(),
+        total_time: time.as_nanos() as i64,
+    }
+}
+
+fn check_implementation<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    implementation: Address,
+    min_block: i64,
+    budget: Budget,
+) -> Result<Vec<ReachableCall>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let blk = min_block as u64;
+    let mut state = p
+        .bc_state_at(TxPosition::new(blk, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk);
+    check_reachability(&caller, &mut state, implementation, budget)
+}
@@ -0,0 +1,66 @@
+use std::{path::Path, sync::Arc};
+
+use clap::{command, Parser};
+use libsofl_core::{blockchain::transaction::Tx, engine::types::DatabaseRef};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+};
+use proxyex_detector::{
+    harness::{record_case, KNOWN_CASES},
+    provider::DetectorProvider,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    /// Directory where the recorded cases are saved
+    #[arg(short, long, default_value = "fixtures/cases")]
+    output: String,
+
+    /// Names of the known cases to record, separated by comma (all known cases by default)
+    cases: Option<String>,
+}
+
+fn main() {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    proxyex_detector::with_provider!(|p| record_all(args, p))
+}
+
+fn record_all<T: Tx, DB: DatabaseRef, P: DetectorProvider<T, DB>>(args: Cli, p: Arc<P>)
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let names: Option<Vec<&str>> = args.cases.as_ref().map(|s| s.split(',').collect());
+    std::fs::create_dir_all(&args.output).expect("failed to create output directory");
+    for spec in KNOWN_CASES {
+        if let Some(names) = &names {
+            if !names.contains(&spec.name) {
+                continue;
+            }
+        }
+        info!(case = spec.name, "Recording");
+        let case = match record_case(p.clone(), spec) {
+            Ok(case) => case,
+            Err(e) => {
+                error!(case = spec.name, error = ?e, "Failed to record case");
+                continue;
+            }
+        };
+        let path = Path::new(&args.output).join(format!("{}.json", spec.name));
+        case.save(&path).expect("failed to save case");
+        info!(
+            case = spec.name,
+            txs = case.txs.len(),
+            path = path.to_string_lossy().to_string(),
+            "Recorded"
+        );
+    }
+}
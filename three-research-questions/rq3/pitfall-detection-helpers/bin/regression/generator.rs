@@ -4,7 +4,7 @@ use libsofl_core::{
     conversion::ConvertTo,
     engine::types::{Address, TxHash},
 };
-use proxyex_detector::entities;
+use proxyex_detector::{entities, run::RunTracker};
 use sea_orm::{
     sea_query::{Expr, Query},
     ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect,
@@ -13,8 +13,8 @@ use sea_orm::{
 pub struct DBIterator {
     regression_mutex: Arc<Mutex<()>>,
     db: DatabaseConnection,
+    tracker: RunTracker,
     window_size: u64,
-    tx_offset: u64,
 
     only_proxies: Option<Vec<Address>>,
 
@@ -35,12 +35,13 @@ impl DBIterator {
         window_size: usize,
         only_proxies: Option<Vec<Address>>,
         regression_mutex: Arc<Mutex<()>>,
+        tracker: RunTracker,
     ) -> Self {
         Self {
             regression_mutex,
             db,
+            tracker,
             window_size: window_size as u64,
-            tx_offset: 0,
             only_proxies,
             txs: vec![],
         }
@@ -87,18 +88,10 @@ impl DBIterator {
                             .and_where(entities::proxy::Column::InvocationCount.gt(1))
                             .take(),
                     ))
-                    .add(
-                        Expr::exists(
-                            Query::select()
-                                .from(entities::regression::Entity)
-                                .and_where(Expr::col(entities::regression::Column::Tx).equals((
-                                    entities::invocation::Entity,
-                                    entities::invocation::Column::Tx,
-                                )))
-                                .take(),
-                        )
-                        .not(),
-                    )
+                    .add(self.tracker.unfinished((
+                        entities::invocation::Entity,
+                        entities::invocation::Column::Tx,
+                    )))
                     .add(Expr::exists(
                         Query::select()
                             .from(entities::version::Entity)
@@ -126,31 +119,24 @@ impl DBIterator {
             });
             // let stmt = query.build(sea_orm::DatabaseBackend::Postgres);
             // println!("{}", stmt.sql);
+            // txs are marked running once loaded, so the query always starts from the beginning
             let lck = self.regression_mutex.lock().unwrap();
             let txs: Vec<entities::invocation::Model> = query
                 // .order_by_asc(entities::invocation::Column::Block)
                 .limit(self.window_size)
-                .offset(self.tx_offset)
                 .all(&self.db)
                 .await?;
             drop(lck);
+            self.tracker
+                .start(&self.db, txs.iter().map(|m| m.tx.clone()))
+                .await?;
             self.txs.extend(
                 txs.iter()
                     .map(|m| (m.proxy.cvt(), m.implementation.cvt(), m.tx.cvt(), m.block))
                     .collect::<Vec<_>>(),
             );
-            if txs.len() < self.window_size as usize {
-                if self.tx_offset == 0 {
-                    return Ok(());
-                }
-                // current proxy is exhausted
-                self.tx_offset = 0;
-            } else {
-                if self.tx_offset > self.window_size * 4 {
-                    self.tx_offset = 0;
-                } else {
-                    self.tx_offset += self.window_size;
-                }
+            if txs.is_empty() {
+                return Ok(());
             }
         }
         Ok(())
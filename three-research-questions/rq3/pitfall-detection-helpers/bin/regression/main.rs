@@ -1,17 +1,17 @@
 mod generator;
 
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
 
 use clap::Parser;
-use crossbeam::channel;
+use crossbeam::{channel, sync::WaitGroup};
 use generator::{DBIterator, Item};
 use libsofl_core::{
+    blockchain::transaction::Tx,
     conversion::ConvertTo,
-    engine::types::{Address, Bytecode},
-};
-use libsofl_reth::{
-    blockchain::provider::{RethProvider, StateProviderFactory},
-    config::RethConfig,
+    engine::types::{Address, Bytecode, DatabaseRef, TxHash},
 };
 use libsofl_utils::{
     config::Config,
@@ -19,12 +19,24 @@ use libsofl_utils::{
     sync::runtime::AsyncRuntime,
 };
 use proxyex_detector::{
+    budget::{Budget, BudgetConfig},
     config::ProxyExDetectorConfig,
+    dataset::ProxyData,
     entities,
-    replaced_replay::{check_regression, regression_one_tx, RegressionIssue},
+    error::DetectorError,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    replaced_replay::{
+        check_regression, implementation_code_at, regression_one_tx, RegressionIssue,
+    },
+    run::RunTracker,
+    shutdown::Shutdown,
 };
 use rayon::ThreadPoolBuilder;
-use sea_orm::{sea_query::OnConflict, ColumnTrait, Condition, DbErr, EntityTrait, QueryFilter};
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, TryIntoModel,
+};
 use tracing_indicatif::IndicatifLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
@@ -37,6 +49,27 @@ pub struct Cli {
     #[arg(short, long, default_value = "1")]
     jobs: usize,
 
+    /// Retry the txs that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Retry the txs that failed in previous runs with these kinds of errors
+    #[arg(long, value_delimiter = ',', value_parser = DetectorError::KINDS)]
+    retry_kind: Vec<String>,
+
+    /// Test the txs of the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`)
+    /// instead of the database, with the versions taken from the `impls` of each proxy
+    #[arg(long, requires = "output", conflicts_with = "proxies")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+
     proxies: Option<String>,
 }
 
@@ -58,52 +91,94 @@ async fn main() -> Result<(), DbErr> {
         .with(indicatif_layer)
         .init();
 
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| analyze_file(p, args.jobs, proxies, files));
+        return Ok(());
+    }
+
     let only_proxies: Option<Vec<Address>> = args.proxies.map(|s| {
         s.split(',')
             .map(|ss| ConvertTo::<Address>::cvt(&ss))
             .collect()
     });
     let cfg = ProxyExDetectorConfig::must_load();
-    let provider = RethConfig::must_load().bc_provider().unwrap();
-    let provider = Arc::new(provider);
 
-    analyze_all(cfg, provider, args.jobs, only_proxies).await;
+    let tracker = RunTracker::new("regression");
+    let shutdown = Shutdown::install();
+    let db = cfg.db().await?;
+    tracker.prepare(&db, args.retry_failed).await?;
+    tracker.retry_kinds(&db, entities::error::Column::Tx, &args.retry_kind).await?;
+
+    let cloned_tracker = tracker.clone();
+    let cloned_shutdown = shutdown.clone();
+    proxyex_detector::with_provider!(
+        |p| analyze_all(cfg, p, args.jobs, only_proxies, cloned_tracker, cloned_shutdown).await
+    );
+    shutdown.report(&tracker, &db).await?;
 
     Ok(())
 }
 
-async fn analyze_all(
+async fn analyze_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
     cfg: ProxyExDetectorConfig,
-    p: Arc<RethProvider>,
+    p: Arc<P>,
     jobs: usize,
     only_proxies: Option<Vec<Address>>,
-) {
+    tracker: RunTracker,
+    shutdown: Shutdown,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let regression_mu = Mutex::new(());
     let regression_mu = Arc::new(regression_mu);
 
     let db = cfg.db().await.unwrap();
-    let mut generator = DBIterator::new(db, jobs * 2, only_proxies, regression_mu.clone());
+    let mut generator = DBIterator::new(
+        db,
+        jobs * 2,
+        only_proxies,
+        regression_mu.clone(),
+        tracker.clone(),
+    );
 
     let pool = ThreadPoolBuilder::new().num_threads(jobs).build().unwrap();
-    let (issue_tx, issue_rx) = channel::bounded::<Vec<RegressionIssue>>(jobs * 2);
+    let (issue_tx, issue_rx) = channel::bounded::<(TxHash, Vec<RegressionIssue>)>(jobs * 2);
 
     let db = cfg.db().await.unwrap();
     let r_mu = regression_mu.clone();
-    pool.spawn(move || {
+    let cloned_tracker = tracker.clone();
+    let issue_thread = thread::spawn(move || {
         let rt = AsyncRuntime::new();
         let mut issues_buf = Vec::new();
+        let mut txs_buf = Vec::new();
         loop {
-            let issues = match issue_rx.recv() {
-                Ok(x) => x,
-                Err(_) => break,
+            // the buffered issues are flushed once all workers exit
+            let closed = match issue_rx.recv() {
+                Ok((tx_hash, issues)) => {
+                    debug!(count = issues.len(), "received regression issues");
+                    issues_buf.extend(issues);
+                    txs_buf.push(tx_hash);
+                    false
+                }
+                Err(_) => true,
             };
-            debug!(count = issues.len(), "received regression issues");
-            issues_buf.extend(issues);
-            if issues_buf.len() >= jobs {
+            if closed || issues_buf.len() >= jobs || txs_buf.len() >= jobs {
                 let lck = r_mu.lock().unwrap();
                 debug!(count = issues_buf.len(), "inserting regression issues");
                 let len = issues_buf.len();
                 let task = async {
+                    if issues_buf.is_empty() {
+                        return Ok(());
+                    }
                     entities::regression::Entity::insert_many(
                         issues_buf
                             .into_iter()
@@ -113,45 +188,68 @@ async fn analyze_all(
                     .on_conflict(OnConflict::new().do_nothing().to_owned())
                     .exec(&db)
                     .await
+                    .map(|_| ())
                 };
                 let r = rt.block_on(task);
-                match r {
+                let saved = match r {
                     Ok(_) => {
-                        info!(count = len, "inserted regression issues")
+                        info!(count = len, "inserted regression issues");
+                        true
                     }
                     Err(e) => {
                         if e != DbErr::RecordNotInserted {
-                            error!(e = ?e, "failed to insert regression issues")
+                            error!(e = ?e, "failed to insert regression issues");
+                            false
                         } else {
-                            debug!(count = len, "duplicate regression issues")
+                            debug!(count = len, "duplicate regression issues");
+                            true
                         }
                     }
-                }
+                };
                 drop(lck);
                 issues_buf = Vec::new();
+                // txs whose issues failed to insert stay running and are tested again in the next run
+                for tx_hash in txs_buf.drain(..) {
+                    if saved {
+                        let task = cloned_tracker.done(&db, tx_hash.to_string().to_lowercase());
+                        rt.block_on(task).unwrap();
+                    }
+                }
             } else {
                 debug!(count = issues_buf.len(), "buffering regression issues");
             }
+            if closed {
+                break;
+            }
         }
     });
 
+    let budget = BudgetConfig::load_or(Default::default()).expect("load budget config failed").tx_budget();
     let (task_tx, task_rx) = channel::bounded::<Item>(jobs * 2);
     let finished = Mutex::new(0);
     let finished = Arc::new(finished);
+    let wg = WaitGroup::new();
     for _ in 0..jobs {
         let task_rx = task_rx.clone();
         let p = p.clone();
         let issue_tx = issue_tx.clone();
         let finished = finished.clone();
         let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let shutdown = shutdown.clone();
+        let wg = wg.clone();
         pool.spawn(move || {
             let rt = AsyncRuntime::new();
             let db = rt.block_on(cfg.db()).unwrap();
             loop {
                 let (proxy, implementation, blk, tx_hash) = match task_rx.recv() {
                     Ok(a) => a,
-                    Err(_) => return,
+                    Err(_) => break,
                 };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
                 debug!(proxy = proxy.to_string().to_lowercase(), tx = tx_hash.to_string(), "geting regression versions");
                 let task = async {
                     entities::version::Entity::find()
@@ -166,45 +264,19 @@ async fn analyze_all(
                         .unwrap()
                 };
                 let alt_versions: Vec<entities::version::Model> = rt.block_on(task);
-                let alts: Vec<(Address, Bytecode)> = alt_versions
-                .into_iter()
-                .filter(|m| m.min_block > blk)
-                .map(|m| {
-                    let code =
-                        p.bp.state_by_block_number_or_tag((m.min_block as u64).into())
-                            .unwrap()
-                            .account_code(m.implementation.cvt())
-                            .unwrap()
-                            .unwrap_or_default()
-                            .bytes()
-                            .to_owned();
-                    (m.implementation.cvt(), code.cvt())
-                })
-                .collect();
-                let alt_count = alts.len();
-                let (original_insp, alt_insps) = match regression_one_tx(
-                    p.clone(),
-                    proxy,
-                    implementation,
-                    alts,
-                    tx_hash,
-                ) {
-                    Ok(x) => x,
-                    Err(e) => {
-                        error!(e = ?e, proxy = proxy.to_string().to_lowercase(), tx = tx_hash.to_string(), alts = alt_count, "failed to regression test on tx");
-                        return;
-                    }
-                };
-                assert_eq!(alt_insps.len(), alt_count);
-                let rs = match check_regression(original_insp, alt_insps, tx_hash) {
+                let versions = alt_versions
+                    .into_iter()
+                    .map(|m| -> (Address, i64) { (m.implementation.cvt(), m.min_block) });
+                let rs = match regression_tx(p.clone(), (proxy, implementation, blk, tx_hash), versions, budget) {
                     Ok(x) => x,
                     Err(e) => {
-                        error!(e = ?e, proxy = proxy.to_string().to_lowercase(), tx = tx_hash.to_string(), "failed to check regression");
-                        return;
+                        error!(e = ?e, proxy = proxy.to_string().to_lowercase(), tx = tx_hash.to_string(), "failed to regression test on tx");
+                        rt.block_on(fail_tx(&db, &tracker, proxy, tx_hash, e)).unwrap();
+                        continue;
                     }
                 };
-                assert_eq!(rs.len(), alt_count);
-                issue_tx.send(rs).unwrap();
+                let alt_count = rs.len();
+                issue_tx.send((tx_hash, rs)).unwrap();
                 let mut finished = finished.lock().unwrap();
                 *finished += 1;
                 let finished = *finished;
@@ -213,10 +285,11 @@ async fn analyze_all(
                 // *c += 1;
                 // progress_span.pb_set_message(format!("Analyzed {}", *c).as_str());
             }
+            drop(wg);
         });
     }
 
-    loop {
+    while !shutdown.requested() {
         let (proxy, implementation, blk, tx_hash) = match generator.next_async().await {
             Some(a) => a,
             None => break,
@@ -224,6 +297,105 @@ async fn analyze_all(
 
         task_tx.send((proxy, implementation, blk, tx_hash)).unwrap();
     }
-    drop(task_rx);
+    drop(task_tx);
     info!("All tasks sent");
+    wg.wait();
+
+    info!("Waiting for issue thread to finish");
+    drop(issue_tx);
+    issue_thread.join().unwrap();
+}
+
+/// Test the txs of the proxies in a ProxyData file, writing the rows of the `regression` table to the files.
+fn analyze_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    jobs: usize,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let txs = proxies.flat_map(|data| {
+        let proxy: Address = data.proxy.cvt();
+        let versions = data
+            .versions()
+            .into_iter()
+            .map(|(i, min_block)| -> (Address, i64) { (i.cvt(), min_block as i64) })
+            .collect::<Vec<_>>();
+        let versions = Arc::new(versions);
+        data.ordered_impls().into_iter().map(move |i| {
+            let item: Item = (proxy, i.implementation.cvt(), i.block as i64, i.tx.cvt());
+            (item, versions.clone())
+        })
+    });
+    check_into_files(txs, files, jobs, &shutdown, |(item, versions)| {
+        let (proxy, _, _, tx_hash) = item;
+        let rs = match regression_tx(p.clone(), item, versions.iter().copied(), budget) {
+            Ok(rs) => rs,
+            Err(e) => {
+                error!(e = ?e, proxy = proxy.to_string().to_lowercase(), tx = tx_hash.to_string(), "failed to regression test on tx");
+                return vec![Err(ErrorRecord::new(proxy, Some(tx_hash), e))];
+            }
+        };
+        info!(
+            proxy = proxy.to_string().to_lowercase(),
+            tx = tx_hash.to_string(),
+            alts = rs.len(),
+            "regression tested"
+        );
+        rs.into_iter()
+            .map(|issue| {
+                let row: entities::regression::ActiveModel = issue.into();
+                Ok(row.try_into_model().unwrap())
+            })
+            .collect()
+    });
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// Replay a tx of the proxy with each version used after the block of the tx,
+/// given as (implementation, min_block), and compare the storage accesses with the original execution.
+fn regression_tx<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    (proxy, implementation, blk, tx_hash): Item,
+    versions: impl Iterator<Item = (Address, i64)>,
+    budget: Budget,
+) -> Result<Vec<RegressionIssue>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let alts = versions
+        .filter(|(_, min_block)| *min_block > blk)
+        .map(|(alt, min_block)| {
+            let code = implementation_code_at(p.as_ref(), alt, min_block as u64)?;
+            Ok((alt, code))
+        })
+        .collect::<Result<Vec<(Address, Bytecode)>, DetectorError>>()?;
+    let alt_count = alts.len();
+    let (original_insp, alt_insps) =
+        regression_one_tx(p.clone(), proxy, implementation, alts, tx_hash, budget)?;
+    assert_eq!(alt_insps.len(), alt_count);
+    let rs = check_regression(original_insp, alt_insps, tx_hash)?;
+    assert_eq!(rs.len(), alt_count);
+    Ok(rs)
+}
+
+/// Record the failure of a tx in both the `error` and `run` tables.
+async fn fail_tx(
+    db: &DatabaseConnection,
+    tracker: &RunTracker,
+    proxy: Address,
+    tx_hash: TxHash,
+    e: DetectorError,
+) -> Result<(), DbErr> {
+    entities::error::Entity::insert(e.to_model(&tracker.detector, proxy, Some(tx_hash)))
+        .exec(db)
+        .await?;
+    tracker
+        .fail(db, tx_hash.to_string().to_lowercase(), e.to_string())
+        .await
 }
@@ -1,34 +1,49 @@
-use futures::{future::BoxFuture, FutureExt};
+use std::collections::{HashMap, VecDeque};
+
 use libsofl_core::{
     conversion::ConvertTo,
     engine::types::{Address, TxHash},
 };
-use libsofl_utils::log::debug;
-use proxyex_detector::entities;
+use libsofl_utils::log::{debug, error};
+use proxyex_detector::{
+    clone::not_redundant_clone, dataset::ProxyData, entities, io::JsonlSource, run::RunTracker,
+};
 use sea_orm::{
-    sea_query::{Expr, Query},
     ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
     QuerySelect,
 };
 use tracing_subscriber::field::debug;
 
-pub async fn build_from_proxies(db: DatabaseConnection, proxy_data: &str) -> DBIterator {
+/// (proxy, implementation, tx, index, total, block)
+pub type Item = (Address, Address, TxHash, usize, usize, u64);
+
+pub async fn build_from_proxies(
+    db: DatabaseConnection,
+    proxy_data: &str,
+    tracker: RunTracker,
+) -> DBIterator {
     let proxy_addresses = proxy_data.split(',').map(|s| s.to_string()).collect();
-    DBIterator::new(db, 10000, Some(proxy_addresses))
+    DBIterator::new(db, 10000, Some(proxy_addresses), false, tracker)
 }
 
-pub async fn build_from_all(db: DatabaseConnection) -> DBIterator {
-    DBIterator::new(db, 10000, None)
+/// `representatives`: skip the clones other than the representative of their implementation
+pub async fn build_from_all(
+    db: DatabaseConnection,
+    representatives: bool,
+    tracker: RunTracker,
+) -> DBIterator {
+    DBIterator::new(db, 10000, None, representatives, tracker)
 }
 
 pub struct DBIterator {
-    proxy_offset: u64,
     invocation_offset: u64,
     window_size: u64,
     db: DatabaseConnection,
 
     // to filter
     proxy_addresses: Option<Vec<String>>,
+    representatives: bool,
+    tracker: RunTracker,
 
     // buffer
     proxies: Vec<entities::proxy::Model>,
@@ -40,21 +55,24 @@ impl DBIterator {
         db: DatabaseConnection,
         window_size: usize,
         proxy_addresses: Option<Vec<String>>,
+        representatives: bool,
+        tracker: RunTracker,
     ) -> Self {
         Self {
-            proxy_offset: 0,
             invocation_offset: 0,
             window_size: window_size as u64,
             db,
             proxies: vec![],
             invocations: vec![],
             proxy_addresses,
+            representatives,
+            tracker,
         }
     }
 }
 
 impl DBIterator {
-    pub async fn next_async(&mut self) -> Option<(Address, Address, TxHash, usize, usize)> {
+    pub async fn next_async(&mut self) -> Option<Item> {
         if self.invocations.len() <= 0 {
             // load more invocations
             self.load_invocations().await.unwrap();
@@ -75,7 +93,14 @@ impl DBIterator {
             total,
             "Next invocation"
         );
-        Some((proxy.cvt(), impl_.cvt(), tx.cvt(), index, total))
+        Some((
+            proxy.cvt(),
+            impl_.cvt(),
+            tx.cvt(),
+            index,
+            total,
+            invocation.block as u64,
+        ))
     }
 
     async fn load_invocations(&mut self) -> Result<(), DbErr> {
@@ -136,209 +161,133 @@ impl DBIterator {
     }
 
     async fn load_proxies(&mut self) -> Result<(), DbErr> {
-        loop {
-            // try to fetch more proxies
-            let select = match self.proxy_addresses.clone() {
-                Some(proxy_address) => {
-                    let select = entities::proxy::Entity::find().filter(
-                        Condition::all()
-                            .add(entities::proxy::Column::Address.is_in(proxy_address))
-                            .add(
-                                Expr::exists(
-                                    Query::select()
-                                        .from(entities::collision::Entity)
-                                        .and_where(
-                                            Expr::col(entities::collision::Column::Proxy)
-                                                .equals(entities::proxy::Column::Address),
-                                        )
-                                        .take(),
-                                )
-                                .not(),
-                            ),
-                    );
-                    select
+        // proxies are marked running once loaded, so the query always starts from the beginning
+        let unfinished = self
+            .tracker
+            .unfinished((entities::proxy::Entity, entities::proxy::Column::Address));
+        let select = match self.proxy_addresses.clone() {
+            Some(proxy_address) => {
+                let select = entities::proxy::Entity::find().filter(
+                    Condition::all()
+                        .add(entities::proxy::Column::Address.is_in(proxy_address))
+                        .add(unfinished),
+                );
+                select
+            }
+            None => {
+                let mut cond = Condition::all()
+                    .add(unfinished)
+                    .add(entities::proxy::Column::InvocationCount.gt(0));
+                if self.representatives {
+                    cond = cond.add(not_redundant_clone((
+                        entities::proxy::Entity,
+                        entities::proxy::Column::Address,
+                    )));
                 }
-                None => {
-                    let select = entities::proxy::Entity::find().filter(
-                        Condition::all()
-                            .add(
-                                Expr::exists(
-                                    Query::select()
-                                        .from(entities::collision::Entity)
-                                        .and_where(
-                                            Expr::col(entities::collision::Column::Proxy)
-                                                .equals(entities::proxy::Column::Address),
-                                        )
-                                        .take(),
-                                )
-                                .not(),
-                            )
-                            .add(entities::proxy::Column::InvocationCount.gt(0)),
-                    );
-                    select
+                entities::proxy::Entity::find().filter(cond)
+            }
+        };
+        debug!(window_size = self.window_size, "Querying proxies");
+        let proxies = select
+            .order_by_asc(entities::proxy::Column::InvocationCount)
+            .limit(self.window_size)
+            .all(&self.db)
+            .await?;
+        self.tracker
+            .start(&self.db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        self.proxies.extend(proxies);
+        Ok(())
+    }
+
+    /// The implementation versions of a proxy in the `version` table, ordered by block.
+    pub async fn versions(&self, proxy: Address) -> Result<Vec<(Address, u64)>, DbErr> {
+        let versions = entities::version::Entity::find()
+            .filter(entities::version::Column::Proxy.eq(proxy.to_string().to_lowercase()))
+            .order_by_asc(entities::version::Column::MinBlock)
+            .all(&self.db)
+            .await?;
+        Ok(versions
+            .into_iter()
+            .map(|v| (v.implementation.cvt(), v.min_block as u64))
+            .collect())
+    }
+}
+
+/// FileIterator feeds the invocations of the proxies in a `ProxyData` JSONL file, bypassing the database.
+pub struct FileIterator {
+    source: JsonlSource<ProxyData>,
+    invocations: VecDeque<Item>,
+    versions: HashMap<Address, Vec<(Address, u64)>>,
+}
+
+impl FileIterator {
+    pub fn open(path: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            source: JsonlSource::open(path)?,
+            invocations: VecDeque::new(),
+            versions: HashMap::new(),
+        })
+    }
+
+    pub fn next(&mut self) -> Option<Item> {
+        while self.invocations.is_empty() {
+            let data = match self.source.next()? {
+                Ok(data) => data,
+                Err(e) => {
+                    error!(error = ?e, "Skip malformed proxy data");
+                    continue;
                 }
             };
-            let proxy_cursor = select
-                .cursor_by(entities::proxy::Column::InvocationCount)
-                .order_by_asc(entities::proxy::Column::InvocationCount);
-            debug!(
-                offset = self.proxy_offset,
-                window_size = self.window_size,
-                "Querying proxies"
-            );
-            let proxies = proxy_cursor
-                .offset(self.proxy_offset)
-                .limit(self.window_size)
-                .all(&self.db)
-                .await
-                .unwrap();
-            self.proxies.extend(proxies.clone());
-            if proxies.len() < self.window_size as usize {
-                if self.proxy_offset == 0 {
-                    // no more proxies
-                    return Ok(());
-                }
-                self.proxy_offset = 0;
-            } else {
-                self.proxy_offset += self.window_size;
-                return Ok(());
-            }
+            let proxy: Address = data.proxy.cvt();
+            let impls = data.ordered_impls();
+            let total = impls.len();
+            self.invocations
+                .extend(impls.into_iter().enumerate().map(|(index, invocation)| {
+                    (
+                        proxy,
+                        invocation.implementation.cvt(),
+                        invocation.tx.cvt(),
+                        index,
+                        total,
+                        invocation.block,
+                    )
+                }));
+            let versions = data
+                .versions()
+                .into_iter()
+                .map(|(implementation, block)| (implementation.cvt(), block))
+                .collect();
+            self.versions.insert(proxy, versions);
         }
+        self.invocations.pop_front()
     }
 
-    #[allow(unused)]
-    pub fn next_async0(&mut self) -> BoxFuture<Option<(Address, Address, TxHash, usize, usize)>> {
-        async move {
-            if self.invocations.len() > 0 {
-                let (index, total, invocation) = self.invocations.remove(0);
-                let proxy = invocation.proxy;
-                let impl_ = invocation.implementation;
-                let tx = invocation.tx;
-                debug!(
-                    proxy = proxy,
-                    impl_ = impl_,
-                    tx = tx,
-                    index,
-                    total,
-                    "Next invocation"
-                );
-                Some((proxy.cvt(), impl_.cvt(), tx.cvt(), index, total))
-            } else if self.proxies.len() > 0 {
-                // try to fetch more invocation
-                debug!("Querying invocations");
-                // loop {
-                //     let proxy = self.proxies.get(0).unwrap();
-                //     let replay = entities::collision::Entity::find_by_id(proxy.address.clone())
-                //         .one(&self.db)
-                //         .await
-                //         .unwrap();
-                //     if replay.is_some() {
-                //         self.proxies.remove(0);
-                //         continue;
-                //     } else {
-                //         break;
-                //     }
-                // }
-                let proxy = self.proxies.get(0).unwrap();
+    /// The implementation versions of a proxy read from the file, ordered by block.
+    pub fn versions(&self, proxy: Address) -> Vec<(Address, u64)> {
+        self.versions.get(&proxy).cloned().unwrap_or_default()
+    }
+}
 
-                let invocation_cursor = entities::invocation::Entity::find()
-                    .filter(entities::invocation::Column::Proxy.eq(proxy.address.clone()))
-                    .cursor_by((
-                        entities::invocation::Column::Block,
-                        entities::invocation::Column::Tx,
-                    ))
-                    .order_by_asc(entities::invocation::Column::Block)
-                    .order_by_asc(entities::invocation::Column::Tx);
+/// The invocations to replay, either from the database or from a file.
+pub enum Feed {
+    Db(DBIterator),
+    File(FileIterator),
+}
 
-                let invocations = invocation_cursor
-                    .offset(self.invocation_offset)
-                    .limit(self.window_size)
-                    .all(&self.db)
-                    .await
-                    .unwrap();
-                self.invocations = invocations
-                    .into_iter()
-                    .enumerate()
-                    .map(|(i, inv)| {
-                        (
-                            i + self.invocation_offset as usize,
-                            proxy.invocation_count as usize,
-                            inv,
-                        )
-                    })
-                    .collect();
-                self.invocation_offset += self.window_size;
-                if self.invocations.len() == 0 {
-                    // no more invocation for this proxy
-                    // move to the next one
-                    self.proxies.remove(0);
-                    self.invocation_offset = 0;
-                }
-                self.next_async().await
-            } else {
-                // try to fetch more proxies
-                let select = match self.proxy_addresses.clone() {
-                    Some(proxy_address) => {
-                        let select = entities::proxy::Entity::find().filter(
-                            Condition::all()
-                                .add(entities::proxy::Column::Address.is_in(proxy_address))
-                                .add(
-                                    Expr::exists(
-                                        Query::select()
-                                            .from(entities::collision::Entity)
-                                            .and_where(
-                                                Expr::col(entities::collision::Column::Proxy)
-                                                    .equals(entities::proxy::Column::Address),
-                                            )
-                                            .take(),
-                                    )
-                                    .not(),
-                                ),
-                        );
-                        select
-                    }
-                    None => {
-                        let select = entities::proxy::Entity::find().filter(
-                            Condition::all().add(
-                                Expr::exists(
-                                    Query::select()
-                                        .from(entities::collision::Entity)
-                                        .and_where(
-                                            Expr::col(entities::collision::Column::Proxy)
-                                                .equals(entities::proxy::Column::Address),
-                                        )
-                                        .take(),
-                                )
-                                .not(),
-                            ),
-                        );
-                        select
-                    }
-                };
-                let proxy_cursor = select
-                    .cursor_by(entities::proxy::Column::InvocationCount)
-                    .order_by_asc(entities::proxy::Column::InvocationCount);
-                debug!(
-                    offset = self.proxy_offset,
-                    window_size = self.window_size,
-                    "Querying proxies"
-                );
-                self.proxies = proxy_cursor
-                    .offset(self.proxy_offset)
-                    .limit(self.window_size)
-                    .all(&self.db)
-                    .await
-                    .unwrap();
-                debug!(count = self.proxies.len(), "Got proxies");
-                self.proxy_offset += self.window_size;
-                if self.proxies.is_empty() {
-                    // no more proxies, return None
-                    return None;
-                }
-                self.next_async().await
-            }
+impl Feed {
+    pub async fn next_async(&mut self) -> Option<Item> {
+        match self {
+            Feed::Db(iterator) => iterator.next_async().await,
+            Feed::File(iterator) => iterator.next(),
+        }
+    }
+
+    pub async fn versions(&self, proxy: Address) -> Result<Vec<(Address, u64)>, DbErr> {
+        match self {
+            Feed::Db(iterator) => iterator.versions(proxy).await,
+            Feed::File(iterator) => Ok(iterator.versions(proxy)),
         }
-        .boxed()
     }
 }
 
@@ -348,44 +297,138 @@ mod tests {
         conversion::ConvertTo,
         engine::types::{Address, TxHash},
     };
-    use libsofl_utils::{config::Config, log::config::LogConfig};
-    use proxyex_detector::config::ProxyExDetectorConfig;
+    use proxyex_detector::{entities, run::RunTracker};
+    use sea_orm::{
+        ActiveValue, ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait, Schema,
+    };
+
+    /// A database in memory with one invocation of each proxy (address, clone representative).
+    async fn setup(proxies: &[(&str, Option<bool>)]) -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(DbBackend::Sqlite);
+        for sql in [
+            schema.create_table_from_entity(entities::proxy::Entity),
+            schema.create_table_from_entity(entities::invocation::Entity),
+            schema.create_table_from_entity(entities::clone::Entity),
+            schema.create_table_from_entity(entities::run::Entity),
+        ] {
+            db.execute(db.get_database_backend().build(&sql))
+                .await
+                .unwrap();
+        }
+        for (i, (proxy, representative)) in proxies.iter().enumerate() {
+            let model = entities::proxy::ActiveModel {
+                address: ActiveValue::Set(proxy.to_string()),
+                invocation_count: ActiveValue::Set(1),
+            };
+            entities::proxy::Entity::insert(model)
+                .exec(&db)
+                .await
+                .unwrap();
+            let invocation = entities::invocation::ActiveModel {
+                tx: ActiveValue::Set(format!("0x{:064x}", i)),
+                implementation: ActiveValue::Set(format!("0x{:040x}", 0xaa)),
+                proxy: ActiveValue::Set(proxy.to_string()),
+                block: ActiveValue::Set(i as i64),
+                ..Default::default()
+            };
+            entities::invocation::Entity::insert(invocation)
+                .exec(&db)
+                .await
+                .unwrap();
+            if let Some(representative) = representative {
+                let clone = entities::clone::ActiveModel {
+                    proxy: ActiveValue::Set(proxy.to_string()),
+                    kind: ActiveValue::Set("eip1167".to_string()),
+                    implementation: ActiveValue::Set(format!("0x{:040x}", 0xaa)),
+                    representative: ActiveValue::Set(*representative),
+                };
+                entities::clone::Entity::insert(clone)
+                    .exec(&db)
+                    .await
+                    .unwrap();
+            }
+        }
+        db
+    }
+
+    async fn replayed_proxies(mut iterator: super::DBIterator) -> Vec<String> {
+        let mut proxies = Vec::new();
+        while let Some((proxy, ..)) = iterator.next_async().await {
+            proxies.push(ConvertTo::<String>::cvt(&proxy));
+        }
+        proxies.sort();
+        proxies
+    }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_generator_by_proxies() {
-        let cfg = ProxyExDetectorConfig::must_load();
-        let db = cfg.db().await.unwrap();
-        let mut iterator = super::build_from_proxies(
+        let proxies = (1..=3).map(|i| format!("0x{:040x}", i)).collect::<Vec<_>>();
+        let db = setup(
+            &proxies
+                .iter()
+                .map(|p| (p.as_str(), None))
+                .collect::<Vec<_>>(),
+        )
+        .await;
+        let iterator = super::build_from_proxies(
             db,
-            "0xfdf30a376b31ef67e81e4bfdce6c89088cd1658f,0x04bbd6abb0379576aa5fed534ec4a95e6114184d",
+            &format!("{},{}", proxies[0], proxies[2]),
+            RunTracker::new("replay"),
         )
         .await;
-        let mut proxy_data: Vec<(Address, Address, TxHash, usize, usize)> = Vec::new();
-        proxy_data.push(iterator.next_async().await.unwrap());
-        proxy_data.push(iterator.next_async().await.unwrap());
-        assert_eq!(proxy_data.len(), 2);
-        assert_eq!(
-            ConvertTo::<String>::cvt(&proxy_data[0].0),
-            "0xfdf30a376b31ef67e81e4bfdce6c89088cd1658f"
-        );
         assert_eq!(
-            ConvertTo::<String>::cvt(&proxy_data[1].0),
-            "0x04bbd6abb0379576aa5fed534ec4a95e6114184d"
+            replayed_proxies(iterator).await,
+            vec![proxies[0].clone(), proxies[2].clone()]
         );
     }
 
     #[tokio::test(flavor = "multi_thread")]
     async fn test_generator_all() {
-        let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
-        log_cfg.console_level = "debug".to_string();
-        log_cfg.init();
-
-        let cfg = ProxyExDetectorConfig::must_load();
-        let db = cfg.db().await.unwrap();
-        let mut iterator = super::build_from_all(db).await;
-        for i in 0..69 {
-            let d = iterator.next_async().await.unwrap();
-            println!("{}: {:?}", i, d);
+        let proxies = (1..=3).map(|i| format!("0x{:040x}", i)).collect::<Vec<_>>();
+        let db = setup(
+            &proxies
+                .iter()
+                .map(|p| (p.as_str(), None))
+                .collect::<Vec<_>>(),
+        )
+        .await;
+        let mut iterator = super::build_from_all(db, false, RunTracker::new("replay")).await;
+        let mut items: Vec<(Address, Address, TxHash, usize, usize, u64)> = Vec::new();
+        while let Some(item) = iterator.next_async().await {
+            items.push(item);
+        }
+        // each proxy is loaded once, with its only invocation
+        assert_eq!(items.len(), 3);
+        for (_, _, _, index, total, _) in items {
+            assert_eq!((index, total), (0, 1));
         }
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_generator_representatives() {
+        let representative = format!("0x{:040x}", 1);
+        let redundant = format!("0x{:040x}", 2);
+        let other = format!("0x{:040x}", 3);
+        let proxies = [
+            (representative.as_str(), Some(true)),
+            (redundant.as_str(), Some(false)),
+            (other.as_str(), None),
+        ];
+
+        // the clones other than the representative of their implementation are never replayed
+        let db = setup(&proxies).await;
+        let iterator = super::build_from_all(db, true, RunTracker::new("replay")).await;
+        assert_eq!(
+            replayed_proxies(iterator).await,
+            vec![representative.clone(), other.clone()]
+        );
+
+        let db = setup(&proxies).await;
+        let iterator = super::build_from_all(db, false, RunTracker::new("replay")).await;
+        assert_eq!(
+            replayed_proxies(iterator).await,
+            vec![representative, redundant, other]
+        );
+    }
 }
@@ -1,16 +1,28 @@
 use std::sync::Arc;
 
 use clap::{command, Parser, ValueEnum};
-use generator::DBIterator;
-use libsofl_reth::config::RethConfig;
+use crossbeam::channel::Receiver;
+use generator::{Feed, FileIterator};
+use libsofl_core::{
+    blockchain::transaction::Tx,
+    engine::types::{Address, DatabaseRef},
+};
 use libsofl_utils::{
     config::Config,
     log::{error, info},
 };
 use libsofl_utils::{log::debug, sync::runtime::AsyncRuntime};
-use proxyex_detector::original_replay::OriginalReplayScheduler;
-use proxyex_detector::{config::ProxyExDetectorConfig, entities};
-use sea_orm::{sea_query::OnConflict, ActiveValue, DbErr, EntityTrait};
+use proxyex_detector::original_replay::{
+    OriginalReplayScheduler, ReplayError, SlotCollisionResult,
+};
+use proxyex_detector::replaced_replay::{
+    implementation_code_at, AltVersion, RegressionResult, ReplacedReplayScheduler,
+};
+use proxyex_detector::{
+    budget::BudgetConfig, config::ProxyExDetectorConfig, entities, error::DetectorError,
+    io::OutputFiles, provider::DetectorProvider, run::RunTracker, shutdown::Shutdown,
+};
+use sea_orm::{sea_query::OnConflict, DbErr, EntityTrait, TryIntoModel};
 use tracing_indicatif::IndicatifLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 
@@ -30,10 +42,42 @@ pub struct Cli {
     #[arg(short, long, default_value = "1")]
     jobs: usize,
 
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Retry the proxies that failed in previous runs with these kinds of errors
+    #[arg(long, value_delimiter = ',', value_parser = DetectorError::KINDS)]
+    retry_kind: Vec<String>,
+
+    /// Replay the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`) instead of the database
+    #[arg(long, requires = "output", conflicts_with = "proxy_data")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+
+    /// Replay only one representative clone per implementation (see the `clone` binary)
+    #[arg(long, conflicts_with_all = ["input", "proxy_data"])]
+    representatives: bool,
+
     /// One single proxy data entry or a list of proxy addresses
     proxy_data: Option<String>,
 }
 
+/// Where the results are saved.
+enum Store {
+    /// the `collision` or `regression` table, with the progress recorded in the `run` table
+    Db(RunTracker),
+    /// result files, without the database
+    File(OutputFiles),
+}
+
 #[derive(ValueEnum, Debug, Clone, Copy)]
 enum Mode {
     Original,
@@ -43,7 +87,6 @@ enum Mode {
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), DbErr> {
     let args = Cli::parse();
-    info!("Replay started: {:?}", args);
 
     // prepare logger
     let indicatif_layer = IndicatifLayer::new();
@@ -59,35 +102,89 @@ async fn main() -> Result<(), DbErr> {
         )
         .with(indicatif_layer)
         .init();
+    info!("Replay started: {:?}", args);
 
-    let cfg = ProxyExDetectorConfig::must_load();
+    let shutdown = Shutdown::install();
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let feed = Feed::File(FileIterator::open(&input).expect("Failed to open input"));
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        replay(args, Store::File(files), shutdown.clone(), feed).await;
+        info!(interrupted = shutdown.requested(), "Session finished");
+        return Ok(());
+    }
 
+    let cfg = ProxyExDetectorConfig::must_load();
+    let tracker = match args.mode {
+        Mode::Original => RunTracker::new("replay"),
+        Mode::Replaced => RunTracker::new("replay-replaced"),
+    };
+    let db = cfg.db().await?;
+    tracker.prepare(&db, args.retry_failed).await?;
+    tracker
+        .retry_kinds(&db, entities::error::Column::Proxy, &args.retry_kind)
+        .await?;
     let generator = match args.proxy_data.clone() {
         Some(proxy_data) => {
             let db = cfg.db().await?;
-            build_from_proxies(db, proxy_data.as_str()).await
+            build_from_proxies(db, proxy_data.as_str(), tracker.clone()).await
         }
         None => {
             let db = cfg.db().await?;
-            build_from_all(db).await
+            build_from_all(db, args.representatives, tracker.clone()).await
         }
     };
-    match args.mode {
-        Mode::Original => original_replay(args, generator).await,
-        Mode::Replaced => todo!(),
-    };
+    replay(
+        args,
+        Store::Db(tracker.clone()),
+        shutdown.clone(),
+        Feed::Db(generator),
+    )
+    .await;
+    shutdown.report(&tracker, &db).await?;
     Ok(())
 }
 
-async fn original_replay(args: Cli, mut proxy_data: DBIterator) {
-    let provider = RethConfig::must_load().bc_provider().unwrap();
-    let provider = Arc::new(provider);
+async fn replay(args: Cli, store: Store, shutdown: Shutdown, feed: Feed) {
+    match args.mode {
+        Mode::Original => {
+            proxyex_detector::with_provider!(
+                |p| original_replay(p, args, store, shutdown, feed).await
+            )
+        }
+        Mode::Replaced => {
+            proxyex_detector::with_provider!(
+                |p| replaced_replay(p, args, store, shutdown, feed).await
+            )
+        }
+    }
+}
+
+async fn original_replay<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: Arc<P>,
+    args: Cli,
+    store: Store,
+    shutdown: Shutdown,
+    mut proxy_data: Feed,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let (proxy_result_tx, proxy_result_rx) = crossbeam::channel::bounded(args.jobs);
-    let scheduler = OriginalReplayScheduler::new(provider, args.jobs, proxy_result_tx);
+    let budget = BudgetConfig::load_or(Default::default()).expect("load budget config failed");
+    let scheduler =
+        OriginalReplayScheduler::new(provider, args.jobs, proxy_result_tx).with_budget(budget);
 
     // collector thread received the aggregated proxy analysis result from the scheduler
     let collector_thread = std::thread::spawn(move || {
         info!("Result collector thread started");
+        let tracker = match store {
+            Store::Db(tracker) => tracker,
+            Store::File(files) => return collect_collisions(proxy_result_rx, files),
+        };
         let rt = AsyncRuntime::new();
         let task = async {
             let cfg = proxyex_detector::config::ProxyExDetectorConfig::load_or(Default::default())
@@ -117,6 +214,7 @@ async fn original_replay(args: Cli, mut proxy_data: DBIterator) {
                             finished = finished,
                             "Replay finished"
                         );
+                        let proxy = r.proxy.to_string().to_lowercase();
                         let result: entities::collision::ActiveModel = r.into();
                         let r = entities::collision::Entity::insert(result)
                             .on_conflict(
@@ -131,18 +229,23 @@ async fn original_replay(args: Cli, mut proxy_data: DBIterator) {
                             Err(e) => {
                                 if e != DbErr::RecordNotInserted {
                                     error!(error = ?e, "Failed to save replay result");
+                                    continue;
                                 }
                             }
                         }
+                        tracker.done(&db, proxy).await.expect("Failed to save run");
                     }
                     Err(error) => {
                         error!(error = ?error, "Replay error");
-                        let error = entities::error::ActiveModel {
-                            proxy: ActiveValue::Set(error.proxy.to_string()),
-                            msg: ActiveValue::Set(error.msg),
-                            ..Default::default()
-                        };
-                        entities::error::Entity::insert(error)
+                        tracker
+                            .fail(
+                                &db,
+                                error.proxy.to_string().to_lowercase(),
+                                error.error.to_string(),
+                            )
+                            .await
+                            .expect("Failed to save run");
+                        entities::error::Entity::insert(error.to_model(&tracker.detector))
                             .exec(&db)
                             .await
                             .expect("Failed to save error");
@@ -153,7 +256,8 @@ async fn original_replay(args: Cli, mut proxy_data: DBIterator) {
         rt.block_on(task)
     });
 
-    loop {
+    // the txs already fed are still replayed, while the partial result of the last proxy is abandoned
+    while !shutdown.requested() {
         if let Some(data) = proxy_data.next_async().await {
             debug!("Feed proxy invocation: {:?}", data);
             scheduler.feed_proxy_invocation_in_order(data.0, data.1, data.2, data.3, data.4);
@@ -166,3 +270,212 @@ async fn original_replay(args: Cli, mut proxy_data: DBIterator) {
     scheduler.close();
     collector_thread.join().unwrap();
 }
+
+async fn replaced_replay<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: Arc<P>,
+    args: Cli,
+    store: Store,
+    shutdown: Shutdown,
+    mut proxy_data: Feed,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let (proxy_result_tx, proxy_result_rx) = crossbeam::channel::bounded(args.jobs);
+    let budget = BudgetConfig::load_or(Default::default()).expect("load budget config failed");
+    let scheduler = ReplacedReplayScheduler::new(provider.clone(), args.jobs, proxy_result_tx)
+        .with_budget(budget);
+
+    // collector thread received the aggregated proxy regression result from the scheduler
+    let collector_thread = std::thread::spawn(move || {
+        info!("Result collector thread started");
+        let tracker = match store {
+            Store::Db(tracker) => tracker,
+            Store::File(files) => return collect_regressions(proxy_result_rx, files),
+        };
+        let rt = AsyncRuntime::new();
+        let task = async {
+            let cfg = proxyex_detector::config::ProxyExDetectorConfig::load_or(Default::default())
+                .expect("load config failed");
+            let db = match cfg.db().await {
+                Ok(db) => db,
+                Err(e) => {
+                    error!(error = ?e, "Failed to connect to database");
+                    return;
+                }
+            };
+            let mut finished = 0;
+            loop {
+                debug!("Waiting for result");
+                let result = match proxy_result_rx.recv() {
+                    Ok(r) => r,
+                    Err(_) => {
+                        info!("Result channel closed, result collector thread exit");
+                        break;
+                    }
+                };
+                finished += 1;
+                info!(
+                    proxy = result.proxy.to_string(),
+                    issues = result.issues.len(),
+                    errors = result.errors.len(),
+                    finished = finished,
+                    "Regression finished"
+                );
+                let proxy = result.proxy.to_string().to_lowercase();
+                let failure = result.errors.first().map(|e| e.error.to_string());
+                for error in result.errors {
+                    error!(error = ?error, "Regression error");
+                    entities::error::Entity::insert(error.to_model(&tracker.detector))
+                        .exec(&db)
+                        .await
+                        .expect("Failed to save error");
+                }
+                if !result.issues.is_empty() {
+                    let r = entities::regression::Entity::insert_many(
+                        result
+                            .issues
+                            .into_iter()
+                            .map(|i| i.into())
+                            .collect::<Vec<entities::regression::ActiveModel>>(),
+                    )
+                    .on_conflict(OnConflict::new().do_nothing().to_owned())
+                    .exec(&db)
+                    .await;
+                    match r {
+                        Ok(_) => {}
+                        Err(e) => {
+                            if e != DbErr::RecordNotInserted {
+                                error!(error = ?e, "Failed to save regression issues");
+                            }
+                        }
+                    }
+                }
+                // a proxy with any failed tx is retried as a whole, the issues found are kept
+                match failure {
+                    Some(msg) => tracker.fail(&db, proxy, msg).await,
+                    None => tracker.done(&db, proxy).await,
+                }
+                .expect("Failed to save run");
+            }
+        };
+        rt.block_on(task)
+    });
+
+    let mut versions: Option<(Address, Arc<Vec<AltVersion>>)> = None;
+    // the txs already fed are still replayed, while the partial result of the last proxy is abandoned
+    while !shutdown.requested() {
+        if let Some(data) = proxy_data.next_async().await {
+            debug!("Feed proxy invocation: {:?}", data);
+            let (proxy, implementation, tx, index, total, block) = data;
+            let proxy_versions = match &versions {
+                Some((p, vs)) if *p == proxy => vs.clone(),
+                _ => {
+                    let vs = match proxy_data.versions(proxy).await {
+                        Ok(vs) => load_versions(provider.as_ref(), proxy, vs),
+                        Err(e) => {
+                            error!(error = ?e, "Failed to load versions");
+                            Vec::new()
+                        }
+                    };
+                    let vs = Arc::new(vs);
+                    versions = Some((proxy, vs.clone()));
+                    vs
+                }
+            };
+            scheduler.feed_proxy_invocation_in_order(
+                proxy,
+                implementation,
+                proxy_versions,
+                tx,
+                block,
+                index,
+                total,
+            );
+        } else {
+            break;
+        }
+    }
+
+    info!("Waiting for scheduler to finish");
+    scheduler.close();
+    collector_thread.join().unwrap();
+}
+
+/// Load the code of each implementation version of a proxy.
+fn load_versions<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    provider: &P,
+    proxy: Address,
+    versions: Vec<(Address, u64)>,
+) -> Vec<AltVersion>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut alts = Vec::new();
+    for (implementation, min_block) in versions {
+        match implementation_code_at(provider, implementation, min_block) {
+            Ok(code) => alts.push((implementation, min_block, code)),
+            Err(e) => {
+                error!(
+                    error = ?e,
+                    proxy = proxy.to_string().to_lowercase(),
+                    implementation = implementation.to_string().to_lowercase(),
+                    "Failed to load implementation code"
+                );
+            }
+        }
+    }
+    alts
+}
+
+/// Write the collision results as the rows of the `collision` table.
+fn collect_collisions(
+    rx: Receiver<Result<SlotCollisionResult, ReplayError>>,
+    mut files: OutputFiles,
+) {
+    let mut finished = 0;
+    for result in rx {
+        match result {
+            Ok(r) => {
+                finished += 1;
+                info!(
+                    proxy = r.proxy.to_string(),
+                    finished = finished,
+                    "Replay finished"
+                );
+                let row: entities::collision::ActiveModel = r.into();
+                files.save_result(&row.try_into_model().unwrap());
+            }
+            Err(error) => {
+                error!(error = ?error, "Replay error");
+                files.save_error(&error);
+            }
+        }
+    }
+    info!("Result channel closed, result collector thread exit");
+    files.finish();
+}
+
+/// Write the regression issues as the rows of the `regression` table.
+fn collect_regressions(rx: Receiver<RegressionResult>, mut files: OutputFiles) {
+    let mut finished = 0;
+    for result in rx {
+        finished += 1;
+        info!(
+            proxy = result.proxy.to_string(),
+            issues = result.issues.len(),
+            errors = result.errors.len(),
+            finished = finished,
+            "Regression finished"
+        );
+        for error in &result.errors {
+            error!(error = ?error, "Regression error");
+            files.save_error(error);
+        }
+        for issue in result.issues {
+            let row: entities::regression::ActiveModel = issue.into();
+            files.save_result(&row.try_into_model().unwrap());
+        }
+    }
+    info!("Result channel closed, result collector thread exit");
+    files.finish();
+}
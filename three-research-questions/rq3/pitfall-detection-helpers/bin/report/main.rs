@@ -0,0 +1,71 @@
+use std::io::Write;
+
+use clap::{command, Parser};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, info},
+};
+use proxyex_detector::{
+    config::ProxyExDetectorConfig,
+    report::{load_findings, sarif::SarifLog, FindingKind, Report},
+};
+use sea_orm::DbErr;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    /// The finding tables to report, all of them by default
+    #[arg(short, long, value_delimiter = ',', value_parser = FindingKind::TABLES)]
+    tables: Vec<String>,
+
+    #[arg(short, long, default_value = "json", value_parser = ["json", "sarif"])]
+    format: String,
+
+    /// Write the report to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+    let db = ProxyExDetectorConfig::must_load().db().await?;
+
+    let kinds = if args.tables.is_empty() {
+        FindingKind::ALL.to_vec()
+    } else {
+        args.tables
+            .iter()
+            .filter_map(|t| FindingKind::from_table(t))
+            .collect()
+    };
+    let mut findings = Vec::new();
+    for kind in kinds {
+        let f = load_findings(&db, kind).await?;
+        info!(table = kind.table(), findings = f.len(), "Findings loaded");
+        findings.extend(f);
+    }
+    let report = Report::new(findings);
+
+    let content = match args.format.as_str() {
+        "sarif" => serde_json::to_string_pretty(&SarifLog::from(&report)),
+        _ => serde_json::to_string_pretty(&report),
+    }
+    .expect("serialize report failed");
+    match args.output {
+        Some(path) => std::fs::write(&path, content).expect("write report failed"),
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(content.as_bytes()).unwrap();
+            stdout.write_all(b"\n").unwrap();
+        }
+    }
+    info!(findings = report.findings.len(), "Report written");
+    Ok(())
+}
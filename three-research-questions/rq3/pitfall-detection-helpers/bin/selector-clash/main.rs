@@ -0,0 +1,362 @@
+use std::{sync::Arc, thread};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::types::{Address, DatabaseRef},
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig},
+    config::ProxyExDetectorConfig,
+    dataset::ProxyData,
+    entities,
+    error::DetectorError,
+    io::{check_into_files, ErrorRecord, JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    run::RunTracker,
+    selector::{detect_clashes, selector_to_string, Handler},
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    sea_query::{Expr, OnConflict, Query},
+    ColumnTrait, Condition, DbErr, EntityTrait, QueryFilter, QuerySelect,
+};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`) instead of the database
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    if let Some(input) = args.input.clone() {
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| check_file(args, proxies, files, p));
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("selector-clash");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) = channel::bounded::<(
+        Address,
+        Vec<entities::selector_clash::ActiveModel>,
+        Option<DetectorError>,
+    )>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        loop {
+            let (proxy, clashes, err) = match result_rx.recv() {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            let task = async {
+                if clashes.is_empty() {
+                    return Ok(());
+                }
+                entities::selector_clash::Entity::insert_many(clashes)
+                    .on_conflict(
+                        OnConflict::columns([
+                            entities::selector_clash::Column::Proxy,
+                            entities::selector_clash::Column::Implementation,
+                        ])
+                        .do_nothing()
+                        .to_owned(),
+                    )
+                    .exec(&db)
+                    .await
+                    .map(|_| ())
+            };
+            match rt.block_on(task) {
+                Ok(_) => {}
+                Err(e) => {
+                    if e != DbErr::RecordNotInserted {
+                        panic!("{:?}", e);
+                    }
+                }
+            };
+            // a proxy with any version failed to check is retried as a whole
+            match err {
+                Some(e) => {
+                    let error = e.to_model(&cloned_tracker.detector, proxy, None);
+                    rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                        .unwrap();
+                    let proxy = proxy.to_string().to_lowercase();
+                    rt.block_on(cloned_tracker.fail(&db, proxy, e.to_string()))
+                }
+                None => {
+                    let proxy = proxy.to_string().to_lowercase();
+                    rt.block_on(cloned_tracker.done(&db, proxy))
+                }
+            }
+            .unwrap();
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            loop {
+                let proxy = match proxy_rx.recv() {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                info!(
+                    proxy = proxy.address.to_string().to_lowercase(),
+                    "Checking proxy"
+                );
+                let task = async {
+                    entities::version::Entity::find()
+                        .filter(
+                            entities::version::Column::Proxy
+                                .eq(proxy.address.to_string().to_lowercase()),
+                        )
+                        .all(&db)
+                        .await
+                };
+                let versions = rt.block_on(task).unwrap();
+                let mut results: Vec<entities::selector_clash::ActiveModel> = Vec::new();
+                let mut err = None;
+                for version in versions {
+                    let r = check_version(
+                        p.clone(),
+                        proxy.address.cvt(),
+                        version.implementation.cvt(),
+                        version.min_block + 1,
+                        budget,
+                    );
+                    match r {
+                        Ok(row) => results.push(row.into()),
+                        Err(e) => {
+                            error!(
+                                proxy = proxy.address.to_string().to_lowercase(),
+                                implementation = version.implementation,
+                                err = e.to_string(),
+                                "Failed to check selector clashes"
+                            );
+                            err = Some(e);
+                        }
+                    }
+                }
+                result_tx.send((proxy.address.cvt(), results, err)).unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies =
+            entities::proxy::Entity::find()
+                .filter(
+                    Condition::all()
+                        .add(Expr::exists(
+                            Query::select()
+                                .from(entities::version::Entity)
+                                .and_where(
+                                    Expr::col((
+                                        entities::version::Entity,
+                                        entities::version::Column::Proxy,
+                                    ))
+                                    .equals((
+                                        entities::proxy::Entity,
+                                        entities::proxy::Column::Address,
+                                    )),
+                                )
+                                .take(),
+                        ))
+                        .add(tracker.unfinished((
+                            entities::proxy::Entity,
+                            entities::proxy::Column::Address,
+                        ))),
+                )
+                .limit(1000)
+                .all(&db)
+                .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// Check the versions of the proxies in a ProxyData file, writing the rows of the `selector_clash` table to the files.
+fn check_file<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    check_into_files(proxies, files, args.jobs, &shutdown, |data| {
+        let proxy: Address = data.proxy.cvt();
+        info!(proxy = data.proxy.as_str(), "Checking proxy");
+        data.versions()
+            .into_iter()
+            .map(|(implementation, min_block)| {
+                check_version(
+                    p.clone(),
+                    proxy,
+                    implementation.cvt(),
+                    min_block as i64 + 1,
+                    budget,
+                )
+                .map_err(|e| {
+                    error!(
+                        proxy = data.proxy.as_str(),
+                        implementation = implementation.as_str(),
+                        err = e.to_string(),
+                        "Failed to check selector clashes"
+                    );
+                    ErrorRecord::new(proxy, None, e)
+                })
+            })
+            .collect()
+    });
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// Check the selector clashes between the proxy and an implementation at the block it is used,
+/// returning the row of the `selector_clash` table.
+fn check_version<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: Address,
+    implementation: Address,
+    blk: i64,
+    budget: Budget,
+) -> Result<entities::selector_clash::Model, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let start_at = std::time::Instant::now();
+    let mut state = p
+        .bc_state_at(TxPosition::new(blk as u64, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk as u64);
+    let (proxy_selectors, implementation_selectors, clashes) =
+        detect_clashes(&caller, &mut state, proxy, implementation, budget)?;
+    let proxy_selectors = proxy_selectors
+        .iter()
+        .map(selector_to_string)
+        .collect::<Vec<_>>();
+    let implementation_selectors = implementation_selectors
+        .iter()
+        .map(selector_to_string)
+        .collect::<Vec<_>>();
+    Ok(entities::selector_clash::Model {
+        proxy: proxy.to_string().to_lowercase(),
+        implementation: implementation.to_string().to_lowercase(),
+        problematic: clashes.iter().any(|c| c.handler == Handler::Proxy),
+        proxy_selectors: serde_json::to_value(proxy_selectors).unwrap(),
+        implementation_selectors: serde_json::to_value(implementation_selectors).unwrap(),
+        clashes: serde_json::to_value(clashes).unwrap(),
+        total_time: start_at.elapsed().as_nanos() as i64,
+    })
+}
@@ -0,0 +1,380 @@
+use std::{sync::Arc, thread};
+
+use clap::{command, Parser};
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        transition::TransitionSpecBuilder,
+        types::{Address, BlockHashOrNumber, Bytes, DatabaseRef},
+    },
+};
+use libsofl_utils::{
+    config::Config,
+    log::{config::LogConfig, error, info},
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    clone::not_redundant_clone,
+    config::ProxyExDetectorConfig,
+    dataset, entities,
+    error::DetectorError,
+    inspectors::slot_write::SlotWriteInspector,
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+    slot_history::{cross_check, SlotWrite},
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, TransactionTrait,
+};
+
+/// Reconstruct the writes to the standard slots (implementation, admin, beacon, initializer) of each proxy:
+/// the txs of every block from its creation are replayed to find the writes, which are saved in the `slot_history` table.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[arg(short = 'l', long, default_value = "info")]
+    log_level: String,
+
+    #[arg(short, long, default_value = "1")]
+    jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check only one representative clone per implementation (see the `clone` binary)
+    #[arg(long)]
+    representatives: bool,
+
+    /// The last block of the history, the end of the dataset (the last invocation of any proxy) by default
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Cross-check the writes to the implementation slots with the `version` table,
+    /// saving the discrepancies in the `version_discrepancy` table
+    #[arg(long)]
+    check_versions: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<(), DbErr> {
+    let args = Cli::parse();
+    let cfg = ProxyExDetectorConfig::must_load();
+    let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
+    log_cfg.console_level = args.log_level.clone();
+    log_cfg.init();
+
+    proxyex_detector::with_provider!(|p| check_all(args, cfg, p).await)
+}
+
+async fn check_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("slot-history");
+    let shutdown = Shutdown::install();
+    let (proxy_tx, proxy_rx) = channel::bounded::<entities::proxy::Model>(args.jobs);
+    let (result_tx, result_rx) =
+        channel::bounded::<(String, Result<Vec<SlotWrite>, DetectorError>)>(args.jobs);
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let check_versions = args.check_versions;
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        let mut discrepancies = 0;
+        for (proxy, r) in result_rx {
+            let writes = match r {
+                Ok(writes) => writes,
+                Err(e) => {
+                    let error = e.to_model(&cloned_tracker.detector, proxy.cvt(), None);
+                    rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                        .unwrap();
+                    rt.block_on(cloned_tracker.fail(&db, proxy, e.to_string()))
+                        .unwrap();
+                    continue;
+                }
+            };
+            if check_versions {
+                discrepancies += rt.block_on(check_proxy(&db, &proxy, &writes)).unwrap();
+            }
+            rt.block_on(save_history(&db, &proxy, writes)).unwrap();
+            rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+        }
+        if check_versions {
+            info!(discrepancies = discrepancies, "Versions cross-checked");
+        }
+    });
+
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let db = cfg.db().await.unwrap();
+    // without `--to-block`, the history of every proxy goes to the end of the dataset
+    let to_block = match args.to_block {
+        Some(b) => Some(b),
+        None => dataset::last_invocation_block(&db).await?,
+    };
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let wg = WaitGroup::new();
+    for _ in 0..args.jobs {
+        let proxy_rx = proxy_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let wg = wg.clone();
+        let cfg = cfg.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            for proxy in proxy_rx {
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                info!(
+                    proxy = proxy.address.as_str(),
+                    "Reconstructing slot history"
+                );
+                let r = rt
+                    .block_on(first_block(&db, &proxy.address))
+                    .map_err(DetectorError::from)
+                    .and_then(|from| match from {
+                        Some(from) => slot_history(
+                            p.clone(),
+                            proxy.address.cvt(),
+                            from,
+                            to_block.unwrap_or(from).max(from),
+                            budget,
+                        ),
+                        None => Ok(vec![]),
+                    });
+                if let Err(e) = &r {
+                    error!(
+                        proxy = proxy.address.as_str(),
+                        err = e.to_string(),
+                        "Failed to reconstruct slot history"
+                    );
+                }
+                result_tx.send((proxy.address, r)).unwrap();
+            }
+            drop(wg);
+        });
+    }
+
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let mut cond = Condition::all()
+            .add(tracker.unfinished((entities::proxy::Entity, entities::proxy::Column::Address)));
+        if args.representatives {
+            cond = cond.add(not_redundant_clone((
+                entities::proxy::Entity,
+                entities::proxy::Column::Address,
+            )));
+        }
+        let proxies = entities::proxy::Entity::find()
+            .filter(cond)
+            .limit(1000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.address.clone()))
+            .await?;
+        for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
+            proxy_tx.send(proxy).unwrap();
+        }
+    }
+
+    drop(proxy_tx);
+    info!("Waiting for all tasks to finish");
+    wg.wait();
+
+    drop(result_tx);
+    info!("Waiting for result thread to finish");
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// The first block of the history: the creation, or else the first invocation.
+/// None if the proxy is neither created nor invoked.
+async fn first_block(db: &DatabaseConnection, proxy: &str) -> Result<Option<u64>, DbErr> {
+    let creation = entities::creation::Entity::find()
+        .filter(entities::creation::Column::Proxy.eq(proxy))
+        .one(db)
+        .await?;
+    if let Some(c) = creation {
+        return Ok(Some(c.creation_block as u64));
+    }
+    let first_invocation: Option<Option<i64>> = entities::invocation::Entity::find()
+        .select_only()
+        .column_as(entities::invocation::Column::Block.min(), "first_block")
+        .filter(entities::invocation::Column::Proxy.eq(proxy))
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(first_invocation.flatten().map(|b| b as u64))
+}
+
+/// The writes to the standard slots of the proxy in `[from, to]`, found by replaying the txs of every block,
+/// so that a value written and restored later, or written again with the same value, is seen.
+/// Writes before `from` are unknown unless the proxy is created in `from`.
+fn slot_history<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: Address,
+    from: u64,
+    to: u64,
+    budget: Budget,
+) -> Result<Vec<SlotWrite>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut writes = Vec::new();
+    for block in from..=to {
+        writes.extend(replay_block(p.clone(), proxy, block, budget)?);
+    }
+    Ok(writes)
+}
+
+/// Replay the txs of the block one after another, and collect the last write of each tx to each standard slot of the proxy.
+fn replay_block<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: Address,
+    block: u64,
+    budget: Budget,
+) -> Result<Vec<SlotWrite>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let txs = p
+        .txs_in_block(BlockHashOrNumber::Number(block))
+        .map_err(DetectorError::tx_not_found)?;
+    let mut state = p
+        .bc_state_at(TxPosition::new(block, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let mut writes: Vec<SlotWrite> = Vec::new();
+    for (tx_index, tx) in txs.into_iter().enumerate() {
+        let tx_hash = tx.hash();
+        let mut insp = SlotWriteInspector::new(proxy, BudgetMeter::new(budget));
+        let spec = TransitionSpecBuilder::new()
+            .at_block(&p, block)
+            .append_tx(tx)
+            .build();
+        state
+            .transit(spec, &mut insp)
+            .map_err(DetectorError::evm_halt)?;
+        insp.meter.check()?;
+        for (slot, value) in insp.writes {
+            let write = SlotWrite {
+                block,
+                tx_index: tx_index as u64,
+                tx: tx_hash,
+                slot,
+                value,
+            };
+            match writes
+                .iter_mut()
+                .find(|w| w.tx == tx_hash && w.slot == slot)
+            {
+                Some(w) => *w = write,
+                None => writes.push(write),
+            }
+        }
+    }
+    Ok(writes)
+}
+
+/// Replace the discrepancies between the writes to the implementation slots and the versions of the proxy
+/// in the `version_discrepancy` table, and return their number. The versions resolved through a beacon are not checked.
+async fn check_proxy(
+    db: &DatabaseConnection,
+    proxy: &str,
+    writes: &[SlotWrite],
+) -> Result<usize, DbErr> {
+    let versions = entities::version::Entity::find()
+        .filter(entities::version::Column::Proxy.eq(proxy))
+        .filter(entities::version::Column::Beacon.is_null())
+        .order_by_asc(entities::version::Column::MinBlock)
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|v| (v.implementation.cvt(), v.min_block as u64))
+        .collect::<Vec<(Address, u64)>>();
+    let discrepancies = cross_check(writes, &versions);
+    for d in &discrepancies {
+        info!(
+            proxy = proxy,
+            discrepancy = serde_json::to_string(d).unwrap(),
+            "Version discrepancy"
+        );
+    }
+    let txn = db.begin().await?;
+    entities::version_discrepancy::Entity::delete_many()
+        .filter(entities::version_discrepancy::Column::Proxy.eq(proxy))
+        .exec(&txn)
+        .await?;
+    if !discrepancies.is_empty() {
+        entities::version_discrepancy::Entity::insert_many(
+            discrepancies.iter().map(|d| d.to_model(proxy)),
+        )
+        .exec(&txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(discrepancies.len())
+}
+
+/// Replace the slot history of the proxy.
+async fn save_history(
+    db: &DatabaseConnection,
+    proxy: &str,
+    writes: Vec<SlotWrite>,
+) -> Result<(), DbErr> {
+    let writes = writes
+        .into_iter()
+        .map(|w| entities::slot_history::ActiveModel {
+            proxy: ActiveValue::Set(proxy.to_string()),
+            slot: ActiveValue::Set(w.slot.to_string()),
+            tx: ActiveValue::Set(w.tx.to_string().to_lowercase()),
+            block: ActiveValue::Set(w.block as i64),
+            tx_index: ActiveValue::Set(w.tx_index as i64),
+            value: ActiveValue::Set(Bytes::from(w.value.to_be_bytes_vec()).to_string()),
+        })
+        .collect::<Vec<_>>();
+    // a proxy may be checked again up to a later block
+    let txn = db.begin().await?;
+    entities::slot_history::Entity::delete_many()
+        .filter(entities::slot_history::Column::Proxy.eq(proxy))
+        .exec(&txn)
+        .await?;
+    if !writes.is_empty() {
+        entities::slot_history::Entity::insert_many(writes)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await
+}
@@ -6,15 +6,18 @@ use libsofl_core::engine::{
         Interpreter,
     },
 };
+use proxyex_detector::budget::BudgetMeter;
 
 pub struct HasDelegateCallOrNot {
     pub contract: Address,
     pub has_delegatecall: bool,
     pub updated_contract: bool,
+    pub meter: BudgetMeter,
 }
 
 impl<S: BcState> Inspector<S> for HasDelegateCallOrNot {
     fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
         let opcode = interp.current_opcode();
         if interp.contract().address == self.contract && opcode == opcode::SSTORE {
             self.updated_contract = true;
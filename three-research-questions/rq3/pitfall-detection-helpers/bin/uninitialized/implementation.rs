@@ -0,0 +1,471 @@
+use std::{
+    sync::{atomic::AtomicI32, Arc},
+    thread,
+    time::Duration,
+};
+
+use crossbeam::{channel, sync::WaitGroup};
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::{
+        memory::MemoryBcState,
+        state::BcState,
+        types::{Address, Bytes, Database, DatabaseRef},
+    },
+};
+use libsofl_utils::{
+    config::Config,
+    log::{error, info},
+    solidity::caller::HighLevelCaller,
+    sync::runtime::AsyncRuntime,
+};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    config::ProxyExDetectorConfig,
+    dataset::{first_used_implementations, ProxyData},
+    entities,
+    error::DetectorError,
+    inspectors::dangerous::DangerousInspector,
+    io::{check_into_files, ErrorRecord, OutputFiles},
+    provider::DetectorProvider,
+    run::RunTracker,
+    selector::{code_of, extract_selectors, selector_to_string, SIMULATED_ARGS},
+    shutdown::Shutdown,
+};
+use rayon::ThreadPoolBuilder;
+use sea_orm::{sea_query::OnConflict, ColumnTrait, DbErr, EntityTrait, QueryFilter, QuerySelect};
+
+use crate::{has_delegatecall::HasDelegateCallOrNot, Cli};
+
+/// A function of the implementation that reaches SELFDESTRUCT or DELEGATECALL after initialization.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DangerousCall {
+    pub selector: String,
+    pub selfdestruct: bool,
+    pub delegatecall: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImplInitializeResult {
+    pub implementation: Address,
+    pub min_block: i64,
+    pub frontrun_input: Option<Bytes>,
+    pub dangerous_calls: Vec<DangerousCall>,
+    pub time: Duration,
+}
+
+impl From<ImplInitializeResult> for entities::impl_initialize::Model {
+    fn from(r: ImplInitializeResult) -> Self {
+        Self {
+            implementation: r.implementation.to_string().to_lowercase(),
+            min_block: r.min_block,
+            uninitialized: r.frontrun_input.is_some(),
+            frontrun_input: r.frontrun_input.map(|b| b.to_string().to_lowercase()),
+            selfdestruct: r.dangerous_calls.iter().any(|c| c.selfdestruct),
+            delegatecall: r.dangerous_calls.iter().any(|c| c.delegatecall),
+            dangerous_calls: serde_json::to_value(r.dangerous_calls).unwrap(),
+            total_time: r.time.as_nanos() as i64,
+        }
+    }
+}
+
+/// Check whether the implementations in the `version` table can be initialized by anyone.
+pub async fn check_all_implementations<
+    T: Tx + 'static,
+    DB: DatabaseRef + 'static,
+    P: DetectorProvider<T, DB>,
+>(
+    args: Cli,
+    cfg: ProxyExDetectorConfig,
+    p: Arc<P>,
+    knowledge: Arc<Vec<(Bytes, Bytes)>>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("uninitialized-implementation");
+    let shutdown = Shutdown::install();
+    let (task_tx, task_rx) = channel::bounded::<(Address, i64)>(1000);
+    let (result_tx, result_rx) = channel::bounded::<ImplInitializeResult>(1000);
+
+    let pool = ThreadPoolBuilder::default()
+        .num_threads(args.jobs)
+        .build()
+        .unwrap();
+
+    let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
+    let result_thread = thread::spawn(move || {
+        let rt = AsyncRuntime::new();
+        let db = rt.block_on(cloned_cfg.db()).unwrap();
+        loop {
+            let result = match result_rx.recv() {
+                Ok(r) => r,
+                Err(_) => break,
+            };
+            let implementation = result.implementation.to_string().to_lowercase();
+            let model: entities::impl_initialize::ActiveModel =
+                entities::impl_initialize::Model::from(result).into();
+            let task = entities::impl_initialize::Entity::insert(model)
+                .on_conflict(
+                    OnConflict::column(entities::impl_initialize::Column::Implementation)
+                        .do_nothing()
+                        .to_owned(),
+                )
+                .exec(&db);
+            match rt.block_on(task) {
+                Ok(_) => {}
+                Err(e) => {
+                    if e != DbErr::RecordNotInserted {
+                        panic!("{:?}", e);
+                    }
+                }
+            }
+            rt.block_on(cloned_tracker.done(&db, implementation))
+                .unwrap();
+        }
+    });
+
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    let wg = WaitGroup::new();
+    let finished = Arc::new(AtomicI32::new(0));
+    for _ in 0..args.jobs {
+        let wg = wg.clone();
+        let task_rx = task_rx.clone();
+        let result_tx = result_tx.clone();
+        let p = p.clone();
+        let finished = finished.clone();
+        let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let knowledge = knowledge.clone();
+        let shutdown = shutdown.clone();
+        pool.spawn(move || {
+            let rt = AsyncRuntime::new();
+            let db = rt.block_on(cfg.db()).unwrap();
+            loop {
+                let (implementation, min_block) = match task_rx.recv() {
+                    Ok(v) => v,
+                    Err(_) => break,
+                };
+                // abandon the items not started yet, they are run again in the next session
+                if shutdown.requested() {
+                    continue;
+                }
+                let start_at = std::time::Instant::now();
+                let r = check_implementation(
+                    p.clone(),
+                    knowledge.clone(),
+                    implementation,
+                    min_block,
+                    budget,
+                );
+                let (frontrun_input, dangerous_calls) = match r {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!(
+                            implementation = implementation.to_string().to_lowercase(),
+                            err = e.to_string(),
+                            "Failed to check implementation"
+                        );
+                        let error = e.to_model(&tracker.detector, implementation, None);
+                        rt.block_on(entities::error::Entity::insert(error).exec(&db))
+                            .unwrap();
+                        let task = tracker.fail(
+                            &db,
+                            implementation.to_string().to_lowercase(),
+                            e.to_string(),
+                        );
+                        rt.block_on(task).unwrap();
+                        continue;
+                    }
+                };
+                result_tx
+                    .send(ImplInitializeResult {
+                        implementation,
+                        min_block,
+                        frontrun_input,
+                        dangerous_calls,
+                        time: start_at.elapsed(),
+                    })
+                    .unwrap();
+
+                finished.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                info!(
+                    finished = finished.load(std::sync::atomic::Ordering::SeqCst),
+                    "finished implementation"
+                );
+            }
+            drop(wg);
+        });
+    }
+
+    // each implementation is checked at the first block it is used by any proxy
+    let db = cfg.db().await.unwrap();
+    tracker.prepare(&db, args.retry_failed).await?;
+    // implementations are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let implementations = entities::version::Entity::find()
+            .select_only()
+            .column(entities::version::Column::Implementation)
+            .column_as(entities::version::Column::MinBlock.min(), "min_block")
+            .filter(tracker.unfinished((
+                entities::version::Entity,
+                entities::version::Column::Implementation,
+            )))
+            .group_by(entities::version::Column::Implementation)
+            .limit(10000)
+            .into_tuple::<(String, i64)>()
+            .all(&db)
+            .await?;
+        if implementations.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, implementations.iter().map(|(i, _)| i.clone()))
+            .await?;
+        for (implementation, min_block) in implementations {
+            if shutdown.requested() {
+                break;
+            }
+            task_tx.send((implementation.cvt(), min_block)).unwrap();
+        }
+    }
+
+    info!("Waiting for all tasks to finish");
+    drop(task_tx);
+    wg.wait();
+
+    info!("Waiting for result thread to finish");
+    drop(result_tx);
+    result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
+
+    Ok(())
+}
+
+/// Check whether the implementations of the proxies in a ProxyData file can be initialized by anyone,
+/// each at the first block it is used by any proxy, writing the rows of the `impl_initialize` table to the files.
+pub fn check_file_implementations<
+    T: Tx + 'static,
+    DB: DatabaseRef + 'static,
+    P: DetectorProvider<T, DB>,
+>(
+    args: Cli,
+    proxies: impl Iterator<Item = ProxyData>,
+    files: OutputFiles,
+    p: Arc<P>,
+    knowledge: Arc<Vec<(Bytes, Bytes)>>,
+) where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let shutdown = Shutdown::install();
+    // the first use of an implementation is only known once all proxies are read
+    let implementations = first_used_implementations(proxies);
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
+    check_into_files(
+        implementations.into_iter(),
+        files,
+        args.jobs,
+        &shutdown,
+        |(implementation, min_block)| {
+            let addr: Address = implementation.cvt();
+            let start_at = std::time::Instant::now();
+            let r =
+                check_implementation(p.clone(), knowledge.clone(), addr, min_block as i64, budget)
+                    .map(|(frontrun_input, dangerous_calls)| {
+                        entities::impl_initialize::Model::from(ImplInitializeResult {
+                            implementation: addr,
+                            min_block: min_block as i64,
+                            frontrun_input,
+                            dangerous_calls,
+                            time: start_at.elapsed(),
+                        })
+                    })
+                    .map_err(|e| {
+                        error!(
+                            implementation = implementation.as_str(),
+                            err = e.to_string(),
+                            "Failed to check implementation"
+                        );
+                        ErrorRecord::new(addr, None, e)
+                    });
+            vec![r]
+        },
+    );
+    info!(interrupted = shutdown.requested(), "Session finished");
+}
+
+/// Check whether anyone can initialize the implementation contract with its own state at `min_block`,
+/// and which dangerous opcodes become reachable after the initialization.
+/// Returns a timeout error if a call exceeds the budget.
+pub fn check_implementation<
+    T: Tx + 'static,
+    DB: DatabaseRef + 'static,
+    P: DetectorProvider<T, DB>,
+>(
+    p: Arc<P>,
+    knowledge: Arc<Vec<(Bytes, Bytes)>>,
+    implementation: Address,
+    min_block: i64,
+    budget: Budget,
+) -> Result<(Option<Bytes>, Vec<DangerousCall>), DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let blk = min_block as u64;
+    let base_state = p
+        .bc_state_at(TxPosition::new(blk, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let caller = HighLevelCaller::default()
+        .bypass_check()
+        .at_block(p.clone(), blk);
+    for (_, input) in knowledge.iter() {
+        let mut state = MemoryBcState::fork(&base_state);
+        if initialize(
+            &caller,
+            &mut state,
+            implementation,
+            input.to_owned(),
+            budget,
+        )? {
+            let dangerous_calls =
+                probe_dangerous_calls(&caller, &mut state, implementation, budget)?;
+            return Ok((Some(input.to_owned()), dangerous_calls));
+        }
+    }
+    Ok((None, Vec::new()))
+}
+
+/// Call the implementation with the initialization calldata, and tell whether it initialized the implementation,
+/// i.e., the call succeeded and wrote the storage of the implementation without any DELEGATECALL.
+fn initialize<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    implementation: Address,
+    input: Bytes,
+    budget: Budget,
+) -> Result<bool, DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let mut insp = HasDelegateCallOrNot {
+        contract: implementation,
+        has_delegatecall: false,
+        updated_contract: false,
+        meter: BudgetMeter::new(budget),
+    };
+    let r = caller.call(state, implementation, input, None, &mut insp);
+    insp.meter.check()?;
+    Ok(r.is_ok() && !insp.has_delegatecall && insp.updated_contract)
+}
+
+/// Call each function of the initialized implementation and record those reaching dangerous opcodes.
+fn probe_dangerous_calls<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    implementation: Address,
+    budget: Budget,
+) -> Result<Vec<DangerousCall>, DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let selectors = extract_selectors(
+        &code_of(state, implementation).map_err(DetectorError::state_unavailable)?,
+    );
+    let mut dangerous_calls = Vec::new();
+    for selector in selectors {
+        let mut input = selector.to_vec();
+        input.extend(vec![0u8; 32 * SIMULATED_ARGS]);
+        let mut insp = DangerousInspector::new(implementation, BudgetMeter::new(budget));
+        let _ = caller.simulate_call(state, implementation, Bytes::from(input), None, &mut insp);
+        insp.meter.check()?;
+        if insp.reached() {
+            dangerous_calls.push(DangerousCall {
+                selector: selector_to_string(&selector),
+                selfdestruct: insp.selfdestruct,
+                delegatecall: insp.delegatecall,
+            });
+        }
+    }
+    Ok(dangerous_calls)
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::{memory::MemoryBcState, types::Bytes};
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+    use proxyex_detector::{budget::Budget, selector::selector_to_string};
+
+    use super::{initialize, probe_dangerous_calls};
+
+    #[test]
+    fn test_initialize_implementation() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                bool public initialized;
+                address public owner;
+                function initialize() public {
+                    require(!initialized);
+                    initialized = true;
+                    owner = msg.sender;
+                }
+                function upgradeToAndCall(address _target, bytes memory _data) public {
+                    require(msg.sender == owner);
+                    (bool ok, ) = _target.delegatecall(_data);
+                    require(ok);
+                }
+                function kill() public {
+                    require(msg.sender == owner);
+                    selfdestruct(payable(msg.sender));
+                }
+            }
+            "#,
+            vec!["Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let implementation = addrs.remove(0);
+        let caller = HighLevelCaller::default().bypass_check();
+        let input = Bytes::from(vec![0x81, 0x29, 0xfc, 0x1c]); // initialize()
+
+        assert!(initialize(
+            &caller,
+            &mut state,
+            implementation,
+            input.clone(),
+            Budget::default()
+        )
+        .unwrap());
+        // the functions guarded by the owner become reachable once initialized
+        let dangerous_calls =
+            probe_dangerous_calls(&caller, &mut state, implementation, Budget::default()).unwrap();
+        assert_eq!(dangerous_calls.len(), 2);
+        let kill = dangerous_calls
+            .iter()
+            .find(|c| c.selector == selector_to_string(&[0x41, 0xc0, 0xe1, 0xb5]))
+            .unwrap();
+        assert!(kill.selfdestruct && !kill.delegatecall);
+
+        // an implementation initialized before cannot be initialized again
+        assert!(!initialize(
+            &caller,
+            &mut state,
+            implementation,
+            input,
+            Budget::default()
+        )
+        .unwrap());
+    }
+}
@@ -1,5 +1,6 @@
 mod call_extractor;
 mod has_delegatecall;
+mod implementation;
 pub mod initialize_extractor;
 
 use std::{
@@ -8,34 +9,40 @@ use std::{
     thread,
 };
 
-use clap::{command, Parser};
+use clap::{command, error::ErrorKind, CommandFactory, Parser, ValueEnum};
 use crossbeam::{channel, sync::WaitGroup};
 use has_delegatecall::HasDelegateCallOrNot;
 use libsofl_core::{
-    blockchain::{
-        provider::{BcProvider, BcStateProvider},
-        transaction::Tx,
-    },
+    blockchain::transaction::Tx,
     conversion::ConvertTo,
     engine::{
         state::BcState,
         transition::TransitionSpecBuilder,
-        types::{Address, Bytes, TxHash},
+        types::{Address, Bytes, DatabaseRef, TxHash},
     },
     error::SoflError,
 };
-use libsofl_reth::{blockchain::provider::RethProvider, config::RethConfig};
 use libsofl_utils::{
     config::Config,
     log::{config::LogConfig, error, info},
     solidity::caller::HighLevelCaller,
     sync::runtime::AsyncRuntime,
 };
-use proxyex_detector::{config::ProxyExDetectorConfig, entities};
+use proxyex_detector::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    config::ProxyExDetectorConfig,
+    dataset::ProxyData,
+    entities,
+    error::DetectorError,
+    io::{JsonlSource, OutputFiles},
+    provider::DetectorProvider,
+    run::RunTracker,
+    shutdown::Shutdown,
+};
 use rayon::ThreadPoolBuilder;
 use sea_orm::{
     sea_query::{Expr, OnConflict, Query},
-    Condition, DbErr, EntityTrait, PaginatorTrait, QueryFilter,
+    Condition, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QuerySelect,
 };
 
 use crate::initialize_extractor::InitializeExtractor;
@@ -55,24 +62,82 @@ pub struct Cli {
         default_value = "proxyex_detector_public_initialize.csv"
     )]
     initialize_knowledge: String,
+
+    #[arg(short, long, value_enum, default_value = "proxy")]
+    mode: Mode,
+
+    /// Retry the proxies or implementations that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Check the implementations of the proxies in a ProxyData JSONL file (`{proxy, impls: [{tx, impl, block}]}`)
+    /// instead of the database, only with `--mode implementation`
+    #[arg(long, requires = "output")]
+    input: Option<String>,
+
+    /// Write the results to this JSONL file (or Parquet file, with the `parquet` feature) instead of the database
+    #[arg(short, long, requires = "input")]
+    output: Option<String>,
+
+    /// Write the failures to this JSONL (or Parquet) file, only with `--output`
+    #[arg(long, requires = "output")]
+    error_output: Option<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum Mode {
+    /// Front-run the initialization of proxies right after creation
+    Proxy,
+    /// Initialize the implementations in the `version` table with their own state
+    Implementation,
 }
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), DbErr> {
     let args = Cli::parse();
-    let cfg = ProxyExDetectorConfig::must_load();
     let mut log_cfg = LogConfig::load_or(Default::default()).unwrap();
     log_cfg.console_level = args.log_level.clone();
     log_cfg.init();
 
-    let p = RethConfig::must_load().bc_provider().unwrap();
-    let p = Arc::new(p);
-
     let knowledge = load_initialize_knowledge(&args.initialize_knowledge).unwrap();
     let knowledge = Arc::new(knowledge);
 
-    // collect_all(args, cfg, p).await
-    frontrun_all(args, cfg, p, knowledge).await
+    if let Some(input) = args.input.clone() {
+        // the creation txs and the initialize calls of the proxies are only in the database
+        if !matches!(args.mode, Mode::Implementation) {
+            Cli::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--input is only supported with --mode implementation",
+                )
+                .exit();
+        }
+        // neither the input nor the results touch the database
+        let proxies = JsonlSource::<ProxyData>::open(&input)
+            .expect("Failed to open input")
+            .valid();
+        let files = OutputFiles::open(
+            args.output.as_deref().unwrap(),
+            args.error_output.as_deref(),
+        )
+        .expect("Failed to open output");
+        proxyex_detector::with_provider!(|p| {
+            implementation::check_file_implementations(args, proxies, files, p, knowledge)
+        });
+        return Ok(());
+    }
+
+    let cfg = ProxyExDetectorConfig::must_load();
+
+    // proxyex_detector::with_provider!(|p| collect_all(args, cfg, p).await)
+    match args.mode {
+        Mode::Proxy => {
+            proxyex_detector::with_provider!(|p| frontrun_all(args, cfg, p, knowledge).await)
+        }
+        Mode::Implementation => proxyex_detector::with_provider!(|p| {
+            implementation::check_all_implementations(args, cfg, p, knowledge).await
+        }),
+    }
 }
 
 fn load_initialize_knowledge(path: &str) -> Result<Vec<(Bytes, Bytes)>, std::io::Error> {
@@ -89,12 +154,17 @@ fn load_initialize_knowledge(path: &str) -> Result<Vec<(Bytes, Bytes)>, std::io:
     Ok(knowledge)
 }
 
-async fn frontrun_all(
+async fn frontrun_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
     args: Cli,
     cfg: ProxyExDetectorConfig,
-    p: Arc<RethProvider>,
+    p: Arc<P>,
     knowledge: Arc<Vec<(Bytes, Bytes)>>,
-) -> Result<(), DbErr> {
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tracker = RunTracker::new("uninitialized");
+    let shutdown = Shutdown::install();
     let (task_tx, task_rx) = channel::bounded::<(Address, TxHash)>(1000);
     let (result_tx, result_rx) = channel::bounded::<entities::initialize::Model>(1000);
 
@@ -104,13 +174,16 @@ async fn frontrun_all(
         .unwrap();
 
     let cloned_cfg = cfg.clone();
+    let cloned_tracker = tracker.clone();
     let result_thread = thread::spawn(move || {
         let rt = AsyncRuntime::new();
         let db = rt.block_on(cloned_cfg.db()).unwrap();
         let mut cache: Vec<entities::initialize::ActiveModel> = Vec::new();
+        let mut cached_proxies: Vec<String> = Vec::new();
         loop {
             match result_rx.recv() {
                 Ok(proxy) => {
+                    cached_proxies.push(proxy.proxy.clone());
                     cache.push(proxy.into());
                 }
                 Err(_) => {
@@ -125,6 +198,9 @@ async fn frontrun_all(
                     match rt.block_on(task) {
                         Ok(_) => {
                             info!("{} contracts saved", count);
+                            for proxy in cached_proxies.drain(..) {
+                                rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+                            }
                         }
                         Err(e) => {
                             error!("save failed: {}", e);
@@ -146,15 +222,23 @@ async fn frontrun_all(
                 match rt.block_on(task) {
                     Ok(_) => {
                         info!("{} contracts saved", count);
+                        for proxy in cached_proxies.drain(..) {
+                            rt.block_on(cloned_tracker.done(&db, proxy)).unwrap();
+                        }
                     }
                     Err(e) => {
                         error!("save failed: {}", e);
+                        // the proxies stay running and are checked again in the next run
+                        cached_proxies.clear();
                     }
                 }
             }
         }
     });
 
+    let budget = BudgetConfig::load_or(Default::default())
+        .expect("load budget config failed")
+        .tx_budget();
     let wg = WaitGroup::new();
     let finished = Arc::new(AtomicI32::new(0));
     for _ in 0..args.jobs {
@@ -164,6 +248,8 @@ async fn frontrun_all(
         let p = p.clone();
         let finished = finished.clone();
         let cfg = cfg.clone();
+        let tracker = tracker.clone();
+        let shutdown = shutdown.clone();
         let knowledge = knowledge.clone();
         pool.spawn(move || {
             let rt = AsyncRuntime::new();
@@ -171,6 +257,10 @@ async fn frontrun_all(
             loop {
                 match task_rx.recv() {
                     Ok((contract, creation_tx)) => {
+                        // abandon the items not started yet, they are run again in the next session
+                        if shutdown.requested() {
+                            continue;
+                        }
                         let task = async {
                             entities::initialize::Entity::find_by_id(
                                 contract.to_string().to_lowercase(),
@@ -184,13 +274,33 @@ async fn frontrun_all(
                                 break;
                             }
                         };
-                        let uninitialized = check_uninitialized(
+                        let uninitialized = match check_uninitialized(
                             p.clone(),
                             knowledge.clone(),
                             contract,
                             creation_tx,
-                        )
-                        .unwrap();
+                            budget,
+                        ) {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!(
+                                    proxy = contract.to_string().to_lowercase(),
+                                    err = e.to_string(),
+                                    "Failed to check proxy"
+                                );
+                                let error =
+                                    e.to_model(&tracker.detector, contract, Some(creation_tx));
+                                let task = entities::error::Entity::insert(error).exec(&db);
+                                rt.block_on(task).unwrap();
+                                let task = tracker.fail(
+                                    &db,
+                                    contract.to_string().to_lowercase(),
+                                    e.to_string(),
+                                );
+                                rt.block_on(task).unwrap();
+                                continue;
+                            }
+                        };
                         proxy.uninitialized = Some(uninitialized.is_some());
                         proxy.frontrun_input = match uninitialized {
                             Some(input) => Some(input.to_string().to_lowercase()),
@@ -214,25 +324,45 @@ async fn frontrun_all(
     }
 
     let db = cfg.db().await.unwrap();
-    let mut paginator = entities::creation::Entity::find()
-        .filter(
-            Condition::all().add(Expr::exists(
-                Query::select()
-                    .from(entities::initialize::Entity)
-                    .and_where(
-                        Expr::col((
-                            entities::creation::Entity,
-                            entities::creation::Column::Proxy,
-                        ))
-                        .equals(entities::initialize::Column::Proxy),
-                    )
-                    .and_where(Expr::col(entities::initialize::Column::Uninitialized).is_null())
-                    .take(),
-            )),
-        )
-        .paginate(&db, 10000);
-    while let Some(proxies) = paginator.fetch_and_next().await.unwrap() {
+    tracker.prepare(&db, args.retry_failed).await?;
+    // proxies are marked running once dispatched, so the query always starts from the beginning
+    while !shutdown.requested() {
+        let proxies = entities::creation::Entity::find()
+            .filter(
+                Condition::all()
+                    .add(Expr::exists(
+                        Query::select()
+                            .from(entities::initialize::Entity)
+                            .and_where(
+                                Expr::col((
+                                    entities::creation::Entity,
+                                    entities::creation::Column::Proxy,
+                                ))
+                                .equals(entities::initialize::Column::Proxy),
+                            )
+                            .and_where(
+                                Expr::col(entities::initialize::Column::Uninitialized).is_null(),
+                            )
+                            .take(),
+                    ))
+                    .add(tracker.unfinished((
+                        entities::creation::Entity,
+                        entities::creation::Column::Proxy,
+                    ))),
+            )
+            .limit(10000)
+            .all(&db)
+            .await?;
+        if proxies.is_empty() {
+            break;
+        }
+        tracker
+            .start(&db, proxies.iter().map(|p| p.proxy.clone()))
+            .await?;
         for proxy in proxies {
+            if shutdown.requested() {
+                break;
+            }
             task_tx
                 .send((proxy.proxy.cvt(), proxy.creation_tx.cvt()))
                 .unwrap();
@@ -246,16 +376,20 @@ async fn frontrun_all(
     info!("Waiting for result thread to finish");
     drop(result_tx);
     result_thread.join().unwrap();
+    shutdown.report(&tracker, &db).await?;
 
     Ok(())
 }
 
 #[allow(unused)]
-async fn collect_all(
+async fn collect_all<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
     args: Cli,
     cfg: ProxyExDetectorConfig,
-    p: Arc<RethProvider>,
-) -> Result<(), DbErr> {
+    p: Arc<P>,
+) -> Result<(), DbErr>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let (task_tx, task_rx) = channel::bounded::<Address>(1000);
     let (result_tx, result_rx) = channel::bounded::<(Address, Option<Bytes>, Option<Bytes>)>(1000);
 
@@ -418,11 +552,18 @@ async fn collect_all(
 }
 
 #[allow(unused)]
-fn collect_initialize_input(
-    p: Arc<RethProvider>,
+fn collect_initialize_input<
+    T: Tx + 'static,
+    DB: DatabaseRef + 'static,
+    P: DetectorProvider<T, DB>,
+>(
+    p: Arc<P>,
     contract: Address,
     creation_tx: TxHash,
-) -> Result<Option<Bytes>, SoflError> {
+) -> Result<Option<Bytes>, SoflError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let tx = p.tx(creation_tx.cvt())?;
     let pos = tx.position().unwrap();
     let mut state = p.bc_state_at(pos)?;
@@ -437,26 +578,38 @@ fn collect_initialize_input(
 
 /// Check if a contract is uninitialized after creation.
 #[allow(unused)]
-fn check_uninitialized(
-    p: Arc<RethProvider>,
+fn check_uninitialized<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
     knowledge: Arc<Vec<(Bytes, Bytes)>>,
     contract: Address,
     creation_tx: TxHash,
-) -> Result<Option<Bytes>, SoflError> {
-    let creation_tx = p.tx(creation_tx.cvt())?;
-    let mut pos = creation_tx.position().unwrap();
-    pos.shift(&p, 1).unwrap();
-    let mut state = p.bc_state_at(pos)?;
+    budget: Budget,
+) -> Result<Option<Bytes>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let creation_tx = p
+        .tx(creation_tx.cvt())
+        .map_err(DetectorError::tx_not_found)?;
+    let mut pos = creation_tx
+        .position()
+        .ok_or_else(|| DetectorError::TxNotFound("position of creation tx".to_string()))?;
+    pos.shift(&p, 1).map_err(DetectorError::tx_not_found)?;
+    let mut state = p
+        .bc_state_at(pos)
+        .map_err(DetectorError::state_unavailable)?;
     for (_, input) in knowledge.iter() {
         let mut insp = HasDelegateCallOrNot {
             contract,
             has_delegatecall: false,
             updated_contract: false,
+            meter: BudgetMeter::new(budget),
         };
         let r = HighLevelCaller::default()
             .bypass_check()
             .at_block(p.clone(), pos.block)
             .simulate_call(&mut state, contract, input.to_owned(), None, &mut insp);
+        insp.meter.check()?;
         if r.is_ok() && !insp.has_delegatecall && insp.updated_contract {
             return Ok(Some(input.to_owned()));
         }
@@ -465,12 +618,15 @@ fn check_uninitialized(
 }
 
 #[allow(unused)]
-fn frontrun_call(
-    p: Arc<RethProvider>,
+fn frontrun_call<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
     contract: Address,
     creation_tx: TxHash,
     input: Bytes,
-) -> Result<bool, SoflError> {
+) -> Result<bool, SoflError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
     let creation_tx = p.tx(creation_tx.cvt())?;
     let mut pos = creation_tx.position().unwrap();
     pos.shift(&p, 1).unwrap();
@@ -479,6 +635,7 @@ fn frontrun_call(
         contract,
         has_delegatecall: false,
         updated_contract: false,
+        meter: BudgetMeter::default(),
     };
     let r = HighLevelCaller::default()
         .bypass_check()
@@ -1,13 +1,10 @@
 use libsofl_core::{conversion::ConvertTo, engine::types::Address};
-use proxyex_detector::entities;
-use sea_orm::{
-    sea_query::{Expr, Query},
-    DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect,
-};
+use proxyex_detector::{entities, run::RunTracker};
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect};
 
 pub struct DBIterator {
     db: DatabaseConnection,
-    offset: u64,
+    tracker: RunTracker,
     window_size: u64,
 
     // buffer
@@ -15,10 +12,10 @@ pub struct DBIterator {
 }
 
 impl DBIterator {
-    pub fn new(db: DatabaseConnection, window_size: usize) -> Self {
+    pub fn new(db: DatabaseConnection, window_size: usize, tracker: RunTracker) -> Self {
         Self {
             db,
-            offset: 0,
+            tracker,
             window_size: window_size as u64,
             proxies: vec![],
         }
@@ -45,38 +42,23 @@ impl DBIterator {
             // short circuit if there is still proxies in the bugger
             return Ok(());
         }
-        loop {
-            let proxy_addresses: Vec<(String,)> = entities::proxy::Entity::find()
-                .column(entities::proxy::Column::Address)
-                .filter(
-                    Expr::exists(
-                        Query::select()
-                            .from(entities::version::Entity)
-                            .and_where(
-                                Expr::col(entities::version::Column::Proxy)
-                                    .equals(entities::proxy::Column::Address),
-                            )
-                            .take(),
-                    )
-                    .not(),
-                )
-                .offset(self.offset)
-                .limit(self.window_size)
-                .into_tuple()
-                .all(&self.db)
-                .await?;
-            let proxy_addrs: Vec<Address> = proxy_addresses.iter().map(|t| t.0.cvt()).collect();
-            self.proxies.extend(proxy_addrs);
-            if proxy_addresses.len() < self.window_size as usize {
-                if self.offset == 0 {
-                    // no more proxies
-                    return Ok(());
-                }
-                self.offset = 0;
-            } else {
-                self.offset += self.window_size;
-                return Ok(());
-            }
-        }
+        // proxies are marked running once loaded, so the query always starts from the beginning
+        let proxy_addresses: Vec<(String,)> = entities::proxy::Entity::find()
+            .select_only()
+            .column(entities::proxy::Column::Address)
+            .filter(
+                self.tracker
+                    .unfinished((entities::proxy::Entity, entities::proxy::Column::Address)),
+            )
+            .limit(self.window_size)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+        self.tracker
+            .start(&self.db, proxy_addresses.iter().map(|t| t.0.clone()))
+            .await?;
+        let proxy_addrs: Vec<Address> = proxy_addresses.iter().map(|t| t.0.cvt()).collect();
+        self.proxies.extend(proxy_addrs);
+        Ok(())
     }
 }
@@ -1,13 +1,23 @@
 mod generator;
 
+use std::{collections::HashSet, sync::Arc};
+
 use clap::Parser;
 use generator::DBIterator;
 use indicatif::ProgressStyle;
+use libsofl_core::{
+    blockchain::{transaction::Tx, tx_position::TxPosition},
+    conversion::ConvertTo,
+    engine::types::DatabaseRef,
+};
 use libsofl_utils::{
     config::Config,
     log::{error, info, info_span},
 };
-use proxyex_detector::{config::ProxyExDetectorConfig, entities};
+use proxyex_detector::{
+    beacon::beacon_of, config::ProxyExDetectorConfig, entities, error::DetectorError,
+    provider::DetectorProvider, run::RunTracker, shutdown::Shutdown,
+};
 use sea_orm::{
     sea_query::OnConflict, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
     QueryFilter, QueryOrder, QuerySelect,
@@ -23,8 +33,20 @@ pub struct Cli {
 
     #[arg(short, long, default_value = "1")]
     jobs: usize,
+
+    /// Retry the proxies that failed in previous runs
+    #[arg(long)]
+    retry_failed: bool,
+
+    /// Resolve the EIP-1967 beacon of the proxy at each block it is invoked in, on the blockchain provider,
+    /// and key the versions on (implementation, beacon), so that a change of the beacon starts a new version
+    #[arg(long)]
+    beacon: bool,
 }
 
+/// Resolve the beacon of a proxy at a block.
+type BeaconResolver<'a> = dyn Fn(&str, i64) -> Result<Option<String>, DetectorError> + 'a;
+
 // collect implementation versions of a proxy
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), DbErr> {
@@ -48,15 +70,41 @@ async fn main() -> Result<(), DbErr> {
 
     let cfg = ProxyExDetectorConfig::must_load();
 
+    let tracker = RunTracker::new("version");
+    let shutdown = Shutdown::install();
     let db = cfg.db().await?;
-    let iterator = DBIterator::new(db, args.jobs * 2);
+    tracker.prepare(&db, args.retry_failed).await?;
+    let iterator = DBIterator::new(db, args.jobs * 2, tracker.clone());
 
     let db = cfg.db().await?;
-    analyze_all(&db, iterator, args.jobs).await;
+    if args.beacon {
+        proxyex_detector::with_provider!(|p| {
+            let resolve = |proxy: &str, block: i64| beacon_at(p.clone(), proxy, block);
+            analyze_all(
+                &db,
+                iterator,
+                &tracker,
+                &shutdown,
+                args.jobs,
+                Some(&resolve),
+            )
+            .await
+        })
+    } else {
+        analyze_all(&db, iterator, &tracker, &shutdown, args.jobs, None).await;
+    }
+    shutdown.report(&tracker, &db).await?;
     Ok(())
 }
 
-async fn analyze_all(db: &DatabaseConnection, mut iterator: DBIterator, jobs: usize) {
+async fn analyze_all(
+    db: &DatabaseConnection,
+    mut iterator: DBIterator,
+    tracker: &RunTracker,
+    shutdown: &Shutdown,
+    jobs: usize,
+    resolve_beacon: Option<&BeaconResolver<'_>>,
+) {
     // progress bar
     let progress_span = info_span!("versioning");
     let pb_style = ProgressStyle::default_spinner();
@@ -66,26 +114,35 @@ async fn analyze_all(db: &DatabaseConnection, mut iterator: DBIterator, jobs: us
     let mut total = 0;
 
     loop {
+        let mut proxies = Vec::new();
         let mut tasks = Vec::new();
         let mut complete = false;
         for _ in 0..jobs {
+            // the proxies of the current batch are still collected and saved
+            if shutdown.requested() {
+                complete = true;
+                break;
+            }
             let proxy = iterator.next_async().await;
             if proxy.is_none() {
                 complete = true;
                 break;
             }
-            let proxy = proxy.unwrap();
-            let task = collect_versions(db, proxy.to_string().to_lowercase());
+            let proxy = proxy.unwrap().to_string().to_lowercase();
+            let task = collect_versions(db, proxy.clone(), resolve_beacon);
+            proxies.push(proxy);
             tasks.push(task);
         }
         let versions_vec = futures::future::join_all(tasks).await;
 
         let mut tasks = Vec::new();
-        for versions in versions_vec.into_iter() {
+        let mut collected = Vec::new();
+        for (proxy, versions) in proxies.into_iter().zip(versions_vec.into_iter()) {
             let versions = match versions {
                 Ok(versions) => versions,
                 Err(err) => {
                     error!(err = ?err, "Failed to collect versions");
+                    tracker.fail(db, proxy, format!("{:?}", err)).await.unwrap();
                     continue;
                 }
             };
@@ -96,9 +153,13 @@ async fn analyze_all(db: &DatabaseConnection, mut iterator: DBIterator, jobs: us
                     .exec(db);
                 tasks.push(task);
             }
+            collected.push(proxy);
         }
         total += tasks.len();
         futures::future::join_all(tasks).await;
+        for proxy in collected {
+            tracker.done(db, proxy).await.unwrap();
+        }
 
         progress_span.pb_set_message(format!("Versioned {}", total).as_str());
 
@@ -109,12 +170,16 @@ async fn analyze_all(db: &DatabaseConnection, mut iterator: DBIterator, jobs: us
     info!("Versioning finished");
 }
 
-/// Collect the implementation versions of a proxy.
+/// Collect the implementation versions of a proxy, keyed on (implementation, beacon) if `resolve_beacon` is given.
 /// The implementations are ordered by the block number (ascending).
 async fn collect_versions(
     db: &DatabaseConnection,
     proxy: String,
-) -> Result<Vec<entities::version::ActiveModel>, DbErr> {
+    resolve_beacon: Option<&BeaconResolver<'_>>,
+) -> Result<Vec<entities::version::ActiveModel>, DetectorError> {
+    if let Some(resolve) = resolve_beacon {
+        return collect_beacon_versions(db, proxy, resolve).await;
+    }
     let window_size = 100;
     let mut offset = 0;
     let mut versions = Vec::new();
@@ -137,7 +202,7 @@ async fn collect_versions(
                 proxy: ActiveValue::Set(proxy.clone()),
                 implementation: ActiveValue::Set(v.0),
                 min_block: ActiveValue::Set(v.1),
-                ..Default::default()
+                beacon: ActiveValue::Set(None),
             };
             versions.push(version);
         }
@@ -149,6 +214,66 @@ async fn collect_versions(
     Ok(versions)
 }
 
+/// Collect the versions of a proxy keyed on (implementation, beacon), ordered by the block they are first used in.
+/// The beacon is resolved at each block the proxy is invoked in, so that a proxy switching to another beacon
+/// starts a new version even if the implementation returned by the new beacon is the same.
+async fn collect_beacon_versions(
+    db: &DatabaseConnection,
+    proxy: String,
+    resolve: &BeaconResolver<'_>,
+) -> Result<Vec<entities::version::ActiveModel>, DetectorError> {
+    let invocations: Vec<(String, i64)> = entities::invocation::Entity::find()
+        .select_only()
+        .column(entities::invocation::Column::Implementation)
+        .column(entities::invocation::Column::Block)
+        .distinct()
+        .filter(entities::invocation::Column::Proxy.eq(proxy.clone()))
+        .order_by_asc(entities::invocation::Column::Block)
+        .into_tuple()
+        .all(db)
+        .await?;
+    let mut versions = Vec::new();
+    let mut seen = HashSet::new();
+    // the beacon of the last resolved block, shared by the invocations in the same block
+    let mut resolved: Option<(i64, Option<String>)> = None;
+    for (implementation, block) in invocations {
+        let beacon = match &resolved {
+            Some((b, beacon)) if *b == block => beacon.clone(),
+            _ => {
+                let beacon = resolve(&proxy, block)?;
+                resolved = Some((block, beacon.clone()));
+                beacon
+            }
+        };
+        if seen.insert((implementation.clone(), beacon.clone())) {
+            versions.push(entities::version::ActiveModel {
+                proxy: ActiveValue::Set(proxy.clone()),
+                implementation: ActiveValue::Set(implementation),
+                min_block: ActiveValue::Set(block),
+                beacon: ActiveValue::Set(beacon),
+            });
+        }
+    }
+    Ok(versions)
+}
+
+/// The beacon of the proxy at the end of a block it is invoked in.
+fn beacon_at<T: Tx + 'static, DB: DatabaseRef + 'static, P: DetectorProvider<T, DB>>(
+    p: Arc<P>,
+    proxy: &str,
+    block: i64,
+) -> Result<Option<String>, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    // the beacon may be set in the block it is first used, the same as fake-proxy-detector
+    let mut state = p
+        .bc_state_at(TxPosition::new(block as u64 + 1, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let beacon = beacon_of(&mut state, proxy.cvt()).map_err(DetectorError::state_unavailable)?;
+    Ok(beacon.map(|b| b.to_string().to_lowercase()))
+}
+
 #[cfg(test)]
 mod tests {
     use libsofl_utils::config::Config;
@@ -161,6 +286,7 @@ mod tests {
         let versions = super::collect_versions(
             &db,
             "0x759b4da08fe959fde5bfc35bca733e79310c9531".to_string(),
+            None,
         )
         .await
         .unwrap();
@@ -193,6 +319,7 @@ mod tests {
         let versions = super::collect_versions(
             &db,
             "0x05462671c05adc39a6521fa60d5e9443e9e9d2b9".to_string(),
+            None,
         )
         .await
         .unwrap();
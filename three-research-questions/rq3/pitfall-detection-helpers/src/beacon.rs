@@ -0,0 +1,287 @@
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        types::{Address, Bytes, Database, U256},
+    },
+    error::SoflError,
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    budget::{Budget, BudgetMeter},
+    error::DetectorError,
+    inspectors::delegation::BeaconInspector,
+    slot::{EIP1822_PROXIABLE_SLOT, EIP1967_BEACON_SLOT, EIP1967_IMPLEMENTATION_SLOT},
+};
+
+/// `implementation()`, called by a beacon proxy on its beacon (EIP-1967).
+pub const IMPLEMENTATION_SELECTOR: [u8; 4] = [0x5c, 0x60, 0xda, 0x1b];
+
+/// The implementation of a proxy and where it is read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedImplementation {
+    /// zero if the proxy has no implementation in the standard slots
+    pub implementation: Address,
+    /// the beacon returning the implementation, None if the implementation is stored in the proxy
+    pub beacon: Option<Address>,
+}
+
+fn storage<S: BcState>(state: &mut S, address: Address, slot: &str) -> Result<U256, SoflError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    state
+        .storage(address, slot.cvt())
+        .map_err(|e| SoflError::Custom(format!("{:?}", e)))
+}
+
+/// The beacon in the EIP-1967 beacon slot of the proxy, if any.
+pub fn beacon_of<S: BcState>(state: &mut S, proxy: Address) -> Result<Option<Address>, SoflError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let beacon = storage(state, proxy, EIP1967_BEACON_SLOT)?;
+    if beacon == U256::ZERO {
+        return Ok(None);
+    }
+    Ok(Some(beacon.cvt()))
+}
+
+/// Simulate the `implementation()` call on the beacon within the budget.
+/// Returns None if the call fails or does not return an address, and a timeout error if the budget is exhausted.
+pub fn beacon_implementation<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    beacon: Address,
+    budget: Budget,
+) -> Result<Option<Address>, DetectorError> {
+    let mut insp = BeaconInspector::default();
+    insp.meter = BudgetMeter::new(budget);
+    let input = Bytes::from(IMPLEMENTATION_SELECTOR.to_vec());
+    let _ = caller.simulate_call(state, beacon, input, None, &mut insp);
+    insp.meter.check()?;
+    Ok(insp.implementation)
+}
+
+/// Resolve the implementation of a proxy from the EIP-1822 and EIP-1967 implementation slots,
+/// or from the beacon in the EIP-1967 beacon slot.
+pub fn resolve_implementation<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    budget: Budget,
+) -> Result<ResolvedImplementation, DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    for slot in [EIP1822_PROXIABLE_SLOT, EIP1967_IMPLEMENTATION_SLOT] {
+        let implementation =
+            storage(state, proxy, slot).map_err(DetectorError::state_unavailable)?;
+        if implementation != U256::ZERO {
+            return Ok(ResolvedImplementation {
+                implementation: implementation.cvt(),
+                beacon: None,
+            });
+        }
+    }
+    let beacon = beacon_of(state, proxy).map_err(DetectorError::state_unavailable)?;
+    let implementation = match beacon {
+        Some(beacon) => beacon_implementation(caller, state, beacon, budget)?,
+        None => None,
+    }
+    .unwrap_or(Address::ZERO);
+    Ok(ResolvedImplementation {
+        implementation,
+        beacon,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use libsofl_core::engine::{
+        memory::MemoryBcState,
+        types::{Address, U256},
+    };
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::{
+        budget::{Budget, BudgetMeter},
+        inspectors::{collision::StorageAccessInspector, delegation::ImplInspector},
+        slot::StandardSlot,
+    };
+
+    use super::{beacon_implementation, resolve_implementation, ResolvedImplementation};
+
+    #[test]
+    fn test_beacon_proxy() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                uint256 public value;
+                function set_value(uint256 _value) public {
+                    value = _value;
+                }
+            }
+            contract Beacon {
+                address public implementation;
+                constructor() {
+                    implementation = address(new Impl());
+                }
+                function upgrade(address _impl) public {
+                    implementation = _impl;
+                }
+            }
+            contract BeaconProxy {
+                constructor() {
+                    address beacon = address(new Beacon());
+                    assembly {
+                        sstore(0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50, beacon)
+                    }
+                }
+                fallback() external payable {
+                    address beacon;
+                    assembly {
+                        beacon := sload(0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50)
+                    }
+                    address _impl = Beacon(beacon).implementation();
+                    assembly {
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), _impl, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            "#,
+            vec!["BeaconProxy", "Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (proxy, new_implementation) = (addrs.remove(0), addrs.remove(0));
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let r = resolve_implementation(&caller, &mut state, proxy, Budget::default()).unwrap();
+        let beacon = r.beacon.unwrap();
+        let implementation = r.implementation;
+        assert_ne!(implementation, Address::ZERO);
+
+        // the implementation is the one the proxy delegates to, through the beacon
+        let mut insp = ImplInspector {
+            proxy,
+            implementation: None,
+            beacon: None,
+            meter: BudgetMeter::default(),
+        };
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "set_value(uint256)",
+                &[U256::from(1).into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert_eq!(insp.implementation, Some(implementation));
+        assert_eq!(insp.beacon, Some(beacon));
+
+        // storage accesses of the beacon are neither the proxy's nor the implementation's
+        let mut insp = StorageAccessInspector::new(proxy, implementation, 0, 1, false);
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "set_value(uint256)",
+                &[U256::from(2).into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        let proxy_sloads = insp
+            .proxy_sloads
+            .iter()
+            .map(|(_, k, _)| *k)
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            proxy_sloads,
+            HashSet::from([StandardSlot::Eip1967Beacon.slot()])
+        );
+        assert!(insp
+            .implementation_sstores
+            .iter()
+            .all(|(a, k, _)| *a == proxy && *k == U256::ZERO));
+        assert!(insp
+            .implementation_sloads
+            .iter()
+            .chain(insp.proxy_sloads.iter())
+            .all(|(a, _, _)| *a != beacon));
+
+        // a beacon upgrade changes the implementation of the proxy
+        caller
+            .invoke(
+                &mut state,
+                beacon,
+                "upgrade(address)",
+                &[new_implementation.into()],
+                None,
+                &mut ImplInspector {
+                    proxy,
+                    implementation: None,
+                    beacon: None,
+                    meter: BudgetMeter::default(),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            resolve_implementation(&caller, &mut state, proxy, Budget::default()).unwrap(),
+            ResolvedImplementation {
+                implementation: new_implementation,
+                beacon: Some(beacon),
+            }
+        );
+        assert_eq!(super::beacon_of(&mut state, proxy).unwrap(), Some(beacon));
+    }
+
+    #[test]
+    fn test_beacon_budget() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract SpinningBeacon {
+                function implementation() public pure returns (address) {
+                    uint256 i;
+                    while (true) {
+                        i += 1;
+                    }
+                    return address(uint160(i));
+                }
+            }
+            "#,
+            vec!["SpinningBeacon"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let beacon = addrs.pop().unwrap();
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let budget = Budget {
+            timeout: None,
+            max_steps: Some(10_000),
+        };
+        let r = beacon_implementation(&caller, &mut state, beacon, budget);
+        assert_eq!(r.unwrap_err().kind(), "timeout");
+    }
+}
@@ -0,0 +1,230 @@
+use std::time::{Duration, Instant};
+
+use libsofl_core::engine::types::{InstructionResult, Interpreter};
+use libsofl_utils::config::Config;
+
+use crate::error::DetectorError;
+
+/// The wall-clock time is only checked every this many steps, since reading the clock on each step is costly.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// Budgets of the analyses, configured in the `[budget]` section.
+/// A missing entry means no limit.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BudgetConfig {
+    /// wall-clock time of executing one tx (or one simulated call), in milliseconds
+    pub tx_timeout_ms: Option<u64>,
+    /// number of EVM steps of executing one tx (or one simulated call)
+    pub max_steps: Option<u64>,
+    /// wall-clock time of replaying all txs of one proxy, in milliseconds
+    pub proxy_timeout_ms: Option<u64>,
+}
+
+impl Default for BudgetConfig {
+    fn default() -> Self {
+        Self {
+            // the same as the timeout of ProxyEx
+            tx_timeout_ms: Some(60_000),
+            max_steps: None,
+            proxy_timeout_ms: None,
+        }
+    }
+}
+
+impl Config for BudgetConfig {
+    fn section_name() -> &'static str {
+        "budget"
+    }
+}
+
+impl BudgetConfig {
+    /// The budget of executing one tx.
+    pub fn tx_budget(&self) -> Budget {
+        Budget {
+            timeout: self.tx_timeout_ms.map(Duration::from_millis),
+            max_steps: self.max_steps,
+        }
+    }
+
+    pub fn proxy_timeout(&self) -> Option<Duration> {
+        self.proxy_timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Budget of one execution. The default budget is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub timeout: Option<Duration>,
+    pub max_steps: Option<u64>,
+}
+
+impl Budget {
+    /// Shrink the timeout so that the execution ends before the deadline (e.g., of the proxy).
+    /// Returns a timeout error if the deadline has passed.
+    pub fn until(self, deadline: Option<Instant>) -> Result<Self, DetectorError> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return Ok(self),
+        };
+        let left = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+            .ok_or_else(|| DetectorError::Timeout("proxy deadline passed".to_string()))?;
+        Ok(Self {
+            timeout: Some(self.timeout.map_or(left, |t| t.min(left))),
+            ..self
+        })
+    }
+}
+
+/// BudgetMeter counts the steps and the time of one execution.
+/// Inspectors call `step` on each of their steps, which halts the EVM once the budget is exhausted.
+#[derive(Debug, Clone)]
+pub struct BudgetMeter {
+    pub budget: Budget,
+    pub steps: u64,
+    started_at: Instant,
+    exhausted: Option<String>,
+}
+
+impl Default for BudgetMeter {
+    fn default() -> Self {
+        Self::new(Budget::default())
+    }
+}
+
+impl BudgetMeter {
+    /// The clock starts when the meter is created.
+    pub fn new(budget: Budget) -> Self {
+        Self {
+            budget,
+            steps: 0,
+            started_at: Instant::now(),
+            exhausted: None,
+        }
+    }
+
+    /// Count one step. Once the budget is exhausted, every following step halts its frame,
+    /// so that the execution unwinds to the top-level call.
+    #[inline]
+    pub fn step(&mut self, interp: &mut Interpreter<'_>) {
+        if self.exhausted.is_none() {
+            self.steps += 1;
+            if let Some(max_steps) = self.budget.max_steps {
+                if self.steps > max_steps {
+                    self.exhausted = Some(format!("more than {} steps", max_steps));
+                }
+            }
+            if let Some(timeout) = self.budget.timeout {
+                if self.steps % CLOCK_INTERVAL == 0 && self.started_at.elapsed() > timeout {
+                    self.exhausted = Some(format!(
+                        "more than {}ms after {} steps",
+                        timeout.as_millis(),
+                        self.steps
+                    ));
+                }
+            }
+        }
+        if self.exhausted.is_some() {
+            interp.instruction_result = InstructionResult::OutOfGas;
+        }
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.exhausted.is_some()
+    }
+
+    /// Returns a timeout error if the budget is exhausted.
+    pub fn check(&self) -> Result<(), DetectorError> {
+        match &self.exhausted {
+            Some(reason) => Err(DetectorError::Timeout(reason.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use libsofl_core::engine::{
+        inspector::EvmInspector,
+        memory::MemoryBcState,
+        state::BcState,
+        types::{EVMData, Inspector, Interpreter},
+    };
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use super::{Budget, BudgetMeter};
+
+    struct MeteredInspector {
+        meter: BudgetMeter,
+    }
+
+    impl<S: BcState> Inspector<S> for MeteredInspector {
+        fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+            self.meter.step(interp);
+        }
+    }
+
+    impl<S: BcState> EvmInspector<S> for MeteredInspector {}
+
+    #[test]
+    fn test_budget_until_deadline() {
+        let budget = Budget {
+            timeout: Some(Duration::from_secs(60)),
+            max_steps: None,
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let b = budget.until(Some(deadline)).unwrap();
+        assert!(b.timeout.unwrap() <= Duration::from_secs(10));
+        assert_eq!(budget.until(None).unwrap(), budget);
+
+        let e = budget.until(Some(Instant::now())).unwrap_err();
+        assert_eq!(e.kind(), "timeout");
+    }
+
+    #[test]
+    fn test_halt_infinite_loop() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Loop {
+                function spin() public pure returns (uint256 i) {
+                    while (true) {
+                        i += 1;
+                    }
+                }
+            }
+            "#,
+            vec!["Loop"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let contract = addrs.pop().unwrap();
+
+        let mut insp = MeteredInspector {
+            meter: BudgetMeter::new(Budget {
+                timeout: None,
+                max_steps: Some(10_000),
+            }),
+        };
+        let r = HighLevelCaller::default().bypass_check().invoke(
+            &mut state,
+            contract,
+            "spin()",
+            &[],
+            None,
+            &mut insp,
+        );
+        assert!(r.is_err());
+        assert!(insp.meter.exhausted());
+        assert_eq!(insp.meter.check().unwrap_err().kind(), "timeout");
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, HashSet};
+
+use libsofl_core::{
+    engine::types::{opcode, Address, Database},
+    error::SoflError,
+};
+use sea_orm::sea_query::{Expr, IntoColumnRef, Query, SimpleExpr};
+
+use crate::{entities::clone, selector::code_of};
+
+/// EIP-1167 runtime code before the PUSHn of the implementation.
+const EIP1167_HEAD: [u8; 9] = [0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x36, 0x3d];
+/// EIP-1167 runtime code after the implementation, up to the PUSH1 of the jump destination.
+const EIP1167_TAIL: [u8; 10] = [0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60];
+/// The jump destination of EIP-1167 with a PUSH20 implementation.
+const EIP1167_JUMPDEST: usize = 0x2b;
+/// `JUMPI REVERT JUMPDEST RETURN`, ending the EIP-1167 and ClonesWithImmutableArgs runtime code.
+const DELEGATE_END: [u8; 4] = [0x57, 0xfd, 0x5b, 0xf3];
+
+/// ClonesWithImmutableArgs (wighawag) runtime code, with the 2-byte length of the args after each PUSH2.
+const CWIA_HEAD: [u8; 9] = [0x3d, 0x3d, 0x3d, 0x3d, 0x36, 0x3d, 0x3d, 0x37, 0x61];
+const CWIA_MIDDLE: [u8; 6] = [0x60, 0x37, 0x36, 0x39, 0x36, 0x61];
+const CWIA_PUSH_IMPLEMENTATION: [u8; 3] = [0x01, 0x3d, 0x73];
+const CWIA_TAIL: [u8; 15] = [
+    0x5a, 0xf4, 0x3d, 0x82, 0x80, 0x3e, 0x90, 0x3d, 0x91, 0x60, 0x35, 0x57, 0xfd, 0x5b, 0xf3,
+];
+
+/// EIP-3448 MetaProxy runtime code, followed by the metadata and its length.
+const METAPROXY_HEAD: [u8; 21] = [
+    0x36, 0x3d, 0x3d, 0x37, 0x3d, 0x3d, 0x3d, 0x3d, 0x60, 0x36, 0x80, 0x38, 0x03, 0x80, 0x91, 0x36,
+    0x39, 0x36, 0x01, 0x3d, 0x73,
+];
+const METAPROXY_TAIL: [u8; 13] = [
+    0x5a, 0xf4, 0x3d, 0x3d, 0x93, 0x80, 0x3e, 0x60, 0x34, 0x57, 0xfd, 0x5b, 0xf3,
+];
+
+/// The family of a minimal proxy, i.e., a proxy whose implementation is hard-coded in its bytecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneKind {
+    /// EIP-1167, e.g., OpenZeppelin `Clones`
+    Eip1167,
+    /// EIP-1167 with a shorter PUSH of an implementation address with leading zero bytes
+    Eip1167Vanity,
+    /// immutable args appended to the code, wighawag's ClonesWithImmutableArgs or EIP-1167 followed by data (Solady `LibClone`)
+    ClonesWithImmutableArgs,
+    /// EIP-3448 MetaProxy
+    Eip3448,
+}
+
+impl CloneKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CloneKind::Eip1167 => "eip1167",
+            CloneKind::Eip1167Vanity => "eip1167_vanity",
+            CloneKind::ClonesWithImmutableArgs => "clones_with_immutable_args",
+            CloneKind::Eip3448 => "eip3448",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinimalProxy {
+    pub kind: CloneKind,
+    pub implementation: Address,
+}
+
+/// An address pushed with fewer than 20 bytes has leading zero bytes.
+fn left_padded(bytes: &[u8]) -> Address {
+    let mut address = [0u8; 20];
+    address[20 - bytes.len()..].copy_from_slice(bytes);
+    Address::from(address)
+}
+
+fn recognize_eip1167(code: &[u8]) -> Option<MinimalProxy> {
+    let rest = code.strip_prefix(&EIP1167_HEAD)?;
+    let (push, rest) = rest.split_first()?;
+    if !(opcode::PUSH1..=opcode::PUSH20).contains(push) {
+        return None;
+    }
+    let n = (push - opcode::PUSH1) as usize + 1;
+    let implementation = rest.get(..n)?;
+    let (jumpdest, rest) = rest[n..].strip_prefix(&EIP1167_TAIL)?.split_first()?;
+    // a shorter PUSH moves the jump destination forward
+    if *jumpdest as usize != EIP1167_JUMPDEST - (20 - n) {
+        return None;
+    }
+    let rest = rest.strip_prefix(&DELEGATE_END)?;
+    let kind = if !rest.is_empty() {
+        CloneKind::ClonesWithImmutableArgs
+    } else if n < 20 {
+        CloneKind::Eip1167Vanity
+    } else {
+        CloneKind::Eip1167
+    };
+    Some(MinimalProxy {
+        kind,
+        implementation: left_padded(implementation),
+    })
+}
+
+fn recognize_cwia(code: &[u8]) -> Option<MinimalProxy> {
+    let rest = code.strip_prefix(&CWIA_HEAD)?;
+    let rest = rest.get(2..)?.strip_prefix(&CWIA_MIDDLE)?;
+    let rest = rest.get(2..)?.strip_prefix(&CWIA_PUSH_IMPLEMENTATION)?;
+    let implementation = rest.get(..20)?;
+    if !rest[20..].starts_with(&CWIA_TAIL) {
+        return None;
+    }
+    Some(MinimalProxy {
+        kind: CloneKind::ClonesWithImmutableArgs,
+        implementation: Address::from_slice(implementation),
+    })
+}
+
+fn recognize_metaproxy(code: &[u8]) -> Option<MinimalProxy> {
+    let rest = code.strip_prefix(&METAPROXY_HEAD)?;
+    let implementation = rest.get(..20)?;
+    if !rest[20..].starts_with(&METAPROXY_TAIL) {
+        return None;
+    }
+    Some(MinimalProxy {
+        kind: CloneKind::Eip3448,
+        implementation: Address::from_slice(implementation),
+    })
+}
+
+/// Recognize the runtime code of a minimal proxy and extract its hard-coded implementation.
+pub fn recognize(code: &[u8]) -> Option<MinimalProxy> {
+    recognize_eip1167(code)
+        .or_else(|| recognize_cwia(code))
+        .or_else(|| recognize_metaproxy(code))
+}
+
+/// The minimal proxy at the address, None if its code is not a known template.
+pub fn minimal_proxy_of<D: Database>(
+    state: &mut D,
+    address: Address,
+) -> Result<Option<MinimalProxy>, SoflError>
+where
+    D::Error: std::fmt::Debug,
+{
+    Ok(recognize(&code_of(state, address)?))
+}
+
+/// Choose one representative among the clones of each implementation, given as (proxy, implementation, invocation count):
+/// the clone with the most invocations, then the smallest address.
+pub fn representatives(clones: &[(String, String, i32)]) -> HashSet<String> {
+    let mut chosen: BTreeMap<&str, (&str, i32)> = BTreeMap::new();
+    for (proxy, implementation, count) in clones {
+        let better = match chosen.get(implementation.as_str()) {
+            Some((p, c)) => {
+                (*count, std::cmp::Reverse(proxy.as_str())) > (*c, std::cmp::Reverse(*p))
+            }
+            None => true,
+        };
+        if better {
+            chosen.insert(implementation, (proxy, *count));
+        }
+    }
+    chosen.values().map(|(p, _)| p.to_string()).collect()
+}
+
+/// Condition that the item (a proxy column of the iterated table) is not a clone other than the representative of its implementation,
+/// so that a detector analyzes the same implementation only once.
+pub fn not_redundant_clone<C: IntoColumnRef>(item: C) -> SimpleExpr {
+    Expr::exists(
+        Query::select()
+            .from(clone::Entity)
+            .and_where(Expr::col((clone::Entity, clone::Column::Proxy)).equals(item))
+            .and_where(Expr::col((clone::Entity, clone::Column::Representative)).eq(false))
+            .take(),
+    )
+    .not()
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::{
+        conversion::ConvertTo,
+        engine::types::{Address, Bytes},
+    };
+
+    use super::{recognize, representatives, CloneKind};
+
+    fn code(hex: &str) -> Bytes {
+        hex.cvt()
+    }
+
+    #[test]
+    fn test_recognize() {
+        let implementation: Address = "0xbebebebebebebebebebebebebebebebebebebebe".cvt();
+        let cases = [
+            (
+                "0x363d3d373d3d3d363d73bebebebebebebebebebebebebebebebebebebebe5af43d82803e903d91602b57fd5bf3",
+                CloneKind::Eip1167,
+                implementation,
+            ),
+            (
+                "0x363d3d373d3d3d363d6fbebebebebebebebebebebebebebebebe5af43d82803e903d91602757fd5bf3",
+                CloneKind::Eip1167Vanity,
+                "0x00000000bebebebebebebebebebebebebebebebe".cvt(),
+            ),
+            (
+                "0x3d3d3d3d363d3d376100066037363936610006013d73bebebebebebebebebebebebebebebebebebebebe5af43d82803e903d91603557fd5bf3aabbccdd0006",
+                CloneKind::ClonesWithImmutableArgs,
+                implementation,
+            ),
+            (
+                "0x363d3d373d3d3d363d73bebebebebebebebebebebebebebebebebebebebe5af43d82803e903d91602b57fd5bf3aabb",
+                CloneKind::ClonesWithImmutableArgs,
+                implementation,
+            ),
+            (
+                "0x363d3d373d3d3d3d60368038038091363936013d73bebebebebebebebebebebebebebebebebebebebe5af43d3d93803e603457fd5bf3aabb0000000000000000000000000000000000000000000000000000000000000002",
+                CloneKind::Eip3448,
+                implementation,
+            ),
+        ];
+        for (hex, kind, implementation) in cases {
+            let proxy = recognize(&code(hex)).unwrap();
+            assert_eq!(proxy.kind, kind, "{}", hex);
+            assert_eq!(proxy.implementation, implementation, "{}", hex);
+        }
+
+        // a vanity PUSH with the jump destination of PUSH20
+        assert!(recognize(&code(
+            "0x363d3d373d3d3d363d6fbebebebebebebebebebebebebebebebe5af43d82803e903d91602b57fd5bf3"
+        ))
+        .is_none());
+        // a truncated clone and an ordinary contract
+        assert!(recognize(&code("0x363d3d373d3d3d363d73bebebebe")).is_none());
+        assert!(recognize(&code("0x6080604052348015600f57600080fd5b50")).is_none());
+    }
+
+    #[test]
+    fn test_representatives() {
+        let clones = vec![
+            ("0x02".to_string(), "0xa".to_string(), 3),
+            ("0x01".to_string(), "0xa".to_string(), 3),
+            ("0x03".to_string(), "0xa".to_string(), 1),
+            ("0x04".to_string(), "0xb".to_string(), 0),
+        ];
+        let mut chosen = representatives(&clones).into_iter().collect::<Vec<_>>();
+        chosen.sort();
+        assert_eq!(chosen, vec!["0x01", "0x04"]);
+    }
+}
@@ -3,6 +3,7 @@ use sea_orm::{ConnectOptions, Database, DatabaseConnection, DbErr};
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ProxyExDetectorConfig {
+    /// `postgres://..` or `sqlite://path/to/file.db`, the backend is selected by the scheme
     pub database_url: String,
 }
 
@@ -22,9 +23,49 @@ impl Config for ProxyExDetectorConfig {
 
 impl ProxyExDetectorConfig {
     pub async fn db(&self) -> Result<DatabaseConnection, DbErr> {
-        let mut opt = ConnectOptions::new(self.database_url.clone());
+        let mut opt = ConnectOptions::new(self.connect_url());
         opt.sqlx_logging(false)
             .sqlx_logging_level(log::LevelFilter::Off);
         Database::connect(opt).await
     }
+
+    /// The url to connect. A SQLite database file is created if it does not exist yet,
+    /// unless the url sets its own `mode`.
+    pub fn connect_url(&self) -> String {
+        let url = &self.database_url;
+        if !url.starts_with("sqlite:") || url.contains(":memory:") || url.contains("mode=") {
+            return url.clone();
+        }
+        let sep = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}mode=rwc", url, sep)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProxyExDetectorConfig;
+
+    #[test]
+    fn test_connect_url() {
+        let url = |database_url: &str| {
+            ProxyExDetectorConfig {
+                database_url: database_url.to_string(),
+            }
+            .connect_url()
+        };
+        assert_eq!(
+            url("postgres://localhost:5432/postgres"),
+            "postgres://localhost:5432/postgres"
+        );
+        assert_eq!(url("sqlite::memory:"), "sqlite::memory:");
+        assert_eq!(url("sqlite://proxyex.db"), "sqlite://proxyex.db?mode=rwc");
+        assert_eq!(
+            url("sqlite://proxyex.db?cache=shared"),
+            "sqlite://proxyex.db?cache=shared&mode=rwc"
+        );
+        assert_eq!(
+            url("sqlite://proxyex.db?mode=ro"),
+            "sqlite://proxyex.db?mode=ro"
+        );
+    }
 }
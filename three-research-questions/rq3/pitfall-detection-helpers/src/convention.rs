@@ -0,0 +1,352 @@
+use std::str::FromStr;
+
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        types::{Address, Bytes, Database, U256},
+    },
+    error::SoflError,
+};
+use libsofl_utils::{config::Config, solidity::caller::HighLevelCaller};
+
+use crate::{
+    beacon::{beacon_implementation, beacon_of},
+    budget::Budget,
+    clone::minimal_proxy_of,
+    error::DetectorError,
+    selector::code_of,
+    slot::{EIP1822_PROXIABLE_SLOT, EIP1967_IMPLEMENTATION_SLOT, OZ_LEGACY_IMPLEMENTATION_SLOT},
+};
+
+/// A per-project implementation slot, configured in the `[conventions]` section.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SlotConvention {
+    /// the name reported when the slot holds the implementation
+    pub name: String,
+    /// the slot in hex
+    pub slot: String,
+    /// only take the value as an implementation if it is an address with code,
+    /// for a slot that may hold an ordinary state variable instead, e.g., slot 0
+    #[serde(default)]
+    pub require_code: bool,
+}
+
+/// How to find the implementation of a proxy, configured in the `[conventions]` section.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ConventionConfig {
+    /// slots tried after the built-in conventions
+    pub custom_slots: Vec<SlotConvention>,
+    /// selectors (in hex) called on the proxy one by one until it delegates, the fallback is called at last
+    pub probe_selectors: Vec<String>,
+}
+
+impl Default for ConventionConfig {
+    fn default() -> Self {
+        Self {
+            custom_slots: vec![],
+            // owner(), implementation(), name(), symbol(), totalSupply()
+            probe_selectors: vec![
+                "0x8da5cb5b".to_string(),
+                "0x5c60da1b".to_string(),
+                "0x06fdde03".to_string(),
+                "0x95d89b41".to_string(),
+                "0x18160ddd".to_string(),
+            ],
+        }
+    }
+}
+
+impl Config for ConventionConfig {
+    fn section_name() -> &'static str {
+        "conventions"
+    }
+}
+
+impl ConventionConfig {
+    /// Load the `[conventions]` section, failing on the first custom slot or probe selector that is not valid.
+    pub fn load_checked() -> Result<Self, SoflError> {
+        let cfg =
+            Self::load_or(Default::default()).map_err(|e| SoflError::Custom(format!("{:?}", e)))?;
+        cfg.registry()?;
+        cfg.probe_inputs()?;
+        Ok(cfg)
+    }
+
+    /// The built-in conventions followed by the custom slots.
+    pub fn registry(&self) -> Result<ConventionRegistry, SoflError> {
+        let mut registry = ConventionRegistry::default();
+        for custom in &self.custom_slots {
+            let slot = U256::from_str(&custom.slot).map_err(|e| {
+                SoflError::Custom(format!(
+                    "invalid slot `{}` of custom convention `{}`: {}",
+                    custom.slot, custom.name, e
+                ))
+            })?;
+            registry.conventions.push(Convention::Slot {
+                name: custom.name.clone(),
+                slot,
+                require_code: custom.require_code,
+            });
+        }
+        Ok(registry)
+    }
+
+    /// The calldata to probe the proxy with, ending with the empty calldata reaching the fallback.
+    pub fn probe_inputs(&self) -> Result<Vec<Bytes>, SoflError> {
+        let mut inputs = Vec::new();
+        for selector in &self.probe_selectors {
+            match Bytes::from_str(selector) {
+                Ok(input) if input.len() == 4 => inputs.push(input),
+                _ => {
+                    return Err(SoflError::Custom(format!(
+                        "invalid probe selector `{}`, expected 4 bytes in hex",
+                        selector
+                    )))
+                }
+            }
+        }
+        inputs.push(Bytes::new());
+        Ok(inputs)
+    }
+}
+
+/// Where a proxy stores its implementation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Convention {
+    /// the implementation is stored in a slot of the proxy
+    Slot {
+        name: String,
+        slot: U256,
+        require_code: bool,
+    },
+    /// the implementation is returned by `implementation()` of the beacon in the EIP-1967 beacon slot
+    Beacon,
+}
+
+impl Convention {
+    fn slot(name: &str, slot: &str, require_code: bool) -> Self {
+        Convention::Slot {
+            name: name.to_string(),
+            slot: slot.cvt(),
+            require_code,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Convention::Slot { name, .. } => name,
+            Convention::Beacon => "eip1967-beacon",
+        }
+    }
+}
+
+/// The implementation of a proxy and the convention it is found by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchedImplementation {
+    pub implementation: Address,
+    /// the name of the convention, or the kind of the minimal proxy (e.g., `eip1167`)
+    pub convention: String,
+}
+
+/// The conventions tried in order to find the implementation of a proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionRegistry {
+    pub conventions: Vec<Convention>,
+}
+
+impl Default for ConventionRegistry {
+    fn default() -> Self {
+        Self {
+            conventions: vec![
+                Convention::slot("eip1822", EIP1822_PROXIABLE_SLOT, false),
+                Convention::slot("eip1967", EIP1967_IMPLEMENTATION_SLOT, false),
+                Convention::Beacon,
+                Convention::slot("oz-legacy", OZ_LEGACY_IMPLEMENTATION_SLOT, false),
+                // the `masterCopy` (or `singleton`) of Gnosis Safe proxies
+                Convention::slot("gnosis-safe", "0x0", true),
+            ],
+        }
+    }
+}
+
+impl ConventionRegistry {
+    /// Find the implementation of a proxy.
+    /// A minimal proxy is recognized by its code first, since its implementation is not stored in any slot.
+    /// Returns None if no convention matches. The `implementation()` call on a beacon is bounded by the budget.
+    pub fn resolve<S: BcState>(
+        &self,
+        caller: &HighLevelCaller,
+        state: &mut S,
+        proxy: Address,
+        budget: Budget,
+    ) -> Result<Option<MatchedImplementation>, DetectorError>
+    where
+        <S as Database>::Error: std::fmt::Debug,
+    {
+        if let Some(clone) =
+            minimal_proxy_of(state, proxy).map_err(DetectorError::state_unavailable)?
+        {
+            return Ok(Some(MatchedImplementation {
+                implementation: clone.implementation,
+                convention: clone.kind.as_str().to_string(),
+            }));
+        }
+        for convention in &self.conventions {
+            let implementation = match convention {
+                Convention::Slot {
+                    slot, require_code, ..
+                } => {
+                    let value = state
+                        .storage(proxy, *slot)
+                        .map_err(DetectorError::state_unavailable)?;
+                    // the upper bytes of an address are zero
+                    if value == U256::ZERO || value >> 160 != U256::ZERO {
+                        continue;
+                    }
+                    let implementation: Address = value.cvt();
+                    if *require_code
+                        && code_of(state, implementation)
+                            .map_err(DetectorError::state_unavailable)?
+                            .is_empty()
+                    {
+                        continue;
+                    }
+                    Some(implementation)
+                }
+                Convention::Beacon => {
+                    match beacon_of(state, proxy).map_err(DetectorError::state_unavailable)? {
+                        Some(beacon) => beacon_implementation(caller, state, beacon, budget)?,
+                        None => None,
+                    }
+                }
+            };
+            if let Some(implementation) = implementation {
+                return Ok(Some(MatchedImplementation {
+                    implementation,
+                    convention: convention.name().to_string(),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::{memory::MemoryBcState, types::Address};
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::Budget;
+
+    use super::{ConventionConfig, ConventionRegistry, SlotConvention};
+
+    #[test]
+    fn test_resolve() {
+        let mut state = MemoryBcState::fresh();
+        let addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                uint256 public value;
+            }
+            contract ZosProxy {
+                constructor() {
+                    address _impl = address(new Impl());
+                    assembly {
+                        sstore(0x7050c9e0f4ca769c69bd3a8ef740bc37934f8e2c036e5a723fd8ee048ed3f8c3, _impl)
+                    }
+                }
+            }
+            contract SafeProxy {
+                address internal singleton;
+                constructor() {
+                    singleton = address(new Impl());
+                }
+            }
+            contract Owned {
+                address internal owner;
+                constructor() {
+                    owner = address(uint160(0xdead));
+                }
+            }
+            contract CustomProxy {
+                constructor() {
+                    address _impl = address(new Impl());
+                    assembly {
+                        sstore(0x1234, _impl)
+                    }
+                }
+            }
+            "#,
+            vec!["ZosProxy", "SafeProxy", "Owned", "CustomProxy"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let caller = HighLevelCaller::default().bypass_check();
+        let registry = ConventionRegistry::default();
+        let convention = |state: &mut MemoryBcState, registry: &ConventionRegistry, proxy| {
+            registry
+                .resolve(&caller, state, proxy, Budget::default())
+                .unwrap()
+                .map(|m| {
+                    assert_ne!(m.implementation, Address::ZERO);
+                    m.convention
+                })
+        };
+
+        assert_eq!(
+            convention(&mut state, &registry, addrs[0]).as_deref(),
+            Some("oz-legacy")
+        );
+        assert_eq!(
+            convention(&mut state, &registry, addrs[1]).as_deref(),
+            Some("gnosis-safe")
+        );
+        // slot 0 holds an address without code
+        assert_eq!(convention(&mut state, &registry, addrs[2]), None);
+        assert_eq!(convention(&mut state, &registry, addrs[3]), None);
+
+        let registry = ConventionConfig {
+            custom_slots: vec![SlotConvention {
+                name: "custom".to_string(),
+                slot: "0x1234".to_string(),
+                require_code: false,
+            }],
+            probe_selectors: vec![],
+        }
+        .registry()
+        .unwrap();
+        assert_eq!(
+            convention(&mut state, &registry, addrs[3]).as_deref(),
+            Some("custom")
+        );
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert_eq!(ConventionConfig::default().probe_inputs().unwrap().len(), 6);
+
+        let cfg = ConventionConfig {
+            custom_slots: vec![SlotConvention {
+                name: "typo".to_string(),
+                slot: "0x12g4".to_string(),
+                require_code: false,
+            }],
+            probe_selectors: vec!["0x8da5cb5b".to_string(), "0x8da5cb".to_string()],
+        };
+        let err = format!("{:?}", cfg.registry().unwrap_err());
+        assert!(
+            err.contains("`0x12g4`") && err.contains("`typo`"),
+            "{}",
+            err
+        );
+        let err = format!("{:?}", cfg.probe_inputs().unwrap_err());
+        assert!(err.contains("`0x8da5cb`"), "{}", err);
+    }
+}
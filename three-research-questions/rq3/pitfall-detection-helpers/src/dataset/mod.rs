@@ -1,3 +1,19 @@
+use std::collections::BTreeMap;
+
+use libsofl_utils::log::debug;
+use sea_orm::{
+    sea_query::{self, Expr},
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, DatabaseTransaction, DbErr,
+    EntityTrait, PaginatorTrait, QueryFilter, QuerySelect, TransactionTrait,
+};
+
+use crate::entities;
+
+pub mod trace;
+
+/// Number of invocations inserted in one statement.
+const INSERT_BATCH: usize = 1000;
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ImplInvocation {
     pub tx: String,
@@ -14,8 +30,240 @@ pub struct ProxyData {
     pub impls: Vec<ImplInvocation>,
 }
 
+impl ProxyData {
+    /// The invocations in the order they are replayed, i.e., by block and tx, without duplicated txs.
+    pub fn ordered_impls(&self) -> Vec<ImplInvocation> {
+        let mut impls = self.impls.clone();
+        impls.sort_by(|a, b| (a.block, &a.tx).cmp(&(b.block, &b.tx)));
+        impls.dedup_by(|a, b| a.tx == b.tx);
+        impls
+    }
+
+    /// The implementation versions, each with the first block it is invoked (ascending),
+    /// the same as the `version` table.
+    pub fn versions(&self) -> Vec<(String, u64)> {
+        let mut versions: Vec<(String, u64)> = Vec::new();
+        for invocation in self.ordered_impls() {
+            if !versions
+                .iter()
+                .any(|(i, _)| *i == invocation.implementation)
+            {
+                versions.push((invocation.implementation, invocation.block));
+            }
+        }
+        versions
+    }
+}
+
+/// Each implementation with the first block it is invoked by any of the proxies, ordered by implementation,
+/// the same as the implementations of the `version` table grouped with their minimum `min_block`.
+pub fn first_used_implementations<I: IntoIterator<Item = ProxyData>>(
+    data: I,
+) -> Vec<(String, u64)> {
+    let mut implementations: BTreeMap<String, u64> = BTreeMap::new();
+    for proxy in data {
+        for (implementation, block) in proxy.versions() {
+            let min_block = implementations
+                .entry(implementation.to_lowercase())
+                .or_insert(block);
+            *min_block = (*min_block).min(block);
+        }
+    }
+    implementations.into_iter().collect()
+}
+
+/// Save the invocations of one proxy in one transaction, replacing those saved before,
+/// so that an interrupted import can simply be run again.
+/// Returns false if the proxy has been saved already, i.e., its invocation count matches the data.
+pub async fn save_invocations(db: &DatabaseConnection, data: ProxyData) -> Result<bool, DbErr> {
+    let proxy_addr = data.proxy.to_lowercase();
+    let impls = data.ordered_impls();
+    let existing = entities::proxy::Entity::find_by_id(proxy_addr.clone())
+        .one(db)
+        .await?;
+    if existing.map_or(false, |p| p.invocation_count as usize == impls.len()) {
+        debug!(proxy = proxy_addr, "Proxy already imported");
+        return Ok(false);
+    }
+
+    let txn = db.begin().await?;
+    let proxy = entities::proxy::ActiveModel {
+        address: ActiveValue::Set(proxy_addr.clone()),
+        invocation_count: ActiveValue::Set(impls.len() as i32),
+    };
+    entities::proxy::Entity::insert(proxy)
+        .on_conflict(
+            sea_query::OnConflict::column(entities::proxy::Column::Address)
+                .update_column(entities::proxy::Column::InvocationCount)
+                .to_owned(),
+        )
+        .exec(&txn)
+        .await?;
+    // the invocations of a partial or outdated import are replaced
+    entities::invocation::Entity::delete_many()
+        .filter(entities::invocation::Column::Proxy.eq(proxy_addr.clone()))
+        .exec(&txn)
+        .await?;
+    for chunk in impls.chunks(INSERT_BATCH) {
+        let invocations = chunk.iter().map(|i| entities::invocation::ActiveModel {
+            tx: ActiveValue::Set(i.tx.to_lowercase()),
+            implementation: ActiveValue::Set(i.implementation.to_lowercase()),
+            proxy: ActiveValue::Set(proxy_addr.clone()),
+            block: ActiveValue::Set(i.block as i64),
+            ..Default::default()
+        });
+        entities::invocation::Entity::insert_many(invocations)
+            .exec(&txn)
+            .await?;
+    }
+    txn.commit().await?;
+    debug!(
+        proxy = proxy_addr,
+        invocations = impls.len(),
+        "Invocations saved"
+    );
+    Ok(true)
+}
+
+/// The last block in which any proxy is invoked, i.e., the end of the dataset. None if there is no invocation.
+pub async fn last_invocation_block(db: &DatabaseConnection) -> Result<Option<u64>, DbErr> {
+    let block: Option<Option<i64>> = entities::invocation::Entity::find()
+        .select_only()
+        .column_as(entities::invocation::Column::Block.max(), "last_block")
+        .into_tuple()
+        .one(db)
+        .await?;
+    Ok(block.flatten().map(|b| b as u64))
+}
+
+/// Save the invocations found in one block, replacing those of the same proxies saved before in the block,
+/// and update the invocation count of the proxies.
+/// The block is saved in one transaction, so that a block range can simply be extracted again.
+pub async fn save_block_invocations(
+    db: &DatabaseConnection,
+    block: u64,
+    invocations: Vec<(String, ImplInvocation)>,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    for (proxy_addr, impls) in by_proxy(invocations) {
+        let replaced = Condition::all().add(entities::invocation::Column::Block.eq(block as i64));
+        replace_invocations(&txn, &proxy_addr, impls, replaced).await?;
+    }
+    txn.commit().await?;
+    debug!(block = block, "Block invocations saved");
+    Ok(())
+}
+
+/// Save the invocations found in one shard of traces, replacing those of the same proxies and txs saved before,
+/// and update the invocation count of the proxies.
+/// The shard is saved in one transaction, so that the shards can be ingested one by one and simply ingested again.
+pub async fn save_shard_invocations(
+    db: &DatabaseConnection,
+    invocations: Vec<(String, ImplInvocation)>,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    for (proxy_addr, impls) in by_proxy(invocations) {
+        let txs = impls
+            .iter()
+            .map(|i| i.tx.to_lowercase())
+            .collect::<Vec<_>>();
+        // keep the number of bound parameters of each statement small
+        let mut replaced = Condition::any();
+        for chunk in txs.chunks(INSERT_BATCH) {
+            replaced = replaced.add(entities::invocation::Column::Tx.is_in(chunk.to_vec()));
+        }
+        replace_invocations(&txn, &proxy_addr, impls, replaced).await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// The invocations grouped by proxy (lower case), each ordered by block and tx without duplicated txs.
+fn by_proxy(invocations: Vec<(String, ImplInvocation)>) -> BTreeMap<String, Vec<ImplInvocation>> {
+    let mut proxies: BTreeMap<String, Vec<ImplInvocation>> = BTreeMap::new();
+    for (proxy, invocation) in invocations {
+        proxies
+            .entry(proxy.to_lowercase())
+            .or_default()
+            .push(invocation);
+    }
+    proxies
+        .into_iter()
+        .map(|(proxy, impls)| {
+            let impls = ProxyData {
+                proxy: proxy.clone(),
+                impls,
+            }
+            .ordered_impls();
+            (proxy, impls)
+        })
+        .collect()
+}
+
+/// Replace the invocations of a proxy matching `replaced` with `impls`, and update its invocation count.
+async fn replace_invocations(
+    txn: &DatabaseTransaction,
+    proxy_addr: &str,
+    impls: Vec<ImplInvocation>,
+    replaced: Condition,
+) -> Result<(), DbErr> {
+    // a new proxy is counted below
+    let proxy = entities::proxy::ActiveModel {
+        address: ActiveValue::Set(proxy_addr.to_string()),
+        invocation_count: ActiveValue::Set(0),
+    };
+    let r = entities::proxy::Entity::insert(proxy)
+        .on_conflict(
+            sea_query::OnConflict::column(entities::proxy::Column::Address)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec(txn)
+        .await;
+    match r {
+        Ok(_) | Err(DbErr::RecordNotInserted) => {}
+        Err(e) => return Err(e),
+    }
+    entities::invocation::Entity::delete_many()
+        .filter(entities::invocation::Column::Proxy.eq(proxy_addr))
+        .filter(replaced)
+        .exec(txn)
+        .await?;
+    for chunk in impls.chunks(INSERT_BATCH) {
+        let invocations = chunk.iter().map(|i| entities::invocation::ActiveModel {
+            tx: ActiveValue::Set(i.tx.to_lowercase()),
+            implementation: ActiveValue::Set(i.implementation.to_lowercase()),
+            proxy: ActiveValue::Set(proxy_addr.to_string()),
+            block: ActiveValue::Set(i.block as i64),
+            ..Default::default()
+        });
+        entities::invocation::Entity::insert_many(invocations)
+            .exec(txn)
+            .await?;
+    }
+    let count = entities::invocation::Entity::find()
+        .filter(entities::invocation::Column::Proxy.eq(proxy_addr))
+        .count(txn)
+        .await?;
+    entities::proxy::Entity::update_many()
+        .col_expr(
+            entities::proxy::Column::InvocationCount,
+            Expr::value(count as i32),
+        )
+        .filter(entities::proxy::Column::Address.eq(proxy_addr))
+        .exec(txn)
+        .await?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use sea_orm::{
+        ColumnTrait, ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait,
+        PaginatorTrait, QueryFilter, Schema,
+    };
+
+    use crate::entities;
 
     #[test]
     fn test_load_data() {
@@ -33,4 +281,159 @@ mod tests {
         );
         assert_eq!(d.impls[0].block, 11203251);
     }
+
+    #[test]
+    fn test_versions() {
+        let data = r#"{"proxy": "0x01", "impls": [{"tx": "0x03", "impl": "0xb", "block": 3}, {"tx": "0x01", "impl": "0xa", "block": 1}, {"tx": "0x02", "impl": "0xb", "block": 2}, {"tx": "0x01", "impl": "0xa", "block": 1}]}"#;
+        let d: super::ProxyData = serde_json::from_str(data).unwrap();
+        let txs = d
+            .ordered_impls()
+            .into_iter()
+            .map(|i| i.tx)
+            .collect::<Vec<_>>();
+        assert_eq!(txs, vec!["0x01", "0x02", "0x03"]);
+        assert_eq!(
+            d.versions(),
+            vec![("0xa".to_string(), 1), ("0xb".to_string(), 2)]
+        );
+
+        let other = r#"{"proxy": "0x02", "impls": [{"tx": "0x04", "impl": "0xB", "block": 1}, {"tx": "0x05", "impl": "0xc", "block": 5}]}"#;
+        let other: super::ProxyData = serde_json::from_str(other).unwrap();
+        assert_eq!(
+            super::first_used_implementations([d, other]),
+            vec![
+                ("0xa".to_string(), 1),
+                ("0xb".to_string(), 1),
+                ("0xc".to_string(), 5)
+            ]
+        );
+    }
+
+    async fn setup() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(DbBackend::Sqlite);
+        for sql in [
+            schema.create_table_from_entity(entities::proxy::Entity),
+            schema.create_table_from_entity(entities::invocation::Entity),
+        ] {
+            db.execute(db.get_database_backend().build(&sql))
+                .await
+                .unwrap();
+        }
+        db
+    }
+
+    async fn invocations(db: &DatabaseConnection, proxy: &str) -> (i32, u64) {
+        let p = entities::proxy::Entity::find_by_id(proxy.to_string())
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+        let n = entities::invocation::Entity::find()
+            .filter(entities::invocation::Column::Proxy.eq(proxy))
+            .count(db)
+            .await
+            .unwrap();
+        (p.invocation_count, n)
+    }
+
+    #[tokio::test]
+    async fn test_reimport_invocations() {
+        let db = setup().await;
+        let data = r#"{"proxy": "0xABC", "impls": [{"tx": "0x01", "impl": "0xA", "block": 1}, {"tx": "0x02", "impl": "0xA", "block": 2}]}"#;
+        let data: super::ProxyData = serde_json::from_str(data).unwrap();
+        assert!(super::save_invocations(&db, data.clone()).await.unwrap());
+        assert_eq!(invocations(&db, "0xabc").await, (2, 2));
+
+        // importing the same data again is a no-op
+        assert!(!super::save_invocations(&db, data.clone()).await.unwrap());
+        assert_eq!(invocations(&db, "0xabc").await, (2, 2));
+
+        // a proxy with more invocations replaces those imported before
+        let mut more = data;
+        more.impls.push(super::ImplInvocation {
+            tx: "0x03".to_string(),
+            implementation: "0xB".to_string(),
+            block: 3,
+        });
+        assert!(super::save_invocations(&db, more).await.unwrap());
+        assert_eq!(invocations(&db, "0xabc").await, (3, 3));
+    }
+
+    #[tokio::test]
+    async fn test_save_block_invocations() {
+        let db = setup().await;
+        let data = r#"{"proxy": "0xabc", "impls": [{"tx": "0x01", "impl": "0xa", "block": 1}]}"#;
+        let data: super::ProxyData = serde_json::from_str(data).unwrap();
+        assert!(super::save_invocations(&db, data).await.unwrap());
+
+        let invocation = |tx: &str| super::ImplInvocation {
+            tx: tx.to_string(),
+            implementation: "0xb".to_string(),
+            block: 5,
+        };
+        let found = vec![
+            ("0xABC".to_string(), invocation("0x05")),
+            ("0xabc".to_string(), invocation("0x06")),
+            ("0xdef".to_string(), invocation("0x06")),
+        ];
+        super::save_block_invocations(&db, 5, found.clone())
+            .await
+            .unwrap();
+        assert_eq!(invocations(&db, "0xabc").await, (3, 3));
+        assert_eq!(invocations(&db, "0xdef").await, (1, 1));
+
+        // extracting the block again replaces its invocations
+        super::save_block_invocations(&db, 5, found).await.unwrap();
+        assert_eq!(invocations(&db, "0xabc").await, (3, 3));
+    }
+
+    #[tokio::test]
+    async fn test_last_invocation_block() {
+        let db = setup().await;
+        assert_eq!(super::last_invocation_block(&db).await.unwrap(), None);
+        let invocation = |tx: &str, block: u64| super::ImplInvocation {
+            tx: tx.to_string(),
+            implementation: "0xa".to_string(),
+            block,
+        };
+        super::save_shard_invocations(
+            &db,
+            vec![
+                ("0xabc".to_string(), invocation("0x01", 7)),
+                ("0xdef".to_string(), invocation("0x02", 3)),
+            ],
+        )
+        .await
+        .unwrap();
+        assert_eq!(super::last_invocation_block(&db).await.unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_save_shard_invocations() {
+        let db = setup().await;
+        let invocation = |tx: &str, block: u64| super::ImplInvocation {
+            tx: tx.to_string(),
+            implementation: "0xa".to_string(),
+            block,
+        };
+        let shard = vec![
+            ("0xabc".to_string(), invocation("0x01", 1)),
+            ("0xABC".to_string(), invocation("0x02", 2)),
+        ];
+        super::save_shard_invocations(&db, shard.clone())
+            .await
+            .unwrap();
+        assert_eq!(invocations(&db, "0xabc").await, (2, 2));
+
+        // the invocations of another shard are added
+        super::save_shard_invocations(&db, vec![("0xabc".to_string(), invocation("0x03", 3))])
+            .await
+            .unwrap();
+        assert_eq!(invocations(&db, "0xabc").await, (3, 3));
+
+        // ingesting a shard again replaces its invocations
+        super::save_shard_invocations(&db, shard).await.unwrap();
+        assert_eq!(invocations(&db, "0xabc").await, (3, 3));
+    }
 }
@@ -0,0 +1,288 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+};
+
+use crate::io::JsonlSource;
+
+use super::{ImplInvocation, ProxyData};
+
+/// The block before which the invocations are collected (inclusive), the same as `TIMESTAMP` of
+/// `logic-contract-prep/scripts-analyze/analyze.py`.
+pub const DEFAULT_CUTOFF_BLOCK: u64 = 18112972;
+
+/// One row of the BigQuery `crypto_ethereum.traces` table, as dumped in `logic-contract-prep/data/*.json`.
+/// Only the fields needed to find the delegatecalls of proxies are kept.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Trace {
+    pub transaction_hash: Option<String>,
+    pub from_address: Option<String>,
+    pub to_address: Option<String>,
+    pub input: Option<String>,
+    /// `call`, `delegatecall`, `staticcall` or `callcode`; absent for rewards, creations and suicides.
+    pub call_type: Option<String>,
+    /// The comma-separated path of the trace in the call tree, absent (or empty) for the top-level call.
+    pub trace_address: Option<String>,
+    #[serde(deserialize_with = "deserialize_block_number")]
+    pub block_number: u64,
+}
+
+/// BigQuery exports `block_number` as a string.
+fn deserialize_block_number<'de, D: serde::Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum BlockNumber {
+        Number(u64),
+        String(String),
+    }
+    match serde::Deserialize::deserialize(d)? {
+        BlockNumber::Number(n) => Ok(n),
+        BlockNumber::String(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+impl Trace {
+    fn is_call_type(&self, call_type: &str) -> bool {
+        self.call_type.as_deref() == Some(call_type)
+    }
+
+    fn trace_address(&self) -> &str {
+        self.trace_address.as_deref().unwrap_or("")
+    }
+
+    /// The trace address of the call this trace is made in, `""` for the top-level call.
+    fn parent_trace_address(&self) -> Option<&str> {
+        match self.trace_address() {
+            "" => None,
+            a => Some(a.rsplit_once(',').map_or("", |(parent, _)| parent)),
+        }
+    }
+}
+
+/// Find the invocations of proxies in the traces of one tx, i.e., the delegatecalls forwarding the
+/// calldata of the call to the proxy, and return them with their proxy.
+/// A delegatecall of a proxy not in `proxies` is ignored, unless `proxies` is None.
+pub fn find_invocations(
+    traces: &[Trace],
+    proxies: Option<&HashSet<String>>,
+) -> Vec<(String, ImplInvocation)> {
+    let mut invocations = Vec::new();
+    for delegatecall in traces.iter().filter(|t| t.is_call_type("delegatecall")) {
+        let (proxy, implementation, tx) = match (
+            &delegatecall.from_address,
+            &delegatecall.to_address,
+            &delegatecall.transaction_hash,
+        ) {
+            (Some(proxy), Some(implementation), Some(tx)) => (
+                proxy.to_lowercase(),
+                implementation.to_lowercase(),
+                tx.to_lowercase(),
+            ),
+            _ => continue,
+        };
+        if proxies.map_or(false, |ps| !ps.contains(&proxy)) {
+            continue;
+        }
+        let parent = delegatecall.parent_trace_address();
+        let forwarded = traces.iter().any(|call| {
+            call.is_call_type("call")
+                && Some(call.trace_address()) == parent
+                && call.input == delegatecall.input
+                && call
+                    .to_address
+                    .as_ref()
+                    .map_or(false, |to| to.to_lowercase() == proxy)
+        });
+        if forwarded {
+            invocations.push((
+                proxy,
+                ImplInvocation {
+                    tx,
+                    implementation,
+                    block: delegatecall.block_number,
+                },
+            ));
+        }
+    }
+    invocations
+}
+
+/// Read one shard of traces and find the invocations of proxies in blocks up to `cutoff_block`.
+/// The traces of a tx are expected to be in the same shard.
+pub fn read_shard<P: AsRef<Path>>(
+    path: P,
+    cutoff_block: u64,
+    proxies: Option<&HashSet<String>>,
+) -> io::Result<Vec<(String, ImplInvocation)>> {
+    // only the calls are kept in memory, grouped by tx
+    let mut txs: HashMap<String, Vec<Trace>> = HashMap::new();
+    for trace in JsonlSource::<Trace>::open(path)? {
+        let trace = trace?;
+        if trace.block_number > cutoff_block
+            || !(trace.is_call_type("call") || trace.is_call_type("delegatecall"))
+        {
+            continue;
+        }
+        if let Some(tx) = &trace.transaction_hash {
+            txs.entry(tx.to_lowercase()).or_default().push(trace);
+        }
+    }
+    Ok(txs
+        .values()
+        .flat_map(|traces| find_invocations(traces, proxies))
+        .collect())
+}
+
+/// Read the proxy addresses, one per line, e.g., `all_proxy.txt` of proxy-contract-prep.
+pub fn read_proxies<P: AsRef<Path>>(path: P) -> io::Result<HashSet<String>> {
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|l| l.trim().to_lowercase())
+        .filter(|l| !l.is_empty())
+        .collect())
+}
+
+/// ProxyDataCollector merges the invocations found in the shards into one `ProxyData` per proxy.
+#[derive(Debug, Default)]
+pub struct ProxyDataCollector {
+    proxies: HashMap<String, Vec<ImplInvocation>>,
+}
+
+impl ProxyDataCollector {
+    pub fn extend(&mut self, invocations: Vec<(String, ImplInvocation)>) {
+        for (proxy, invocation) in invocations {
+            self.proxies.entry(proxy).or_default().push(invocation);
+        }
+    }
+
+    /// The proxies ordered by their number of invocations (ascending, as `impl.json`) and address,
+    /// each with its invocations ordered by block and tx, without duplicated txs.
+    pub fn finish(self) -> Vec<ProxyData> {
+        let mut data = self
+            .proxies
+            .into_iter()
+            .map(|(proxy, impls)| {
+                let impls = ProxyData {
+                    proxy: proxy.clone(),
+                    impls,
+                }
+                .ordered_impls();
+                ProxyData { proxy, impls }
+            })
+            .collect::<Vec<_>>();
+        data.sort_by(|a, b| (a.impls.len(), &a.proxy).cmp(&(b.impls.len(), &b.proxy)));
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{find_invocations, ProxyDataCollector, Trace};
+
+    fn trace(json: &str) -> Trace {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_find_invocations() {
+        let traces = vec![
+            // the top-level call to the proxy, forwarded to the implementation
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xeoa", "to_address": "0xP", "input": "0xa9059cbb01", "call_type": "call", "block_number": "100"}"#,
+            ),
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xp", "to_address": "0xI", "input": "0xa9059cbb01", "call_type": "delegatecall", "trace_address": "0", "block_number": "100"}"#,
+            ),
+            // a nested call to another proxy, forwarded with the same calldata
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xi", "to_address": "0xq", "input": "0x01", "call_type": "call", "trace_address": "0,2", "block_number": "100"}"#,
+            ),
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xq", "to_address": "0xj", "input": "0x01", "call_type": "delegatecall", "trace_address": "0,2,0", "block_number": "100"}"#,
+            ),
+            // a library delegatecall with different calldata is not an invocation
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xp", "to_address": "0xlib", "input": "0x02", "call_type": "delegatecall", "trace_address": "1", "block_number": "100"}"#,
+            ),
+            // same calldata, but made in a call to another contract
+            trace(
+                r#"{"transaction_hash": "0xT1", "from_address": "0xi", "to_address": "0xk", "input": "0x01", "call_type": "delegatecall", "trace_address": "0,3", "block_number": "100"}"#,
+            ),
+        ];
+        let invocations = find_invocations(&traces, None);
+        let found = invocations
+            .iter()
+            .map(|(p, i)| {
+                (
+                    p.as_str(),
+                    i.implementation.as_str(),
+                    i.tx.as_str(),
+                    i.block,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![("0xp", "0xi", "0xt1", 100), ("0xq", "0xj", "0xt1", 100)]
+        );
+
+        let proxies = HashSet::from(["0xq".to_string()]);
+        let invocations = find_invocations(&traces, Some(&proxies));
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].0, "0xq");
+    }
+
+    #[test]
+    fn test_read_shard() {
+        let path = std::env::temp_dir().join("proxyex-detector-test-trace-shard.json");
+        std::fs::write(
+            &path,
+            [
+                r#"{"transaction_hash": "0x02", "from_address": "0xp", "to_address": "0xi", "input": "0x", "call_type": "delegatecall", "trace_address": "0", "block_number": "20"}"#,
+                r#"{"to_address": "0xminer", "trace_type": "reward", "reward_type": "block", "block_number": "20"}"#,
+                r#"{"transaction_hash": "0x02", "from_address": "0xeoa", "to_address": "0xp", "input": "0x", "call_type": "call", "block_number": "20"}"#,
+                r#"{"transaction_hash": "0x03", "from_address": "0xeoa", "to_address": "0xp", "input": "0x", "call_type": "call", "block_number": "31"}"#,
+                r#"{"transaction_hash": "0x03", "from_address": "0xp", "to_address": "0xj", "input": "0x", "call_type": "delegatecall", "trace_address": "0", "block_number": "31"}"#,
+            ]
+            .join("\n"),
+        )
+        .unwrap();
+        // the tx after the cutoff block is ignored
+        let invocations = super::read_shard(&path, 30, None).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].1.tx, "0x02");
+        assert_eq!(invocations[0].1.block, 20);
+    }
+
+    #[test]
+    fn test_collect_proxy_data() {
+        let invocation = |tx: &str, implementation: &str, block| super::ImplInvocation {
+            tx: tx.to_string(),
+            implementation: implementation.to_string(),
+            block,
+        };
+        let mut collector = ProxyDataCollector::default();
+        collector.extend(vec![
+            ("0xp".to_string(), invocation("0x03", "0xj", 3)),
+            ("0xq".to_string(), invocation("0x04", "0xk", 4)),
+        ]);
+        collector.extend(vec![
+            ("0xp".to_string(), invocation("0x01", "0xi", 1)),
+            // the same tx found in another shard
+            ("0xp".to_string(), invocation("0x01", "0xi", 1)),
+        ]);
+        let data = collector.finish();
+        assert_eq!(data[0].proxy, "0xq");
+        assert_eq!(data[1].proxy, "0xp");
+        let txs = data[1]
+            .impls
+            .iter()
+            .map(|i| i.tx.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(txs, vec!["0x01", "0x03"]);
+    }
+}
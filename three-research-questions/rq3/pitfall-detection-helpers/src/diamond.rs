@@ -0,0 +1,513 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use libsofl_core::engine::{
+    state::BcState,
+    types::{Address, Bytes, TxHash, U256},
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    inspectors::{collision::StorageAccessInspector, delegation::ReturnInspector},
+    selector::Selector,
+    slot::{classify, Preimages, SlotLabel},
+};
+
+/// `DiamondCut((address,uint8,bytes4[])[],address,bytes)`, emitted by a diamond (EIP-2535) when its selectors are routed to other facets.
+pub const DIAMOND_CUT_TOPIC: [u8; 32] = [
+    0x8f, 0xaa, 0x70, 0x87, 0x86, 0x71, 0xcc, 0xd2, 0x12, 0xd2, 0x07, 0x71, 0xb7, 0x95, 0xc5, 0x0a,
+    0xf8, 0xfd, 0x3f, 0xf6, 0xcf, 0x27, 0xf4, 0xbd, 0xe5, 0x7e, 0x5d, 0x4d, 0xe0, 0xae, 0xb6, 0x73,
+];
+
+/// `facets()` of the diamond loupe, returning `(address,bytes4[])[]`.
+pub const FACETS_SELECTOR: Selector = [0x7a, 0x0e, 0xd6, 0x27];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FacetCutAction {
+    Add,
+    Replace,
+    Remove,
+}
+
+/// One entry of a `DiamondCut` event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacetCut {
+    /// zero when the selectors are removed
+    pub facet: Address,
+    pub action: FacetCutAction,
+    pub selectors: Vec<Selector>,
+}
+
+fn read_usize(data: &[u8], offset: usize) -> Option<usize> {
+    let word = data.get(offset..offset.checked_add(32)?)?;
+    if word[..24].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(u64::from_be_bytes(word[24..].try_into().unwrap()) as usize)
+}
+
+fn read_address(data: &[u8], offset: usize) -> Option<Address> {
+    let word = data.get(offset..offset.checked_add(32)?)?;
+    if word[..12].iter().any(|b| *b != 0) {
+        return None;
+    }
+    Some(Address::from_slice(&word[12..]))
+}
+
+/// `bytes4[]` at the offset, each selector left-aligned in a word.
+fn read_selectors(data: &[u8], offset: usize) -> Option<Vec<Selector>> {
+    let len = read_usize(data, offset)?;
+    (0..len)
+        .map(|i| {
+            let start = offset.checked_add(32 * (i + 1))?;
+            data.get(start..start.checked_add(4)?)?.try_into().ok()
+        })
+        .collect()
+}
+
+/// The dynamic tuples of the array at the offset, each decoded from its head by `f`.
+fn read_tuples<T>(
+    data: &[u8],
+    offset: usize,
+    f: impl Fn(&[u8], usize) -> Option<T>,
+) -> Option<Vec<T>> {
+    let len = read_usize(data, offset)?;
+    let base = offset.checked_add(32)?;
+    (0..len)
+        .map(|i| {
+            let tuple = base.checked_add(read_usize(data, base + 32 * i)?)?;
+            // the head of the tuple is read at fixed offsets from here
+            if tuple > data.len() {
+                return None;
+            }
+            f(data, tuple)
+        })
+        .collect()
+}
+
+/// Decode the data of a `DiamondCut` event, None if it is malformed.
+pub fn decode_diamond_cut(data: &[u8]) -> Option<Vec<FacetCut>> {
+    read_tuples(data, read_usize(data, 0)?, |d, t| {
+        let action = match read_usize(d, t + 32)? {
+            0 => FacetCutAction::Add,
+            1 => FacetCutAction::Replace,
+            2 => FacetCutAction::Remove,
+            _ => return None,
+        };
+        Some(FacetCut {
+            facet: read_address(d, t)?,
+            action,
+            selectors: read_selectors(d, t.checked_add(read_usize(d, t + 64)?)?)?,
+        })
+    })
+}
+
+/// Decode the output of `facets()`, None if it is malformed.
+pub fn decode_facets(data: &[u8]) -> Option<Vec<(Address, Vec<Selector>)>> {
+    read_tuples(data, read_usize(data, 0)?, |d, t| {
+        Some((
+            read_address(d, t)?,
+            read_selectors(d, t.checked_add(read_usize(d, t + 32)?)?)?,
+        ))
+    })
+}
+
+/// Ask the diamond loupe for the facets and their selectors.
+/// Returns None if the contract does not implement `facets()`, i.e., it is not a diamond.
+pub fn loupe_facets<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    diamond: Address,
+) -> Option<Vec<(Address, Vec<Selector>)>> {
+    let mut insp = ReturnInspector::default();
+    let input = Bytes::from(FACETS_SELECTOR.to_vec());
+    let _ = caller.simulate_call(state, diamond, input, None, &mut insp);
+    decode_facets(&insp.output?)
+}
+
+/// The selectors routed to a facet from one `DiamondCut` (or the first invocation of the diamond) to the next one changing them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacetVersion {
+    pub facet: Address,
+    pub selectors: BTreeSet<Selector>,
+    pub min_block: u64,
+    /// the block of the `DiamondCut` changing the selectors of the facet, None if still in use
+    pub max_block: Option<u64>,
+    /// the tx emitting the `DiamondCut`, None for the facets of the diamond at its first invocation
+    pub tx: Option<TxHash>,
+}
+
+/// Track the versions of each facet, starting from the facets of the diamond at `first_block`
+/// and applying the `DiamondCut`s (block, tx, cuts) in order.
+pub fn facet_versions(
+    initial: &[(Address, Vec<Selector>)],
+    first_block: u64,
+    cuts: &[(u64, TxHash, Vec<FacetCut>)],
+) -> Vec<FacetVersion> {
+    let mut routing: BTreeMap<Selector, Address> = BTreeMap::new();
+    for (facet, selectors) in initial {
+        for selector in selectors {
+            routing.insert(*selector, *facet);
+        }
+    }
+    let selectors_of = |routing: &BTreeMap<Selector, Address>| {
+        let mut facets: BTreeMap<Address, BTreeSet<Selector>> = BTreeMap::new();
+        for (selector, facet) in routing {
+            facets.entry(*facet).or_default().insert(*selector);
+        }
+        facets
+    };
+
+    let mut versions = Vec::new();
+    // the index of the current version of each facet
+    let mut current: BTreeMap<Address, usize> = BTreeMap::new();
+    for (facet, selectors) in selectors_of(&routing) {
+        current.insert(facet, versions.len());
+        versions.push(FacetVersion {
+            facet,
+            selectors,
+            min_block: first_block,
+            max_block: None,
+            tx: None,
+        });
+    }
+    for (block, tx, cuts) in cuts {
+        for cut in cuts {
+            for selector in &cut.selectors {
+                match cut.action {
+                    FacetCutAction::Add | FacetCutAction::Replace => {
+                        routing.insert(*selector, cut.facet);
+                    }
+                    FacetCutAction::Remove => {
+                        routing.remove(selector);
+                    }
+                }
+            }
+        }
+        let mut facets = selectors_of(&routing);
+        let involved = current
+            .keys()
+            .chain(facets.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for facet in involved {
+            let selectors = facets.remove(&facet).unwrap_or_default();
+            if let Some(i) = current.get(&facet) {
+                if versions[*i].selectors == selectors {
+                    continue;
+                }
+                versions[*i].max_block = Some(*block);
+                current.remove(&facet);
+            }
+            if !selectors.is_empty() {
+                current.insert(facet, versions.len());
+                versions.push(FacetVersion {
+                    facet,
+                    selectors,
+                    min_block: *block,
+                    max_block: None,
+                    tx: Some(*tx),
+                });
+            }
+        }
+    }
+    versions
+}
+
+/// A storage slot of the diamond written by two contracts (the diamond itself or its facets)
+/// and read by at least one of them, the same as the proxy-implementation collisions of `SlotCollisionResult`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FacetCollision {
+    /// the diamond for the diamond-facet collisions
+    pub first: Address,
+    pub second: Address,
+    /// storage layout labels of the conflicting slots
+    pub slot_labels: Vec<(U256, SlotLabel)>,
+}
+
+/// DiamondAccesses aggregates the storage accesses to the diamond made by the diamond and each facet over the txs replayed.
+#[derive(Debug, Default)]
+pub struct DiamondAccesses {
+    diamond: Option<Address>,
+    sstores: HashMap<Address, HashSet<U256>>,
+    sloads: HashMap<Address, HashSet<U256>>,
+    preimages: Preimages,
+}
+
+impl DiamondAccesses {
+    /// Add the accesses recorded by an inspector constructed `with_facets`.
+    pub fn extend(&mut self, insp: &StorageAccessInspector) {
+        self.diamond = Some(insp.proxy);
+        let slots = |accesses: &HashSet<(Address, U256, U256)>| {
+            accesses.iter().map(|(_, s, _)| *s).collect::<Vec<_>>()
+        };
+        self.sstores
+            .entry(insp.proxy)
+            .or_default()
+            .extend(slots(&insp.proxy_sstores));
+        self.sloads
+            .entry(insp.proxy)
+            .or_default()
+            .extend(slots(&insp.proxy_sloads));
+        for (facet, sstores) in &insp.facet_sstores {
+            self.sstores
+                .entry(*facet)
+                .or_default()
+                .extend(slots(sstores));
+        }
+        for (facet, sloads) in &insp.facet_sloads {
+            self.sloads.entry(*facet).or_default().extend(slots(sloads));
+        }
+        self.preimages
+            .extend(insp.preimages.iter().map(|(h, p)| (*h, p.clone())));
+    }
+
+    /// The collisions of every pair of contracts writing the diamond's storage, the diamond first.
+    pub fn collisions(&self) -> Vec<FacetCollision> {
+        let mut writers = self.sstores.keys().cloned().collect::<Vec<_>>();
+        writers.sort_by_key(|a| (Some(*a) != self.diamond, *a));
+        let empty = HashSet::new();
+        let mut collisions = Vec::new();
+        for (i, first) in writers.iter().enumerate() {
+            for second in &writers[i + 1..] {
+                let mut slots = self.sstores[first]
+                    .intersection(&self.sstores[second])
+                    .filter(|s| {
+                        self.sloads.get(first).unwrap_or(&empty).contains(s)
+                            || self.sloads.get(second).unwrap_or(&empty).contains(s)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if slots.is_empty() {
+                    continue;
+                }
+                slots.sort();
+                collisions.push(FacetCollision {
+                    first: *first,
+                    second: *second,
+                    slot_labels: slots
+                        .into_iter()
+                        .map(|s| (s, classify(s, &self.preimages)))
+                        .collect(),
+                });
+            }
+        }
+        collisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashSet};
+
+    use libsofl_core::engine::{
+        memory::MemoryBcState,
+        types::{Address, TxHash, U256},
+    };
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::inspectors::collision::StorageAccessInspector;
+
+    use super::{
+        facet_versions, loupe_facets, DiamondAccesses, FacetCut, FacetCutAction, FacetVersion,
+    };
+
+    #[test]
+    fn test_diamond() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract FacetA {
+                uint256 a;
+                uint256 b;
+                function setA(uint256 _a) public {
+                    a = _a;
+                    b = _a;
+                }
+            }
+            contract FacetB {
+                uint256 a;
+                function setB(uint256 _a) public {
+                    a = a + _a;
+                }
+            }
+            contract Diamond {
+                struct FacetCut { address facetAddress; uint8 action; bytes4[] functionSelectors; }
+                struct Facet { address facetAddress; bytes4[] functionSelectors; }
+                event DiamondCut(FacetCut[] _diamondCut, address _init, bytes _calldata);
+
+                mapping(bytes4 => address) selectorToFacet;
+                address[] facetList;
+
+                function cut(address facet, uint8 action, bytes4 selector) internal {
+                    FacetCut[] memory cuts = new FacetCut[](1);
+                    bytes4[] memory selectors = new bytes4[](1);
+                    selectors[0] = selector;
+                    cuts[0] = FacetCut(facet, action, selectors);
+                    selectorToFacet[selector] = facet;
+                    if (facet != address(0)) {
+                        facetList.push(facet);
+                    }
+                    emit DiamondCut(cuts, address(0), "");
+                }
+                function addA(address facet) public {
+                    cut(facet, 0, FacetA.setA.selector);
+                }
+                function addB(address facet) public {
+                    cut(facet, 0, FacetB.setB.selector);
+                }
+                function facets() external view returns (Facet[] memory fs) {
+                    fs = new Facet[](facetList.length);
+                    for (uint256 i = 0; i < facetList.length; i++) {
+                        bytes4[] memory selectors = new bytes4[](1);
+                        selectors[0] = i == 0 ? FacetA.setA.selector : FacetB.setB.selector;
+                        fs[i] = Facet(facetList[i], selectors);
+                    }
+                }
+                fallback() external payable {
+                    address facet = selectorToFacet[msg.sig];
+                    require(facet != address(0));
+                    assembly {
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), facet, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            "#,
+            vec!["Diamond", "FacetA", "FacetB"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (diamond, facet_a, facet_b) = (addrs.remove(0), addrs.remove(0), addrs.remove(0));
+        let caller = HighLevelCaller::default().bypass_check();
+        let mut insp = StorageAccessInspector::new(diamond, facet_a, 0, 1, false)
+            .with_facets(HashSet::from([facet_a, facet_b]));
+        for (func, facet) in [("addA(address)", facet_a), ("addB(address)", facet_b)] {
+            caller
+                .invoke(&mut state, diamond, func, &[facet.into()], None, &mut insp)
+                .unwrap();
+        }
+        assert_eq!(
+            insp.diamond_cuts
+                .iter()
+                .map(|c| (c.facet, c.action))
+                .collect::<Vec<_>>(),
+            vec![
+                (facet_a, FacetCutAction::Add),
+                (facet_b, FacetCutAction::Add)
+            ]
+        );
+        let facets = loupe_facets(&caller, &mut state, diamond).unwrap();
+        assert_eq!(
+            facets.iter().map(|(f, _)| *f).collect::<Vec<_>>(),
+            vec![facet_a, facet_b]
+        );
+        assert_eq!(facets[1].1, insp.diamond_cuts[1].selectors);
+        assert!(loupe_facets(&caller, &mut state, facet_a).is_none());
+
+        for (func, value) in [("setA(uint256)", 1), ("setB(uint256)", 2)] {
+            caller
+                .invoke(
+                    &mut state,
+                    diamond,
+                    func,
+                    &[U256::from(value).into()],
+                    None,
+                    &mut insp,
+                )
+                .unwrap();
+        }
+        let mut accesses = DiamondAccesses::default();
+        accesses.extend(&insp);
+        let collisions = accesses
+            .collisions()
+            .into_iter()
+            .map(|c| {
+                let slots = c.slot_labels.iter().map(|(s, _)| *s).collect::<Vec<_>>();
+                (c.first, c.second, slots)
+            })
+            .collect::<Vec<_>>();
+        // FacetA writes the length of facetList, read by the loupe;
+        // FacetA and FacetB share slot 0, which FacetB reads
+        assert_eq!(
+            collisions,
+            vec![
+                (diamond, facet_a, vec![U256::from(1)]),
+                (facet_a.min(facet_b), facet_a.max(facet_b), vec![U256::ZERO]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_facet_versions() {
+        let (a, b) = (Address::with_last_byte(0xa), Address::with_last_byte(0xb));
+        let (s1, s2, s3) = ([0, 0, 0, 1], [0, 0, 0, 2], [0, 0, 0, 3]);
+        let (tx1, tx2) = (TxHash::with_last_byte(1), TxHash::with_last_byte(2));
+        let versions = facet_versions(
+            &[(a, vec![s1, s2])],
+            10,
+            &[
+                (
+                    20,
+                    tx1,
+                    vec![FacetCut {
+                        facet: b,
+                        action: FacetCutAction::Add,
+                        selectors: vec![s3],
+                    }],
+                ),
+                (
+                    30,
+                    tx2,
+                    vec![
+                        FacetCut {
+                            facet: b,
+                            action: FacetCutAction::Replace,
+                            selectors: vec![s2],
+                        },
+                        FacetCut {
+                            facet: Address::ZERO,
+                            action: FacetCutAction::Remove,
+                            selectors: vec![s1],
+                        },
+                    ],
+                ),
+            ],
+        );
+        assert_eq!(
+            versions,
+            vec![
+                FacetVersion {
+                    facet: a,
+                    selectors: BTreeSet::from([s1, s2]),
+                    min_block: 10,
+                    max_block: Some(30),
+                    tx: None,
+                },
+                FacetVersion {
+                    facet: b,
+                    selectors: BTreeSet::from([s3]),
+                    min_block: 20,
+                    max_block: Some(30),
+                    tx: Some(tx1),
+                },
+                FacetVersion {
+                    facet: b,
+                    selectors: BTreeSet::from([s2, s3]),
+                    min_block: 30,
+                    max_block: None,
+                    tx: Some(tx2),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "admin_routing")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+
+    /// None if the proxy has no admin in the EIP-1967 admin slot
+    pub admin: Option<String>,
+    pub implementation: Option<String>,
+
+    /// whether the admin reaches logic functions or non-admins reach admin functions
+    pub problematic: bool,
+
+    pub probes: serde_json::Value, // Vec<RoutingProbe>
+    pub admin_reached_logic: serde_json::Value, // Vec<String>
+    pub non_admin_reached_admin: serde_json::Value, // Vec<String>
+
+    /// total time used to check the proxy
+    pub total_time: i64, // nanoseconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,35 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "clone")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+
+    pub kind: String, // CloneKind
+
+    /// the implementation hard-coded in the bytecode of the proxy
+    pub implementation: String,
+
+    /// whether the proxy is analyzed on behalf of all the clones of its implementation
+    pub representative: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,6 +1,6 @@
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
 #[sea_orm(table_name = "collision")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -14,6 +14,11 @@ pub struct Model {
     pub implementation_sstores: serde_json::Value, // Vec<(TxHash, Vec<(U256, U256)>)> slot and value
     pub implementation_sloads: serde_json::Value, // Vec<(TxHash, Vec<(U256, U256)>)> slot and value
 
+    /// storage layout labels of the conflicting slots, Vec<(U256, SlotLabel)>
+    pub slot_labels: Option<serde_json::Value>,
+    /// KECCAK256 preimages needed to label the conflicting slots, Vec<(TxHash, Vec<(U256, Bytes)>)>
+    pub preimages: Option<serde_json::Value>,
+
     /// total time used to replay the whole proxy
     pub total_time: i64,
     /// average time used to replay each tx in the proxy
@@ -10,6 +10,15 @@ pub struct Model {
     pub proxy: String,
 
     pub msg: String,
+
+    // the failure class, see `crate::error::DetectorError`
+    pub kind: String,
+
+    // the tx that fails, if the failure is specific to a tx
+    pub tx: Option<String>,
+
+    // the detector in which the failure happens
+    pub detector: String,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
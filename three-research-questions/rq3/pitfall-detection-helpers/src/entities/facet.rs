@@ -0,0 +1,38 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "facet")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub facet: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub min_block: i64, // the block of the DiamondCut routing the selectors to the facet, or the first invocation of the diamond
+
+    pub max_block: Option<i64>, // the block of the DiamondCut changing the selectors of the facet, None if still in use
+
+    pub selectors: serde_json::Value, // Vec<String>
+
+    /// the tx emitting the DiamondCut, None for the facets of the diamond at its first invocation
+    pub tx: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,35 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "facet_collision")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+    /// the diamond itself for the diamond-facet collisions
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub first: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub second: String,
+
+    /// storage layout labels of the conflicting slots, Vec<(U256, SlotLabel)>
+    pub slot_labels: serde_json::Value,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
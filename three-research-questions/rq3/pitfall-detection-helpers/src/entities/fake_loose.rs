@@ -1,6 +1,6 @@
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
 #[sea_orm(table_name = "fake_loose")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -10,6 +10,9 @@ pub struct Model {
 
     pub mismatched_impls: serde_json::Value, // Vec<(Slot_Address, Identified_Address, i64)>
 
+    /// the convention the implementation is found by, e.g., `eip1967`, None if no convention matches
+    pub convention: Option<String>,
+
     /// total time used to check the whole proxy
     pub total_time: i64, // nanoseconds
 }
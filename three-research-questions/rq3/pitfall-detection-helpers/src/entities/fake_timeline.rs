@@ -0,0 +1,43 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "fake_timeline")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub block: i64, // the checkpoint, compared at the end of the block
+
+    /// the implementation declared by the slot, the zero address if no convention matches
+    pub slot_implementation: String,
+    pub convention: Option<String>,
+
+    /// the implementation the proxy delegates to, None if unknown
+    pub actual_implementation: Option<String>,
+
+    pub mismatched: bool,
+
+    /// whether the declared implementation changed at this block
+    pub slot_changed: bool,
+    /// whether the delegated implementation changed at this block
+    pub delegation_changed: bool,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "impl_initialize")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub implementation: String,
+
+    pub min_block: i64, // the block at which the implementation state is checked
+
+    /// whether anyone can initialize the implementation contract itself
+    pub uninitialized: bool,
+    pub frontrun_input: Option<String>,
+
+    /// SELFDESTRUCT or DELEGATECALL reachable after the initialization
+    pub selfdestruct: bool,
+    pub delegatecall: bool,
+    pub dangerous_calls: serde_json::Value, // Vec<DangerousCall>
+
+    /// total time used to check the implementation
+    pub total_time: i64, // nanoseconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -12,3 +12,14 @@ pub mod version;
 pub mod fake_loose;
 pub mod collision;
 pub mod regression_filter;
+pub mod selector_clash;
+pub mod admin_routing;
+pub mod impl_initialize;
+pub mod reachability;
+pub mod run;
+pub mod facet;
+pub mod facet_collision;
+pub mod clone;
+pub mod fake_timeline;
+pub mod slot_history;
+pub mod version_discrepancy;
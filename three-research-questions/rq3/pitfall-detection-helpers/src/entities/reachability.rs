@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "reachability")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub implementation: String,
+
+    pub min_block: i64, // the block at which the implementation state is checked
+
+    /// SELFDESTRUCT or attacker-controlled DELEGATECALL reachable when calling the implementation directly
+    pub selfdestruct: bool,
+    pub attacker_delegatecall: bool,
+    pub reachable_calls: serde_json::Value, // Vec<ReachableCall>
+
+    /// total time used to check the implementation
+    pub total_time: i64, // nanoseconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
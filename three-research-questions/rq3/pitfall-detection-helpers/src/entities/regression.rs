@@ -1,6 +1,6 @@
 use sea_orm::entity::prelude::*;
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
 #[sea_orm(table_name = "regression")]
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
@@ -15,6 +15,8 @@ pub struct Model {
     pub original_sstores: serde_json::Value,
     pub alt_sloads: serde_json::Value,
     pub alt_sstores: serde_json::Value,
+    /// KECCAK256 preimages needed to label the accessed slots, Vec<(U256, Bytes)>
+    pub preimages: Option<serde_json::Value>,
 
     pub different_slots: bool,
     pub different_values: bool,
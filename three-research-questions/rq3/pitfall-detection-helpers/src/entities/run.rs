@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "run")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub detector: String,
+    /// the input item of the detector, e.g., a proxy, an implementation or a tx
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub item: String,
+
+    pub status: String, // pending, running, done or failed
+    pub attempts: i32,
+    pub error: Option<String>,
+
+    pub created_at: i64, // unix milliseconds
+    pub updated_at: i64, // unix milliseconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,39 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, serde::Serialize, serde::Deserialize)]
+#[sea_orm(table_name = "selector_clash")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub implementation: String,
+
+    /// whether the proxy shadows any function of the implementation
+    pub problematic: bool,
+
+    pub proxy_selectors: serde_json::Value, // Vec<String>
+    pub implementation_selectors: serde_json::Value, // Vec<String>
+    pub clashes: serde_json::Value, // Vec<SelectorClash>
+
+    /// total time used to check the proxy and the implementation
+    pub total_time: i64, // nanoseconds
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
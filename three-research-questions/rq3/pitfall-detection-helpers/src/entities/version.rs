@@ -9,6 +9,9 @@ pub struct Model {
     pub implementation: String,
 
     pub min_block: i64, // the minimal block number that the implementation is used
+
+    /// the beacon returning the implementation, None if the implementation is stored in the proxy (or not resolved)
+    pub beacon: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
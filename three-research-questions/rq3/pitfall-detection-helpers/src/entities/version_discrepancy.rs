@@ -0,0 +1,37 @@
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "version_discrepancy")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub proxy: String,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub kind: String, // unrecorded or unused
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub implementation: String,
+    /// the first block the version is used (unrecorded), or the block the implementation is written (unused)
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub block: i64,
+
+    /// the tx writing the implementation, only for unused implementations
+    pub tx: Option<String>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::proxy::Entity",
+        from = "Column::Proxy"
+        to = "super::proxy::Column::Address"
+    )]
+    Proxy,
+}
+
+impl Related<super::proxy::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Proxy.def()
+    }
+}
+
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,123 @@
+use std::fmt::{self, Debug, Display};
+
+use libsofl_core::engine::types::{Address, TxHash};
+use sea_orm::{ActiveValue, DbErr};
+
+use crate::entities;
+
+/// Failure classes of the analyses.
+/// The kind of each failure is saved in the `error` table, so that the failures can be triaged and re-run by kind.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", content = "msg", rename_all = "snake_case")]
+pub enum DetectorError {
+    /// the tx (or its position) is not found in the blockchain provider
+    TxNotFound(String),
+    /// the state at a block or before a tx cannot be loaded
+    StateUnavailable(String),
+    /// the EVM fails to execute a tx
+    EvmHalt(String),
+    /// the records of an inspector are inconsistent with what the analysis expects
+    InspectorInvariant(String),
+    /// reading or writing the database fails
+    Db(String),
+    /// the analysis exceeds its time budget
+    Timeout(String),
+}
+
+impl DetectorError {
+    pub const KINDS: [&'static str; 6] = [
+        "tx_not_found",
+        "state_unavailable",
+        "evm_halt",
+        "inspector_invariant",
+        "db",
+        "timeout",
+    ];
+
+    pub fn tx_not_found<E: Debug>(e: E) -> Self {
+        Self::TxNotFound(format!("{:?}", e))
+    }
+
+    pub fn state_unavailable<E: Debug>(e: E) -> Self {
+        Self::StateUnavailable(format!("{:?}", e))
+    }
+
+    pub fn evm_halt<E: Debug>(e: E) -> Self {
+        Self::EvmHalt(format!("{:?}", e))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DetectorError::TxNotFound(_) => Self::KINDS[0],
+            DetectorError::StateUnavailable(_) => Self::KINDS[1],
+            DetectorError::EvmHalt(_) => Self::KINDS[2],
+            DetectorError::InspectorInvariant(_) => Self::KINDS[3],
+            DetectorError::Db(_) => Self::KINDS[4],
+            DetectorError::Timeout(_) => Self::KINDS[5],
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        match self {
+            DetectorError::TxNotFound(msg)
+            | DetectorError::StateUnavailable(msg)
+            | DetectorError::EvmHalt(msg)
+            | DetectorError::InspectorInvariant(msg)
+            | DetectorError::Db(msg)
+            | DetectorError::Timeout(msg) => msg,
+        }
+    }
+
+    /// The row of the `error` table, `detector` is the name of the detector in which the failure happens.
+    pub fn to_model(
+        &self,
+        detector: &str,
+        proxy: Address,
+        tx: Option<TxHash>,
+    ) -> entities::error::ActiveModel {
+        entities::error::ActiveModel {
+            proxy: ActiveValue::Set(proxy.to_string().to_lowercase()),
+            msg: ActiveValue::Set(self.msg().to_string()),
+            kind: ActiveValue::Set(self.kind().to_string()),
+            tx: ActiveValue::Set(tx.map(|tx| tx.to_string().to_lowercase())),
+            detector: ActiveValue::Set(detector.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl Display for DetectorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.msg())
+    }
+}
+
+impl std::error::Error for DetectorError {}
+
+impl From<DbErr> for DetectorError {
+    fn from(e: DbErr) -> Self {
+        Self::Db(format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::DbErr;
+
+    use super::DetectorError;
+
+    #[test]
+    fn test_error_kind() {
+        let e = DetectorError::tx_not_found("0x01");
+        assert_eq!(e.kind(), "tx_not_found");
+        assert_eq!(e.msg(), "\"0x01\"");
+        assert_eq!(e.to_string(), "tx_not_found: \"0x01\"");
+
+        let e: DetectorError = DbErr::RecordNotFound("proxy".to_string()).into();
+        assert_eq!(e.kind(), "db");
+        assert_eq!(
+            serde_json::to_value(&e).unwrap()["kind"],
+            serde_json::json!("db")
+        );
+    }
+}
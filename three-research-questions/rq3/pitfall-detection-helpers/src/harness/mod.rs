@@ -0,0 +1,608 @@
+use std::{
+    collections::BTreeSet,
+    io::{BufReader, BufWriter},
+    path::Path,
+    sync::Arc,
+};
+
+use libsofl_core::{
+    blockchain::{
+        provider::{BcProvider, BcStateProvider},
+        transaction::Tx,
+    },
+    conversion::ConvertTo,
+    engine::{
+        memory::MemoryBcState,
+        state::BcState,
+        transition::TransitionSpecBuilder,
+        types::{
+            Address, BlockEnv, BlockHashOrNumber, Bytecode, Bytes, CfgEnv, DatabaseRef, TxEnv,
+            TxHash, U256,
+        },
+    },
+    error::SoflError,
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    budget::Budget,
+    error::DetectorError,
+    inspectors::prestate::PrestateInspector,
+    original_replay::{replay_one_tx, ReplayError, SlotCollisionResult},
+    provider::fixture::{BlockFixture, FixtureProvider, FixtureSet, TxEnvFixture, TxFixture},
+    replaced_replay::{implementation_code_at, regression_one_invocation, AltVersion},
+};
+
+/// An implementation version used to replace the implementation in regression tests.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AltFixture {
+    pub implementation: Address,
+    pub min_block: u64,
+    pub code: Bytes,
+}
+
+/// The regression outcome of one tx on one alternative implementation.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ExpectedRegression {
+    pub tx: TxHash,
+    pub alt_implementation: Address,
+    pub different_slots: bool,
+    pub different_values: bool,
+    pub proxy_reverted: bool,
+}
+
+/// Expected detector results of a case.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Expected {
+    /// slots written by both proxy and implementation and read by either of them
+    pub collision_slots: BTreeSet<U256>,
+    pub regressions: Vec<ExpectedRegression>,
+    /// whether the front-running call succeeds after the last tx
+    pub frontrun_success: Option<bool>,
+}
+
+/// A recorded detection case: the invocations of a proxy together with
+/// the pre-states needed to replay them offline, and the expected results.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Case {
+    pub name: String,
+    pub proxy: Address,
+    pub implementation: Address,
+    pub chain_id: u64,
+    pub txs: Vec<TxFixture>,
+    pub alt_implementations: Vec<AltFixture>,
+    /// calldata sent to `proxy` after the last tx, used by uninitialized proxy detection
+    pub frontrun: Option<Bytes>,
+    pub expected: Expected,
+}
+
+/// Description of a case to be recorded from a live provider.
+#[derive(Debug, Clone, Copy)]
+pub struct CaseSpec {
+    pub name: &'static str,
+    pub proxy: &'static str,
+    pub implementation: &'static str,
+    pub txs: &'static [&'static str],
+    /// (alternative implementation, block at which its code is taken)
+    pub alt_implementations: &'static [(&'static str, u64)],
+    pub frontrun: Option<&'static str>,
+}
+
+/// The block (end of 2023) at which the code of the alternative implementations of the known cases is taken.
+/// The live tests read the code at the latest block; this block, after the case txs and the deployment
+/// of every alternative implementation, is pinned instead so that recording a case again gives the same fixture.
+/// As their `min_block`, it is also later than the case txs, so that the txs are simulated on them.
+pub const ALT_CODE_BLOCK: u64 = 18900000;
+
+/// Known cases, recorded by `bin/record-fixture` into `fixtures/cases`.
+pub const KNOWN_CASES: &[CaseSpec] = &[
+    CaseSpec {
+        name: "audius",
+        proxy: "0x4deca517d6817b6510798b7328f2314d3003abac",
+        implementation: "0x35dd16dfa4ea1522c29ddd087e8f076cad0ae5e8",
+        txs: &["0xfefd829e246002a8fd061eede7501bccb6e244a9aacea0ebceaecef5d877a984"],
+        alt_implementations: &[("0x1c91af03a390b4c619b444425b3119e553b5b44b", ALT_CODE_BLOCK)],
+        frontrun: None,
+    },
+    CaseSpec {
+        name: "comptroller",
+        proxy: "0xe2e17b2cbbf48211fa7eb8a875360e5e39ba2602",
+        implementation: "0xd30378faec598befa2e419d2bcc0e17473965536",
+        txs: &["0xe8a31330950b545ce2bbc24c70882d736b6f070e27f1ca27c89b2dfd23327a08"],
+        alt_implementations: &[("0xaf082ef22e8c51357c10ffd157dc82f79ea09f39", ALT_CODE_BLOCK)],
+        frontrun: None,
+    },
+    CaseSpec {
+        name: "root_chain_manager",
+        proxy: "0xa0c68c638235ee32657e8f720a23cec1bfc77c77",
+        implementation: "0x0bff34272af650632236703a3d6d8e3c133421cb",
+        txs: &["0x6303ba187ec21d1380ecfbd03f945bea0bb831f4212833920045ff893a3a9937"],
+        alt_implementations: &[("0x4015ccad9218b109d3339b356392c6ee8438e5d0", ALT_CODE_BLOCK)],
+        frontrun: None,
+    },
+    CaseSpec {
+        name: "wormhole",
+        proxy: "0x736d2a394f7810c17b3c6fed017d5bc7d60c077d",
+        implementation: "0x736d2a394f7810c17b3c6fed017d5bc7d60c077d",
+        txs: &["0xa52ffec49d2dba0bb04ae9c95dd3876232b316fdef4fe5ec1dd7327b7bdfd4c3"],
+        alt_implementations: &[],
+        frontrun: Some("0xf6079017000000000000000000000000000000000000000000000000000000000000008000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000"),
+    },
+];
+
+impl Case {
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<Self, std::io::Error> {
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<(), std::io::Error> {
+        let writer = BufWriter::new(std::fs::File::create(path)?);
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+
+    /// A provider serving the recorded txs and pre-states of this case.
+    pub fn provider(&self) -> FixtureProvider {
+        let mut provider = FixtureProvider::default();
+        provider.extend(FixtureSet {
+            chain_id: self.chain_id,
+            txs: self.txs.clone(),
+        });
+        provider
+    }
+
+    /// Replay the case on its own recorded fixtures.
+    pub fn replay(&self) -> Result<Expected, ReplayError> {
+        self.run(Arc::new(self.provider()))
+    }
+
+    /// Run the detectors on the txs of this case with the given provider.
+    pub fn run<
+        T: Tx,
+        DB: DatabaseRef,
+        P: BcProvider<T> + BcStateProvider<DB> + Sync + Send + 'static,
+    >(
+        &self,
+        provider: Arc<P>,
+    ) -> Result<Expected, ReplayError>
+    where
+        <DB as DatabaseRef>::Error: std::fmt::Debug,
+    {
+        let total = self.txs.len();
+        let versions: Vec<AltVersion> = self
+            .alt_implementations
+            .iter()
+            .map(|a| {
+                (
+                    a.implementation,
+                    a.min_block,
+                    Bytecode::new_raw(a.code.clone()),
+                )
+            })
+            .collect();
+
+        let mut insps = Vec::new();
+        let mut regressions = Vec::new();
+        for (index, tx) in self.txs.iter().enumerate() {
+            insps.push(replay_one_tx(
+                provider.clone(),
+                self.proxy,
+                self.implementation,
+                tx.hash,
+                index,
+                total,
+                Budget::default(),
+            )?);
+            let issues = regression_one_invocation(
+                provider.clone(),
+                self.proxy,
+                self.implementation,
+                &versions,
+                tx.hash,
+                tx.block,
+                index,
+                total,
+                Budget::default(),
+            )?;
+            regressions.extend(issues.into_iter().map(|i| ExpectedRegression {
+                tx: i.tx,
+                alt_implementation: i.alt_implementation,
+                different_slots: i.different_slots,
+                different_values: i.different_values,
+                proxy_reverted: i.proxy_reverted,
+            }));
+        }
+        let collision = SlotCollisionResult::new(&insps);
+        let collision_slots = collision
+            .proxy_sstores
+            .iter()
+            .flat_map(|(_, s)| s.iter().map(|(slot, _)| *slot))
+            .collect();
+
+        let frontrun_success = match &self.frontrun {
+            Some(input) => Some(
+                self.frontrun(provider.clone(), input.clone())
+                    .map_err(|e| {
+                        let tx = self.txs.last().map(|t| t.hash).unwrap_or_default();
+                        ReplayError::new(self.proxy, tx, total, total, DetectorError::evm_halt(e))
+                    })?,
+            ),
+            None => None,
+        };
+
+        Ok(Expected {
+            collision_slots,
+            regressions,
+            frontrun_success,
+        })
+    }
+
+    /// Send the front-running call to the proxy right after the last tx.
+    fn frontrun<T: Tx, DB: DatabaseRef, P: BcProvider<T> + BcStateProvider<DB>>(
+        &self,
+        provider: Arc<P>,
+        input: Bytes,
+    ) -> Result<bool, SoflError>
+    where
+        <DB as DatabaseRef>::Error: std::fmt::Debug,
+    {
+        let last = self
+            .txs
+            .last()
+            .ok_or_else(|| SoflError::NotFound("no tx in case".to_string()))?;
+        let tx = provider.tx(last.hash.cvt())?;
+        let pos = tx.position().unwrap();
+        let mut state = provider.bc_state_at(pos)?;
+        let spec = TransitionSpecBuilder::default()
+            .at_block(provider.clone(), pos.block)
+            .append_tx(tx)
+            .build();
+        let mut insp = PrestateInspector::new();
+        state.transit(spec, &mut insp)?;
+        let r = HighLevelCaller::default()
+            .bypass_check()
+            .at_block(provider.clone(), pos.block)
+            .call(&mut state, self.proxy, input, None, &mut insp);
+        Ok(r.is_ok())
+    }
+}
+
+/// Record a case from a live provider.
+/// The expected results are the results of the detectors on the live provider.
+pub fn record_case<
+    T: Tx,
+    DB: DatabaseRef,
+    P: BcProvider<T> + BcStateProvider<DB> + Sync + Send + 'static,
+>(
+    provider: Arc<P>,
+    spec: &CaseSpec,
+) -> Result<Case, SoflError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let proxy: Address = spec.proxy.cvt();
+    let implementation: Address = spec.implementation.cvt();
+    let frontrun: Option<Bytes> = spec.frontrun.map(|input| input.cvt());
+
+    let mut alts = Vec::new();
+    for (alt, block) in spec.alt_implementations {
+        let alt: Address = alt.cvt();
+        let code = implementation_code_at(provider.as_ref(), alt, *block)
+            .map_err(|e| SoflError::Custom(format!("{:?}", e)))?;
+        alts.push(AltFixture {
+            implementation: alt,
+            min_block: *block,
+            code: code.original_bytes(),
+        });
+    }
+
+    let mut txs = Vec::new();
+    for (index, tx) in spec.txs.iter().enumerate() {
+        let is_last = index + 1 == spec.txs.len();
+        let tx = record_tx(
+            provider.clone(),
+            proxy,
+            implementation,
+            tx.cvt(),
+            &alts,
+            if is_last { frontrun.clone() } else { None },
+        )?;
+        txs.push(tx);
+    }
+
+    let mut case = Case {
+        name: spec.name.to_string(),
+        proxy,
+        implementation,
+        chain_id: provider.chain_id(),
+        txs,
+        alt_implementations: alts,
+        frontrun,
+        expected: Expected::default(),
+    };
+    case.expected = case
+        .run(provider)
+        .map_err(|e| SoflError::Custom(format!("{:?}", e)))?;
+    Ok(case)
+}
+
+/// Record the pre-state touched by a tx, by its replays on alternative implementations,
+/// and by the front-running call after it.
+fn record_tx<T: Tx, DB: DatabaseRef, P: BcProvider<T> + BcStateProvider<DB>>(
+    provider: Arc<P>,
+    proxy: Address,
+    implementation: Address,
+    tx_hash: TxHash,
+    alts: &Vec<AltFixture>,
+    frontrun: Option<Bytes>,
+) -> Result<TxFixture, SoflError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let tx = provider.tx(tx_hash.cvt())?;
+    let pos = tx.position().unwrap();
+    let block = match pos.block {
+        BlockHashOrNumber::Number(n) => n,
+        BlockHashOrNumber::Hash(h) => {
+            return Err(SoflError::NotFound(format!("block number of {}", h)))
+        }
+    };
+    let mut tx_env = TxEnv::default();
+    tx.fill_tx_env(&mut tx_env)?;
+    let mut block_env = BlockEnv::default();
+    provider.fill_block_env(&mut block_env, pos.block)?;
+    let mut cfg_env = CfgEnv::default();
+    provider.fill_cfg_env(&mut cfg_env, pos.block)?;
+
+    let base_state = provider.bc_state_at(pos)?;
+    let mut insp = PrestateInspector::new();
+    let mut state = MemoryBcState::fork(&base_state);
+    insp.record_account(&mut state, tx_env.caller);
+    insp.record_account(&mut state, block_env.coinbase);
+    insp.record_account(&mut state, implementation);
+    let spec = TransitionSpecBuilder::default()
+        .at_block(provider.clone(), pos.block)
+        .append_tx_env(tx_env.clone())
+        .build();
+    state.transit(spec, &mut insp)?;
+    // later txs of the case are replayed on this state, together with their own pre-states
+    let post_state = insp.post_state(&mut state);
+    if let Some(input) = frontrun {
+        // untouched accounts and slots still hold their pre-tx values after the tx
+        let _ = HighLevelCaller::default()
+            .bypass_check()
+            .at_block(provider.clone(), pos.block)
+            .call(&mut state, proxy, input, None, &mut insp);
+    }
+
+    // alternative implementations may touch slots that the original one does not,
+    // they are taken as `regression_one_invocation` does, including a version going back to the original one
+    for alt in alts.iter().filter(|a| a.min_block > block) {
+        let mut state = MemoryBcState::fork(&base_state);
+        state
+            .replace_account_code(implementation, Bytecode::new_raw(alt.code.clone()))
+            .map_err(|e| SoflError::Custom(format!("{:?}", e)))?;
+        let spec = TransitionSpecBuilder::default()
+            .at_block(provider.clone(), pos.block)
+            .bypass_check()
+            .append_tx_env(tx_env.clone())
+            .build();
+        state.transit(spec, &mut insp)?;
+    }
+
+    Ok(TxFixture {
+        hash: tx_hash,
+        block,
+        index: pos.index,
+        spec_id: cfg_env.spec_id as u8,
+        block_env: BlockFixture::from_env(&block_env),
+        tx_env: TxEnvFixture::from_env(&tx_env),
+        pre_state: insp.accounts,
+        post_state,
+    })
+}
+
+/// Path of the recorded fixture of a known case.
+pub fn case_path(name: &str) -> std::path::PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join("cases")
+        .join(format!("{}.json", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use libsofl_core::{
+        conversion::ConvertTo,
+        engine::{
+            memory::MemoryBcState,
+            types::{Address, Bytes, Database, SpecId, U256},
+        },
+    };
+    use libsofl_utils::solidity::scripting::{deploy_contracts, SolScriptConfig};
+
+    use crate::provider::fixture::{AccountFixture, BlockFixture, TxEnvFixture, TxFixture};
+
+    use super::{case_path, Case, Expected};
+
+    fn code_of<D: Database>(state: &mut D, address: Address) -> Bytes
+    where
+        D::Error: std::fmt::Debug,
+    {
+        let info = state.basic(address).unwrap().unwrap();
+        match info.code {
+            Some(code) => code.original_bytes(),
+            None => state.code_by_hash(info.code_hash).unwrap().original_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_synthetic_collision() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Proxy {
+                address public implementation;
+                function set_implementation(address _impl) public {
+                    implementation = _impl;
+                }
+                fallback() external payable {
+                    address _impl = implementation;
+                    assembly {
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), _impl, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            contract Impl {
+                uint256 public value;
+                function set_value(uint256 _value) public {
+                    value = _value;
+                }
+            }
+            "#,
+            vec!["Proxy", "Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (proxy, implementation) = (addrs.remove(0), addrs.remove(0));
+        let proxy_code = code_of(&mut state, proxy);
+        let implementation_code = code_of(&mut state, implementation);
+
+        let caller: Address = "0x00000000000000000000000000000000000000aa".cvt();
+        let fixture = |hash: &str, index: u64, data: Bytes, slot0: Address| {
+            let mut pre_state = BTreeMap::new();
+            pre_state.insert(
+                proxy,
+                AccountFixture {
+                    code: proxy_code.clone(),
+                    storage: [(U256::ZERO, ConvertTo::<U256>::cvt(&slot0))].into(),
+                    ..Default::default()
+                },
+            );
+            pre_state.insert(
+                implementation,
+                AccountFixture {
+                    code: implementation_code.clone(),
+                    ..Default::default()
+                },
+            );
+            TxFixture {
+                hash: hash.cvt(),
+                block: 1,
+                index,
+                spec_id: SpecId::SHANGHAI as u8,
+                block_env: BlockFixture {
+                    number: 1,
+                    gas_limit: U256::from(30000000),
+                    ..Default::default()
+                },
+                tx_env: TxEnvFixture {
+                    caller,
+                    to: Some(proxy),
+                    data,
+                    gas_limit: 1000000,
+                    ..Default::default()
+                },
+                pre_state,
+                post_state: BTreeMap::new(),
+            }
+        };
+        // set_implementation(implementation)
+        let set_implementation: Bytes = format!(
+            "0x4cd69da0000000000000000000000000{}",
+            implementation
+                .to_string()
+                .to_lowercase()
+                .trim_start_matches("0x")
+        )
+        .cvt();
+        // set_value(1) via the proxy
+        let set_value: Bytes =
+            "0xb0f2b72a0000000000000000000000000000000000000000000000000000000000000001".cvt();
+        let case = Case {
+            name: "synthetic".to_string(),
+            proxy,
+            implementation,
+            chain_id: 1,
+            txs: vec![
+                fixture(
+                    "0x1111111111111111111111111111111111111111111111111111111111111111",
+                    0,
+                    set_implementation,
+                    Address::ZERO,
+                ),
+                fixture(
+                    "0x2222222222222222222222222222222222222222222222222222222222222222",
+                    1,
+                    set_value,
+                    implementation,
+                ),
+            ],
+            alt_implementations: vec![],
+            frontrun: None,
+            expected: Expected {
+                collision_slots: [U256::ZERO].into(),
+                regressions: vec![],
+                frontrun_success: None,
+            },
+        };
+
+        // the case survives a round trip through its file format
+        let json = serde_json::to_string(&case).unwrap();
+        let case: Case = serde_json::from_str(&json).unwrap();
+        assert_eq!(case.replay().unwrap(), case.expected);
+    }
+
+    fn check_known_case(name: &str) -> Expected {
+        let case = Case::load(case_path(name)).unwrap();
+        let actual = case.replay().unwrap();
+        assert_eq!(actual, case.expected);
+        actual
+    }
+
+    #[test]
+    #[ignore = "requires fixtures recorded by bin/record-fixture"]
+    fn test_audius_attack() {
+        let r = check_known_case("audius");
+        assert!(r
+            .regressions
+            .iter()
+            .any(|i| i.different_slots || i.different_values));
+    }
+
+    #[test]
+    #[ignore = "requires fixtures recorded by bin/record-fixture"]
+    fn test_comptroller_enter_markets() {
+        let r = check_known_case("comptroller");
+        assert!(r
+            .regressions
+            .iter()
+            .all(|i| !i.different_slots && !i.different_values));
+    }
+
+    #[test]
+    #[ignore = "requires fixtures recorded by bin/record-fixture"]
+    fn test_root_chain_manager_exit() {
+        let r = check_known_case("root_chain_manager");
+        assert!(r
+            .regressions
+            .iter()
+            .all(|i| !i.different_slots && !i.different_values));
+    }
+
+    #[test]
+    #[ignore = "requires fixtures recorded by bin/record-fixture"]
+    fn test_wormhole_initializer() {
+        let r = check_known_case("wormhole");
+        assert_eq!(r.frontrun_success, Some(true));
+    }
+}
@@ -1,17 +1,29 @@
 use core::panic;
-use std::{collections::HashSet, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
 
 use libsofl_core::engine::{
     inspector::EvmInspector,
     state::BcState,
     types::{
         opcode, Address, Bytes, CallInputs, CreateInputs, EVMData, Gas, Inspector,
-        InstructionResult, Interpreter, U256,
+        InstructionResult, Interpreter, B256, U256,
     },
 };
 
+use crate::{
+    budget::BudgetMeter,
+    diamond::{decode_diamond_cut, FacetCut, DIAMOND_CUT_TOPIC},
+    slot::{Preimages, MAX_PREIMAGE_LEN},
+};
+
 /// StorageCollisionInspector checks whether the transaction to a proxy contract has the following scenario:
 /// 1. Proxy contract write the same storage slot as the implementation contract.
+///
+/// For a diamond (EIP-2535), the other facets are given `with_facets`: their accesses are attributed to the implementation
+/// as well, and recorded per facet so that the facets can be checked against each other (see `diamond::DiamondAccesses`).
 #[derive(Debug)]
 pub struct StorageAccessInspector {
     // input
@@ -20,7 +32,10 @@ pub struct StorageAccessInspector {
     pub proxy: Address,
     pub implementation: Address,
     pub alt_implementation: Option<Address>,
+    /// the logic contracts of a diamond, empty for other proxies
+    pub facets: HashSet<Address>,
     pub ignore_failed_calls: bool,
+    pub meter: BudgetMeter,
 
     // output
     pub proxy_reverted: bool,
@@ -30,6 +45,12 @@ pub struct StorageAccessInspector {
     pub proxy_sloads: HashSet<(Address, U256, U256)>,
     pub implementation_sstores: HashSet<(Address, U256, U256)>,
     pub implementation_sloads: HashSet<(Address, U256, U256)>,
+    pub preimages: Preimages,
+    /// accesses of each facet, only recorded with facets
+    pub facet_sstores: HashMap<Address, HashSet<(Address, U256, U256)>>,
+    pub facet_sloads: HashMap<Address, HashSet<(Address, U256, U256)>>,
+    /// the `DiamondCut` events emitted by the proxy in the successful txs, only recorded with facets
+    pub diamond_cuts: Vec<FacetCut>,
 
     // call stack
     pub _proxy_sstores: Vec<HashSet<(Address, U256, U256)>>,
@@ -40,6 +61,8 @@ pub struct StorageAccessInspector {
     // internal
     code_address: Vec<Option<Address>>,
     state_address: Vec<Option<Address>>,
+    pending_preimage: Option<Bytes>,
+    pending_cuts: Vec<FacetCut>,
 }
 
 impl StorageAccessInspector {
@@ -61,9 +84,17 @@ impl StorageAccessInspector {
             alt_implementation: None,
             implementation_sstores: HashSet::new(),
             implementation_sloads: HashSet::new(),
+            preimages: Preimages::new(),
+            facets: HashSet::new(),
+            facet_sstores: HashMap::new(),
+            facet_sloads: HashMap::new(),
+            diamond_cuts: Vec::new(),
             code_address: Vec::new(),
             state_address: Vec::new(),
+            pending_preimage: None,
+            pending_cuts: Vec::new(),
             ignore_failed_calls,
+            meter: BudgetMeter::default(),
             proxy_reverted: false,
             proxy_created: false,
 
@@ -93,9 +124,17 @@ impl StorageAccessInspector {
             alt_implementation: Some(alt_implementation),
             implementation_sstores: HashSet::new(),
             implementation_sloads: HashSet::new(),
+            preimages: Preimages::new(),
+            facets: HashSet::new(),
+            facet_sstores: HashMap::new(),
+            facet_sloads: HashMap::new(),
+            diamond_cuts: Vec::new(),
             code_address: Vec::new(),
             state_address: Vec::new(),
+            pending_preimage: None,
+            pending_cuts: Vec::new(),
             ignore_failed_calls,
+            meter: BudgetMeter::default(),
             proxy_reverted: false,
             proxy_created: false,
 
@@ -109,11 +148,31 @@ impl StorageAccessInspector {
     pub fn set_implementation(&mut self, implementation: Address) {
         self.implementation = implementation;
     }
+
+    /// Attribute the accesses of the facets of a diamond to the implementation, and record them per facet.
+    pub fn with_facets(mut self, facets: HashSet<Address>) -> Self {
+        self.facets = facets;
+        self
+    }
+
+    fn is_logic(&self, code_address: &Address) -> bool {
+        *code_address == self.implementation || self.facets.contains(code_address)
+    }
+
+    /// Bound the execution with a budget, the clock starts now.
+    pub fn with_meter(mut self, meter: BudgetMeter) -> Self {
+        self.meter = meter;
+        self
+    }
 }
 
 impl<S: BcState> Inspector<S> for StorageAccessInspector {
     #[inline]
     fn step(&mut self, interp: &mut Interpreter<'_>, data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+        if self.meter.exhausted() {
+            return;
+        }
         if self.code_address.is_empty() {
             panic!("code_address is empty: {:?}", self.proxy);
         }
@@ -127,12 +186,29 @@ impl<S: BcState> Inspector<S> for StorageAccessInspector {
             .last()
             .unwrap()
             .unwrap_or(interp.contract().address); // None when current call is create
-        if current_code_addr != self.proxy && current_code_addr != self.implementation
+        if current_code_addr != self.proxy && !self.is_logic(&current_code_addr)
             || current_state_addr != self.proxy
         {
             return;
         }
 
+        if interp.current_opcode() == opcode::KECCAK256 {
+            // record preimages of proxy/implementation frames to label the accessed slots,
+            // the hash is only available after the opcode, see step_end
+            let offset = interp.stack().peek(0).unwrap();
+            let len = interp.stack().peek(1).unwrap();
+            if len >= U256::from(32)
+                && len <= U256::from(MAX_PREIMAGE_LEN)
+                && offset.saturating_add(len) <= U256::from(interp.shared_memory.len())
+            {
+                let preimage = interp
+                    .shared_memory
+                    .slice(offset.to::<usize>(), len.to::<usize>())
+                    .to_vec();
+                self.pending_preimage = Some(Bytes::from(preimage));
+            }
+        }
+
         let current_state_addr = self
             .state_address
             .last()
@@ -156,6 +232,12 @@ impl<S: BcState> Inspector<S> for StorageAccessInspector {
                         key,
                         value,
                     ));
+                    if !self.facets.is_empty() {
+                        self.facet_sstores
+                            .entry(current_code_addr)
+                            .or_default()
+                            .insert((current_state_addr, key, value));
+                    }
                 }
             }
             opcode::SLOAD | opcode::TLOAD => {
@@ -172,11 +254,46 @@ impl<S: BcState> Inspector<S> for StorageAccessInspector {
                         key,
                         value,
                     ));
+                    if !self.facets.is_empty() {
+                        self.facet_sloads
+                            .entry(current_code_addr)
+                            .or_default()
+                            .insert((current_state_addr, key, value));
+                    }
                 }
             }
             _ => {}
         }
     }
+    #[inline]
+    fn step_end(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        if let Some(preimage) = self.pending_preimage.take() {
+            if interp.instruction_result.is_ok() {
+                let hash = interp.stack().peek(0).unwrap();
+                self.preimages.insert(hash, preimage);
+            }
+        }
+    }
+
+    #[inline]
+    fn log(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        address: &Address,
+        topics: &[B256],
+        data: &Bytes,
+    ) {
+        if self.facets.is_empty()
+            || *address != self.proxy
+            || topics.first().map_or(true, |t| t.0 != DIAMOND_CUT_TOPIC)
+        {
+            return;
+        }
+        if let Some(cuts) = decode_diamond_cut(data) {
+            self.pending_cuts.extend(cuts);
+        }
+    }
+
     #[inline]
     fn call(
         &mut self,
@@ -294,8 +411,13 @@ impl<S: BcState> EvmInspector<S> for StorageAccessInspector {
         &mut self,
         _tx: &libsofl_core::engine::types::TxEnv,
         _state: &S,
-        _result: &libsofl_core::engine::types::ExecutionResult,
+        result: &libsofl_core::engine::types::ExecutionResult,
     ) {
+        // the cuts of a reverted tx are not applied
+        let cuts = std::mem::take(&mut self.pending_cuts);
+        if result.is_success() {
+            self.diamond_cuts.extend(cuts);
+        }
         assert!(self.code_address.is_empty());
         assert!(self.state_address.is_empty());
         self.proxy_sloads.extend(self._proxy_sloads.pop().unwrap());
@@ -0,0 +1,256 @@
+use libsofl_core::engine::{
+    inspector::EvmInspector,
+    state::BcState,
+    types::{
+        opcode, Address, Bytes, CallInputs, CallScheme, CreateInputs, EVMData, Gas, Inspector,
+        InstructionResult, Interpreter,
+    },
+};
+
+use crate::budget::BudgetMeter;
+
+/// DangerousInspector records SELFDESTRUCT and DELEGATECALL (with its targets) executed in the context of a contract,
+/// in call frames that succeed, i.e., not reverted with their frame or one of its callers.
+#[derive(Debug, Default)]
+pub struct DangerousInspector {
+    pub contract: Address,
+    pub selfdestruct: bool,
+    pub delegatecall: bool,
+    pub delegatecall_targets: Vec<Address>,
+    pub meter: BudgetMeter,
+
+    // hits of each call frame, kept only if the frame succeeds
+    frames: Vec<Vec<Hit>>,
+}
+
+#[derive(Debug)]
+enum Hit {
+    Selfdestruct,
+    Delegatecall(Address),
+}
+
+impl DangerousInspector {
+    pub fn new(contract: Address, meter: BudgetMeter) -> Self {
+        Self {
+            contract,
+            meter,
+            ..Default::default()
+        }
+    }
+
+    pub fn reached(&self) -> bool {
+        self.selfdestruct || self.delegatecall
+    }
+
+    fn end_frame(&mut self, ret: InstructionResult) {
+        let hits = self.frames.pop().unwrap_or_default();
+        if !ret.is_ok() {
+            return;
+        }
+        if let Some(parent) = self.frames.last_mut() {
+            parent.extend(hits);
+            return;
+        }
+        for hit in hits {
+            match hit {
+                Hit::Selfdestruct => self.selfdestruct = true,
+                Hit::Delegatecall(target) => {
+                    self.delegatecall = true;
+                    self.delegatecall_targets.push(target);
+                }
+            }
+        }
+    }
+}
+
+impl<S: BcState> Inspector<S> for DangerousInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+        if interp.contract().address != self.contract
+            || interp.current_opcode() != opcode::SELFDESTRUCT
+        {
+            return;
+        }
+        if let Some(frame) = self.frames.last_mut() {
+            frame.push(Hit::Selfdestruct);
+        }
+    }
+
+    fn call(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        // a DELEGATECALL belongs to the frame it creates, so that it is dropped if the callee reverts
+        let mut frame = Vec::new();
+        if inputs.context.scheme == CallScheme::DelegateCall
+            && inputs.context.address == self.contract
+        {
+            frame.push(Hit::Delegatecall(inputs.context.code_address));
+        }
+        self.frames.push(frame);
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.end_frame(ret);
+        (ret, remaining_gas, out)
+    }
+
+    fn create(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CreateInputs,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.frames.push(Vec::new());
+        (InstructionResult::Continue, None, Gas::new(0), Bytes::new())
+    }
+
+    fn create_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CreateInputs,
+        ret: InstructionResult,
+        address: Option<Address>,
+        remaining_gas: Gas,
+        out: Bytes,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.end_frame(ret);
+        (ret, address, remaining_gas, out)
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for DangerousInspector {}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::{memory::MemoryBcState, types::Address};
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::BudgetMeter;
+
+    use super::DangerousInspector;
+
+    #[test]
+    fn test_selfdestruct_after_initialize() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                address public owner;
+                function initialize() public {
+                    require(owner == address(0));
+                    owner = msg.sender;
+                }
+                function kill() public {
+                    require(msg.sender == owner);
+                    selfdestruct(payable(msg.sender));
+                }
+            }
+            "#,
+            vec!["Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let implementation = addrs.remove(0);
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let mut insp = DangerousInspector::new(implementation, BudgetMeter::default());
+        let _ = caller.invoke(&mut state, implementation, "kill()", &[], None, &mut insp);
+        assert!(!insp.reached());
+
+        let mut insp = DangerousInspector::new(implementation, BudgetMeter::default());
+        caller
+            .invoke(
+                &mut state,
+                implementation,
+                "initialize()",
+                &[],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        caller
+            .invoke(&mut state, implementation, "kill()", &[], None, &mut insp)
+            .unwrap();
+        assert!(insp.selfdestruct);
+        assert!(!insp.delegatecall);
+    }
+
+    #[test]
+    fn test_reverted_frames() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                function kill() public {
+                    selfdestruct(payable(msg.sender));
+                }
+                function kill_and_revert() public {
+                    this.kill();
+                    revert();
+                }
+                function delegate_and_revert(address _target) public {
+                    (bool ok, ) = _target.delegatecall("");
+                    require(ok);
+                    revert();
+                }
+                function try_kill() public {
+                    try this.kill_and_revert() {} catch {}
+                }
+            }
+            "#,
+            vec!["Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let implementation = addrs.remove(0);
+        let target = Address::from([0xbe; 20]);
+        let caller = HighLevelCaller::default().bypass_check();
+
+        // the selfdestruct and the delegatecall are executed, but reverted with their callers
+        let mut insp = DangerousInspector::new(implementation, BudgetMeter::default());
+        let _ = caller.invoke(
+            &mut state,
+            implementation,
+            "kill_and_revert()",
+            &[],
+            None,
+            &mut insp,
+        );
+        let _ = caller.invoke(
+            &mut state,
+            implementation,
+            "delegate_and_revert(address)",
+            &[target.into()],
+            None,
+            &mut insp,
+        );
+        caller
+            .invoke(
+                &mut state,
+                implementation,
+                "try_kill()",
+                &[],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert!(!insp.reached());
+        assert!(insp.delegatecall_targets.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use libsofl_core::engine::{
+    inspector::EvmInspector,
+    state::BcState,
+    types::{
+        Address, Bytes, CallInputs, CallScheme, EVMData, Gas, Inspector, InstructionResult,
+        Interpreter,
+    },
+};
+
+use crate::{beacon::IMPLEMENTATION_SELECTOR, budget::BudgetMeter};
+
+/// ImplInspector records the implementation that the proxy delegates the top-level call to.
+/// For a beacon proxy, the beacon that the proxy asks for the implementation is recorded as well.
+pub struct ImplInspector {
+    pub proxy: Address,
+    pub implementation: Option<Address>,
+    pub beacon: Option<Address>,
+    pub meter: BudgetMeter,
+}
+
+impl<S: BcState> Inspector<S> for ImplInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+    }
+
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, S>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        if data.journaled_state.depth() == 1
+            && inputs.context.scheme == CallScheme::DelegateCall
+            && inputs.context.address == self.proxy
+        {
+            self.implementation = Some(inputs.context.code_address);
+        } else if data.journaled_state.depth() == 1
+            && inputs.context.scheme != CallScheme::DelegateCall
+            && inputs.context.caller == self.proxy
+            && inputs.input.starts_with(&IMPLEMENTATION_SELECTOR)
+        {
+            self.beacon = Some(inputs.contract);
+        }
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for ImplInspector {}
+
+/// BeaconInspector records the address returned by the top-level call, i.e., `implementation()` of a beacon.
+#[derive(Default)]
+pub struct BeaconInspector {
+    pub implementation: Option<Address>,
+    pub meter: BudgetMeter,
+
+    depth: usize,
+}
+
+impl<S: BcState> Inspector<S> for BeaconInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+    }
+
+    fn call(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.depth += 1;
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.depth -= 1;
+        // an address is returned as a word with 12 zero bytes
+        if self.depth == 0 && ret.is_ok() && out.len() == 32 && out[..12].iter().all(|b| *b == 0) {
+            let implementation = Address::from_slice(&out[12..]);
+            if implementation != Address::ZERO {
+                self.implementation = Some(implementation);
+            }
+        }
+        (ret, remaining_gas, out)
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for BeaconInspector {}
+
+/// ReturnInspector records the output of the top-level call if it succeeds, e.g., the facets returned by a diamond loupe.
+#[derive(Default)]
+pub struct ReturnInspector {
+    pub output: Option<Bytes>,
+    pub meter: BudgetMeter,
+
+    depth: usize,
+}
+
+impl<S: BcState> Inspector<S> for ReturnInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+    }
+
+    fn call(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.depth += 1;
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.depth -= 1;
+        if self.depth == 0 && ret.is_ok() {
+            self.output = Some(out.clone());
+        }
+        (ret, remaining_gas, out)
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for ReturnInspector {}
+
+/// RoutingInspector makes the top-level call to the proxy from `sender`,
+/// and records whether the proxy delegates the call.
+pub struct RoutingInspector {
+    pub proxy: Address,
+    pub sender: Address,
+    pub delegated: bool,
+    pub meter: BudgetMeter,
+}
+
+impl<S: BcState> Inspector<S> for RoutingInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+    }
+
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, S>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        let depth = data.journaled_state.depth();
+        if depth == 0 {
+            inputs.context.caller = self.sender;
+            inputs.transfer.source = self.sender;
+        } else if depth == 1
+            && inputs.context.scheme == CallScheme::DelegateCall
+            && inputs.context.address == self.proxy
+        {
+            self.delegated = true;
+        }
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for RoutingInspector {}
@@ -0,0 +1,223 @@
+use std::{collections::HashSet, sync::Arc};
+
+use libsofl_core::engine::{
+    inspector::EvmInspector,
+    state::BcState,
+    types::{
+        Address, Bytes, CallInputs, CallScheme, CreateInputs, EVMData, Gas, Inspector,
+        InstructionResult, Interpreter,
+    },
+};
+
+use crate::budget::BudgetMeter;
+
+/// InvocationInspector records the invocations of proxies in a tx, i.e., the delegatecalls made by a proxy
+/// with the calldata of the call to the proxy, the same as the invocations found in the traces (see `dataset::trace`).
+///
+/// Unlike `ImplInspector`, which only watches the top-level call to one proxy,
+/// proxies called by other contracts at any depth are recorded as well,
+/// so that the `invocation` rows can be derived by replaying all the txs of a block.
+pub struct InvocationInspector {
+    /// the proxies watched, any contract if None
+    pub proxies: Option<Arc<HashSet<Address>>>,
+    pub meter: BudgetMeter,
+
+    /// (proxy, implementation) of each invocation, in execution order
+    pub invocations: Vec<(Address, Address)>,
+
+    // scheme, state address and calldata of each call frame, None for creations
+    frames: Vec<Option<(CallScheme, Address, Bytes)>>,
+}
+
+impl InvocationInspector {
+    pub fn new(proxies: Option<Arc<HashSet<Address>>>, meter: BudgetMeter) -> Self {
+        Self {
+            proxies,
+            meter,
+            invocations: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    fn is_watched(&self, proxy: &Address) -> bool {
+        self.proxies.as_ref().map_or(true, |ps| ps.contains(proxy))
+    }
+}
+
+impl<S: BcState> Inspector<S> for InvocationInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+    }
+
+    fn call(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        if inputs.context.scheme == CallScheme::DelegateCall {
+            let proxy = inputs.context.address;
+            if let Some(Some((CallScheme::Call, address, input))) = self.frames.last() {
+                if *address == proxy && *input == inputs.input && self.is_watched(&proxy) {
+                    self.invocations.push((proxy, inputs.context.code_address));
+                }
+            }
+        }
+        self.frames.push(Some((
+            inputs.context.scheme,
+            inputs.context.address,
+            inputs.input.clone(),
+        )));
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.frames.pop();
+        (ret, remaining_gas, out)
+    }
+
+    fn create(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CreateInputs,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.frames.push(None);
+        (InstructionResult::Continue, None, Gas::new(0), Bytes::new())
+    }
+
+    fn create_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CreateInputs,
+        ret: InstructionResult,
+        address: Option<Address>,
+        remaining_gas: Gas,
+        out: Bytes,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.frames.pop();
+        (ret, address, remaining_gas, out)
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for InvocationInspector {}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use libsofl_core::engine::{memory::MemoryBcState, types::U256};
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::BudgetMeter;
+
+    use super::InvocationInspector;
+
+    #[test]
+    fn test_invocations() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Proxy {
+                address public implementation;
+                function set_implementation(address _impl) public {
+                    implementation = _impl;
+                }
+                fallback() external payable {
+                    address _impl = implementation;
+                    assembly {
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), _impl, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            contract Lib {
+                function double(uint256 x) public pure returns (uint256) {
+                    return x * 2;
+                }
+            }
+            contract Impl {
+                uint256 public value;
+                function set_value(address lib, uint256 _value) public {
+                    (bool ok, bytes memory r) = lib.delegatecall(abi.encodeWithSignature("double(uint256)", _value));
+                    require(ok);
+                    value = abi.decode(r, (uint256));
+                }
+            }
+            contract Caller {
+                function call_proxy(address proxy, address lib, uint256 _value) public {
+                    (bool ok, ) = proxy.call(abi.encodeWithSignature("set_value(address,uint256)", lib, _value));
+                    require(ok);
+                }
+            }
+            "#,
+            vec!["Proxy", "Lib", "Impl", "Caller"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (proxy, lib, implementation, caller_contract) = (
+            addrs.remove(0),
+            addrs.remove(0),
+            addrs.remove(0),
+            addrs.remove(0),
+        );
+        let caller = HighLevelCaller::default().bypass_check();
+        let mut insp = InvocationInspector::new(None, BudgetMeter::default());
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "set_implementation(address)",
+                &[implementation.into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert!(insp.invocations.is_empty());
+
+        // the proxy is called by another contract, and the implementation delegates to a library
+        let mut insp = InvocationInspector::new(None, BudgetMeter::default());
+        caller
+            .invoke(
+                &mut state,
+                caller_contract,
+                "call_proxy(address,address,uint256)",
+                &[proxy.into(), lib.into(), U256::from(1).into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert_eq!(insp.invocations, vec![(proxy, implementation)]);
+
+        // proxies not watched are ignored
+        let mut insp = InvocationInspector::new(
+            Some(Arc::new(HashSet::from([caller_contract]))),
+            BudgetMeter::default(),
+        );
+        caller
+            .invoke(
+                &mut state,
+                caller_contract,
+                "call_proxy(address,address,uint256)",
+                &[proxy.into(), lib.into(), U256::from(2).into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert!(insp.invocations.is_empty());
+    }
+}
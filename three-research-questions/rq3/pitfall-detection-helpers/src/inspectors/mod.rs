@@ -1,2 +1,8 @@
 pub mod collision;
+pub mod dangerous;
+pub mod delegation;
 pub mod ether;
+pub mod invocation;
+pub mod prestate;
+pub mod slot_write;
+pub mod storage_write;
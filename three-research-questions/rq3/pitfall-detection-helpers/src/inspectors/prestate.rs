@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::{
+        inspector::EvmInspector,
+        state::BcState,
+        types::{
+            opcode, Address, Bytes, CallInputs, CreateInputs, Database, EVMData, Gas, Inspector,
+            InstructionResult, Interpreter, U256,
+        },
+    },
+};
+
+use crate::provider::fixture::AccountFixture;
+
+/// PrestateInspector records the accounts and storage slots touched by transactions,
+/// with the values they hold when they are touched for the first time.
+/// The recorded pre-state is enough to replay the transactions on a fresh state.
+#[derive(Debug, Default)]
+pub struct PrestateInspector {
+    pub accounts: BTreeMap<Address, AccountFixture>,
+}
+
+impl PrestateInspector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an account (without storage) if it has not been recorded yet.
+    pub fn record_account<D: Database>(&mut self, db: &mut D, address: Address)
+    where
+        D::Error: std::fmt::Debug,
+    {
+        if self.accounts.contains_key(&address) {
+            return;
+        }
+        let info = db.basic(address).unwrap().unwrap_or_default();
+        let code = match info.code {
+            Some(code) => code,
+            None => db.code_by_hash(info.code_hash).unwrap(),
+        };
+        self.accounts.insert(
+            address,
+            AccountFixture {
+                balance: info.balance,
+                nonce: info.nonce,
+                code: code.original_bytes(),
+                storage: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Record a storage slot if it has not been recorded yet.
+    pub fn record_slot<D: Database>(&mut self, db: &mut D, address: Address, slot: U256)
+    where
+        D::Error: std::fmt::Debug,
+    {
+        self.record_account(db, address);
+        let account = self.accounts.get_mut(&address).unwrap();
+        if account.storage.contains_key(&slot) {
+            return;
+        }
+        let value = db.storage(address, slot).unwrap();
+        account.storage.insert(slot, value);
+    }
+
+    /// The current state of the recorded accounts and storage slots, e.g., after the transactions.
+    pub fn post_state<D: Database>(&self, db: &mut D) -> BTreeMap<Address, AccountFixture>
+    where
+        D::Error: std::fmt::Debug,
+    {
+        let mut post = PrestateInspector::new();
+        for (address, account) in self.accounts.iter() {
+            post.record_account(db, *address);
+            for slot in account.storage.keys() {
+                post.record_slot(db, *address, *slot);
+            }
+        }
+        post.accounts
+    }
+}
+
+impl<S: BcState> Inspector<S> for PrestateInspector {
+    #[inline]
+    fn step(&mut self, interp: &mut Interpreter<'_>, data: &mut EVMData<'_, S>) {
+        let address = interp.contract().address;
+        match interp.current_opcode() {
+            opcode::SLOAD | opcode::SSTORE => {
+                let slot = interp.stack().peek(0).unwrap();
+                self.record_slot(&mut *data.db, address, slot);
+            }
+            opcode::BALANCE | opcode::EXTCODESIZE | opcode::EXTCODECOPY | opcode::EXTCODEHASH => {
+                let target: Address = interp.stack().peek(0).unwrap().cvt();
+                self.record_account(&mut *data.db, target);
+            }
+            opcode::SELFBALANCE => {
+                self.record_account(&mut *data.db, address);
+            }
+            _ => {}
+        }
+    }
+
+    #[inline]
+    fn call(
+        &mut self,
+        data: &mut EVMData<'_, S>,
+        inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.record_account(&mut *data.db, inputs.context.caller);
+        self.record_account(&mut *data.db, inputs.context.address);
+        self.record_account(&mut *data.db, inputs.context.code_address);
+        self.record_account(&mut *data.db, inputs.transfer.target);
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    #[inline]
+    fn create(
+        &mut self,
+        data: &mut EVMData<'_, S>,
+        inputs: &mut CreateInputs,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.record_account(&mut *data.db, inputs.caller);
+        (InstructionResult::Continue, None, Gas::new(0), Bytes::new())
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for PrestateInspector {}
@@ -0,0 +1,177 @@
+use libsofl_core::engine::{
+    inspector::EvmInspector,
+    state::BcState,
+    types::{
+        opcode, Address, Bytes, CallInputs, CreateInputs, EVMData, Gas, Inspector,
+        InstructionResult, Interpreter, U256,
+    },
+};
+
+use crate::{budget::BudgetMeter, slot::StandardSlot};
+
+/// SlotWriteInspector records the SSTOREs of a tx to the slots of a proxy in `StandardSlot::HISTORY`,
+/// including those of its constructor, that take effect, i.e., not reverted with their call frame or the tx.
+pub struct SlotWriteInspector {
+    pub proxy: Address,
+    pub meter: BudgetMeter,
+
+    /// (slot, value) of each write, in execution order
+    pub writes: Vec<(StandardSlot, U256)>,
+
+    // writes of each call frame, kept only if the frame succeeds
+    frames: Vec<Vec<(StandardSlot, U256)>>,
+}
+
+impl SlotWriteInspector {
+    pub fn new(proxy: Address, meter: BudgetMeter) -> Self {
+        Self {
+            proxy,
+            meter,
+            writes: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    fn end_frame(&mut self, ret: InstructionResult) {
+        let writes = self.frames.pop().unwrap_or_default();
+        if !ret.is_ok() {
+            return;
+        }
+        match self.frames.last_mut() {
+            Some(parent) => parent.extend(writes),
+            None => self.writes.extend(writes),
+        }
+    }
+}
+
+impl<S: BcState> Inspector<S> for SlotWriteInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+        if interp.current_opcode() != opcode::SSTORE || interp.contract().address != self.proxy {
+            return;
+        }
+        let key = interp.stack().peek(0).unwrap();
+        let value = interp.stack().peek(1).unwrap();
+        if let Some(slot) = StandardSlot::from_history_slot(key) {
+            if let Some(frame) = self.frames.last_mut() {
+                frame.push((slot, slot.mask(value)));
+            }
+        }
+    }
+
+    fn call(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CallInputs,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.frames.push(Vec::new());
+        (InstructionResult::Continue, Gas::new(0), Bytes::new())
+    }
+
+    fn call_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CallInputs,
+        remaining_gas: Gas,
+        ret: InstructionResult,
+        out: Bytes,
+    ) -> (InstructionResult, Gas, Bytes) {
+        self.end_frame(ret);
+        (ret, remaining_gas, out)
+    }
+
+    fn create(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &mut CreateInputs,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.frames.push(Vec::new());
+        (InstructionResult::Continue, None, Gas::new(0), Bytes::new())
+    }
+
+    fn create_end(
+        &mut self,
+        _data: &mut EVMData<'_, S>,
+        _inputs: &CreateInputs,
+        ret: InstructionResult,
+        address: Option<Address>,
+        remaining_gas: Gas,
+        out: Bytes,
+    ) -> (InstructionResult, Option<Address>, Gas, Bytes) {
+        self.end_frame(ret);
+        (ret, address, remaining_gas, out)
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for SlotWriteInspector {}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::{
+        memory::MemoryBcState,
+        types::{Address, U256},
+    };
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::{budget::BudgetMeter, slot::StandardSlot};
+
+    use super::SlotWriteInspector;
+
+    #[test]
+    fn test_slot_writes() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Proxy {
+                function upgrade(address _impl, bool _revert) public {
+                    assembly {
+                        sstore(0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc, _impl)
+                        sstore(0, add(shl(16, _impl), 1))
+                        sstore(1, _impl)
+                    }
+                    require(!_revert);
+                }
+                function try_upgrade(address _impl) public {
+                    try this.upgrade(_impl, true) {} catch {}
+                    this.upgrade(_impl, false);
+                }
+            }
+            "#,
+            vec!["Proxy"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let proxy = addrs.remove(0);
+        let implementation = Address::from([0xbe; 20]);
+        let caller = HighLevelCaller::default().bypass_check();
+
+        // the writes of the reverted call are dropped, only the initializer flags of slot 0 are kept,
+        // and slot 1 is not tracked
+        let mut insp = SlotWriteInspector::new(proxy, BudgetMeter::default());
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "try_upgrade(address)",
+                &[implementation.into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert_eq!(
+            insp.writes,
+            vec![
+                (
+                    StandardSlot::Eip1967Implementation,
+                    U256::from_be_slice(implementation.as_slice())
+                ),
+                (StandardSlot::OzInitializableV4, U256::from(1))
+            ]
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+
+use libsofl_core::engine::{
+    inspector::EvmInspector,
+    state::BcState,
+    types::{opcode, Address, EVMData, Inspector, Interpreter},
+};
+
+use crate::budget::BudgetMeter;
+
+/// StorageWriteInspector tells whether a tx executes an SSTORE in the storage of any of the contracts watched,
+/// e.g., a proxy and its beacon, so that what is derived from their storage is only read again after such a tx.
+/// Writes reverted afterwards are counted as well.
+pub struct StorageWriteInspector {
+    pub contracts: HashSet<Address>,
+    pub meter: BudgetMeter,
+
+    pub written: bool,
+}
+
+impl StorageWriteInspector {
+    pub fn new(contracts: HashSet<Address>, meter: BudgetMeter) -> Self {
+        Self {
+            contracts,
+            meter,
+            written: false,
+        }
+    }
+}
+
+impl<S: BcState> Inspector<S> for StorageWriteInspector {
+    fn step(&mut self, interp: &mut Interpreter<'_>, _data: &mut EVMData<'_, S>) {
+        self.meter.step(interp);
+        if interp.current_opcode() == opcode::SSTORE
+            && self.contracts.contains(&interp.contract().address)
+        {
+            self.written = true;
+        }
+    }
+}
+
+impl<S: BcState> EvmInspector<S> for StorageWriteInspector {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use libsofl_core::engine::{memory::MemoryBcState, types::Address};
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::BudgetMeter;
+
+    use super::StorageWriteInspector;
+
+    #[test]
+    fn test_storage_writes() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Proxy {
+                address public implementation;
+                function upgrade(address _impl) public {
+                    implementation = _impl;
+                }
+            }
+            "#,
+            vec!["Proxy"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let proxy = addrs.remove(0);
+        let implementation = Address::from([0xbe; 20]);
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let mut insp = StorageWriteInspector::new(HashSet::from([proxy]), BudgetMeter::default());
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "implementation()",
+                &[],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert!(!insp.written);
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "upgrade(address)",
+                &[implementation.into()],
+                None,
+                &mut insp,
+            )
+            .unwrap();
+        assert!(insp.written);
+    }
+}
@@ -0,0 +1,641 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Lines, Write},
+    marker::PhantomData,
+    path::Path,
+    thread,
+};
+
+use crossbeam::channel;
+use libsofl_core::engine::types::{Address, TxHash};
+use libsofl_utils::log::error;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::DetectorError, shutdown::Shutdown};
+
+/// Records are flushed to the output file every this many records.
+const FLUSH_INTERVAL: usize = 100;
+
+/// JsonlSource reads one record per line from a JSONL file, e.g., the `ProxyData` produced by `logic-contract-prep`.
+/// Blank lines are skipped.
+pub struct JsonlSource<T> {
+    lines: Lines<BufReader<File>>,
+    line_no: usize,
+    _record: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> JsonlSource<T> {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            lines: BufReader::new(File::open(path)?).lines(),
+            line_no: 0,
+            _record: PhantomData,
+        })
+    }
+}
+
+impl<T: DeserializeOwned> JsonlSource<T> {
+    /// The records of the file, skipping (and logging) the malformed lines.
+    pub fn valid(self) -> impl Iterator<Item = T> {
+        self.filter_map(|r| match r {
+            Ok(record) => Some(record),
+            Err(e) => {
+                error!(error = ?e, "Skip malformed record");
+                None
+            }
+        })
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for JsonlSource<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            self.line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(serde_json::from_str(&line).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", self.line_no, e),
+                )
+            }));
+        }
+    }
+}
+
+/// A file that results are written to, record by record, instead of the database.
+pub trait RecordSink: Send {
+    fn write(&mut self, record: serde_json::Value) -> io::Result<()>;
+
+    /// Flush the records written and close the file.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+impl dyn RecordSink {
+    pub fn write_record<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        let record = serde_json::to_value(record)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write(record)
+    }
+}
+
+/// Open the sink by the extension of the path: Parquet for `.parquet` (with the `parquet` feature), JSONL otherwise.
+pub fn open_sink<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn RecordSink>> {
+    let path = path.as_ref();
+    if path.extension().map_or(false, |e| e == "parquet") {
+        #[cfg(feature = "parquet")]
+        return Ok(Box::new(parquet_sink::ParquetSink::create(path)?));
+        #[cfg(not(feature = "parquet"))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "parquet output requires the `parquet` feature",
+        ));
+    }
+    Ok(Box::new(JsonlSink::create(path)?))
+}
+
+/// A failure written to the error file, with the fields of the `error` table but the detector,
+/// which is the one writing the file.
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorRecord {
+    /// the proxy (or implementation) whose analysis fails
+    pub proxy: Address,
+    pub tx: Option<TxHash>,
+    #[serde(flatten)]
+    pub error: DetectorError,
+}
+
+impl ErrorRecord {
+    pub fn new(proxy: Address, tx: Option<TxHash>, error: DetectorError) -> Self {
+        Self { proxy, tx, error }
+    }
+}
+
+/// The files a detector writes its results (and failures) to, instead of the database.
+pub struct OutputFiles {
+    pub results: Box<dyn RecordSink>,
+    pub errors: Option<Box<dyn RecordSink>>,
+}
+
+impl OutputFiles {
+    pub fn open(results: &str, errors: Option<&str>) -> io::Result<Self> {
+        Ok(Self {
+            results: open_sink(results)?,
+            errors: errors.map(open_sink).transpose()?,
+        })
+    }
+
+    pub fn save_result<T: Serialize>(&mut self, record: &T) {
+        self.results
+            .write_record(record)
+            .expect("Failed to write result record");
+    }
+
+    /// The failure is dropped if no error file is given.
+    pub fn save_error<T: Serialize>(&mut self, error: &T) {
+        if let Some(errors) = &mut self.errors {
+            errors
+                .write_record(error)
+                .expect("Failed to write error record");
+        }
+    }
+
+    pub fn finish(self) {
+        self.results.finish().expect("Failed to write results");
+        if let Some(errors) = self.errors {
+            errors.finish().expect("Failed to write errors");
+        }
+    }
+}
+
+/// Check the items on `jobs` threads and write the records returned for each item to the files,
+/// the counterpart of the worker pool and the result thread of a detector without the database.
+/// The items not started when a shutdown is requested are abandoned.
+pub fn check_into_files<I, R, F>(
+    items: impl Iterator<Item = I>,
+    mut files: OutputFiles,
+    jobs: usize,
+    shutdown: &Shutdown,
+    check: F,
+) where
+    I: Send,
+    R: Serialize + Send,
+    F: Fn(I) -> Vec<Result<R, ErrorRecord>> + Sync,
+{
+    let (item_tx, item_rx) = channel::bounded::<I>(jobs);
+    let (record_tx, record_rx) = channel::bounded::<Result<R, ErrorRecord>>(jobs);
+    thread::scope(|s| {
+        for _ in 0..jobs {
+            let item_rx = item_rx.clone();
+            let record_tx = record_tx.clone();
+            let check = &check;
+            s.spawn(move || {
+                for item in item_rx {
+                    if shutdown.requested() {
+                        continue;
+                    }
+                    for record in check(item) {
+                        record_tx.send(record).unwrap();
+                    }
+                }
+            });
+        }
+        drop(record_tx);
+        s.spawn(move || {
+            for record in record_rx {
+                match record {
+                    Ok(r) => files.save_result(&r),
+                    Err(e) => files.save_error(&e),
+                }
+            }
+            files.finish();
+        });
+        for item in items {
+            if shutdown.requested() {
+                break;
+            }
+            item_tx.send(item).unwrap();
+        }
+        drop(item_tx);
+    });
+}
+
+/// JsonlSink writes one JSON record per line.
+/// Records are flushed periodically, so that the results survive an interrupted run.
+pub struct JsonlSink<W: Write = File> {
+    writer: BufWriter<W>,
+    unflushed: usize,
+}
+
+impl JsonlSink<File> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            unflushed: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+impl<W: Write + Send> RecordSink for JsonlSink<W> {
+    fn write(&mut self, record: serde_json::Value) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        self.unflushed += 1;
+        if self.unflushed >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.unflushed = 0;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(feature = "parquet")]
+pub mod parquet_sink {
+    use std::{fs::File, io, path::Path, sync::Arc};
+
+    use arrow_array::{
+        builder::{BooleanBuilder, Float64Builder, Int64Builder, StringBuilder},
+        ArrayRef, RecordBatch,
+    };
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+
+    use super::RecordSink;
+
+    /// Records are written to the file as a row group every this many records.
+    const ROW_GROUP_SIZE: usize = 10000;
+
+    type Record = serde_json::Map<String, serde_json::Value>;
+
+    /// ParquetSink writes the records as the rows of a Parquet file, one row group every `ROW_GROUP_SIZE` records,
+    /// so that only the records of the current row group are kept in memory.
+    /// Each top-level field becomes a column: booleans, integers and floats keep their types,
+    /// while strings and nested values (e.g., the slot accesses) are stored as (JSON) text.
+    ///
+    /// The column types are taken from the records of the first row group.
+    /// A column whose values are all null there is stored as text, and a later record with a field
+    /// not seen in the first row group, or with a value of another type, fails to be written.
+    pub struct ParquetSink {
+        file: Option<File>,
+        writer: Option<ArrowWriter<File>>,
+        schema: Option<SchemaRef>,
+        records: Vec<Record>,
+        row_group_size: usize,
+    }
+
+    impl ParquetSink {
+        pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            Self::with_row_group_size(path, ROW_GROUP_SIZE)
+        }
+
+        pub fn with_row_group_size<P: AsRef<Path>>(
+            path: P,
+            row_group_size: usize,
+        ) -> io::Result<Self> {
+            Ok(Self {
+                file: Some(File::create(path)?),
+                writer: None,
+                schema: None,
+                records: Vec::new(),
+                row_group_size: row_group_size.max(1),
+            })
+        }
+
+        /// Write the records kept as a row group, creating the writer with their schema the first time.
+        fn flush_row_group(&mut self) -> io::Result<()> {
+            if self.records.is_empty() {
+                return Ok(());
+            }
+            if self.writer.is_none() {
+                let file = self.file.take().expect("parquet file already taken");
+                let schema = Arc::new(infer_schema(&self.records));
+                self.writer =
+                    Some(ArrowWriter::try_new(file, schema.clone(), None).map_err(to_io)?);
+                self.schema = Some(schema);
+            }
+            let (writer, schema) = match (&mut self.writer, &self.schema) {
+                (Some(writer), Some(schema)) => (writer, schema.clone()),
+                _ => unreachable!("parquet writer created with its schema"),
+            };
+            for record in &self.records {
+                if let Some(name) = record.keys().find(|n| schema.field_with_name(n).is_err()) {
+                    return Err(invalid_data(format!("unknown parquet column: {}", name)));
+                }
+            }
+            let columns = schema
+                .fields()
+                .iter()
+                .map(|f| column(&self.records, f))
+                .collect::<io::Result<Vec<_>>>()?;
+            let batch = RecordBatch::try_new(schema, columns).map_err(invalid_data)?;
+            writer.write(&batch).map_err(to_io)?;
+            // close the row group, so that its rows are on disk
+            writer.flush().map_err(to_io)?;
+            self.records.clear();
+            Ok(())
+        }
+    }
+
+    /// The columns in the order they first appear, with the type of their values.
+    fn infer_schema(records: &[Record]) -> Schema {
+        let mut fields: Vec<(String, Option<DataType>)> = Vec::new();
+        for record in records {
+            for (name, value) in record {
+                let t = match value {
+                    serde_json::Value::Null => None,
+                    serde_json::Value::Bool(_) => Some(DataType::Boolean),
+                    serde_json::Value::Number(n) if n.is_i64() => Some(DataType::Int64),
+                    serde_json::Value::Number(_) => Some(DataType::Float64),
+                    _ => Some(DataType::Utf8),
+                };
+                match fields.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, existing)) => {
+                        *existing = match (existing.take(), t) {
+                            (None, t) | (t, None) => t,
+                            (Some(a), Some(b)) if a == b => Some(a),
+                            (Some(DataType::Int64), Some(DataType::Float64))
+                            | (Some(DataType::Float64), Some(DataType::Int64)) => {
+                                Some(DataType::Float64)
+                            }
+                            _ => Some(DataType::Utf8),
+                        }
+                    }
+                    None => fields.push((name.clone(), t)),
+                }
+            }
+        }
+        Schema::new(
+            fields
+                .into_iter()
+                .map(|(name, t)| Field::new(name, t.unwrap_or(DataType::Utf8), true))
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn column(records: &[Record], field: &Field) -> io::Result<ArrayRef> {
+        let values = records
+            .iter()
+            .map(|r| r.get(field.name()).filter(|v| !v.is_null()));
+        let mismatch = |v: &serde_json::Value| {
+            invalid_data(format!(
+                "parquet column {} of type {} cannot hold {}",
+                field.name(),
+                field.data_type(),
+                v
+            ))
+        };
+        let array: ArrayRef = match field.data_type() {
+            DataType::Boolean => {
+                let mut b = BooleanBuilder::new();
+                for v in values {
+                    b.append_option(
+                        v.map(|v| v.as_bool().ok_or_else(|| mismatch(v)))
+                            .transpose()?,
+                    );
+                }
+                Arc::new(b.finish())
+            }
+            DataType::Int64 => {
+                let mut b = Int64Builder::new();
+                for v in values {
+                    b.append_option(
+                        v.map(|v| v.as_i64().ok_or_else(|| mismatch(v)))
+                            .transpose()?,
+                    );
+                }
+                Arc::new(b.finish())
+            }
+            DataType::Float64 => {
+                let mut b = Float64Builder::new();
+                for v in values {
+                    b.append_option(
+                        v.map(|v| v.as_f64().ok_or_else(|| mismatch(v)))
+                            .transpose()?,
+                    );
+                }
+                Arc::new(b.finish())
+            }
+            _ => {
+                let mut b = StringBuilder::new();
+                for v in values {
+                    match v {
+                        None => b.append_null(),
+                        Some(serde_json::Value::String(s)) => b.append_value(s),
+                        Some(v) => b.append_value(v.to_string()),
+                    }
+                }
+                Arc::new(b.finish())
+            }
+        };
+        Ok(array)
+    }
+
+    fn to_io(e: parquet::errors::ParquetError) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+
+    fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+
+    impl RecordSink for ParquetSink {
+        fn write(&mut self, record: serde_json::Value) -> io::Result<()> {
+            match record {
+                serde_json::Value::Object(record) => {
+                    self.records.push(record);
+                    if self.records.len() >= self.row_group_size {
+                        self.flush_row_group()?;
+                    }
+                    Ok(())
+                }
+                _ => Err(invalid_data("parquet records must be JSON objects")),
+            }
+        }
+
+        fn finish(mut self: Box<Self>) -> io::Result<()> {
+            self.flush_row_group()?;
+            let writer = match self.writer.take() {
+                Some(writer) => writer,
+                // no record is written, the file has no column
+                None => {
+                    let file = self.file.take().expect("parquet file already taken");
+                    ArrowWriter::try_new(file, Arc::new(Schema::empty()), None).map_err(to_io)?
+                }
+            };
+            writer.close().map_err(to_io)?;
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::fs::File;
+
+        use arrow_array::{Array, BooleanArray, Int64Array, StringArray};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        use serde_json::json;
+
+        use super::{ParquetSink, RecordSink};
+
+        #[test]
+        fn test_parquet_sink_round_trip() {
+            let path = std::env::temp_dir().join("proxyex-detector-test-parquet-sink.parquet");
+            let mut sink: Box<dyn RecordSink> =
+                Box::new(ParquetSink::with_row_group_size(&path, 2).unwrap());
+            for i in 0..5i64 {
+                sink.write_record(&json!({
+                    "proxy": format!("0x{:02x}", i),
+                    "block": i,
+                    "problematic": i % 2 == 0,
+                    "slots": [i],
+                }))
+                .unwrap();
+            }
+            sink.finish().unwrap();
+
+            let builder =
+                ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+            // the rows are written as they come, two by two
+            assert_eq!(builder.metadata().num_row_groups(), 3);
+            let batches = builder
+                .build()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let mut rows = Vec::new();
+            for batch in batches {
+                let column = |name| batch.column(batch.schema().index_of(name).unwrap()).clone();
+                let proxy = column("proxy");
+                let proxy = proxy.as_any().downcast_ref::<StringArray>().unwrap();
+                let block = column("block");
+                let block = block.as_any().downcast_ref::<Int64Array>().unwrap();
+                let problematic = column("problematic");
+                let problematic = problematic.as_any().downcast_ref::<BooleanArray>().unwrap();
+                let slots = column("slots");
+                let slots = slots.as_any().downcast_ref::<StringArray>().unwrap();
+                for i in 0..batch.num_rows() {
+                    rows.push(json!({
+                        "proxy": proxy.value(i),
+                        "block": block.value(i),
+                        "problematic": problematic.value(i),
+                        "slots": serde_json::from_str::<serde_json::Value>(slots.value(i)).unwrap(),
+                    }));
+                }
+                assert_eq!(proxy.null_count(), 0);
+            }
+            assert_eq!(rows.len(), 5);
+            assert_eq!(
+                rows[3],
+                json!({"proxy": "0x03", "block": 3, "problematic": false, "slots": [3]})
+            );
+            std::fs::remove_file(path).unwrap();
+        }
+
+        #[test]
+        fn test_parquet_sink_unknown_column() {
+            let path = std::env::temp_dir().join("proxyex-detector-test-parquet-unknown.parquet");
+            let mut sink = ParquetSink::with_row_group_size(&path, 1).unwrap();
+            sink.write(json!({"proxy": "0x01"})).unwrap();
+            let e = sink
+                .write(json!({"proxy": "0x02", "tx": "0x03"}))
+                .unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use libsofl_core::engine::types::Address;
+    use serde_json::json;
+
+    use crate::{dataset::ProxyData, error::DetectorError, shutdown::Shutdown};
+
+    use super::{check_into_files, ErrorRecord, JsonlSink, JsonlSource, OutputFiles, RecordSink};
+
+    #[test]
+    fn test_jsonl_source() {
+        let path = std::env::temp_dir().join("proxyex-detector-test-jsonl-source.jsonl");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(
+            file,
+            r#"{{"proxy": "0x01", "impls": [{{"tx": "0x02", "impl": "0x03", "block": 1}}]}}"#
+        )
+        .unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, r#"{{"proxy": "0x04"}}"#).unwrap();
+        drop(file);
+
+        let mut source = JsonlSource::<ProxyData>::open(&path).unwrap();
+        let d = source.next().unwrap().unwrap();
+        assert_eq!(d.proxy, "0x01");
+        assert_eq!(d.impls[0].implementation, "0x03");
+        // the blank line is skipped, and the malformed entry is reported with its line number
+        let e = source.next().unwrap().unwrap_err();
+        assert!(e.to_string().starts_with("line 3:"), "{}", e);
+        assert!(source.next().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_jsonl_sink() {
+        let mut sink = JsonlSink::new(Vec::new());
+        (&mut sink as &mut dyn RecordSink)
+            .write_record(&json!({"proxy": "0x01", "problematic": true}))
+            .unwrap();
+        sink.write(json!({"proxy": "0x02"})).unwrap();
+        let out = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        let records = out
+            .lines()
+            .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            records,
+            vec![
+                json!({"proxy": "0x01", "problematic": true}),
+                json!({"proxy": "0x02"})
+            ]
+        );
+        assert!(out.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_check_into_files() {
+        let dir = std::env::temp_dir();
+        let results = dir.join("proxyex-detector-test-check-results.jsonl");
+        let errors = dir.join("proxyex-detector-test-check-errors.jsonl");
+        let files =
+            OutputFiles::open(results.to_str().unwrap(), Some(errors.to_str().unwrap())).unwrap();
+        check_into_files(1..=10u8, files, 4, &Shutdown::default(), |i| {
+            if i % 5 == 0 {
+                let e = DetectorError::Timeout(format!("item {}", i));
+                return vec![Err(ErrorRecord::new(Address::from([i; 20]), None, e))];
+            }
+            vec![Ok(json!({ "item": i }))]
+        });
+
+        let read = |path| {
+            std::fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let mut items = read(&results)
+            .iter()
+            .map(|r| r["item"].as_u64().unwrap())
+            .collect::<Vec<_>>();
+        items.sort();
+        assert_eq!(items, vec![1, 2, 3, 4, 6, 7, 8, 9]);
+        let errors_written = read(&errors);
+        assert_eq!(errors_written.len(), 2);
+        assert_eq!(errors_written[0]["kind"], json!("timeout"));
+        assert!(errors_written[0]["tx"].is_null());
+        std::fs::remove_file(results).unwrap();
+        std::fs::remove_file(errors).unwrap();
+    }
+}
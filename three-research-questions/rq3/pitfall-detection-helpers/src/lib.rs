@@ -1,7 +1,25 @@
+pub mod beacon;
+pub mod budget;
+pub mod clone;
 pub mod config;
+pub mod convention;
 pub mod entities;
+pub mod error;
 pub mod inspectors;
+pub mod io;
 pub mod original_replay;
 pub mod dataset;
+pub mod diamond;
+pub mod harness;
 pub mod pool;
-pub mod replaced_replay;
\ No newline at end of file
+pub mod provider;
+pub mod reachability;
+pub mod replaced_replay;
+pub mod report;
+pub mod routing;
+pub mod run;
+pub mod selector;
+pub mod shutdown;
+pub mod slot;
+pub mod slot_history;
+pub mod timeline;
\ No newline at end of file
@@ -1,4 +1,8 @@
-use std::{collections::HashSet, sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use crossbeam::channel::{self, Sender};
 use indicatif::ProgressStyle;
@@ -11,15 +15,21 @@ use libsofl_core::{
     engine::{
         state::BcState,
         transition::TransitionSpecBuilder,
-        types::{Address, DatabaseRef, TxHash, U256},
+        types::{Address, Bytes, DatabaseRef, TxHash, U256},
     },
 };
-use libsofl_reth::blockchain::provider::RethProvider;
 use libsofl_utils::log::{debug, error, info, info_span};
 use sea_orm::ActiveValue;
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
-use crate::{entities, inspectors::collision::StorageAccessInspector, pool::FIFOTaskPool};
+use crate::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    entities,
+    error::DetectorError,
+    inspectors::collision::StorageAccessInspector,
+    pool::FIFOTaskPool,
+    slot::{classify, relevant_preimages, Preimages, SlotLabel},
+};
 
 #[derive(Debug, serde::Serialize)]
 pub struct ReplayError {
@@ -27,30 +37,44 @@ pub struct ReplayError {
     pub tx: TxHash,
     pub index: usize,
     pub total: usize,
-    pub msg: String,
+    pub error: DetectorError,
 }
 
 impl ReplayError {
-    pub fn new(proxy: Address, tx: TxHash, index: usize, total: usize, msg: String) -> Self {
+    pub fn new(
+        proxy: Address,
+        tx: TxHash,
+        index: usize,
+        total: usize,
+        error: DetectorError,
+    ) -> Self {
         Self {
             total,
             tx,
             proxy,
             index,
-            msg,
+            error,
         }
     }
+
+    /// The row of the `error` table, `detector` is the name of the detector replaying the tx.
+    pub fn to_model(&self, detector: &str) -> entities::error::ActiveModel {
+        self.error.to_model(detector, self.proxy, Some(self.tx))
+    }
 }
 
-pub struct OriginalReplayScheduler {
-    p: Arc<RethProvider>,
+pub struct OriginalReplayScheduler<P> {
+    p: Arc<P>,
     pool: FIFOTaskPool<Result<(TxHash, StorageAccessInspector), ReplayError>>,
     result_thread: std::thread::JoinHandle<()>,
+    budget: BudgetConfig,
+    // deadline of the proxy being fed
+    deadline: Mutex<Option<Instant>>,
 }
 
-impl OriginalReplayScheduler {
+impl<P: Sync + Send + 'static> OriginalReplayScheduler<P> {
     pub fn new(
-        p: Arc<RethProvider>,
+        p: Arc<P>,
         n_threads: usize,
         result_tx: Sender<Result<SlotCollisionResult, ReplayError>>,
     ) -> Self {
@@ -73,6 +97,10 @@ impl OriginalReplayScheduler {
                             r
                         }
                         Err(_) => {
+                            // the feeding is stopped in the middle of a proxy, e.g., on shutdown
+                            if !insps.is_empty() {
+                                info!(replayed = insps.len(), "Partial proxy result abandoned");
+                            }
                             debug!("result channel closed, result aggregator thread exits");
                             break;
                         }
@@ -135,20 +163,43 @@ impl OriginalReplayScheduler {
             p,
             pool,
             result_thread,
+            budget: BudgetConfig::default(),
+            deadline: Mutex::new(None),
         }
     }
 
-    pub fn feed_proxy_invocation_in_order(
+    /// Bound the replay of each tx and each proxy with the budget.
+    pub fn with_budget(mut self, budget: BudgetConfig) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// The txs of a proxy fed after the proxy deadline are not replayed but fail with a timeout,
+    /// and the deadline starts when the first tx of the proxy is fed.
+    pub fn feed_proxy_invocation_in_order<T: Tx + 'static, DB: DatabaseRef + 'static>(
         &self,
         proxy: Address,
         implementation: Address,
         tx: TxHash,
         index: usize,
         total: usize,
-    ) {
+    ) where
+        P: BcProvider<T> + BcStateProvider<DB>,
+        <DB as DatabaseRef>::Error: std::fmt::Debug,
+    {
         let p = self.p.clone();
-        self.pool
-            .add_task(move || replay_one_tx(p, proxy, implementation, tx, index, total))
+        let mut deadline = self.deadline.lock().unwrap();
+        if index == 0 {
+            *deadline = self.budget.proxy_timeout().map(|t| Instant::now() + t);
+        }
+        let deadline = *deadline;
+        let budget = self.budget.tx_budget();
+        self.pool.add_task(move || {
+            let budget = budget
+                .until(deadline)
+                .map_err(|e| ReplayError::new(proxy, tx, index, total, e))?;
+            replay_one_tx(p, proxy, implementation, tx, index, total, budget)
+        })
     }
 
     pub fn close(self) {
@@ -170,6 +221,7 @@ pub fn replay_one_tx<
     tx: TxHash,
     index: usize,
     total: usize,
+    budget: Budget,
 ) -> Result<(TxHash, StorageAccessInspector), ReplayError>
 where
     <DB as DatabaseRef>::Error: std::fmt::Debug,
@@ -181,29 +233,39 @@ where
         "Replaying"
     );
 
-    let mut insp = StorageAccessInspector::new(proxy, implementation, index, total, false);
+    let mut insp = StorageAccessInspector::new(proxy, implementation, index, total, false)
+        .with_meter(BudgetMeter::new(budget));
 
     let start_at = std::time::Instant::now();
 
     let tx = provider.tx(tx.cvt()).map_err(|e| {
-        let msg = format!("Error: {:?}", e);
         error!("error {:?}", e);
-        ReplayError::new(proxy, tx_hash, index, total, msg)
+        ReplayError::new(proxy, tx_hash, index, total, DetectorError::tx_not_found(e))
+    })?;
+    let tx_pos = tx.position().ok_or_else(|| {
+        let error = DetectorError::TxNotFound(format!("position of tx {}", tx_hash));
+        ReplayError::new(proxy, tx_hash, index, total, error)
     })?;
-    let tx_pos = tx.position().unwrap();
     let mut state = provider.bc_state_at(tx_pos).map_err(|e| {
-        let msg = format!("Error: {:?}", e);
-        ReplayError::new(proxy, tx_hash, index, total, msg)
+        ReplayError::new(
+            proxy,
+            tx_hash,
+            index,
+            total,
+            DetectorError::state_unavailable(e),
+        )
     })?;
     let spec = TransitionSpecBuilder::new()
         .at_block(&provider, tx_pos.block)
         .append_tx(tx)
         .build();
 
-    let _ = state.transit(spec, &mut insp).map_err(|e| {
-        let msg = format!("Error: {:?}", e);
-        ReplayError::new(proxy, tx_hash, index, total, msg)
-    })?;
+    let _ = state
+        .transit(spec, &mut insp)
+        .map_err(|e| ReplayError::new(proxy, tx_hash, index, total, DetectorError::evm_halt(e)))?;
+    insp.meter
+        .check()
+        .map_err(|e| ReplayError::new(proxy, tx_hash, index, total, e))?;
 
     insp.time_elapsed = start_at.elapsed();
 
@@ -302,6 +364,10 @@ pub struct SlotCollisionResult {
     pub proxy_sloads: Vec<(TxHash, HashSet<(U256, U256)>)>,
     pub implementation_ssotres: Vec<(TxHash, HashSet<(U256, U256)>)>,
     pub implementation_sloads: Vec<(TxHash, HashSet<(U256, U256)>)>,
+    /// storage layout labels of the conflicting slots
+    pub slot_labels: Vec<(U256, SlotLabel)>,
+    /// KECCAK256 preimages (hash, preimage) needed to label the conflicting slots in each tx
+    pub preimages: Vec<(TxHash, Vec<(U256, Bytes)>)>,
     pub problematic: bool,
     pub total_time: Duration,
     pub avg_time: Duration,
@@ -320,6 +386,8 @@ impl From<SlotCollisionResult> for entities::collision::ActiveModel {
             implementation_sloads: ActiveValue::Set(
                 serde_json::to_value(r.implementation_sloads).unwrap(),
             ),
+            slot_labels: ActiveValue::Set(Some(serde_json::to_value(r.slot_labels).unwrap())),
+            preimages: ActiveValue::Set(Some(serde_json::to_value(r.preimages).unwrap())),
             total_time: ActiveValue::Set(r.total_time.as_millis() as i64),
             avg_time: ActiveValue::Set(r.avg_time.as_millis() as i64),
         }
@@ -445,6 +513,27 @@ impl SlotCollisionResult {
             .filter(|(_, s)| s.len() > 0)
             .collect::<Vec<_>>();
 
+        // label the conflicting slots with the preimages observed in all txs
+        let mut preimages = Preimages::new();
+        for (_, insp) in insps {
+            preimages.extend(insp.preimages.iter().map(|(h, p)| (*h, p.clone())));
+        }
+        let mut slot_labels = read_write_slots
+            .iter()
+            .map(|s| (*s, classify(*s, &preimages)))
+            .collect::<Vec<_>>();
+        slot_labels.sort_by_key(|(s, _)| *s);
+        let preimages = insps
+            .iter()
+            .map(|(tx, insp)| {
+                (
+                    *tx,
+                    relevant_preimages(read_write_slots.iter().cloned(), &insp.preimages),
+                )
+            })
+            .filter(|(_, p)| p.len() > 0)
+            .collect::<Vec<_>>();
+
         let total_time: Duration = insps.iter().map(|i| i.1.time_elapsed).sum();
         let avg_time = total_time.div_f32(insps.len() as f32);
         Self {
@@ -453,6 +542,8 @@ impl SlotCollisionResult {
             proxy_sloads,
             implementation_ssotres,
             implementation_sloads,
+            slot_labels,
+            preimages,
             problematic: false,
             total_time,
             avg_time,
@@ -507,6 +598,7 @@ mod tests {
             1,
         );
         let e = result_rv.recv().unwrap().unwrap_err();
+        assert_eq!(e.error.kind(), "tx_not_found");
         assert_eq!(
             ConvertTo::<String>::cvt(&e.proxy),
             "0xae7ab96520DE3A18E5e111B5EaAb095312D7fE84"
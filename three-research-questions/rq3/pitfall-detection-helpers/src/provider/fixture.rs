@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::BufReader,
+    path::Path,
+};
+
+use libsofl_core::{
+    blockchain::{
+        provider::{BcProvider, BcStateProvider},
+        transaction::{Tx, TxHashOrPosition},
+        tx_position::TxPosition,
+    },
+    engine::{
+        memory::MemoryBcState,
+        types::{
+            AccountInfo, Address, BlockEnv, BlockHashOrNumber, Bytecode, Bytes, CfgEnv, EmptyDB,
+            Hash, SpecId, TransactTo, TxEnv, TxHash, U256,
+        },
+    },
+    error::SoflError,
+};
+
+/// Block environment of a recorded transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BlockFixture {
+    pub number: u64,
+    pub coinbase: Address,
+    pub timestamp: U256,
+    pub gas_limit: U256,
+    pub basefee: U256,
+    pub difficulty: U256,
+    pub prevrandao: Option<Hash>,
+}
+
+impl BlockFixture {
+    pub fn from_env(env: &BlockEnv) -> Self {
+        Self {
+            number: env.number.to(),
+            coinbase: env.coinbase,
+            timestamp: env.timestamp,
+            gas_limit: env.gas_limit,
+            basefee: env.basefee,
+            difficulty: env.difficulty,
+            prevrandao: env.prevrandao,
+        }
+    }
+
+    pub fn fill_block_env(&self, env: &mut BlockEnv) {
+        env.number = U256::from(self.number);
+        env.coinbase = self.coinbase;
+        env.timestamp = self.timestamp;
+        env.gas_limit = self.gas_limit;
+        env.basefee = self.basefee;
+        env.difficulty = self.difficulty;
+        env.prevrandao = self.prevrandao;
+    }
+}
+
+/// Transaction environment of a recorded transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxEnvFixture {
+    pub caller: Address,
+    /// None for contract creation
+    pub to: Option<Address>,
+    pub value: U256,
+    pub data: Bytes,
+    pub gas_limit: u64,
+    pub gas_price: U256,
+    pub gas_priority_fee: Option<U256>,
+    pub nonce: Option<u64>,
+}
+
+impl TxEnvFixture {
+    pub fn from_env(env: &TxEnv) -> Self {
+        Self {
+            caller: env.caller,
+            to: match env.transact_to {
+                TransactTo::Call(to) => Some(to),
+                TransactTo::Create(_) => None,
+            },
+            value: env.value,
+            data: env.data.clone(),
+            gas_limit: env.gas_limit,
+            gas_price: env.gas_price,
+            gas_priority_fee: env.gas_priority_fee,
+            nonce: env.nonce,
+        }
+    }
+
+    pub fn fill_tx_env(&self, env: &mut TxEnv) {
+        env.caller = self.caller;
+        env.transact_to = match self.to {
+            Some(to) => TransactTo::Call(to),
+            None => TransactTo::create(),
+        };
+        env.value = self.value;
+        env.data = self.data.clone();
+        env.gas_limit = self.gas_limit;
+        env.gas_price = self.gas_price;
+        env.gas_priority_fee = self.gas_priority_fee;
+        env.nonce = self.nonce;
+    }
+}
+
+/// State of an account before a recorded transaction.
+/// Only the storage slots accessed by the transaction are recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountFixture {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// A recorded transaction, with everything needed to replay it without an archive node.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxFixture {
+    pub hash: TxHash,
+    pub block: u64,
+    pub index: u64,
+    pub spec_id: u8,
+    pub block_env: BlockFixture,
+    pub tx_env: TxEnvFixture,
+    pub pre_state: BTreeMap<Address, AccountFixture>,
+    /// State of the accounts and slots of `pre_state` after the transaction.
+    /// Empty in the fixtures recorded before it was added.
+    #[serde(default)]
+    pub post_state: BTreeMap<Address, AccountFixture>,
+}
+
+impl Tx for TxFixture {
+    fn hash(&self) -> TxHash {
+        self.hash
+    }
+
+    fn sender(&self) -> Address {
+        self.tx_env.caller
+    }
+
+    fn position(&self) -> Option<TxPosition> {
+        Some(TxPosition::new(self.block, self.index))
+    }
+
+    fn fill_tx_env(&self, env: &mut TxEnv) -> Result<(), SoflError> {
+        self.tx_env.fill_tx_env(env);
+        Ok(())
+    }
+}
+
+/// Content of a fixture file.
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FixtureSet {
+    pub chain_id: u64,
+    pub txs: Vec<TxFixture>,
+}
+
+/// FixtureProvider serves transactions and pre-states from recorded fixture files.
+///
+/// The state at a position is assembled from the pre-states and post-states of the recorded
+/// transactions before that position, then the pre-state of the transaction at that position,
+/// so only the accounts and slots touched by the recorded transactions are available.
+/// A transaction recorded without its post-state leaves its pre-state in place, so the state
+/// after it is only exact for the accounts and slots that a later transaction records again.
+#[derive(Debug, Default)]
+pub struct FixtureProvider {
+    chain_id: u64,
+    txs: HashMap<TxHash, TxFixture>,
+    // (block, index, tx_hash), sorted
+    positions: Vec<(u64, u64, TxHash)>,
+}
+
+impl FixtureProvider {
+    /// Load all `*.json` fixture files in the directory.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, std::io::Error> {
+        let mut provider = Self::default();
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        for path in paths {
+            if path.extension().map(|e| e != "json").unwrap_or(true) {
+                continue;
+            }
+            let reader = BufReader::new(std::fs::File::open(&path)?);
+            let set: FixtureSet = serde_json::from_reader(reader)?;
+            provider.push_all(set);
+        }
+        provider.positions.sort();
+        Ok(provider)
+    }
+
+    pub fn extend(&mut self, set: FixtureSet) {
+        self.push_all(set);
+        self.positions.sort();
+    }
+
+    /// Add the txs of a set, leaving the positions to be sorted once all sets are added.
+    fn push_all(&mut self, set: FixtureSet) {
+        self.chain_id = set.chain_id;
+        for tx in set.txs {
+            if !self.txs.contains_key(&tx.hash) {
+                self.positions.push((tx.block, tx.index, tx.hash));
+            }
+            self.txs.insert(tx.hash, tx);
+        }
+    }
+
+    fn block_number(&self, block: BlockHashOrNumber) -> Result<u64, SoflError> {
+        match block {
+            BlockHashOrNumber::Number(n) => Ok(n),
+            BlockHashOrNumber::Hash(h) => Err(SoflError::NotFound(format!(
+                "block {} is not identified by number in fixtures",
+                h
+            ))),
+        }
+    }
+
+    fn any_tx_in_block(&self, block: u64) -> Result<&TxFixture, SoflError> {
+        self.positions
+            .iter()
+            .find(|(b, _, _)| *b == block)
+            .map(|(_, _, h)| &self.txs[h])
+            .ok_or_else(|| SoflError::NotFound(format!("block {} not in fixtures", block)))
+    }
+}
+
+impl BcProvider<TxFixture> for FixtureProvider {
+    fn tx(&self, tx: TxHashOrPosition) -> Result<TxFixture, SoflError> {
+        match tx {
+            TxHashOrPosition::Hash(hash) => self
+                .txs
+                .get(&hash)
+                .cloned()
+                .ok_or_else(|| SoflError::NotFound(format!("tx {} not in fixtures", hash))),
+            TxHashOrPosition::Position(pos) => {
+                let block = self.block_number(pos.block)?;
+                self.positions
+                    .iter()
+                    .find(|(b, i, _)| *b == block && *i == pos.index)
+                    .map(|(_, _, h)| self.txs[h].clone())
+                    .ok_or_else(|| SoflError::NotFound(format!("tx at {:?} not in fixtures", pos)))
+            }
+        }
+    }
+
+    fn txs_in_block(&self, block: BlockHashOrNumber) -> Result<Vec<TxFixture>, SoflError> {
+        let block = self.block_number(block)?;
+        Ok(self
+            .positions
+            .iter()
+            .filter(|(b, _, _)| *b == block)
+            .map(|(_, _, h)| self.txs[h].clone())
+            .collect())
+    }
+
+    fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    fn fill_cfg_env(&self, env: &mut CfgEnv, block: BlockHashOrNumber) -> Result<(), SoflError> {
+        let tx = self.any_tx_in_block(self.block_number(block)?)?;
+        env.chain_id = self.chain_id;
+        env.spec_id = SpecId::try_from_u8(tx.spec_id).unwrap_or(SpecId::LATEST);
+        Ok(())
+    }
+
+    fn fill_block_env(
+        &self,
+        env: &mut BlockEnv,
+        block: BlockHashOrNumber,
+    ) -> Result<(), SoflError> {
+        let tx = self.any_tx_in_block(self.block_number(block)?)?;
+        tx.block_env.fill_block_env(env);
+        Ok(())
+    }
+}
+
+impl BcStateProvider<EmptyDB> for FixtureProvider {
+    fn bc_state_at(&self, pos: TxPosition) -> Result<MemoryBcState<EmptyDB>, SoflError> {
+        let block = self.block_number(pos.block)?;
+        let mut state = MemoryBcState::fresh();
+        // later states override earlier ones, so that each account/slot holds the
+        // latest value recorded before the position.
+        for (b, i, h) in self.positions.iter() {
+            if (*b, *i) > (block, pos.index) {
+                break;
+            }
+            let tx = &self.txs[h];
+            insert_accounts(&mut state, &tx.pre_state)?;
+            if (*b, *i) < (block, pos.index) {
+                insert_accounts(&mut state, &tx.post_state)?;
+            }
+        }
+        Ok(state)
+    }
+}
+
+fn insert_accounts(
+    state: &mut MemoryBcState<EmptyDB>,
+    accounts: &BTreeMap<Address, AccountFixture>,
+) -> Result<(), SoflError> {
+    for (address, account) in accounts.iter() {
+        let code = Bytecode::new_raw(account.code.clone());
+        let info = AccountInfo::new(account.balance, account.nonce, code.hash_slow(), code);
+        state.insert_account_info(*address, info);
+        for (slot, value) in account.storage.iter() {
+            state
+                .insert_account_storage(*address, *slot, *value)
+                .map_err(|e| SoflError::Custom(format!("{:?}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use libsofl_core::{
+        blockchain::{provider::BcProvider, transaction::Tx, tx_position::TxPosition},
+        conversion::ConvertTo,
+        engine::types::{Address, Database, U256},
+    };
+
+    use super::{AccountFixture, FixtureProvider, FixtureSet, TxEnvFixture, TxFixture};
+    use libsofl_core::blockchain::provider::BcStateProvider;
+
+    /// A tx touching slot 0 of account 0x01, with the values of the slot before and after it.
+    fn fixture(hash: &str, block: u64, index: u64, slot_value: u64, post_value: u64) -> TxFixture {
+        let account: Address = "0x0000000000000000000000000000000000000001".cvt();
+        let state_of = |value: u64| {
+            let mut storage = BTreeMap::new();
+            storage.insert(U256::from(0), U256::from(value));
+            let mut state = BTreeMap::new();
+            state.insert(
+                account,
+                AccountFixture {
+                    storage,
+                    ..Default::default()
+                },
+            );
+            state
+        };
+        TxFixture {
+            hash: hash.cvt(),
+            block,
+            index,
+            spec_id: 0,
+            block_env: Default::default(),
+            tx_env: TxEnvFixture {
+                to: Some(account),
+                ..Default::default()
+            },
+            pre_state: state_of(slot_value),
+            post_state: state_of(post_value),
+        }
+    }
+
+    #[test]
+    fn test_fixture_provider() {
+        let mut provider = FixtureProvider::default();
+        provider.extend(FixtureSet {
+            chain_id: 1,
+            txs: vec![
+                fixture(
+                    "0x1111111111111111111111111111111111111111111111111111111111111111",
+                    10,
+                    0,
+                    1,
+                    5,
+                ),
+                fixture(
+                    "0x2222222222222222222222222222222222222222222222222222222222222222",
+                    12,
+                    3,
+                    2,
+                    2,
+                ),
+            ],
+        });
+        let tx = provider
+            .tx("0x2222222222222222222222222222222222222222222222222222222222222222".cvt())
+            .unwrap();
+        assert_eq!(tx.position().unwrap().index, 3);
+
+        let account: Address = "0x0000000000000000000000000000000000000001".cvt();
+        // before the first tx, then after it
+        let mut state = provider.bc_state_at(TxPosition::new(10u64, 0u64)).unwrap();
+        assert_eq!(
+            state.storage(account, U256::from(0)).unwrap(),
+            U256::from(1)
+        );
+        let mut state = provider.bc_state_at(TxPosition::new(11u64, 0u64)).unwrap();
+        assert_eq!(
+            state.storage(account, U256::from(0)).unwrap(),
+            U256::from(5)
+        );
+        let mut state = provider.bc_state_at(tx.position().unwrap()).unwrap();
+        assert_eq!(
+            state.storage(account, U256::from(0)).unwrap(),
+            U256::from(2)
+        );
+    }
+}
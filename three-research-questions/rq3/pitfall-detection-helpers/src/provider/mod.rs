@@ -0,0 +1,82 @@
+pub mod fixture;
+
+use libsofl_core::{
+    blockchain::{
+        provider::{BcProvider, BcStateProvider},
+        transaction::Tx,
+    },
+    engine::types::DatabaseRef,
+};
+use libsofl_utils::config::Config;
+
+/// The blockchain backend on which transactions are replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Reth archive node, configured in the `[reth]` section.
+    Reth,
+    /// Transactions and pre-states recorded in local fixture files.
+    Fixture,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ProviderConfig {
+    pub backend: Backend,
+
+    /// directory of the recorded fixture files, only used by the fixture backend
+    pub fixture_dir: String,
+}
+
+impl Default for ProviderConfig {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Reth,
+            fixture_dir: "fixtures".to_string(),
+        }
+    }
+}
+
+impl Config for ProviderConfig {
+    fn section_name() -> &'static str {
+        "provider"
+    }
+}
+
+/// A blockchain provider on which detectors can replay transactions.
+pub trait DetectorProvider<T: Tx, DB: DatabaseRef>:
+    BcProvider<T> + BcStateProvider<DB> + Sync + Send + 'static
+{
+}
+
+impl<T: Tx, DB: DatabaseRef, P> DetectorProvider<T, DB> for P where
+    P: BcProvider<T> + BcStateProvider<DB> + Sync + Send + 'static
+{
+}
+
+/// Build the provider selected in `config.toml` as `Arc<_>`, bind it to `$p` and evaluate `$body`.
+/// `$body` is instantiated once per backend, so it is usually a call to a function
+/// that is generic over [`DetectorProvider`].
+#[macro_export]
+macro_rules! with_provider {
+    (|$p:ident| $body:expr) => {{
+        let cfg = <$crate::provider::ProviderConfig as ::libsofl_utils::config::Config>::load_or(
+            Default::default(),
+        )
+        .expect("load provider config failed");
+        match cfg.backend {
+            $crate::provider::Backend::Reth => {
+                let $p = <::libsofl_reth::config::RethConfig as ::libsofl_utils::config::Config>::must_load()
+                    .bc_provider()
+                    .expect("failed to open reth provider");
+                let $p = ::std::sync::Arc::new($p);
+                $body
+            }
+            $crate::provider::Backend::Fixture => {
+                let $p = $crate::provider::fixture::FixtureProvider::load(&cfg.fixture_dir)
+                    .expect("failed to load fixtures");
+                let $p = ::std::sync::Arc::new($p);
+                $body
+            }
+        }
+    }};
+}
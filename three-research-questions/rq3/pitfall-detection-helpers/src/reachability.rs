@@ -0,0 +1,141 @@
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        types::{Address, Bytes, Database},
+    },
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    budget::{Budget, BudgetMeter},
+    error::DetectorError,
+    inspectors::dangerous::DangerousInspector,
+    selector::{code_of, extract_selectors, selector_to_string, Selector, SIMULATED_ARGS},
+};
+
+/// The address passed in calldata to check whether a DELEGATECALL target is attacker-controlled.
+pub const ATTACKER: &str = "0x8a1b2c3d4e5f60718293a4b5c6d7e8f901a2b3c4";
+
+/// A function that reaches SELFDESTRUCT or an attacker-controlled DELEGATECALL when called directly.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ReachableCall {
+    pub selector: String,
+    /// calldata that reaches the opcode
+    pub input: Bytes,
+    pub selfdestruct: bool,
+    pub attacker_delegatecall: bool,
+}
+
+/// Calldata to probe a function: the attacker address in one of the argument words, zeros elsewhere.
+pub fn probe_inputs(selector: &Selector) -> Vec<Bytes> {
+    let attacker: Address = ATTACKER.cvt();
+    (0..SIMULATED_ARGS)
+        .map(|i| {
+            let mut input = selector.to_vec();
+            input.extend(vec![0u8; 32 * SIMULATED_ARGS]);
+            let word = 4 + 32 * i;
+            input[word + 12..word + 32].copy_from_slice(attacker.as_slice());
+            Bytes::from(input)
+        })
+        .collect()
+}
+
+/// Call each function of the implementation directly (not through the proxy),
+/// and find those reaching SELFDESTRUCT or a DELEGATECALL to the attacker address.
+/// Returns a timeout error if a call exceeds the budget.
+pub fn check_reachability<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    implementation: Address,
+    budget: Budget,
+) -> Result<Vec<ReachableCall>, DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let attacker: Address = ATTACKER.cvt();
+    let selectors = extract_selectors(
+        &code_of(state, implementation).map_err(DetectorError::state_unavailable)?,
+    );
+    let mut reachable = Vec::new();
+    for selector in selectors {
+        for input in probe_inputs(&selector) {
+            let mut insp = DangerousInspector::new(implementation, BudgetMeter::new(budget));
+            let _ = caller.simulate_call(state, implementation, input.clone(), None, &mut insp);
+            insp.meter.check()?;
+            let attacker_delegatecall = insp.delegatecall_targets.contains(&attacker);
+            if insp.selfdestruct || attacker_delegatecall {
+                reachable.push(ReachableCall {
+                    selector: selector_to_string(&selector),
+                    input,
+                    selfdestruct: insp.selfdestruct,
+                    attacker_delegatecall,
+                });
+                break;
+            }
+        }
+    }
+    Ok(reachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::memory::MemoryBcState;
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::Budget;
+
+    use super::check_reachability;
+
+    #[test]
+    fn test_check_reachability() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                address public owner;
+                address public logic;
+                function execute(uint256 _value, address _target) public {
+                    (bool ok, ) = _target.delegatecall(abi.encode(_value));
+                    require(ok);
+                }
+                function execute_logic() public {
+                    (bool ok, ) = logic.delegatecall("");
+                    require(ok);
+                }
+                function kill() public {
+                    selfdestruct(payable(msg.sender));
+                }
+                function guarded_kill() public {
+                    require(msg.sender == owner);
+                    selfdestruct(payable(msg.sender));
+                }
+                function kill_and_revert() public {
+                    this.kill();
+                    revert();
+                }
+            }
+            "#,
+            vec!["Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let implementation = addrs.remove(0);
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let reachable =
+            check_reachability(&caller, &mut state, implementation, Budget::default()).unwrap();
+        // execute(uint256,address) and kill(), sorted by selector; kill_and_revert() is reverted
+        assert_eq!(reachable.len(), 2);
+        assert_eq!(reachable.iter().filter(|r| r.selfdestruct).count(), 1);
+        assert_eq!(
+            reachable.iter().filter(|r| r.attacker_delegatecall).count(),
+            1
+        );
+    }
+}
@@ -1,23 +1,249 @@
 use std::{collections::HashSet, sync::Arc};
 
+use crossbeam::channel::{self, Sender};
+use indicatif::ProgressStyle;
 use libsofl_core::{
     blockchain::{
         provider::{BcProvider, BcStateProvider},
         transaction::Tx,
+        tx_position::TxPosition,
     },
     conversion::ConvertTo,
     engine::{
         memory::MemoryBcState,
         state::BcState,
         transition::TransitionSpecBuilder,
-        types::{Address, Bytecode, DatabaseRef, TxEnv, TxHash, U256},
+        types::{Address, Bytecode, Bytes, Database, DatabaseRef, TxEnv, TxHash, U256},
     },
 };
+use libsofl_utils::log::{debug, info, info_span};
+use tracing_indicatif::span_ext::IndicatifSpanExt;
 
-use crate::{entities, inspectors::collision::StorageAccessInspector};
+use crate::{
+    budget::{Budget, BudgetConfig, BudgetMeter},
+    entities,
+    error::DetectorError,
+    inspectors::collision::StorageAccessInspector,
+    original_replay::ReplayError,
+    pool::FIFOTaskPool,
+    slot::{relevant_preimages, Preimages},
+};
+
+/// An implementation version of a proxy: (implementation, min_block, code).
+pub type AltVersion = (Address, u64, Bytecode);
 
+/// Regression results of one tx replayed on all later versions of the proxy.
+pub struct RegressionTxResult {
+    pub proxy: Address,
+    pub index: usize,
+    pub total: usize,
+    pub result: Result<Vec<RegressionIssue>, ReplayError>,
+}
+
+/// Regression results of all invocations of one proxy.
 #[derive(Debug)]
-pub struct RegressionError {}
+pub struct RegressionResult {
+    pub proxy: Address,
+    pub issues: Vec<RegressionIssue>,
+    pub errors: Vec<ReplayError>,
+}
+
+pub struct ReplacedReplayScheduler<P> {
+    p: Arc<P>,
+    pool: FIFOTaskPool<RegressionTxResult>,
+    result_thread: std::thread::JoinHandle<()>,
+    budget: BudgetConfig,
+}
+
+impl<P: Sync + Send + 'static> ReplacedReplayScheduler<P> {
+    pub fn new(p: Arc<P>, n_threads: usize, result_tx: Sender<RegressionResult>) -> Self {
+        let (tx_result_tx, tx_result_rv) = channel::bounded(n_threads * 2);
+        let pool = FIFOTaskPool::new(tx_result_tx, n_threads);
+
+        // result thread aggregate tx regression results into proxy results
+        let result_thread = std::thread::spawn(move || {
+            info!("tx regression aggregator thread started");
+
+            let mut proxy_span = info_span!("proxy");
+
+            let mut issues = Vec::new();
+            let mut errors = Vec::new();
+            let mut received = 0;
+            loop {
+                debug!("waiting for one tx regression result");
+                let r: RegressionTxResult = match tx_result_rv.recv() {
+                    Ok(r) => r,
+                    Err(_) => {
+                        // the feeding is stopped in the middle of a proxy, e.g., on shutdown
+                        if received > 0 {
+                            info!(replayed = received, "Partial proxy result abandoned");
+                        }
+                        debug!("result channel closed, result aggregator thread exits");
+                        break;
+                    }
+                };
+
+                if received == 0 {
+                    drop(proxy_span);
+                    // create a new progress bar for a new proxy
+                    info!(
+                        proxy = r.proxy.to_string().to_lowercase().as_str(),
+                        "Regression testing"
+                    );
+                    proxy_span = info_span!("proxy");
+                    proxy_span.pb_set_style(&ProgressStyle::default_bar());
+                    proxy_span.pb_set_length(r.total as u64);
+                    proxy_span.pb_set_message(
+                        format!("proxy: {}", r.proxy.to_string().to_lowercase()).as_str(),
+                    );
+                    proxy_span.pb_start();
+                }
+                proxy_span.pb_set_position(r.index as u64 + 1);
+
+                received += 1;
+                match r.result {
+                    Ok(is) => issues.extend(is),
+                    Err(e) => errors.push(e),
+                }
+                if r.index + 1 == r.total {
+                    debug!("one proxy regression finished, yielding");
+                    assert_eq!(received, r.total);
+                    let _ = result_tx.send(RegressionResult {
+                        proxy: r.proxy,
+                        issues,
+                        errors,
+                    });
+                    issues = Vec::new();
+                    errors = Vec::new();
+                    received = 0;
+                }
+            }
+        });
+
+        Self {
+            p,
+            pool,
+            result_thread,
+            budget: BudgetConfig::default(),
+        }
+    }
+
+    /// Bound the replay and each simulation of a tx with the budget.
+    pub fn with_budget(mut self, budget: BudgetConfig) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Feed one invocation of a proxy.
+    /// Invocations of the same proxy must be fed consecutively, ordered by `index`.
+    /// `versions` are all implementation versions of the proxy,
+    /// the tx is only simulated on those that are used after the tx's block.
+    pub fn feed_proxy_invocation_in_order<T: Tx + 'static, DB: DatabaseRef + 'static>(
+        &self,
+        proxy: Address,
+        implementation: Address,
+        versions: Arc<Vec<AltVersion>>,
+        tx: TxHash,
+        block: u64,
+        index: usize,
+        total: usize,
+    ) where
+        P: BcProvider<T> + BcStateProvider<DB>,
+        <DB as DatabaseRef>::Error: std::fmt::Debug,
+    {
+        let p = self.p.clone();
+        let budget = self.budget.tx_budget();
+        self.pool.add_task(move || {
+            let result = regression_one_invocation(
+                p,
+                proxy,
+                implementation,
+                &versions,
+                tx,
+                block,
+                index,
+                total,
+                budget,
+            );
+            RegressionTxResult {
+                proxy,
+                index,
+                total,
+                result,
+            }
+        })
+    }
+
+    pub fn close(self) {
+        self.pool.close();
+        self.result_thread
+            .join()
+            .expect("result thread join failed");
+    }
+}
+
+/// Get the code of an implementation at the end of the given block,
+/// i.e., the state of `state_by_block_number(block)` that the regression binary read from reth,
+/// which is the state before the first tx of the next block.
+pub fn implementation_code_at<T: Tx, DB: DatabaseRef, P: BcProvider<T> + BcStateProvider<DB>>(
+    provider: &P,
+    implementation: Address,
+    block: u64,
+) -> Result<Bytecode, DetectorError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let mut state = provider
+        .bc_state_at(TxPosition::new(block + 1, 0u64))
+        .map_err(DetectorError::state_unavailable)?;
+    let account = state
+        .basic(implementation)
+        .map_err(DetectorError::state_unavailable)?
+        .unwrap_or_default();
+    match account.code {
+        Some(code) => Ok(code),
+        None => state
+            .code_by_hash(account.code_hash)
+            .map_err(DetectorError::state_unavailable),
+    }
+}
+
+/// Replay one invocation of a proxy and simulate it on every version
+/// that is used later than the invocation's block, the same versions as the regression binary.
+#[allow(clippy::too_many_arguments)]
+pub fn regression_one_invocation<
+    T: Tx,
+    DB: DatabaseRef,
+    P: BcProvider<T> + BcStateProvider<DB> + Sync + Send + 'static,
+>(
+    provider: Arc<P>,
+    proxy: Address,
+    implementation: Address,
+    versions: &Vec<AltVersion>,
+    tx: TxHash,
+    block: u64,
+    index: usize,
+    total: usize,
+    budget: Budget,
+) -> Result<Vec<RegressionIssue>, ReplayError>
+where
+    <DB as DatabaseRef>::Error: std::fmt::Debug,
+{
+    let alts: Vec<(Address, Bytecode)> = versions
+        .iter()
+        .filter(|(_, min_block, _)| *min_block > block)
+        .map(|(alt, _, code)| (*alt, code.clone()))
+        .collect();
+    if alts.is_empty() {
+        // no newer version, nothing to compare with
+        return Ok(Vec::new());
+    }
+    let (original_insp, alt_insps) =
+        regression_one_tx(provider, proxy, implementation, alts, tx, budget)
+            .map_err(|e| ReplayError::new(proxy, tx, index, total, e))?;
+    check_regression(original_insp, alt_insps, tx)
+        .map_err(|e| ReplayError::new(proxy, tx, index, total, e))
+}
 
 /// Replay a transaction and
 /// simulate the transaction on alternative implementations at a specific block.
@@ -31,25 +257,36 @@ pub fn regression_one_tx<
     implementation: Address,
     alt_implementations: Vec<(Address, Bytecode)>,
     tx: TxHash,
-) -> Result<(StorageAccessInspector, Vec<StorageAccessInspector>), RegressionError>
+    budget: Budget,
+) -> Result<(StorageAccessInspector, Vec<StorageAccessInspector>), DetectorError>
 where
     <DB as DatabaseRef>::Error: std::fmt::Debug,
 {
     // replay the transaction
     let start_at = std::time::Instant::now();
-    let tx = provider.tx(tx.cvt()).expect("tx not found");
+    let tx_hash = tx;
+    let tx = provider.tx(tx.cvt()).map_err(DetectorError::tx_not_found)?;
     let mut tx_env = TxEnv::default();
-    tx.fill_tx_env(&mut tx_env).unwrap();
-    let tx_pos = tx.position().unwrap();
+    tx.fill_tx_env(&mut tx_env)
+        .map_err(DetectorError::tx_not_found)?;
+    let tx_pos = tx
+        .position()
+        .ok_or_else(|| DetectorError::TxNotFound(format!("position of tx {}", tx_hash)))?;
     let blk = tx_pos.block;
-    let base_state = provider.bc_state_at(tx_pos).unwrap();
+    let base_state = provider
+        .bc_state_at(tx_pos)
+        .map_err(DetectorError::state_unavailable)?;
     let mut state = MemoryBcState::fork(&base_state);
     let spec = TransitionSpecBuilder::default()
         .at_block(provider.clone(), blk)
         .append_tx_env(tx_env.clone())
         .build();
-    let mut replay_insp = StorageAccessInspector::new(proxy, implementation, 0, 0, true);
-    state.transit(spec, &mut replay_insp).unwrap();
+    let mut replay_insp = StorageAccessInspector::new(proxy, implementation, 0, 0, true)
+        .with_meter(BudgetMeter::new(budget));
+    state
+        .transit(spec, &mut replay_insp)
+        .map_err(DetectorError::evm_halt)?;
+    replay_insp.meter.check()?;
     replay_insp.time_elapsed = start_at.elapsed();
 
     let mut insps = Vec::new();
@@ -59,15 +296,19 @@ where
         let mut state = MemoryBcState::fork(&base_state);
         state
             .replace_account_code(implementation, alt_code)
-            .unwrap();
+            .map_err(DetectorError::state_unavailable)?;
         let spec = TransitionSpecBuilder::default()
             .at_block(provider.clone(), blk)
             .bypass_check()
             .append_tx_env(tx_env.clone())
             .build();
         let mut insp =
-            StorageAccessInspector::new_alt(proxy, implementation, alt_impl, 0, 0, false);
-        state.transit(spec, &mut insp).unwrap();
+            StorageAccessInspector::new_alt(proxy, implementation, alt_impl, 0, 0, false)
+                .with_meter(BudgetMeter::new(budget));
+        state
+            .transit(spec, &mut insp)
+            .map_err(DetectorError::evm_halt)?;
+        insp.meter.check()?;
         insp.time_elapsed = start_at.elapsed();
         insps.push(insp);
     }
@@ -85,6 +326,8 @@ pub struct RegressionIssue {
     pub original_sstores: HashSet<(U256, U256)>,
     pub alt_sloads: HashSet<(U256, U256)>,
     pub alt_sstores: HashSet<(U256, U256)>,
+    /// KECCAK256 preimages (hash, preimage) needed to label the accessed slots
+    pub preimages: Vec<(U256, Bytes)>,
     pub different_slots: bool,
     pub different_values: bool,
     pub proxy_reverted: bool,
@@ -112,6 +355,9 @@ impl From<RegressionIssue> for entities::regression::ActiveModel {
             alt_sstores: sea_orm::ActiveValue::Set(
                 serde_json::to_value(&issue.alt_sstores).unwrap(),
             ),
+            preimages: sea_orm::ActiveValue::Set(Some(
+                serde_json::to_value(&issue.preimages).unwrap(),
+            )),
             different_slots: sea_orm::ActiveValue::Set(issue.different_slots),
             different_values: sea_orm::ActiveValue::Set(issue.different_values),
             proxy_reverted: sea_orm::ActiveValue::Set(issue.proxy_reverted),
@@ -124,7 +370,7 @@ pub fn check_regression(
     original_insp: StorageAccessInspector,
     alt_insps: Vec<StorageAccessInspector>,
     tx: TxHash,
-) -> Result<Vec<RegressionIssue>, RegressionError> {
+) -> Result<Vec<RegressionIssue>, DetectorError> {
     let mut rs = Vec::new();
     let mut original_sloads = HashSet::new();
     let mut original_sstores = HashSet::new();
@@ -140,6 +386,12 @@ pub fn check_regression(
         original_access.iter().map(|(_, s, v)| (*s, *v)).collect();
 
     for alt_insp in alt_insps {
+        let alt_implementation = alt_insp.alt_implementation.ok_or_else(|| {
+            DetectorError::InspectorInvariant(format!(
+                "alternative implementation is not recorded on tx {}",
+                tx
+            ))
+        })?;
         let mut alt_sloads = HashSet::new();
         let mut alt_sstores = HashSet::new();
         alt_sloads.extend(alt_insp.proxy_sloads);
@@ -152,15 +404,20 @@ pub fn check_regression(
             alt_access.iter().map(|(_, s, v)| (*s, *v)).collect();
         let different_slots = original_slots != alt_slots;
         let different_values = original_values != alt_values;
+        let mut preimages = Preimages::new();
+        preimages.extend(original_insp.preimages.iter().map(|(h, p)| (*h, p.clone())));
+        preimages.extend(alt_insp.preimages);
+        let preimages = relevant_preimages(original_slots.union(&alt_slots).cloned(), &preimages);
         rs.push(RegressionIssue {
             proxy: original_insp.proxy,
             implementation: original_insp.implementation,
-            alt_implementation: alt_insp.alt_implementation.unwrap(),
+            alt_implementation,
             tx: tx,
             original_sloads: original_sloads.iter().map(|(_, s, v)| (*s, *v)).collect(),
             original_sstores: original_sstores.iter().map(|(_, s, v)| (*s, *v)).collect(),
             alt_sloads: alt_sloads.into_iter().map(|(_, s, v)| (s, v)).collect(),
             alt_sstores: alt_sstores.into_iter().map(|(_, s, v)| (s, v)).collect(),
+            preimages,
             different_slots,
             different_values,
             proxy_reverted: alt_insp.proxy_reverted,
@@ -182,6 +439,7 @@ mod tests {
     use libsofl_utils::config::Config;
 
     use super::{check_regression, regression_one_tx};
+    use crate::budget::Budget;
 
     #[test]
     fn test_replaced_replay_audius_attack() {
@@ -208,6 +466,7 @@ mod tests {
             implementation,
             vec![(alt_impl, alt_code)],
             attack_tx,
+            Budget::default(),
         )
         .unwrap();
         let issues = check_regression(insp, insps, attack_tx).unwrap();
@@ -241,6 +500,7 @@ mod tests {
             implementation,
             vec![(alt_impl, alt_code)],
             attack_tx,
+            Budget::default(),
         )
         .unwrap();
         let issues = check_regression(insp, insps, attack_tx).unwrap();
@@ -274,6 +534,7 @@ mod tests {
             implementation,
             vec![(alt_impl, alt_code)],
             attack_tx,
+            Budget::default(),
         )
         .unwrap();
         let issues = check_regression(insp, insps, attack_tx).unwrap();
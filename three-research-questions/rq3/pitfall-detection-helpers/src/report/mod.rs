@@ -0,0 +1,707 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use libsofl_core::engine::types::{Bytes, TxHash, U256};
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde_json::json;
+
+use crate::{
+    entities,
+    reachability::ReachableCall,
+    selector::{Handler, SelectorClash},
+    slot::{classify, render_slot, Preimages, SlotLabel},
+};
+
+pub mod sarif;
+
+/// Version of the JSON report schema.
+/// Bump the major version when a field is removed or its meaning changes, and the minor version when a field is added.
+pub const SCHEMA_VERSION: &str = "1.0";
+
+/// The detectors whose findings can be reported, named after the tables of their results.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// proxy and implementation write the same slot (proxy-logic collision)
+    Collision,
+    /// a newer implementation accesses different slots when replaying a tx (logic-logic collision)
+    Regression,
+    /// slots missed or added by a newer implementation, see `bin/regression-filter`
+    RegressionFilter,
+    /// the implementation in the slot is not the one actually delegated to
+    FakeLoose,
+    /// the proxy shadows a function of the implementation
+    SelectorClash,
+    /// the admin reaches logic functions or non-admins reach admin functions
+    AdminRouting,
+    /// SELFDESTRUCT or attacker-controlled DELEGATECALL reachable in an implementation
+    Reachability,
+    /// anyone can initialize the implementation contract itself
+    ImplInitialize,
+}
+
+impl FindingKind {
+    pub const ALL: [FindingKind; 8] = [
+        FindingKind::Collision,
+        FindingKind::Regression,
+        FindingKind::RegressionFilter,
+        FindingKind::FakeLoose,
+        FindingKind::SelectorClash,
+        FindingKind::AdminRouting,
+        FindingKind::Reachability,
+        FindingKind::ImplInitialize,
+    ];
+
+    pub const TABLES: [&'static str; 8] = [
+        "collision",
+        "regression",
+        "regression_filter",
+        "fake_loose",
+        "selector_clash",
+        "admin_routing",
+        "reachability",
+        "impl_initialize",
+    ];
+
+    pub fn table(&self) -> &'static str {
+        Self::TABLES[*self as usize]
+    }
+
+    pub fn from_table(table: &str) -> Option<Self> {
+        Self::TABLES
+            .iter()
+            .position(|t| *t == table)
+            .map(|i| Self::ALL[i])
+    }
+
+    /// The rule id used in SARIF output.
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            FindingKind::Collision => "PX001",
+            FindingKind::Regression => "PX002",
+            FindingKind::RegressionFilter => "PX003",
+            FindingKind::FakeLoose => "PX004",
+            FindingKind::SelectorClash => "PX005",
+            FindingKind::AdminRouting => "PX006",
+            FindingKind::Reachability => "PX007",
+            FindingKind::ImplInitialize => "PX008",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            FindingKind::Collision => "Proxy and implementation write the same storage slot",
+            FindingKind::Regression => {
+                "A newer implementation accesses different storage when replaying a transaction"
+            }
+            FindingKind::RegressionFilter => {
+                "A newer implementation misses or adds storage slots when replaying a transaction"
+            }
+            FindingKind::FakeLoose => {
+                "The implementation in the proxy storage is not the one delegated to"
+            }
+            FindingKind::SelectorClash => "The proxy shadows a function of the implementation",
+            FindingKind::AdminRouting => "Transparent proxy routes calls to the wrong side",
+            FindingKind::Reachability => {
+                "SELFDESTRUCT or attacker-controlled DELEGATECALL reachable in the implementation"
+            }
+            FindingKind::ImplInitialize => "Anyone can initialize the implementation contract",
+        }
+    }
+}
+
+/// Severity of a finding, the same levels as SARIF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+}
+
+/// One finding per proxy/tx/slot (or selector).
+/// Addresses, tx hashes, slots and selectors are lowercase hex strings.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Finding {
+    pub kind: FindingKind,
+    pub level: Level,
+    pub proxy: Option<String>,
+    pub implementation: Option<String>,
+    pub tx: Option<String>,
+    pub slot: Option<String>,
+    /// human-auditable rendering of the slot, e.g., `mapping@3[0x..]`, see `slot::render_slot`
+    pub slot_label: Option<String>,
+    pub selector: Option<String>,
+    pub message: String,
+    /// the detector-specific records behind the finding
+    pub evidence: serde_json::Value,
+}
+
+impl Finding {
+    fn new(kind: FindingKind, level: Level, message: String) -> Self {
+        Self {
+            kind,
+            level,
+            proxy: None,
+            implementation: None,
+            tx: None,
+            slot: None,
+            slot_label: None,
+            selector: None,
+            message,
+            evidence: serde_json::Value::Null,
+        }
+    }
+
+    /// A key that identifies the same finding across runs, used to diff reports.
+    pub fn fingerprint(&self) -> String {
+        [
+            Some(self.kind.table()),
+            self.proxy.as_deref(),
+            self.implementation.as_deref(),
+            self.tx.as_deref(),
+            self.slot.as_deref(),
+            self.selector.as_deref(),
+        ]
+        .iter()
+        .map(|s| s.unwrap_or("-"))
+        .collect::<Vec<_>>()
+        .join("/")
+    }
+}
+
+/// The JSON report. Findings are sorted by fingerprint, so that reports of different runs can be diffed.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Report {
+    pub schema_version: String,
+    pub tool: String,
+    pub tool_version: String,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    pub fn new(mut findings: Vec<Finding>) -> Self {
+        findings.sort_by_cached_key(|f| f.fingerprint());
+        findings.dedup_by(|a, b| a.fingerprint() == b.fingerprint());
+        Self {
+            schema_version: SCHEMA_VERSION.to_string(),
+            tool: env!("CARGO_PKG_NAME").to_string(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            findings,
+        }
+    }
+}
+
+/// Rows of a finding table that can be turned into findings.
+pub trait ToFindings {
+    fn findings(&self) -> Vec<Finding>;
+}
+
+/// Load the findings of one detector from its table.
+pub async fn load_findings(
+    db: &DatabaseConnection,
+    kind: FindingKind,
+) -> Result<Vec<Finding>, DbErr> {
+    use entities::*;
+    let findings = match kind {
+        FindingKind::Collision => to_findings(
+            collision::Entity::find()
+                .order_by_asc(collision::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::Regression => to_findings(
+            regression::Entity::find()
+                .filter(
+                    regression::Column::DifferentSlots
+                        .eq(true)
+                        .or(regression::Column::DifferentValues.eq(true)),
+                )
+                .order_by_asc(regression::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::RegressionFilter => to_findings(
+            regression_filter::Entity::find()
+                .order_by_asc(regression_filter::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::FakeLoose => to_findings(
+            fake_loose::Entity::find()
+                .filter(fake_loose::Column::Problematic.eq(true))
+                .order_by_asc(fake_loose::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::SelectorClash => to_findings(
+            selector_clash::Entity::find()
+                .filter(selector_clash::Column::Problematic.eq(true))
+                .order_by_asc(selector_clash::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::AdminRouting => to_findings(
+            admin_routing::Entity::find()
+                .filter(admin_routing::Column::Problematic.eq(true))
+                .order_by_asc(admin_routing::Column::Proxy)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::Reachability => to_findings(
+            reachability::Entity::find()
+                .filter(
+                    reachability::Column::Selfdestruct
+                        .eq(true)
+                        .or(reachability::Column::AttackerDelegatecall.eq(true)),
+                )
+                .order_by_asc(reachability::Column::Implementation)
+                .all(db)
+                .await?,
+        ),
+        FindingKind::ImplInitialize => to_findings(
+            impl_initialize::Entity::find()
+                .filter(impl_initialize::Column::Uninitialized.eq(true))
+                .order_by_asc(impl_initialize::Column::Implementation)
+                .all(db)
+                .await?,
+        ),
+    };
+    Ok(findings)
+}
+
+fn to_findings<M: ToFindings>(rows: Vec<M>) -> Vec<Finding> {
+    rows.iter().flat_map(|r| r.findings()).collect()
+}
+
+/// Parse a JSON column, falling back to the default for malformed (or legacy) values.
+fn parse<T: serde::de::DeserializeOwned + Default>(value: &serde_json::Value) -> T {
+    serde_json::from_value(value.clone()).unwrap_or_default()
+}
+
+/// `{slot: [values]}` of the accesses in each tx.
+fn accesses_by_tx(
+    accesses: &serde_json::Value,
+) -> BTreeMap<TxHash, BTreeMap<U256, BTreeSet<U256>>> {
+    let mut by_tx: BTreeMap<TxHash, BTreeMap<U256, BTreeSet<U256>>> = BTreeMap::new();
+    for (tx, accesses) in parse::<Vec<(TxHash, Vec<(U256, U256)>)>>(accesses) {
+        let slots = by_tx.entry(tx).or_default();
+        for (slot, value) in accesses {
+            slots.entry(slot).or_default().insert(value);
+        }
+    }
+    by_tx
+}
+
+/// `{slot: [values]}` of the accesses in one tx.
+fn accesses_by_slot(accesses: &[&serde_json::Value]) -> BTreeMap<U256, BTreeSet<U256>> {
+    let mut by_slot: BTreeMap<U256, BTreeSet<U256>> = BTreeMap::new();
+    for accesses in accesses {
+        for (slot, value) in parse::<Vec<(U256, U256)>>(accesses) {
+            by_slot.entry(slot).or_default().insert(value);
+        }
+    }
+    by_slot
+}
+
+fn hex(v: impl ToString) -> String {
+    v.to_string().to_lowercase()
+}
+
+fn slot_hex(slot: U256) -> String {
+    format!("{:#066x}", slot)
+}
+
+impl ToFindings for entities::collision::Model {
+    fn findings(&self) -> Vec<Finding> {
+        let mut preimages = Preimages::new();
+        if let Some(p) = &self.preimages {
+            for (_, p) in parse::<Vec<(TxHash, Vec<(U256, Bytes)>)>>(p) {
+                preimages.extend(p);
+            }
+        }
+        let labels: BTreeMap<U256, SlotLabel> = self
+            .slot_labels
+            .as_ref()
+            .map(|l| parse::<Vec<(U256, SlotLabel)>>(l).into_iter().collect())
+            .unwrap_or_default();
+        let proxy_sstores = accesses_by_tx(&self.proxy_sstores);
+        let impl_sstores = accesses_by_tx(&self.implementation_sstores);
+
+        let mut findings = Vec::new();
+        let txs = proxy_sstores
+            .keys()
+            .chain(impl_sstores.keys())
+            .collect::<BTreeSet<_>>();
+        for tx in txs {
+            let empty = BTreeMap::new();
+            let proxy_writes = proxy_sstores.get(tx).unwrap_or(&empty);
+            let impl_writes = impl_sstores.get(tx).unwrap_or(&empty);
+            let slots = proxy_writes
+                .keys()
+                .chain(impl_writes.keys())
+                .collect::<BTreeSet<_>>();
+            for slot in slots {
+                let label = labels
+                    .get(slot)
+                    .cloned()
+                    .unwrap_or_else(|| classify(*slot, &preimages));
+                // standard slots are intended to be shared by proxy and implementation
+                if label.is_standard() {
+                    continue;
+                }
+                let rendered = render_slot(*slot, &preimages);
+                let mut f = Finding::new(
+                    FindingKind::Collision,
+                    Level::Error,
+                    format!(
+                        "proxy and implementation both write slot {} of proxy {}",
+                        rendered, self.proxy
+                    ),
+                );
+                f.proxy = Some(self.proxy.clone());
+                f.tx = Some(hex(tx));
+                f.slot = Some(slot_hex(*slot));
+                f.slot_label = Some(rendered);
+                f.evidence = json!({
+                    "label": label,
+                    "proxy_values": proxy_writes.get(slot),
+                    "implementation_values": impl_writes.get(slot),
+                });
+                findings.push(f);
+            }
+        }
+        findings
+    }
+}
+
+impl ToFindings for entities::regression::Model {
+    fn findings(&self) -> Vec<Finding> {
+        let preimages: Preimages = self
+            .preimages
+            .as_ref()
+            .map(|p| parse::<Vec<(U256, Bytes)>>(p).into_iter().collect())
+            .unwrap_or_default();
+        let original = accesses_by_slot(&[&self.original_sloads, &self.original_sstores]);
+        let alt = accesses_by_slot(&[&self.alt_sloads, &self.alt_sstores]);
+        let original_sstores = accesses_by_slot(&[&self.original_sstores]);
+        let alt_sstores = accesses_by_slot(&[&self.alt_sstores]);
+
+        let mut findings = Vec::new();
+        let slots = original.keys().chain(alt.keys()).collect::<BTreeSet<_>>();
+        for slot in slots {
+            let change = match (original.contains_key(slot), alt.contains_key(slot)) {
+                (true, false) => "missed",
+                (false, true) => "additional",
+                _ if original_sstores.get(slot) != alt_sstores.get(slot) => "value",
+                _ => continue,
+            };
+            let rendered = render_slot(*slot, &preimages);
+            let level = if self.proxy_reverted {
+                Level::Note
+            } else {
+                Level::Warning
+            };
+            let mut f = Finding::new(
+                FindingKind::Regression,
+                level,
+                format!(
+                    "implementation {} accesses slot {} differently ({}) from {} when replaying tx {}",
+                    self.alt_implementation, rendered, change, self.implementation, self.tx
+                ),
+            );
+            f.proxy = Some(self.proxy.clone());
+            f.implementation = Some(self.alt_implementation.clone());
+            f.tx = Some(self.tx.clone());
+            f.slot = Some(slot_hex(*slot));
+            f.slot_label = Some(rendered);
+            f.evidence = json!({
+                "change": change,
+                "original_implementation": self.implementation,
+                "original_values": original.get(slot),
+                "alt_values": alt.get(slot),
+                "proxy_reverted": self.proxy_reverted,
+            });
+            findings.push(f);
+        }
+        findings
+    }
+}
+
+impl ToFindings for entities::regression_filter::Model {
+    fn findings(&self) -> Vec<Finding> {
+        let missed = parse::<BTreeSet<U256>>(&self.missed_slots);
+        let additional = parse::<BTreeSet<U256>>(&self.additional_slots);
+        let changes = missed
+            .iter()
+            .map(|s| (s, "missed"))
+            .chain(additional.iter().map(|s| (s, "additional")));
+        changes
+            .map(|(slot, change)| {
+                let rendered = render_slot(*slot, &Preimages::new());
+                let mut f = Finding::new(
+                    FindingKind::RegressionFilter,
+                    Level::Warning,
+                    format!(
+                        "implementation {} {} slot {} compared to {} when replaying tx {}",
+                        self.alt_implementation,
+                        if change == "missed" { "misses" } else { "adds" },
+                        rendered,
+                        self.implementation,
+                        self.tx
+                    ),
+                );
+                f.proxy = Some(self.proxy.clone());
+                f.implementation = Some(self.alt_implementation.clone());
+                f.tx = Some(self.tx.clone());
+                f.slot = Some(slot_hex(*slot));
+                f.slot_label = Some(rendered);
+                f.evidence = json!({
+                    "change": change,
+                    "original_implementation": self.implementation,
+                });
+                f
+            })
+            .collect()
+    }
+}
+
+impl ToFindings for entities::fake_loose::Model {
+    fn findings(&self) -> Vec<Finding> {
+        parse::<Vec<(String, String, i64)>>(&self.mismatched_impls)
+            .into_iter()
+            .map(|(slot_impl, actual_impl, block)| {
+                let mut f = Finding::new(
+                    FindingKind::FakeLoose,
+                    Level::Error,
+                    format!(
+                        "proxy {} stores implementation {} but delegates to {}",
+                        self.proxy, slot_impl, actual_impl
+                    ),
+                );
+                f.proxy = Some(self.proxy.clone());
+                f.implementation = Some(hex(&slot_impl));
+                f.evidence = json!({
+                    "slot_implementation": hex(slot_impl),
+                    "actual_implementation": hex(actual_impl),
+                    "block": block,
+                    "convention": self.convention,
+                });
+                f
+            })
+            .collect()
+    }
+}
+
+impl ToFindings for entities::selector_clash::Model {
+    fn findings(&self) -> Vec<Finding> {
+        parse::<Vec<SelectorClash>>(&self.clashes)
+            .into_iter()
+            .filter(|c| c.handler == Handler::Proxy)
+            .map(|c| {
+                let mut f = Finding::new(
+                    FindingKind::SelectorClash,
+                    Level::Warning,
+                    format!(
+                        "proxy {} shadows function {} of implementation {}",
+                        self.proxy, c.selector, self.implementation
+                    ),
+                );
+                f.proxy = Some(self.proxy.clone());
+                f.implementation = Some(self.implementation.clone());
+                f.selector = Some(hex(&c.selector));
+                f.evidence = json!({ "handler": c.handler });
+                f
+            })
+            .collect()
+    }
+}
+
+impl ToFindings for entities::admin_routing::Model {
+    fn findings(&self) -> Vec<Finding> {
+        let admin_reached_logic = parse::<Vec<String>>(&self.admin_reached_logic);
+        let non_admin_reached_admin = parse::<Vec<String>>(&self.non_admin_reached_admin);
+        let routes = admin_reached_logic
+            .into_iter()
+            .map(|s| (s, "admin_reached_logic"))
+            .chain(
+                non_admin_reached_admin
+                    .into_iter()
+                    .map(|s| (s, "non_admin_reached_admin")),
+            );
+        routes
+            .map(|(selector, route)| {
+                let message = if route == "admin_reached_logic" {
+                    format!(
+                        "the admin of proxy {} reaches logic function {}",
+                        self.proxy, selector
+                    )
+                } else {
+                    format!(
+                        "non-admin callers of proxy {} reach admin function {}",
+                        self.proxy, selector
+                    )
+                };
+                let mut f = Finding::new(FindingKind::AdminRouting, Level::Warning, message);
+                f.proxy = Some(self.proxy.clone());
+                f.implementation = self.implementation.clone();
+                f.selector = Some(hex(selector));
+                f.evidence = json!({
+                    "route": route,
+                    "admin": self.admin,
+                });
+                f
+            })
+            .collect()
+    }
+}
+
+impl ToFindings for entities::reachability::Model {
+    fn findings(&self) -> Vec<Finding> {
+        parse::<Vec<ReachableCall>>(&self.reachable_calls)
+            .into_iter()
+            .filter(|c| c.selfdestruct || c.attacker_delegatecall)
+            .map(|c| {
+                let opcode = if c.selfdestruct {
+                    "SELFDESTRUCT"
+                } else {
+                    "attacker-controlled DELEGATECALL"
+                };
+                let mut f = Finding::new(
+                    FindingKind::Reachability,
+                    Level::Error,
+                    format!(
+                        "function {} of implementation {} reaches {}",
+                        c.selector, self.implementation, opcode
+                    ),
+                );
+                f.implementation = Some(self.implementation.clone());
+                f.selector = Some(hex(&c.selector));
+                f.evidence = json!({
+                    "input": c.input,
+                    "selfdestruct": c.selfdestruct,
+                    "attacker_delegatecall": c.attacker_delegatecall,
+                    "min_block": self.min_block,
+                });
+                f
+            })
+            .collect()
+    }
+}
+
+impl ToFindings for entities::impl_initialize::Model {
+    fn findings(&self) -> Vec<Finding> {
+        if !self.uninitialized {
+            return Vec::new();
+        }
+        // initializing the implementation is only exploitable with a dangerous call afterwards
+        let level = if self.selfdestruct || self.delegatecall {
+            Level::Error
+        } else {
+            Level::Note
+        };
+        let mut f = Finding::new(
+            FindingKind::ImplInitialize,
+            level,
+            format!(
+                "anyone can initialize implementation {}",
+                self.implementation
+            ),
+        );
+        f.implementation = Some(self.implementation.clone());
+        f.evidence = json!({
+            "frontrun_input": self.frontrun_input,
+            "selfdestruct": self.selfdestruct,
+            "delegatecall": self.delegatecall,
+            "dangerous_calls": self.dangerous_calls,
+            "min_block": self.min_block,
+        });
+        vec![f]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::{conversion::ConvertTo, engine::types::U256};
+    use serde_json::json;
+
+    use crate::{entities, slot::EIP1967_IMPLEMENTATION_SLOT};
+
+    use super::{FindingKind, Level, Report, ToFindings, SCHEMA_VERSION};
+
+    const PROXY: &str = "0x1111111111111111111111111111111111111111";
+    const TX: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+
+    #[test]
+    fn test_finding_kind_tables() {
+        for kind in FindingKind::ALL {
+            assert_eq!(FindingKind::from_table(kind.table()), Some(kind));
+            assert_eq!(serde_json::to_value(kind).unwrap(), json!(kind.table()));
+        }
+        assert_eq!(FindingKind::from_table("proxy"), None);
+    }
+
+    #[test]
+    fn test_collision_findings() {
+        let standard: U256 = EIP1967_IMPLEMENTATION_SLOT.cvt();
+        let row = entities::collision::Model {
+            proxy: PROXY.to_string(),
+            problematic: true,
+            proxy_sstores: json!([[TX, [["0x0", "0x1"], [standard, "0x5"]]]]),
+            proxy_sloads: json!([]),
+            implementation_sstores: json!([[TX, [["0x0", "0x2"], [standard, "0x5"]]]]),
+            implementation_sloads: json!([[TX, [["0x0", "0x1"]]]]),
+            slot_labels: None,
+            preimages: None,
+            total_time: 0,
+            avg_time: 0,
+        };
+        let findings = row.findings();
+        // the EIP-1967 slot is intended to be shared
+        assert_eq!(findings.len(), 1);
+        let f = &findings[0];
+        assert_eq!(f.kind, FindingKind::Collision);
+        assert_eq!(f.level, Level::Error);
+        assert_eq!(f.proxy.as_deref(), Some(PROXY));
+        assert_eq!(f.tx.as_deref(), Some(TX));
+        assert_eq!(f.slot_label.as_deref(), Some("slot@0"));
+        assert_eq!(f.evidence["label"]["kind"], json!("sequential"));
+        assert_eq!(f.evidence["proxy_values"].as_array().unwrap().len(), 1);
+        assert_eq!(
+            f.fingerprint(),
+            format!(
+                "collision/{}/-/{}/{}/-",
+                PROXY,
+                TX,
+                f.slot.as_deref().unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn test_report_is_stable() {
+        let row = entities::selector_clash::Model {
+            proxy: PROXY.to_string(),
+            implementation: "0x3333333333333333333333333333333333333333".to_string(),
+            problematic: true,
+            proxy_selectors: json!([]),
+            implementation_selectors: json!([]),
+            clashes: json!([
+                {"selector": "0xa9059cbb", "handler": "proxy"},
+                {"selector": "0x095ea7b3", "handler": "proxy"},
+                {"selector": "0x70a08231", "handler": "implementation"},
+            ]),
+            total_time: 0,
+        };
+        let mut findings = row.findings();
+        assert_eq!(findings.len(), 2);
+        findings.extend(row.findings());
+        let report = Report::new(findings.clone());
+        findings.reverse();
+        assert_eq!(Report::new(findings), report);
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.findings.len(), 2);
+        assert_eq!(report.findings[0].selector.as_deref(), Some("0x095ea7b3"));
+    }
+}
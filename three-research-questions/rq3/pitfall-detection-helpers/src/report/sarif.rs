@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+
+use super::{Finding, FindingKind, Level, Report};
+
+pub const SARIF_VERSION: &str = "2.1.0";
+pub const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The subset of SARIF 2.1.0 used to report findings.
+/// Contracts and functions have no source file, so results are located with logical locations.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SarifLog {
+    #[serde(rename = "$schema")]
+    pub schema: &'static str,
+    pub version: &'static str,
+    pub runs: Vec<Run>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Run {
+    pub tool: Tool,
+    pub results: Vec<SarifResult>,
+    pub properties: BTreeMap<&'static str, String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Tool {
+    pub driver: Driver,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Driver {
+    pub name: String,
+    pub version: String,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rule {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub short_description: Message,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct Message {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+    pub rule_id: &'static str,
+    pub rule_index: usize,
+    pub level: Level,
+    pub message: Message,
+    pub locations: Vec<Location>,
+    pub partial_fingerprints: BTreeMap<&'static str, String>,
+    /// the finding in the JSON report schema
+    pub properties: Finding,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    pub logical_locations: Vec<LogicalLocation>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogicalLocation {
+    pub fully_qualified_name: String,
+    pub kind: &'static str,
+}
+
+impl From<&Report> for SarifLog {
+    fn from(report: &Report) -> Self {
+        let rules = FindingKind::ALL
+            .iter()
+            .map(|k| Rule {
+                id: k.rule_id(),
+                name: k.table(),
+                short_description: Message {
+                    text: k.description().to_string(),
+                },
+            })
+            .collect();
+        let results = report
+            .findings
+            .iter()
+            .map(|f| SarifResult {
+                rule_id: f.kind.rule_id(),
+                rule_index: f.kind as usize,
+                level: f.level,
+                message: Message {
+                    text: f.message.clone(),
+                },
+                locations: vec![Location {
+                    logical_locations: logical_locations(f),
+                }],
+                partial_fingerprints: BTreeMap::from([("findingKey/v1", f.fingerprint())]),
+                properties: f.clone(),
+            })
+            .collect();
+        Self {
+            schema: SARIF_SCHEMA,
+            version: SARIF_VERSION,
+            runs: vec![Run {
+                tool: Tool {
+                    driver: Driver {
+                        name: report.tool.clone(),
+                        version: report.tool_version.clone(),
+                        rules,
+                    },
+                },
+                results,
+                properties: BTreeMap::from([("schemaVersion", report.schema_version.clone())]),
+            }],
+        }
+    }
+}
+
+/// The contract (proxy, or implementation if there is no proxy) and, if any, the function of a finding.
+fn logical_locations(f: &Finding) -> Vec<LogicalLocation> {
+    let contract = match f.proxy.as_ref().or(f.implementation.as_ref()) {
+        Some(contract) => contract,
+        None => return Vec::new(),
+    };
+    let mut locations = vec![LogicalLocation {
+        fully_qualified_name: contract.clone(),
+        kind: "module",
+    }];
+    if let Some(selector) = &f.selector {
+        locations.push(LogicalLocation {
+            fully_qualified_name: format!("{}::{}", contract, selector),
+            kind: "function",
+        });
+    }
+    locations
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::report::{Finding, FindingKind, Level, Report};
+
+    use super::SarifLog;
+
+    #[test]
+    fn test_sarif_log() {
+        let finding = Finding {
+            kind: FindingKind::SelectorClash,
+            level: Level::Warning,
+            proxy: Some("0x1111111111111111111111111111111111111111".to_string()),
+            implementation: Some("0x3333333333333333333333333333333333333333".to_string()),
+            tx: None,
+            slot: None,
+            slot_label: None,
+            selector: Some("0xa9059cbb".to_string()),
+            message: "shadowed".to_string(),
+            evidence: json!({"handler": "proxy"}),
+        };
+        let report = Report::new(vec![finding.clone()]);
+        let log = serde_json::to_value(SarifLog::from(&report)).unwrap();
+        assert_eq!(log["version"], json!("2.1.0"));
+        let run = &log["runs"][0];
+        assert_eq!(run["tool"]["driver"]["rules"].as_array().unwrap().len(), 8);
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], json!("PX005"));
+        assert_eq!(
+            run["tool"]["driver"]["rules"][result["ruleIndex"].as_u64().unwrap() as usize]["id"],
+            json!("PX005")
+        );
+        assert_eq!(result["level"], json!("warning"));
+        assert_eq!(
+            result["locations"][0]["logicalLocations"][1]["fullyQualifiedName"],
+            json!("0x1111111111111111111111111111111111111111::0xa9059cbb")
+        );
+        assert_eq!(
+            result["partialFingerprints"]["findingKey/v1"],
+            json!(finding.fingerprint())
+        );
+    }
+}
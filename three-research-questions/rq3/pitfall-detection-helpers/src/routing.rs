@@ -0,0 +1,242 @@
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::{
+        state::BcState,
+        types::{Address, Bytes, Database, U256},
+    },
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    beacon::resolve_implementation,
+    budget::{Budget, BudgetMeter},
+    error::DetectorError,
+    inspectors::delegation::RoutingInspector,
+    selector::{code_of, extract_selectors, selector_to_string, Selector, SIMULATED_ARGS},
+    slot::EIP1967_ADMIN_SLOT,
+};
+
+/// The caller used to simulate calls from a non-admin account,
+/// derived from the admin by flipping all its bits so that it can never be the admin.
+pub fn non_admin_of(admin: Address) -> Address {
+    let bytes = admin.as_slice().iter().map(|b| !b).collect::<Vec<_>>();
+    Address::from_slice(&bytes)
+}
+
+/// Calls with the same selector from the admin and from a non-admin caller.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RoutingProbe {
+    pub selector: String,
+    /// whether the selector is dispatched by the proxy, i.e., an admin function
+    pub admin_function: bool,
+    pub admin_delegated: bool,
+    pub non_admin_delegated: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RoutingResult {
+    pub admin: Address,
+    pub implementation: Address,
+    pub probes: Vec<RoutingProbe>,
+}
+
+impl RoutingResult {
+    /// Logic functions that the admin can reach.
+    pub fn admin_reached_logic(&self) -> Vec<String> {
+        self.probes
+            .iter()
+            .filter(|p| !p.admin_function && p.admin_delegated)
+            .map(|p| p.selector.clone())
+            .collect()
+    }
+
+    /// Admin functions that non-admin callers can reach.
+    pub fn non_admin_reached_admin(&self) -> Vec<String> {
+        self.probes
+            .iter()
+            .filter(|p| p.admin_function && !p.non_admin_delegated)
+            .map(|p| p.selector.clone())
+            .collect()
+    }
+
+    pub fn problematic(&self) -> bool {
+        self.probes.iter().any(|p| {
+            (!p.admin_function && p.admin_delegated) || (p.admin_function && !p.non_admin_delegated)
+        })
+    }
+}
+
+/// Simulate a call with the selector from `sender` and check whether the proxy delegates it.
+/// Returns a timeout error if the call exceeds the budget.
+pub fn delegated_from<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    sender: Address,
+    selector: &Selector,
+    budget: Budget,
+) -> Result<bool, DetectorError> {
+    let mut input = selector.to_vec();
+    input.extend(vec![0u8; 32 * SIMULATED_ARGS]);
+    let mut insp = RoutingInspector {
+        proxy,
+        sender,
+        delegated: false,
+        meter: BudgetMeter::new(budget),
+    };
+    let _ = caller.simulate_call(state, proxy, Bytes::from(input), None, &mut insp);
+    insp.meter.check()?;
+    Ok(insp.delegated)
+}
+
+/// Check the transparent proxy routing: calls from the admin (read from the EIP-1967 admin slot)
+/// should never be delegated, and calls from others should always be delegated.
+/// Returns None if the proxy has no admin in the EIP-1967 admin slot.
+pub fn check_routing<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    budget: Budget,
+) -> Result<Option<RoutingResult>, DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let admin = state
+        .storage(proxy, EIP1967_ADMIN_SLOT.cvt())
+        .map_err(DetectorError::state_unavailable)?;
+    if admin == U256::ZERO {
+        return Ok(None);
+    }
+    let admin: Address = admin.cvt();
+    // the implementation slot is empty for a beacon proxy, whose implementation is returned by the beacon
+    let implementation = resolve_implementation(caller, state, proxy, budget)?.implementation;
+    let non_admin = non_admin_of(admin);
+
+    let admin_selectors =
+        extract_selectors(&code_of(state, proxy).map_err(DetectorError::state_unavailable)?);
+    let logic_selectors = if implementation != Address::ZERO {
+        extract_selectors(
+            &code_of(state, implementation).map_err(DetectorError::state_unavailable)?,
+        )
+    } else {
+        Default::default()
+    };
+
+    let mut probes = Vec::new();
+    for selector in admin_selectors.union(&logic_selectors) {
+        probes.push(RoutingProbe {
+            selector: selector_to_string(selector),
+            admin_function: admin_selectors.contains(selector),
+            admin_delegated: delegated_from(caller, state, proxy, admin, selector, budget)?,
+            non_admin_delegated: delegated_from(caller, state, proxy, non_admin, selector, budget)?,
+        });
+    }
+    Ok(Some(RoutingResult {
+        admin,
+        implementation,
+        probes,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::memory::MemoryBcState;
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::budget::Budget;
+
+    use super::{check_routing, non_admin_of};
+
+    #[test]
+    fn test_check_routing() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Impl {
+                uint256 public value;
+                function set_value(uint256 _value) public {
+                    value = _value;
+                }
+            }
+            contract Base {
+                constructor() {
+                    address impl = address(new Impl());
+                    address admin = msg.sender;
+                    assembly {
+                        sstore(0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc, impl)
+                        sstore(0xb53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103, admin)
+                    }
+                }
+                function _admin() internal view returns (address a) {
+                    assembly {
+                        a := sload(0xb53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103)
+                    }
+                }
+                function _delegate() internal {
+                    assembly {
+                        let impl := sload(0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc)
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), impl, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            contract Transparent is Base {
+                modifier ifAdmin() {
+                    if (msg.sender == _admin()) {
+                        _;
+                    } else {
+                        _delegate();
+                    }
+                }
+                function admin() external ifAdmin returns (address) {
+                    return _admin();
+                }
+                fallback() external payable {
+                    require(msg.sender != _admin(), "admin cannot fallback");
+                    _delegate();
+                }
+            }
+            contract Naive is Base {
+                function admin() external view returns (address) {
+                    return _admin();
+                }
+                fallback() external payable {
+                    _delegate();
+                }
+            }
+            "#,
+            vec!["Transparent", "Naive"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (transparent, naive) = (addrs.remove(0), addrs.remove(0));
+        let caller = HighLevelCaller::default().bypass_check();
+
+        let r = check_routing(&caller, &mut state, transparent, Budget::default())
+            .unwrap()
+            .unwrap();
+        assert_ne!(non_admin_of(r.admin), r.admin);
+        // admin(), value(), set_value(uint256)
+        assert_eq!(r.probes.len(), 3);
+        assert!(!r.problematic());
+
+        let r = check_routing(&caller, &mut state, naive, Budget::default())
+            .unwrap()
+            .unwrap();
+        assert!(r.problematic());
+        assert_eq!(r.non_admin_reached_admin(), vec!["0xf851a440".to_string()]);
+        assert_eq!(
+            r.admin_reached_logic(),
+            vec!["0x3fa4f245".to_string(), "0xb0f2b72a".to_string()]
+        );
+    }
+}
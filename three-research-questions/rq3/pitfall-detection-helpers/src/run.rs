@@ -0,0 +1,375 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sea_orm::{
+    sea_query::{Expr, IntoColumnRef, OnConflict, Query, SimpleExpr},
+    ActiveValue, ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter,
+};
+
+use crate::entities::{error, run};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// Failed before and scheduled to be retried.
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Pending => "pending",
+            RunStatus::Running => "running",
+            RunStatus::Done => "done",
+            RunStatus::Failed => "failed",
+        }
+    }
+}
+
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Number of items that reached each status in a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub done: u64,
+    pub failed: u64,
+    /// dispatched but interrupted before the results are saved
+    pub running: u64,
+}
+
+/// RunTracker records the progress of a detector in the `run` table,
+/// so that the detector picks up where it left off after a crash or Ctrl-C.
+///
+/// Items are marked running when they are dispatched, and done or failed when their results are saved.
+/// Items left running by a previous session (i.e., interrupted) are run again,
+/// while failed items are run again only if they are reset by `prepare`.
+#[derive(Debug, Clone)]
+pub struct RunTracker {
+    pub detector: String,
+    /// start time of this session, unix milliseconds
+    pub session: i64,
+}
+
+impl RunTracker {
+    pub fn new(detector: &str) -> Self {
+        Self {
+            detector: detector.to_string(),
+            session: now_millis(),
+        }
+    }
+
+    /// Prepare a session. Failed items are reset to pending if `retry_failed`.
+    /// Returns the number of items to retry.
+    pub async fn prepare(&self, db: &DatabaseConnection, retry_failed: bool) -> Result<u64, DbErr> {
+        if !retry_failed {
+            return Ok(0);
+        }
+        let r = run::Entity::update_many()
+            .col_expr(
+                run::Column::Status,
+                Expr::value(RunStatus::Pending.as_str()),
+            )
+            .col_expr(run::Column::UpdatedAt, Expr::value(now_millis()))
+            .filter(run::Column::Detector.eq(self.detector.clone()))
+            .filter(run::Column::Status.eq(RunStatus::Failed.as_str()))
+            .exec(db)
+            .await?;
+        Ok(r.rows_affected)
+    }
+
+    /// Reset the failed items to pending if their failures in the `error` table are of the given kinds.
+    /// `item` is the column of the `error` table identifying the items of this detector.
+    /// Returns the number of items to retry.
+    pub async fn retry_kinds(
+        &self,
+        db: &DatabaseConnection,
+        item: error::Column,
+        kinds: &[String],
+    ) -> Result<u64, DbErr> {
+        if kinds.is_empty() {
+            return Ok(0);
+        }
+        let r = run::Entity::update_many()
+            .col_expr(
+                run::Column::Status,
+                Expr::value(RunStatus::Pending.as_str()),
+            )
+            .col_expr(run::Column::UpdatedAt, Expr::value(now_millis()))
+            .filter(run::Column::Detector.eq(self.detector.clone()))
+            .filter(run::Column::Status.eq(RunStatus::Failed.as_str()))
+            .filter(
+                run::Column::Item.in_subquery(
+                    Query::select()
+                        .column(item)
+                        .from(error::Entity)
+                        .and_where(error::Column::Detector.eq(self.detector.clone()))
+                        .and_where(error::Column::Kind.is_in(kinds.iter().cloned()))
+                        .take(),
+                ),
+            )
+            .exec(db)
+            .await?;
+        Ok(r.rows_affected)
+    }
+
+    /// Condition that the item (a column of the iterated table) still needs to run,
+    /// i.e., it is neither done, failed, nor dispatched in this session.
+    pub fn unfinished<C: IntoColumnRef>(&self, item: C) -> SimpleExpr {
+        Expr::exists(
+            Query::select()
+                .from(run::Entity)
+                .and_where(
+                    Expr::col((run::Entity, run::Column::Detector)).eq(self.detector.clone()),
+                )
+                .and_where(Expr::col((run::Entity, run::Column::Item)).equals(item))
+                .cond_where(
+                    Condition::any()
+                        .add(
+                            Expr::col((run::Entity, run::Column::Status))
+                                .is_in([RunStatus::Done.as_str(), RunStatus::Failed.as_str()]),
+                        )
+                        .add(
+                            Condition::all()
+                                .add(
+                                    Expr::col((run::Entity, run::Column::Status))
+                                        .eq(RunStatus::Running.as_str()),
+                                )
+                                .add(
+                                    Expr::col((run::Entity, run::Column::UpdatedAt))
+                                        .gte(self.session),
+                                ),
+                        ),
+                )
+                .take(),
+        )
+        .not()
+    }
+
+    /// The items done or failed, for a detector whose items are not the rows of a table (e.g., blocks).
+    /// Call it after `prepare`, so that the failed items to retry are left out.
+    pub async fn finished(&self, db: &DatabaseConnection) -> Result<HashSet<String>, DbErr> {
+        let runs = run::Entity::find()
+            .filter(run::Column::Detector.eq(self.detector.clone()))
+            .filter(
+                run::Column::Status.is_in([RunStatus::Done.as_str(), RunStatus::Failed.as_str()]),
+            )
+            .all(db)
+            .await?;
+        Ok(runs.into_iter().map(|r| r.item).collect())
+    }
+
+    /// Mark the items as running, counting one more attempt for each of them.
+    pub async fn start<I: IntoIterator<Item = String>>(
+        &self,
+        db: &DatabaseConnection,
+        items: I,
+    ) -> Result<(), DbErr> {
+        let now = now_millis();
+        // an item can only be upserted once in a statement
+        let runs = items
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|item| run::ActiveModel {
+                detector: ActiveValue::Set(self.detector.clone()),
+                item: ActiveValue::Set(item),
+                status: ActiveValue::Set(RunStatus::Running.as_str().to_string()),
+                attempts: ActiveValue::Set(1),
+                error: ActiveValue::Set(None),
+                created_at: ActiveValue::Set(now),
+                updated_at: ActiveValue::Set(now),
+            })
+            .collect::<Vec<_>>();
+        // keep the number of bound parameters of each statement small
+        for chunk in runs.chunks(1000) {
+            run::Entity::insert_many(chunk.to_vec())
+                .on_conflict(
+                    OnConflict::columns([run::Column::Detector, run::Column::Item])
+                        .update_columns([
+                            run::Column::Status,
+                            run::Column::Error,
+                            run::Column::UpdatedAt,
+                        ])
+                        .value(
+                            run::Column::Attempts,
+                            Expr::col((run::Entity, run::Column::Attempts)).add(1),
+                        )
+                        .to_owned(),
+                )
+                .exec(db)
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub async fn done(&self, db: &DatabaseConnection, item: String) -> Result<(), DbErr> {
+        self.finish(db, item, RunStatus::Done, None).await
+    }
+
+    pub async fn fail(
+        &self,
+        db: &DatabaseConnection,
+        item: String,
+        error: String,
+    ) -> Result<(), DbErr> {
+        self.finish(db, item, RunStatus::Failed, Some(error)).await
+    }
+
+    /// Count the items updated in this session by status.
+    pub async fn summary(&self, db: &DatabaseConnection) -> Result<RunSummary, DbErr> {
+        let count = |status: RunStatus| {
+            run::Entity::find()
+                .filter(run::Column::Detector.eq(self.detector.clone()))
+                .filter(run::Column::Status.eq(status.as_str()))
+                .filter(run::Column::UpdatedAt.gte(self.session))
+                .count(db)
+        };
+        Ok(RunSummary {
+            done: count(RunStatus::Done).await?,
+            failed: count(RunStatus::Failed).await?,
+            running: count(RunStatus::Running).await?,
+        })
+    }
+
+    async fn finish(
+        &self,
+        db: &DatabaseConnection,
+        item: String,
+        status: RunStatus,
+        error: Option<String>,
+    ) -> Result<(), DbErr> {
+        run::Entity::update_many()
+            .col_expr(run::Column::Status, Expr::value(status.as_str()))
+            .col_expr(run::Column::Error, Expr::value(error))
+            .col_expr(run::Column::UpdatedAt, Expr::value(now_millis()))
+            .filter(run::Column::Detector.eq(self.detector.clone()))
+            .filter(run::Column::Item.eq(item))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{
+        ActiveValue, ConnectionTrait, Database, DatabaseConnection, DbBackend, EntityTrait,
+        QueryFilter, QueryOrder, Schema,
+    };
+
+    use crate::entities::{error, proxy, run};
+
+    use super::RunTracker;
+
+    async fn setup() -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        let schema = Schema::new(DbBackend::Sqlite);
+        for sql in [
+            schema.create_table_from_entity(proxy::Entity),
+            schema.create_table_from_entity(run::Entity),
+            schema.create_table_from_entity(error::Entity),
+        ] {
+            db.execute(db.get_database_backend().build(&sql))
+                .await
+                .unwrap();
+        }
+        let proxies = ["0x01", "0x02", "0x03"].map(|address| proxy::ActiveModel {
+            address: ActiveValue::Set(address.to_string()),
+            invocation_count: ActiveValue::Set(1),
+        });
+        proxy::Entity::insert_many(proxies).exec(&db).await.unwrap();
+        db
+    }
+
+    async fn unfinished(db: &DatabaseConnection, tracker: &RunTracker) -> Vec<String> {
+        proxy::Entity::find()
+            .filter(tracker.unfinished((proxy::Entity, proxy::Column::Address)))
+            .order_by_asc(proxy::Column::Address)
+            .all(db)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.address)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_tracker_sqlite() {
+        let db = setup().await;
+        let tracker = RunTracker::new("replay");
+        assert_eq!(unfinished(&db, &tracker).await.len(), 3);
+
+        tracker
+            .start(&db, ["0x01".to_string(), "0x02".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(unfinished(&db, &tracker).await, vec!["0x03".to_string()]);
+        tracker.done(&db, "0x01".to_string()).await.unwrap();
+        tracker
+            .fail(&db, "0x02".to_string(), "evm_halt: revert".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            tracker.finished(&db).await.unwrap(),
+            ["0x01".to_string(), "0x02".to_string()].into()
+        );
+        let e = error::ActiveModel {
+            proxy: ActiveValue::Set("0x02".to_string()),
+            msg: ActiveValue::Set("revert".to_string()),
+            kind: ActiveValue::Set("evm_halt".to_string()),
+            tx: ActiveValue::Set(None),
+            detector: ActiveValue::Set("replay".to_string()),
+            ..Default::default()
+        };
+        error::Entity::insert(e).exec(&db).await.unwrap();
+
+        // a new session retries the failed items of the given kinds only
+        let tracker = RunTracker::new("replay");
+        let kinds = ["timeout".to_string()];
+        assert_eq!(
+            tracker
+                .retry_kinds(&db, error::Column::Proxy, &kinds)
+                .await
+                .unwrap(),
+            0
+        );
+        let kinds = ["evm_halt".to_string()];
+        assert_eq!(
+            tracker
+                .retry_kinds(&db, error::Column::Proxy, &kinds)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            unfinished(&db, &tracker).await,
+            vec!["0x02".to_string(), "0x03".to_string()]
+        );
+        assert_eq!(
+            tracker.finished(&db).await.unwrap(),
+            ["0x01".to_string()].into()
+        );
+
+        // starting an item again counts one more attempt
+        tracker.start(&db, ["0x02".to_string()]).await.unwrap();
+        let r = run::Entity::find_by_id(("replay".to_string(), "0x02".to_string()))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.attempts, 2);
+        assert_eq!(r.status, "running");
+        let summary = tracker.summary(&db).await.unwrap();
+        assert_eq!(summary.running, 1);
+    }
+}
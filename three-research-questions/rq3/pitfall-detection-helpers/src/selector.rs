@@ -0,0 +1,290 @@
+use std::collections::BTreeSet;
+
+use libsofl_core::{
+    engine::{
+        state::BcState,
+        types::{opcode, Address, Bytes, Database},
+    },
+    error::SoflError,
+};
+use libsofl_utils::solidity::caller::HighLevelCaller;
+
+use crate::{
+    budget::{Budget, BudgetMeter},
+    error::DetectorError,
+    inspectors::delegation::ImplInspector,
+};
+
+pub type Selector = [u8; 4];
+
+/// Number of zero words appended to the selector when simulating a call,
+/// so that functions with a few static arguments do not revert on decoding.
+pub const SIMULATED_ARGS: usize = 4;
+
+/// Which contract actually handled a call to the proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Handler {
+    /// The proxy handled the call itself, shadowing the implementation function.
+    Proxy,
+    /// The proxy delegated the call to the implementation.
+    Implementation,
+}
+
+/// A selector dispatched by both the proxy and the implementation.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SelectorClash {
+    pub selector: String,
+    pub handler: Handler,
+}
+
+pub fn selector_to_string(selector: &Selector) -> String {
+    Bytes::from(selector.to_vec()).to_string()
+}
+
+/// Extract the selectors from the function dispatcher of a contract, i.e.,
+/// the `PUSH4 selector (DUPn|SWAPn)? EQ PUSHn dest JUMPI` pattern emitted by solc.
+pub fn extract_selectors(code: &[u8]) -> BTreeSet<Selector> {
+    // decode instructions, skipping push data
+    let mut ops: Vec<(u8, &[u8])> = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        let n = if (opcode::PUSH1..=opcode::PUSH32).contains(&op) {
+            (op - opcode::PUSH1 + 1) as usize
+        } else {
+            0
+        };
+        let end = (pc + 1 + n).min(code.len());
+        ops.push((op, &code[pc + 1..end]));
+        pc += 1 + n;
+    }
+
+    let mut selectors = BTreeSet::new();
+    for i in 0..ops.len() {
+        let (op, data) = ops[i];
+        if op != opcode::PUSH4 || data.len() != 4 {
+            continue;
+        }
+        let mut j = i + 1;
+        if j < ops.len()
+            && matches!(ops[j].0, opcode::DUP1..=opcode::DUP16 | opcode::SWAP1..=opcode::SWAP16)
+        {
+            j += 1;
+        }
+        if j + 2 < ops.len()
+            && ops[j].0 == opcode::EQ
+            && (opcode::PUSH1..=opcode::PUSH4).contains(&ops[j + 1].0)
+            && ops[j + 2].0 == opcode::JUMPI
+        {
+            selectors.insert(data.try_into().unwrap());
+        }
+    }
+    selectors
+}
+
+pub fn code_of<D: Database>(state: &mut D, address: Address) -> Result<Bytes, SoflError>
+where
+    D::Error: std::fmt::Debug,
+{
+    let info = state
+        .basic(address)
+        .map_err(|e| SoflError::Custom(format!("{:?}", e)))?
+        .unwrap_or_default();
+    let code = match info.code {
+        Some(code) => code,
+        None => state
+            .code_by_hash(info.code_hash)
+            .map_err(|e| SoflError::Custom(format!("{:?}", e)))?,
+    };
+    Ok(code.original_bytes())
+}
+
+/// Simulate a call to the proxy with the selector and check whether the proxy delegates it.
+/// Returns a timeout error if the call exceeds the budget.
+pub fn handler_of<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    selector: &Selector,
+    budget: Budget,
+) -> Result<Handler, DetectorError> {
+    let mut input = selector.to_vec();
+    input.extend(vec![0u8; 32 * SIMULATED_ARGS]);
+    let mut insp = ImplInspector {
+        proxy,
+        implementation: None,
+        beacon: None,
+        meter: BudgetMeter::new(budget),
+    };
+    // a revert before delegating still means the proxy handled the call
+    let _ = caller.simulate_call(state, proxy, Bytes::from(input), None, &mut insp);
+    insp.meter.check()?;
+    Ok(match insp.implementation {
+        Some(_) => Handler::Implementation,
+        None => Handler::Proxy,
+    })
+}
+
+/// Find the selectors dispatched by both the proxy and the implementation,
+/// and confirm which of them actually handles each clashing selector, each call within the budget.
+pub fn detect_clashes<S: BcState>(
+    caller: &HighLevelCaller,
+    state: &mut S,
+    proxy: Address,
+    implementation: Address,
+    budget: Budget,
+) -> Result<(BTreeSet<Selector>, BTreeSet<Selector>, Vec<SelectorClash>), DetectorError>
+where
+    <S as Database>::Error: std::fmt::Debug,
+{
+    let proxy_selectors =
+        extract_selectors(&code_of(state, proxy).map_err(DetectorError::state_unavailable)?);
+    let implementation_selectors = extract_selectors(
+        &code_of(state, implementation).map_err(DetectorError::state_unavailable)?,
+    );
+    let clashes = proxy_selectors
+        .intersection(&implementation_selectors)
+        .map(|selector| {
+            Ok(SelectorClash {
+                selector: selector_to_string(selector),
+                handler: handler_of(caller, state, proxy, selector, budget)?,
+            })
+        })
+        .collect::<Result<_, DetectorError>>()?;
+    Ok((proxy_selectors, implementation_selectors, clashes))
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::{
+        conversion::ConvertTo,
+        engine::{memory::MemoryBcState, types::Bytes},
+    };
+    use libsofl_utils::solidity::{
+        caller::HighLevelCaller,
+        scripting::{deploy_contracts, SolScriptConfig},
+    };
+
+    use crate::{
+        budget::{Budget, BudgetMeter},
+        error::DetectorError,
+        inspectors::delegation::ImplInspector,
+    };
+
+    use super::{detect_clashes, extract_selectors, handler_of, Handler, SelectorClash};
+
+    #[test]
+    fn test_selector_clash() {
+        let mut state = MemoryBcState::fresh();
+        let mut addrs = deploy_contracts(
+            &mut state,
+            "0.8.12",
+            r#"
+            contract Proxy {
+                address public implementation;
+                function set_implementation(address _impl) public {
+                    implementation = _impl;
+                }
+                fallback() external payable {
+                    address _impl = implementation;
+                    assembly {
+                        calldatacopy(0, 0, calldatasize())
+                        let result := delegatecall(gas(), _impl, 0, calldatasize(), 0, 0)
+                        returndatacopy(0, 0, returndatasize())
+                        switch result
+                        case 0 { revert(0, returndatasize()) }
+                        default { return(0, returndatasize()) }
+                    }
+                }
+            }
+            contract Impl {
+                address public implementation;
+                uint256 public value;
+                function set_value(uint256 _value) public {
+                    value = _value;
+                }
+            }
+            "#,
+            vec!["Proxy", "Impl"],
+            SolScriptConfig::default(),
+        )
+        .unwrap();
+        let (proxy, implementation) = (addrs.remove(0), addrs.remove(0));
+        let caller = HighLevelCaller::default().bypass_check();
+        caller
+            .invoke(
+                &mut state,
+                proxy,
+                "set_implementation(address)",
+                &[implementation.into()],
+                None,
+                &mut ImplInspector {
+                    proxy,
+                    implementation: None,
+                    beacon: None,
+                    meter: BudgetMeter::default(),
+                },
+            )
+            .unwrap();
+
+        let (proxy_selectors, implementation_selectors, clashes) = detect_clashes(
+            &caller,
+            &mut state,
+            proxy,
+            implementation,
+            Budget::default(),
+        )
+        .unwrap();
+        // set_implementation(address), implementation()
+        assert!(proxy_selectors.contains(&[0x4c, 0xd6, 0x9d, 0xa0]));
+        assert!(proxy_selectors.contains(&[0x5c, 0x60, 0xda, 0x1b]));
+        // set_value(uint256)
+        assert!(implementation_selectors.contains(&[0xb0, 0xf2, 0xb7, 0x2a]));
+        assert_eq!(
+            clashes,
+            vec![SelectorClash {
+                selector: "0x5c60da1b".to_string(),
+                handler: Handler::Proxy,
+            }]
+        );
+        assert_eq!(
+            handler_of(
+                &caller,
+                &mut state,
+                proxy,
+                &[0xb0, 0xf2, 0xb7, 0x2a],
+                Budget::default()
+            )
+            .unwrap(),
+            Handler::Implementation
+        );
+        // a call exceeding its budget is a timeout, not a call handled by the proxy
+        let budget = Budget {
+            max_steps: Some(10),
+            ..Default::default()
+        };
+        assert!(matches!(
+            handler_of(
+                &caller,
+                &mut state,
+                proxy,
+                &[0xb0, 0xf2, 0xb7, 0x2a],
+                budget
+            ),
+            Err(DetectorError::Timeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract_selectors() {
+        // PUSH1 0 CALLDATALOAD PUSH1 0xe0 SHR DUP1 PUSH4 0x5c60da1b EQ PUSH2 0x0010 JUMPI
+        // PUSH4 0x12345678 (not followed by EQ) POP
+        let code: Bytes = "0x60003560e01c80635c60da1b1461001057631234567850".cvt();
+        let selectors = extract_selectors(&code);
+        assert_eq!(
+            selectors.into_iter().collect::<Vec<_>>(),
+            vec![[0x5c, 0x60, 0xda, 0x1b]]
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+
+use futures::StreamExt;
+use libsofl_utils::log::info;
+use sea_orm::{DatabaseConnection, DbErr};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook_tokio::Signals;
+
+use crate::run::{RunSummary, RunTracker};
+
+/// Exit code of the process when it is terminated by a second signal.
+pub const FORCED_EXIT_CODE: i32 = 130;
+
+/// Shutdown coordinates a graceful stop of a detector on SIGINT/SIGTERM.
+///
+/// Once a signal is received, the producers stop dispatching items and the workers abandon the items not started yet,
+/// while the results already computed are still flushed to the database.
+/// The abandoned items stay running in the `run` table, and are run again in the next session.
+/// A second signal terminates the process immediately.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Install the handlers of SIGINT and SIGTERM.
+    /// The signals are received on a dedicated thread, since the detectors block their runtime while waiting for the workers.
+    pub fn install() -> Self {
+        let shutdown = Self::default();
+        let cloned = shutdown.clone();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to create signal runtime");
+            rt.block_on(async move {
+                let mut signals =
+                    Signals::new([SIGINT, SIGTERM]).expect("failed to register signal handler");
+                ready_tx.send(()).unwrap();
+                while let Some(sig) = signals.next().await {
+                    if cloned.request() {
+                        std::process::exit(FORCED_EXIT_CODE);
+                    }
+                    info!(
+                        signal = sig,
+                        "Shutdown requested, send again to terminate immediately"
+                    );
+                }
+            });
+        });
+        // the handlers are registered once the function returns
+        ready_rx.recv().expect("signal thread exited");
+        shutdown
+    }
+
+    /// Request a graceful stop, returns whether a stop was already requested.
+    pub fn request(&self) -> bool {
+        self.requested.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Log what has been persisted in this session.
+    pub async fn report(
+        &self,
+        tracker: &RunTracker,
+        db: &DatabaseConnection,
+    ) -> Result<RunSummary, DbErr> {
+        let summary = tracker.summary(db).await?;
+        info!(
+            detector = tracker.detector,
+            interrupted = self.requested(),
+            done = summary.done,
+            failed = summary.failed,
+            abandoned = summary.running,
+            "Session finished"
+        );
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shutdown;
+
+    #[test]
+    fn test_shutdown_request() {
+        let shutdown = Shutdown::default();
+        assert!(!shutdown.requested());
+        // the first request stops gracefully, the second one terminates
+        assert!(!shutdown.request());
+        assert!(shutdown.requested());
+        assert!(shutdown.clone().requested());
+        assert!(shutdown.clone().request());
+    }
+}
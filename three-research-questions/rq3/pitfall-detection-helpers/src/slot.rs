@@ -0,0 +1,356 @@
+use std::{collections::HashMap, fmt};
+
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::types::{Bytes, U256},
+};
+
+/// keccak256("eip1967.proxy.implementation") - 1
+pub const EIP1967_IMPLEMENTATION_SLOT: &str =
+    "0x360894a13ba1a3210667c828492db98dca3e2076cc3735a920a3ca505d382bbc";
+/// keccak256("eip1967.proxy.admin") - 1
+pub const EIP1967_ADMIN_SLOT: &str =
+    "0xb53127684a568b3173ae13b9f8a6016e243e63b6e8ee1178d6a717850b5d6103";
+/// keccak256("eip1967.proxy.beacon") - 1
+pub const EIP1967_BEACON_SLOT: &str =
+    "0xa3f0ad74e5423aebfd80d3ef4346578335a9a72aeaee59ff6cb3582b35133d50";
+/// keccak256("PROXIABLE")
+pub const EIP1822_PROXIABLE_SLOT: &str =
+    "0xc5f16f0fcc639fa48a6947836d9850f504798523bf8c9a3a87d5876cf622bcf7";
+/// ERC-7201 namespace "openzeppelin.storage.Initializable"
+pub const OZ_INITIALIZABLE_SLOT: &str =
+    "0xf0c57e16840df040f15088dc2f81fe391c3923bec73e23a9662efc9c229c6a00";
+/// keccak256("org.zeppelinos.proxy.implementation"), used by OpenZeppelin (zos) proxies before EIP-1967
+pub const OZ_LEGACY_IMPLEMENTATION_SLOT: &str =
+    "0x7050c9e0f4ca769c69bd3a8ef740bc37934f8e2c036e5a723fd8ee048ed3f8c3";
+
+/// Slots below this bound are considered as sequentially allocated state variables.
+pub const MAX_SEQUENTIAL_SLOT: u64 = 1 << 16;
+/// Maximum distance between a hashed slot and the accessed slot,
+/// i.e., the offset of a struct member or an array element.
+pub const MAX_HASHED_OFFSET: u64 = 1 << 16;
+/// Longer KECCAK256 inputs are not recorded as preimages.
+pub const MAX_PREIMAGE_LEN: usize = 1024;
+/// Nested mappings and arrays deeper than this are rendered with raw base slots.
+pub const MAX_RENDER_DEPTH: usize = 8;
+
+/// KECCAK256 preimages observed during execution: hash -> preimage.
+pub type Preimages = HashMap<U256, Bytes>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum StandardSlot {
+    Eip1967Implementation,
+    Eip1967Admin,
+    Eip1967Beacon,
+    Eip1822Proxiable,
+    OzInitializable,
+    /// The sequential slot 0, where OpenZeppelin 4.x `Initializable` keeps `_initialized` (byte 0)
+    /// and `_initializing` (byte 1) when it is the first base contract.
+    /// It is not in `ALL`, since slot 0 holds the first state variable of any contract.
+    OzInitializableV4,
+}
+
+impl StandardSlot {
+    pub const ALL: [StandardSlot; 5] = [
+        StandardSlot::Eip1967Implementation,
+        StandardSlot::Eip1967Admin,
+        StandardSlot::Eip1967Beacon,
+        StandardSlot::Eip1822Proxiable,
+        StandardSlot::OzInitializable,
+    ];
+
+    /// The slots whose writes are reconstructed by `slot-history`:
+    /// the standard slots, and the slot 0 of OpenZeppelin 4.x `Initializable`.
+    pub const HISTORY: [StandardSlot; 6] = [
+        StandardSlot::Eip1967Implementation,
+        StandardSlot::Eip1967Admin,
+        StandardSlot::Eip1967Beacon,
+        StandardSlot::Eip1822Proxiable,
+        StandardSlot::OzInitializable,
+        StandardSlot::OzInitializableV4,
+    ];
+
+    pub fn slot(&self) -> U256 {
+        match self {
+            StandardSlot::Eip1967Implementation => EIP1967_IMPLEMENTATION_SLOT.cvt(),
+            StandardSlot::Eip1967Admin => EIP1967_ADMIN_SLOT.cvt(),
+            StandardSlot::Eip1967Beacon => EIP1967_BEACON_SLOT.cvt(),
+            StandardSlot::Eip1822Proxiable => EIP1822_PROXIABLE_SLOT.cvt(),
+            StandardSlot::OzInitializable => OZ_INITIALIZABLE_SLOT.cvt(),
+            StandardSlot::OzInitializableV4 => U256::ZERO,
+        }
+    }
+
+    pub fn from_slot(slot: U256) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.slot() == slot)
+    }
+
+    pub fn from_history_slot(slot: U256) -> Option<Self> {
+        Self::HISTORY.into_iter().find(|s| s.slot() == slot)
+    }
+
+    /// The part of a value of the slot that the slot stands for,
+    /// i.e., only `_initialized` and `_initializing` of the slot 0, whose other bytes may hold other variables.
+    pub fn mask(&self, value: U256) -> U256 {
+        match self {
+            StandardSlot::OzInitializableV4 => value & U256::from(0xffffu64),
+            _ => value,
+        }
+    }
+}
+
+impl fmt::Display for StandardSlot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            StandardSlot::Eip1967Implementation => "eip1967.proxy.implementation",
+            StandardSlot::Eip1967Admin => "eip1967.proxy.admin",
+            StandardSlot::Eip1967Beacon => "eip1967.proxy.beacon",
+            StandardSlot::Eip1822Proxiable => "PROXIABLE",
+            StandardSlot::OzInitializable => "openzeppelin.storage.Initializable",
+            StandardSlot::OzInitializableV4 => "openzeppelin.Initializable@0",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Storage layout label of a slot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SlotLabel {
+    /// A well-known slot defined by a standard, intended to be shared by proxy and implementation.
+    Standard {
+        standard: StandardSlot,
+    },
+    /// A sequentially allocated state variable.
+    Sequential {
+        slot: U256,
+    },
+    /// `keccak256(key . base) + offset`: a mapping entry (or a member of a mapping value).
+    Mapping {
+        base: U256,
+        key: Bytes,
+        offset: U256,
+    },
+    /// `keccak256(base) + index`: an element of a dynamic array (or the content of long bytes/string).
+    Array {
+        base: U256,
+        index: U256,
+    },
+    Unknown,
+}
+
+impl SlotLabel {
+    /// Whether the slot is intended to be shared between proxy and implementation.
+    pub fn is_standard(&self) -> bool {
+        matches!(self, SlotLabel::Standard { .. })
+    }
+}
+
+impl fmt::Display for SlotLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SlotLabel::Standard { standard } => write!(f, "{}", standard),
+            SlotLabel::Sequential { slot } => write!(f, "slot@{}", slot),
+            SlotLabel::Mapping { base, key, offset } => {
+                write!(f, "mapping@{}[{}]", base, key)?;
+                if *offset != U256::ZERO {
+                    write!(f, "+{}", offset)?;
+                }
+                Ok(())
+            }
+            SlotLabel::Array { base, index } => write!(f, "array@{}[{}]", base, index),
+            SlotLabel::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Classify a slot according to the solidity storage layout,
+/// using the KECCAK256 preimages observed during execution.
+pub fn classify(slot: U256, preimages: &Preimages) -> SlotLabel {
+    if let Some(standard) = StandardSlot::from_slot(slot) {
+        return SlotLabel::Standard { standard };
+    }
+    if slot < U256::from(MAX_SEQUENTIAL_SLOT) {
+        return SlotLabel::Sequential { slot };
+    }
+    // the closest hash below the slot
+    let hashed = preimages
+        .iter()
+        .filter(|(hash, _)| **hash <= slot && slot - **hash < U256::from(MAX_HASHED_OFFSET))
+        .min_by_key(|(hash, _)| slot - **hash);
+    match hashed {
+        Some((hash, preimage)) if preimage.len() == 32 => SlotLabel::Array {
+            base: U256::from_be_slice(preimage),
+            index: slot - *hash,
+        },
+        Some((hash, preimage)) if preimage.len() > 32 => {
+            let n = preimage.len() - 32;
+            SlotLabel::Mapping {
+                base: U256::from_be_slice(&preimage[n..]),
+                key: Bytes::from(preimage[..n].to_vec()),
+                offset: slot - *hash,
+            }
+        }
+        _ => SlotLabel::Unknown,
+    }
+}
+
+/// Render a slot in a human-auditable form, e.g., `mapping@mapping@3[0x..][0x..]+1`.
+/// Unlike the `Display` of `SlotLabel`, the base slots of mappings and arrays are rendered recursively.
+pub fn render_slot(slot: U256, preimages: &Preimages) -> String {
+    render_slot_with_depth(slot, preimages, 0)
+}
+
+fn render_slot_with_depth(slot: U256, preimages: &Preimages, depth: usize) -> String {
+    let label = classify(slot, preimages);
+    if depth >= MAX_RENDER_DEPTH {
+        return label.to_string();
+    }
+    match label {
+        SlotLabel::Mapping { base, key, offset } => {
+            let base = render_slot_with_depth(base, preimages, depth + 1);
+            if offset == U256::ZERO {
+                format!("mapping@{}[{}]", base, key)
+            } else {
+                format!("mapping@{}[{}]+{}", base, key, offset)
+            }
+        }
+        SlotLabel::Array { base, index } => {
+            let base = render_slot_with_depth(base, preimages, depth + 1);
+            format!("array@{}[{}]", base, index)
+        }
+        SlotLabel::Unknown => format!("{:#x}", slot),
+        label => label.to_string(),
+    }
+}
+
+/// Select the preimages needed to label the given slots, including those of nested base slots.
+pub fn relevant_preimages(
+    slots: impl IntoIterator<Item = U256>,
+    preimages: &Preimages,
+) -> Vec<(U256, Bytes)> {
+    let mut relevant = HashMap::new();
+    let mut pending: Vec<(U256, usize)> = slots.into_iter().map(|s| (s, 0)).collect();
+    while let Some((slot, depth)) = pending.pop() {
+        if depth >= MAX_RENDER_DEPTH {
+            continue;
+        }
+        let (hash, base) = match classify(slot, preimages) {
+            SlotLabel::Mapping { base, offset, .. } => (slot - offset, base),
+            SlotLabel::Array { base, index } => (slot - index, base),
+            _ => continue,
+        };
+        if relevant.insert(hash, preimages[&hash].clone()).is_none() {
+            pending.push((base, depth + 1));
+        }
+    }
+    let mut relevant: Vec<_> = relevant.into_iter().collect();
+    relevant.sort_by_key(|(hash, _)| *hash);
+    relevant
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::{
+        conversion::ConvertTo,
+        engine::types::{Bytes, U256},
+    };
+
+    use super::{
+        classify, relevant_preimages, render_slot, Preimages, SlotLabel, StandardSlot,
+        EIP1967_IMPLEMENTATION_SLOT,
+    };
+
+    #[test]
+    fn test_classify() {
+        let mut preimages = Preimages::new();
+        // keccak256(abi.encode(1, 0)), i.e., mapping at slot 0 with key 1
+        let mapping_entry: U256 =
+            "0xada5013122d395ba3c54772283fb069b10426056ef8ca54750cb9bb552a59e7d".cvt();
+        let preimage: Bytes = "0x00000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000".cvt();
+        preimages.insert(mapping_entry, preimage);
+        // keccak256(abi.encode(3)), i.e., dynamic array at slot 3
+        let array_start: U256 =
+            "0xc2575a0e9e593c00f959f8c92f12db2869c3395a3b0502d05e2516446f71f85b".cvt();
+        let preimage: Bytes =
+            "0x0000000000000000000000000000000000000000000000000000000000000003".cvt();
+        preimages.insert(array_start, preimage);
+
+        assert_eq!(
+            classify(EIP1967_IMPLEMENTATION_SLOT.cvt(), &preimages),
+            SlotLabel::Standard {
+                standard: StandardSlot::Eip1967Implementation
+            }
+        );
+        // the slot 0 of OpenZeppelin 4.x `Initializable` is only tracked by `slot-history`
+        assert_eq!(
+            classify(U256::ZERO, &preimages),
+            SlotLabel::Sequential { slot: U256::ZERO }
+        );
+        assert_eq!(
+            StandardSlot::from_history_slot(U256::ZERO),
+            Some(StandardSlot::OzInitializableV4)
+        );
+        assert_eq!(
+            classify(U256::from(5), &preimages),
+            SlotLabel::Sequential {
+                slot: U256::from(5)
+            }
+        );
+        assert_eq!(
+            classify(mapping_entry + U256::from(1), &preimages),
+            SlotLabel::Mapping {
+                base: U256::ZERO,
+                key: Bytes::from(U256::from(1).to_be_bytes_vec()),
+                offset: U256::from(1),
+            }
+        );
+        assert_eq!(
+            classify(array_start + U256::from(2), &preimages),
+            SlotLabel::Array {
+                base: U256::from(3),
+                index: U256::from(2),
+            }
+        );
+        assert_eq!(classify(U256::MAX, &preimages), SlotLabel::Unknown);
+    }
+
+    #[test]
+    fn test_render_slot() {
+        let mut preimages = Preimages::new();
+        // keccak256(abi.encode(1, 0)), i.e., mapping at slot 0 with key 1
+        let outer: U256 =
+            "0xada5013122d395ba3c54772283fb069b10426056ef8ca54750cb9bb552a59e7d".cvt();
+        let preimage: Bytes = "0x00000000000000000000000000000000000000000000000000000000000000010000000000000000000000000000000000000000000000000000000000000000".cvt();
+        preimages.insert(outer, preimage);
+        // keccak256(abi.encode(2, outer)), i.e., nested mapping with key 2
+        let inner: U256 =
+            "0xb37150ceb7e138645bfe4dfcef9a75073e1d7aa14c524dfdd20d3f751fad1084".cvt();
+        let preimage: Bytes = "0x0000000000000000000000000000000000000000000000000000000000000002ada5013122d395ba3c54772283fb069b10426056ef8ca54750cb9bb552a59e7d".cvt();
+        preimages.insert(inner, preimage);
+        // an unrelated hash
+        preimages.insert(U256::MAX - U256::from(1 << 20), Bytes::from(vec![0u8; 32]));
+
+        let key1 = "0x0000000000000000000000000000000000000000000000000000000000000001";
+        let key2 = "0x0000000000000000000000000000000000000000000000000000000000000002";
+        assert_eq!(
+            classify(outer, &preimages).to_string(),
+            format!("mapping@0[{}]", key1)
+        );
+        assert_eq!(
+            render_slot(inner + U256::from(1), &preimages),
+            format!("mapping@mapping@0[{}][{}]+1", key1, key2)
+        );
+        assert_eq!(
+            render_slot(EIP1967_IMPLEMENTATION_SLOT.cvt(), &preimages),
+            "eip1967.proxy.implementation"
+        );
+        assert_eq!(render_slot(U256::from(3), &preimages), "slot@3");
+
+        let relevant = relevant_preimages([inner + U256::from(1)], &preimages);
+        assert_eq!(
+            relevant.iter().map(|(hash, _)| *hash).collect::<Vec<_>>(),
+            vec![outer, inner]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use libsofl_core::{
+    conversion::ConvertTo,
+    engine::types::{Address, TxHash, U256},
+};
+use sea_orm::ActiveValue;
+
+use crate::{entities, slot::StandardSlot};
+
+/// The slots holding the implementation of a proxy.
+pub const IMPLEMENTATION_SLOTS: [StandardSlot; 2] = [
+    StandardSlot::Eip1967Implementation,
+    StandardSlot::Eip1822Proxiable,
+];
+
+/// A write to a standard slot of a proxy that takes effect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotWrite {
+    pub block: u64,
+    /// the index of the tx in its block
+    pub tx_index: u64,
+    pub tx: TxHash,
+    pub slot: StandardSlot,
+    pub value: U256,
+}
+
+/// A disagreement between the implementation slots written and the versions of the `version` table.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VersionDiscrepancy {
+    /// a version whose implementation is not written to an implementation slot by the block it is first used
+    Unrecorded {
+        implementation: Address,
+        min_block: u64,
+    },
+    /// an implementation written to an implementation slot but never used, e.g., an upgrade without invocations
+    Unused {
+        implementation: Address,
+        block: u64,
+        tx: TxHash,
+    },
+}
+
+impl VersionDiscrepancy {
+    /// The row of the `version_discrepancy` table.
+    pub fn to_model(&self, proxy: &str) -> entities::version_discrepancy::ActiveModel {
+        let (kind, implementation, block, tx) = match self {
+            VersionDiscrepancy::Unrecorded {
+                implementation,
+                min_block,
+            } => ("unrecorded", implementation, min_block, None),
+            VersionDiscrepancy::Unused {
+                implementation,
+                block,
+                tx,
+            } => ("unused", implementation, block, Some(tx)),
+        };
+        entities::version_discrepancy::ActiveModel {
+            proxy: ActiveValue::Set(proxy.to_string()),
+            kind: ActiveValue::Set(kind.to_string()),
+            implementation: ActiveValue::Set(implementation.to_string().to_lowercase()),
+            block: ActiveValue::Set(*block as i64),
+            tx: ActiveValue::Set(tx.map(|tx| tx.to_string().to_lowercase())),
+        }
+    }
+}
+
+/// Cross-check the writes to the implementation slots of a proxy with its versions, given as (implementation, min_block).
+pub fn cross_check(writes: &[SlotWrite], versions: &[(Address, u64)]) -> Vec<VersionDiscrepancy> {
+    let upgrades = writes
+        .iter()
+        .filter(|w| IMPLEMENTATION_SLOTS.contains(&w.slot) && w.value != U256::ZERO)
+        .map(|w| (w.value.cvt(), w))
+        .collect::<Vec<(Address, &SlotWrite)>>();
+    let mut discrepancies = Vec::new();
+    for (implementation, min_block) in versions {
+        if !upgrades
+            .iter()
+            .any(|(a, w)| a == implementation && w.block <= *min_block)
+        {
+            discrepancies.push(VersionDiscrepancy::Unrecorded {
+                implementation: *implementation,
+                min_block: *min_block,
+            });
+        }
+    }
+    for (implementation, w) in upgrades {
+        if !versions.iter().any(|(a, _)| *a == implementation) {
+            discrepancies.push(VersionDiscrepancy::Unused {
+                implementation,
+                block: w.block,
+                tx: w.tx,
+            });
+        }
+    }
+    discrepancies
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::types::{Address, TxHash, U256};
+
+    use crate::slot::StandardSlot;
+
+    use super::{cross_check, SlotWrite, VersionDiscrepancy};
+
+    #[test]
+    fn test_cross_check() {
+        let a = Address::from([0xaa; 20]);
+        let b = Address::from([0xbb; 20]);
+        let c = Address::from([0xcc; 20]);
+        let write = |block, slot, implementation: Address| SlotWrite {
+            block,
+            tx_index: 0,
+            tx: TxHash::from([block as u8; 32]),
+            slot,
+            value: U256::from_be_slice(implementation.as_slice()),
+        };
+        let writes = vec![
+            write(10, StandardSlot::Eip1967Implementation, a),
+            write(10, StandardSlot::Eip1967Admin, c),
+            write(20, StandardSlot::Eip1967Implementation, b),
+            write(30, StandardSlot::Eip1967Implementation, c),
+        ];
+        // b is used before it is written, and c is never used
+        let versions = vec![(a, 11), (b, 15)];
+        assert_eq!(
+            cross_check(&writes, &versions),
+            vec![
+                VersionDiscrepancy::Unrecorded {
+                    implementation: b,
+                    min_block: 15,
+                },
+                VersionDiscrepancy::Unused {
+                    implementation: c,
+                    block: 30,
+                    tx: TxHash::from([30u8; 32]),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,116 @@
+use libsofl_core::engine::types::Address;
+
+/// The first value and its changes in a sequence ordered by block.
+/// Of several values in the same block, the last one is kept.
+pub fn changes<T: PartialEq>(values: impl IntoIterator<Item = (u64, T)>) -> Vec<(u64, T)> {
+    let mut changes: Vec<(u64, T)> = vec![];
+    for (block, value) in values {
+        if let Some((last_block, _)) = changes.last() {
+            if *last_block == block {
+                changes.pop();
+            }
+        }
+        if changes.last().map_or(true, |(_, last)| *last != value) {
+            changes.push((block, value));
+        }
+    }
+    changes
+}
+
+/// The declared and the delegated implementation of a proxy at the end of a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub block: u64,
+    /// zero if no convention matches
+    pub slot_implementation: Address,
+    pub convention: Option<String>,
+    /// None before the first delegation observed
+    pub actual_implementation: Option<Address>,
+    pub slot_changed: bool,
+    pub delegation_changed: bool,
+}
+
+impl Checkpoint {
+    pub fn mismatched(&self) -> bool {
+        matches!(self.actual_implementation, Some(actual) if actual != self.slot_implementation)
+    }
+}
+
+/// Merge the changes of the declared implementation (with its convention) and of the delegated implementation,
+/// both ordered by block, into one checkpoint per block where either changes.
+/// The first checkpoint is the first block of either.
+pub fn timeline(
+    slot_changes: &[(u64, (Address, Option<String>))],
+    delegation_changes: &[(u64, Address)],
+) -> Vec<Checkpoint> {
+    let mut blocks = slot_changes
+        .iter()
+        .map(|(b, _)| *b)
+        .chain(delegation_changes.iter().map(|(b, _)| *b))
+        .collect::<Vec<_>>();
+    blocks.sort();
+    blocks.dedup();
+    blocks
+        .into_iter()
+        .map(|block| {
+            let slot = slot_changes.iter().take_while(|(b, _)| *b <= block).last();
+            let (slot_implementation, convention) = match slot {
+                Some((_, (implementation, convention))) => (*implementation, convention.clone()),
+                None => (Address::ZERO, None),
+            };
+            let actual_implementation = delegation_changes
+                .iter()
+                .take_while(|(b, _)| *b <= block)
+                .last()
+                .map(|(_, implementation)| *implementation);
+            Checkpoint {
+                block,
+                slot_implementation,
+                convention,
+                actual_implementation,
+                slot_changed: slot_changes.iter().any(|(b, _)| *b == block),
+                delegation_changed: delegation_changes.iter().any(|(b, _)| *b == block),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use libsofl_core::engine::types::Address;
+
+    use super::{changes, timeline};
+
+    #[test]
+    fn test_timeline() {
+        let a = Address::from([0xaa; 20]);
+        let b = Address::from([0xbb; 20]);
+        let delegations = changes([(10, a), (12, a), (15, b), (15, a), (20, b)]);
+        assert_eq!(delegations, vec![(10, a), (20, b)]);
+
+        // the slot is upgraded to b before the proxy delegates to b
+        let slots = vec![
+            (10, (a, Some("eip1967".to_string()))),
+            (18, (b, Some("eip1967".to_string()))),
+        ];
+        let checkpoints = timeline(&slots, &delegations);
+        assert_eq!(
+            checkpoints
+                .iter()
+                .map(|c| (
+                    c.block,
+                    c.mismatched(),
+                    c.slot_changed,
+                    c.delegation_changed
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (10, false, true, true),
+                (18, true, true, false),
+                (20, false, false, true)
+            ]
+        );
+        assert_eq!(checkpoints[1].actual_implementation, Some(a));
+        assert_eq!(checkpoints[1].slot_implementation, b);
+    }
+}